@@ -0,0 +1,74 @@
+//! Measures `MidiOutputConnection::send` for short messages and counts the
+//! allocations it makes, by sending to a virtual port (so no device is needed).
+//! With CoreMIDI, no allocations should be made once the connection is open.
+//!
+//! Run with `cargo bench --bench send`.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::error::Error;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+const ITERATIONS: usize = 100_000;
+
+fn main() {
+    match run() {
+        Ok(_) => (),
+        Err(err) => println!("Error: {}", err),
+    }
+}
+
+#[cfg(not(any(windows, target_arch = "wasm32")))] // virtual ports are not supported on Windows nor on Web MIDI
+fn run() -> Result<(), Box<dyn Error>> {
+    use midir::os::unix::VirtualOutput;
+    use midir::MidiOutput;
+
+    let midi_out = MidiOutput::new("midir send benchmark")?;
+    let mut conn_out = midi_out.create_virtual("midir send benchmark")?;
+
+    for message in [
+        &[0x90, 60, 100][..],
+        &[0xF8],
+        &[0xF0, 0x7E, 0x7F, 0x06, 0x01, 0xF7],
+    ] {
+        // the first message may allocate buffers that are reused afterwards
+        conn_out.send(message)?;
+        let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+        let start = Instant::now();
+        for _ in 0..ITERATIONS {
+            conn_out.send(message)?;
+        }
+        let elapsed = start.elapsed();
+        let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+        println!(
+            "{:02X?}: {:?} per send, {} allocations per send",
+            message,
+            elapsed / ITERATIONS as u32,
+            allocations as f64 / ITERATIONS as f64
+        );
+    }
+    Ok(())
+}
+
+#[cfg(any(windows, target_arch = "wasm32"))]
+fn run() -> Result<(), Box<dyn Error>> {
+    Err("virtual ports are not supported on this platform".into())
+}
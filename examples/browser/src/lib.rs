@@ -1,10 +1,8 @@
 use js_sys::Array;
 use wasm_bindgen::prelude::*;
-use wasm_bindgen::JsCast;
 use web_sys::console;
 
 use std::error::Error;
-use std::sync::{Arc, Mutex};
 
 use midir::{Ignore, MidiInput};
 
@@ -21,38 +19,23 @@ macro_rules! println {
 pub fn start() {
     std::panic::set_hook(Box::new(console_error_panic_hook::hook));
 
-    let token_outer = Arc::new(Mutex::new(None));
-    let token = token_outer.clone();
-    let closure: Closure<dyn FnMut()> = Closure::wrap(Box::new(move || {
-        if run().unwrap() == true {
-            if let Some(token) = *token.lock().unwrap() {
-                web_sys::window().unwrap().clear_interval_with_handle(token);
-            }
+    wasm_bindgen_futures::spawn_local(async {
+        if let Err(err) = run().await {
+            println!("Error: {}", err);
         }
-    }));
-    *token_outer.lock().unwrap() = web_sys::window()
-        .unwrap()
-        .set_interval_with_callback_and_timeout_and_arguments_0(
-            closure.as_ref().unchecked_ref(),
-            200,
-        )
-        .ok();
-    closure.forget();
+    });
 }
 
-fn run() -> Result<bool, Box<dyn Error>> {
+async fn run() -> Result<(), Box<dyn Error>> {
     let window = web_sys::window().expect("no global `window` exists");
 
     let mut midi_in = MidiInput::new("midir reading input")?;
     midi_in.ignore(Ignore::None);
 
-    // Get an input port
-    let ports = midi_in.ports();
+    // Get an input port (this waits until access to MIDI devices has been granted)
+    let ports = midi_in.ports_async().await;
     let in_port = match &ports[..] {
-        [] => {
-            println!("No ports available yet, will try again");
-            return Ok(false);
-        }
+        [] => return Err("no input port found".into()),
         [ref port] => {
             println!(
                 "Choosing the only available input port: {}",
@@ -92,5 +75,5 @@ fn run() -> Result<bool, Box<dyn Error>> {
 
     println!("Connection open, reading input from '{}'", in_port_name);
     Box::leak(Box::new(_conn_in));
-    Ok(true)
+    Ok(())
 }
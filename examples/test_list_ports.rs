@@ -19,13 +19,13 @@ fn run() -> Result<(), Box<dyn Error>> {
 
     loop {
         println!("Available input ports:");
-        for (i, p) in midi_in.ports().iter().enumerate() {
-            println!("{}: {}", i, midi_in.port_name(p)?);
+        for (i, (_, name)) in midi_in.ports_with_names().iter().enumerate() {
+            println!("{}: {}", i, name);
         }
 
         println!("\nAvailable output ports:");
-        for (i, p) in midi_out.ports().iter().enumerate() {
-            println!("{}: {}", i, midi_out.port_name(p)?);
+        for (i, (_, name)) in midi_out.ports_with_names().iter().enumerate() {
+            println!("{}: {}", i, name);
         }
 
         // run in endless loop if "--loop" parameter is specified
@@ -1,23 +1,53 @@
+use std::collections::HashSet;
 use std::ffi::{CStr, CString};
 use std::io::{stderr, Write};
 use std::mem;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
 use std::thread::{Builder, JoinHandle};
+use std::time::Instant;
 
-use crate::{errors, Ignore, MidiMessage};
+use crate::os::unix::alsa::{Compat, SubscriptionCallback, SubscriptionEvent};
+use crate::time::Timestamp;
+use crate::{
+    errors, DeviceEvent, EarlyMessagePolicy, Ignore, InputExecutor, MidiMessage, RawTap, SysexMode,
+};
 
-use alsa::seq::{Addr, EventType, PortCap, PortInfo, PortSubscribe, PortType, QueueTempo};
+use alsa::seq::{
+    Addr, EventType, PortCap, PortInfo, PortSubscribe, PortType, QuerySubsType, QueueTempo,
+};
 use alsa::{Direction, Seq};
 
 use errors::*;
 
 mod helpers {
     use crate::errors::PortInfoError;
-    use alsa::seq::{Addr, ClientIter, MidiEvent, PortCap, PortInfo, PortIter, PortType, Seq};
+    use alsa::seq::{
+        Addr, ClientIter, MidiEvent, PortCap, PortInfo, PortIter, PortSubscribeIter, PortType,
+        QuerySubsType, Seq,
+    };
+
+    /// Parses a port ID in the `client:port` format that is used by `id()`.
+    pub fn parse_addr(id: &str) -> Option<Addr> {
+        let (client, port) = id.split_once(':')?;
+        Some(Addr {
+            client: client.parse().ok()?,
+            port: port.parse().ok()?,
+        })
+    }
 
     pub fn poll(fds: &mut [libc::pollfd], timeout: i32) -> i32 {
         unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout) }
     }
 
+    /// Whether the port is listed as an input or output port (depending on `capability`).
+    #[inline]
+    pub fn is_listed(p: &PortInfo, capability: PortCap) -> bool {
+        p.get_type()
+            .intersects(PortType::MIDI_GENERIC | PortType::SYNTH | PortType::APPLICATION)
+            && p.get_capability().contains(capability)
+    }
+
     #[inline]
     pub fn get_ports<F, T>(s: &Seq, capability: PortCap, f: F) -> Vec<T>
     where
@@ -25,11 +55,7 @@ mod helpers {
     {
         ClientIter::new(s)
             .flat_map(|c| PortIter::new(s, c.get_client()))
-            .filter(|p| {
-                p.get_type()
-                    .intersects(PortType::MIDI_GENERIC | PortType::SYNTH | PortType::APPLICATION)
-            })
-            .filter(|p| p.get_capability().contains(capability))
+            .filter(|p| is_listed(p, capability))
             .map(f)
             .collect()
     }
@@ -38,18 +64,34 @@ mod helpers {
     pub fn get_port_count(s: &Seq, capability: PortCap) -> usize {
         ClientIter::new(s)
             .flat_map(|c| PortIter::new(s, c.get_client()))
-            .filter(|p| {
-                p.get_type()
-                    .intersects(PortType::MIDI_GENERIC | PortType::SYNTH | PortType::APPLICATION)
-            })
-            .filter(|p| p.get_capability().contains(capability))
+            .filter(|p| is_listed(p, capability))
             .count()
     }
 
+    /// The kernel assigns static client numbers to the sound cards, starting at 16 with
+    /// 4 clients per card (see `seq_clientmgr.c`), and the rawmidi sequencer client of a card
+    /// creates 32 ports per rawmidi device, one for each subdevice (see `seq_midi.c`).
+    pub fn get_hw_info(s: &Seq, addr: Addr) -> Option<(i32, i32)> {
+        const GLOBAL_CLIENTS: i32 = 16;
+        const CLIENTS_PER_CARD: i32 = 4;
+        const CARD_CLIENTS_END: i32 = 128;
+        const PORTS_PER_DEVICE: i32 = 32;
+
+        let pinfo = s.get_any_port_info(addr).ok()?;
+        if !pinfo.get_type().contains(PortType::HARDWARE)
+            || addr.client < GLOBAL_CLIENTS
+            || addr.client >= CARD_CLIENTS_END
+        {
+            return None;
+        }
+        Some((
+            (addr.client - GLOBAL_CLIENTS) / CLIENTS_PER_CARD,
+            addr.port / PORTS_PER_DEVICE,
+        ))
+    }
+
     #[inline]
     pub fn get_port_name(s: &Seq, addr: Addr) -> Result<String, PortInfoError> {
-        use std::fmt::Write;
-
         let pinfo = match s.get_any_port_info(addr) {
             Ok(p) => p,
             Err(_) => return Err(PortInfoError::InvalidPort),
@@ -58,13 +100,23 @@ mod helpers {
         let cinfo = s
             .get_any_client_info(pinfo.get_client())
             .map_err(|_| PortInfoError::CannotRetrievePortName)?;
+        format_port_name(cinfo.get_name(), &pinfo)
+    }
+
+    /// Names are supplied by the devices and might not be valid UTF-8. The `alsa` crate
+    /// checks this (without giving access to the raw bytes for a lossy conversion) and
+    /// returns an error, which must never be unwrapped here.
+    pub fn format_port_name(
+        client_name: alsa::Result<&str>,
+        pinfo: &PortInfo,
+    ) -> Result<String, PortInfoError> {
+        use std::fmt::Write;
+
         let mut output = String::new();
         write!(
             &mut output,
             "{}:{} {}:{}",
-            cinfo
-                .get_name()
-                .map_err(|_| PortInfoError::CannotRetrievePortName)?,
+            client_name.map_err(|_| PortInfoError::CannotRetrievePortName)?,
             pinfo
                 .get_name()
                 .map_err(|_| PortInfoError::CannotRetrievePortName)?,
@@ -75,6 +127,17 @@ mod helpers {
         Ok(output)
     }
 
+    /// Returns `false` if the port already has an exclusive subscription
+    /// in the given direction, which means that nobody else can connect.
+    pub fn is_connectable(s: &Seq, addr: Addr, subs_type: QuerySubsType) -> bool {
+        !PortSubscribeIter::new(s, addr, subs_type).any(|sub| sub.get_exclusive())
+    }
+
+    pub fn get_client_name(s: &Seq, addr: Addr) -> Option<String> {
+        let cinfo = s.get_any_client_info(addr.client).ok()?;
+        cinfo.get_name().ok().map(String::from)
+    }
+
     pub struct EventDecoder {
         ev: MidiEvent,
     }
@@ -104,7 +167,7 @@ mod helpers {
         pub fn new(buffer_size: u32) -> EventEncoder {
             EventEncoder {
                 ev: MidiEvent::new(buffer_size).unwrap(),
-                buffer_size: buffer_size,
+                buffer_size,
             }
         }
 
@@ -133,8 +196,19 @@ mod helpers {
 
 const INITIAL_CODER_BUFFER_SIZE: usize = 32;
 
+/// Whether input ports are timestamped using a queue of the sequencer, which is not the
+/// case with the `avoid_timestamping` feature, in PipeWire compatibility mode or if
+/// timestamping has been disabled.
+fn uses_queue(compat: Compat, timestamping: bool) -> bool {
+    !cfg!(feature = "avoid_timestamping") && compat != Compat::PipeWire && timestamping
+}
+
 pub struct MidiInput {
     ignore_flags: Ignore,
+    sysex_mode: SysexMode,
+    max_sysex_size: Option<usize>,
+    compat: Compat,
+    timestamping: bool,
     seq: Option<Seq>,
 }
 
@@ -147,37 +221,186 @@ impl MidiInputPort {
     pub fn id(&self) -> String {
         format!("{}:{}", self.addr.client, self.addr.port)
     }
+
+    pub fn from_id(id: &str) -> Option<Self> {
+        helpers::parse_addr(id).map(|addr| MidiInputPort { addr })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SourceId {
+    addr: Addr,
+}
+
+impl SourceId {
+    pub fn port_id(&self) -> Option<String> {
+        Some(format!("{}:{}", self.addr.client, self.addr.port))
+    }
 }
 
 pub struct MidiInputConnection<T: 'static> {
-    subscription: Option<PortSubscribe>,
-    thread: Option<JoinHandle<(HandlerData<T>, T)>>,
+    subscriptions: Vec<PortSubscribe>,
+    thread: Option<HandlerThread<T>>,
     vport: i32, // TODO: probably port numbers are only u8, therefore could use Option<u8>
+    client_id: i32,
     trigger_send_fd: i32,
+    // Also owned by the handler data, but needed to clean up if that is lost
+    trigger_rcv_fd: i32,
+    raw_tap: Arc<Mutex<Option<RawTap>>>,
+    subscription_callback: Arc<Mutex<Option<SubscriptionCallback>>>,
+    clear_requested: Arc<AtomicBool>,
+    filtered_count: Arc<AtomicU64>,
+    timestamps_degraded: bool,
+}
+
+/// The handler loop of an input connection, which either runs on its own
+/// thread or has been handed to a user-supplied `InputExecutor`.
+/// The handler data and the user data while the handler loop is not running.
+type HandlerSlot<T> = Arc<Mutex<Option<(HandlerData<T>, T)>>>;
+
+enum HandlerThread<T: 'static> {
+    Spawned(JoinHandle<Option<(HandlerData<T>, T)>>),
+    // The data stays in the slot until the task runs, so that it is not lost if the executor
+    // drops the task instead. The receiver is disconnected once the task is done or dropped.
+    Executor(HandlerSlot<T>, mpsc::Receiver<()>),
+}
+
+impl<T: Send> HandlerThread<T> {
+    /// Starts the handler loop. If no thread can be spawned for it, the handler
+    /// data and the user data are given back, so that the caller can clean up.
+    fn start(
+        handler_data: HandlerData<T>,
+        data: T,
+        executor: Option<&dyn InputExecutor>,
+        name: Option<String>,
+    ) -> Result<Self, Box<(HandlerData<T>, T)>> {
+        fn run<T>((handler_data, mut data): (HandlerData<T>, T)) -> (HandlerData<T>, T) {
+            let h = handle_input(handler_data, &mut data);
+            (h, data) // return both the handler data and the user data
+        }
+        match executor {
+            Some(executor) => {
+                let slot = Arc::new(Mutex::new(Some((handler_data, data))));
+                let (done, rx) = mpsc::channel::<()>();
+                let task_slot = slot.clone();
+                executor.execute(Box::new(move || {
+                    let _done = done;
+                    let taken = lock_slot(&task_slot).take();
+                    if let Some(taken) = taken {
+                        let finished = run(taken);
+                        *lock_slot(&task_slot) = Some(finished);
+                    }
+                }));
+                Ok(HandlerThread::Executor(slot, rx))
+            }
+            None => {
+                let mut threadbuilder = Builder::new();
+                if let Some(name) = name {
+                    threadbuilder = threadbuilder.name(name);
+                }
+                // The data is only handed over once the thread is running, because
+                // `spawn` would drop it together with the closure if it failed
+                let (tx, rx) = mpsc::channel();
+                match threadbuilder.spawn(move || rx.recv().ok().map(run)) {
+                    Ok(handle) => {
+                        let _ = tx.send((handler_data, data));
+                        Ok(HandlerThread::Spawned(handle))
+                    }
+                    Err(_) => Err(Box::new((handler_data, data))),
+                }
+            }
+        }
+    }
+}
+
+impl<T> HandlerThread<T> {
+    fn thread_id(&self) -> Option<std::thread::ThreadId> {
+        match self {
+            HandlerThread::Spawned(handle) => Some(handle.thread().id()),
+            // We don't know which thread the executor runs the handler loop on
+            HandlerThread::Executor(..) => None,
+        }
+    }
+
+    /// Returns `None` if the handler loop has panicked. If it has been handed to
+    /// an executor that dropped it without running it, the data is returned as is.
+    fn join(self) -> Option<(HandlerData<T>, T)> {
+        match self {
+            HandlerThread::Spawned(handle) => handle.join().ok().flatten(),
+            HandlerThread::Executor(slot, rx) => {
+                // Wait until the task has finished or has been dropped
+                let _ = rx.recv();
+                let taken = lock_slot(&slot).take();
+                taken
+            }
+        }
+    }
+}
+
+fn lock_slot<T>(slot: &HandlerSlot<T>) -> std::sync::MutexGuard<'_, Option<(HandlerData<T>, T)>> {
+    // The slot is never left in an inconsistent state, so a poisoned lock can be ignored
+    slot.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+// The address of the sender of each message is passed on (see `connect_multi`)
+type InputCallback<T> = Box<dyn FnMut(u64, &[u8], Addr, &mut T) + Send>;
+
+fn without_source<F, T>(mut callback: F) -> InputCallback<T>
+where
+    F: FnMut(u64, &[u8], &mut T) + Send + 'static,
+{
+    Box::new(move |timestamp, message, _, data| callback(timestamp, message, data))
+}
+type RawCallback<T> = Box<dyn FnMut(&RawEvent<'_>, &mut T) + Send>;
+
+/// An event as it has been received from the sequencer (see `MidiInput::connect_raw`).
+#[non_exhaustive]
+#[allow(dead_code)] // only read by users of the `unsafe-raw` feature
+pub struct RawEvent<'a> {
+    /// The sequencer event, including its type, flags, source and time.
+    pub event: &'a alsa::seq::Event<'a>,
 }
 
 struct HandlerData<T: 'static> {
     ignore_flags: Ignore,
+    sysex_mode: SysexMode,
+    max_sysex_size: Option<usize>,
+    compat: Compat,
+    timestamping: bool,
     seq: Seq,
     trigger_rcv_fd: i32,
-    callback: Box<dyn FnMut(u64, &[u8], &mut T) + Send>,
-    queue_id: i32, // an input queue is needed to get timestamped events
+    callback: InputCallback<T>,
+    // Receives the events instead of `callback` if the connection has been made with `connect_raw`
+    raw_callback: Option<RawCallback<T>>,
+    raw_tap: Arc<Mutex<Option<RawTap>>>,
+    subscription_callback: Arc<Mutex<Option<SubscriptionCallback>>>,
+    clear_requested: Arc<AtomicBool>,
+    filtered_count: Arc<AtomicU64>,
+    queue_id: Option<i32>, // an input queue is needed to get timestamped events
+    // Whether the input queue could not be allocated, so that all timestamps are 0
+    timestamps_degraded: bool,
+    // Messages that have been received but not delivered when the handler stopped
+    pending: Vec<(u64, Vec<u8>)>,
 }
 
 impl MidiInput {
     pub fn new(client_name: &str) -> Result<Self, InitError> {
         let seq = match Seq::open(None, None, true) {
             Ok(s) => s,
-            Err(_) => {
-                return Err(InitError);
-            }
+            Err(err) => return Err(InitError::with_source(err)),
         };
 
-        let c_client_name = CString::new(client_name).map_err(|_| InitError)?;
-        seq.set_client_name(&c_client_name).map_err(|_| InitError)?;
+        let c_client_name = CString::new(client_name)
+            .map_err(|_| InitError::new("the client name contains a null byte"))?;
+        seq.set_client_name(&c_client_name)
+            .map_err(InitError::with_source)?;
 
         Ok(MidiInput {
             ignore_flags: Ignore::None,
+            sysex_mode: SysexMode::Complete,
+            max_sysex_size: None,
+            compat: Compat::None,
+            timestamping: true,
             seq: Some(seq),
         })
     }
@@ -186,13 +409,86 @@ impl MidiInput {
         self.ignore_flags = flags;
     }
 
+    pub fn ignored_flags(&self) -> Ignore {
+        self.ignore_flags
+    }
+
+    pub fn set_sysex_mode(&mut self, mode: SysexMode) {
+        self.sysex_mode = mode;
+    }
+
+    pub fn set_max_sysex_size(&mut self, max: Option<usize>) {
+        self.max_sysex_size = max;
+    }
+
+    pub fn set_compat(&mut self, compat: Compat) {
+        self.compat = compat;
+    }
+
+    pub fn set_timestamping(&mut self, enable: bool) {
+        self.timestamping = enable;
+    }
+
+    pub fn timestamping(&self) -> bool {
+        self.timestamping
+    }
+
+    pub fn set_backend_option(&mut self, key: &str, value: &str) -> Result<(), ConnectErrorKind> {
+        match key {
+            "alsa.input_pool_size" => {
+                let size = value.parse().map_err(|_| {
+                    ConnectErrorKind::Other("invalid value for alsa.input_pool_size")
+                })?;
+                self.seq
+                    .as_ref()
+                    .unwrap()
+                    .set_client_pool_input(size)
+                    .map_err(|_| ConnectErrorKind::Other("could not set the ALSA input pool size"))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn raw_timestamp_fn(&self) -> fn(u64) -> u64 {
+        // The real time of the queue already is in nanoseconds
+        |nanos| nanos
+    }
+
+    pub fn set_early_message_policy(&mut self, _policy: EarlyMessagePolicy) {
+        // The subscription is made synchronously in `connect`, so no message can arrive early
+    }
+
+    pub fn reject_duplicate_virtual_names(&mut self, _reject: bool) {
+        // port names are always qualified by the client, so there are no duplicates
+    }
+
+    pub fn supports_virtual_ports(&self) -> bool {
+        true
+    }
+
+    pub fn set_client_name(&mut self, name: &str) -> Result<(), ConnectErrorKind> {
+        let c_name =
+            CString::new(name).map_err(|_| ConnectErrorKind::Other("invalid client name"))?;
+        self.seq
+            .as_ref()
+            .unwrap()
+            .set_client_name(&c_name)
+            .map_err(|_| ConnectErrorKind::Other("could not rename ALSA client"))
+    }
+
+    #[cfg(not(feature = "jack"))]
     pub(crate) fn ports_internal(&self) -> Vec<crate::common::MidiInputPort> {
+        self.ports()
+            .into_iter()
+            .map(|imp| crate::common::MidiInputPort { imp })
+            .collect()
+    }
+
+    pub(crate) fn ports(&self) -> Vec<MidiInputPort> {
         helpers::get_ports(
             self.seq.as_ref().unwrap(),
             PortCap::READ | PortCap::SUBS_READ,
-            |p| crate::common::MidiInputPort {
-                imp: MidiInputPort { addr: p.addr() },
-            },
+            |p| MidiInputPort { addr: p.addr() },
         )
     }
 
@@ -207,23 +503,71 @@ impl MidiInput {
         helpers::get_port_name(self.seq.as_ref().unwrap(), port.addr)
     }
 
-    fn init_queue(&mut self) -> i32 {
+    pub fn watch(
+        &self,
+        callback: Box<dyn FnMut(DeviceEvent<MidiInputPort>) + Send>,
+    ) -> Result<PortWatcher, InitError> {
+        PortWatcher::start(
+            PortCap::READ | PortCap::SUBS_READ,
+            |addr| MidiInputPort { addr },
+            callback,
+        )
+    }
+
+    pub fn port_metadata(
+        &self,
+        port: &MidiInputPort,
+    ) -> Result<crate::PortMetadata, PortInfoError> {
+        // ALSA only knows the names of clients and ports
+        helpers::get_port_name(self.seq.as_ref().unwrap(), port.addr)?;
+        Ok(crate::PortMetadata::default())
+    }
+
+    pub fn hw_info(&self, port: &MidiInputPort) -> Option<(i32, i32)> {
+        helpers::get_hw_info(self.seq.as_ref().unwrap(), port.addr)
+    }
+
+    pub fn device_name(&self, port: &MidiInputPort) -> Option<String> {
+        helpers::get_client_name(self.seq.as_ref().unwrap(), port.addr)
+    }
+
+    pub fn is_connectable(&self, port: &MidiInputPort) -> bool {
+        helpers::is_connectable(self.seq.as_ref().unwrap(), port.addr, QuerySubsType::READ)
+    }
+
+    /// Creates the input queue, if one is needed (see `uses_queue`). If the queue can't
+    /// be allocated (e.g. because of resource limits), the connection is made without
+    /// it and all timestamps will be 0.
+    fn init_queue(&mut self) -> Option<i32> {
+        if !uses_queue(self.compat, self.timestamping) {
+            return None;
+        }
         let seq = self.seq.as_mut().unwrap();
-        let mut queue_id = 0;
-        // Create the input queue
-        if !cfg!(feature = "avoid_timestamping") {
-            queue_id = seq
-                .alloc_named_queue(unsafe { CStr::from_bytes_with_nul_unchecked(b"midir queue\0") })
-                .unwrap();
-            // Set arbitrary tempo (mm=100) and resolution (240)
-            let qtempo = QueueTempo::empty().unwrap();
-            qtempo.set_tempo(600_000);
-            qtempo.set_ppq(240);
-            seq.set_queue_tempo(queue_id, &qtempo).unwrap();
-            let _ = seq.drain_output();
+        let queue_id = match seq.alloc_named_queue(c"midir queue") {
+            Ok(queue_id) => queue_id,
+            Err(_) => {
+                let _ = writeln!(
+                    stderr(),
+                    "\nWarning in MidiInput::connect: could not allocate ALSA input queue, timestamps will be 0!\n"
+                );
+                return None;
+            }
+        };
+        // Set arbitrary tempo (mm=100) and resolution (240)
+        let qtempo = QueueTempo::empty().unwrap();
+        qtempo.set_tempo(600_000);
+        qtempo.set_ppq(240);
+        if seq.set_queue_tempo(queue_id, &qtempo).is_err() {
+            let _ = seq.free_queue(queue_id);
+            let _ = writeln!(
+                stderr(),
+                "\nWarning in MidiInput::connect: could not set up ALSA input queue, timestamps will be 0!\n"
+            );
+            return None;
         }
+        let _ = seq.drain_output();
 
-        queue_id
+        Some(queue_id)
     }
 
     fn init_trigger(&mut self) -> Result<[i32; 2], ()> {
@@ -236,7 +580,28 @@ impl MidiInput {
         }
     }
 
-    fn create_port(&mut self, port_name: &CStr, queue_id: i32) -> Result<i32, ()> {
+    /// Releases what has been set up by `init_trigger`, `init_queue` and `create_port`
+    /// when connecting fails, so that the `MidiInput` can be reused without leaking them.
+    fn release_connect_resources(
+        &mut self,
+        trigger_fds: [i32; 2],
+        queue_id: Option<i32>,
+        vport: Option<i32>,
+    ) {
+        let seq = self.seq.as_ref().unwrap();
+        if let Some(vport) = vport {
+            let _ = seq.delete_port(vport);
+        }
+        if let Some(queue_id) = queue_id {
+            let _ = seq.free_queue(queue_id);
+        }
+        unsafe {
+            libc::close(trigger_fds[0]);
+            libc::close(trigger_fds[1]);
+        }
+    }
+
+    fn create_port(&mut self, port_name: &CStr, queue_id: Option<i32>) -> Result<i32, ()> {
         let mut pinfo = PortInfo::empty().unwrap();
         // these functions are private, and the values are zeroed already by `empty()`
         //pinfo.set_client(0);
@@ -245,21 +610,28 @@ impl MidiInput {
         pinfo.set_type(PortType::MIDI_GENERIC | PortType::APPLICATION);
         pinfo.set_midi_channels(16);
 
-        if !cfg!(feature = "avoid_timestamping") {
+        if let Some(queue_id) = queue_id {
             pinfo.set_timestamping(true);
             pinfo.set_timestamp_real(true);
             pinfo.set_timestamp_queue(queue_id);
         }
 
         pinfo.set_name(port_name);
-        match self.seq.as_mut().unwrap().create_port(&mut pinfo) {
+        match self.seq.as_mut().unwrap().create_port(&pinfo) {
             Ok(_) => Ok(pinfo.get_port()),
             Err(_) => Err(()),
         }
     }
 
-    fn start_input_queue(&mut self, queue_id: i32) {
-        if !cfg!(feature = "avoid_timestamping") {
+    fn unsubscribe(&self, sub: &PortSubscribe) -> alsa::Result<()> {
+        self.seq
+            .as_ref()
+            .unwrap()
+            .unsubscribe_port(sub.get_sender(), sub.get_dest())
+    }
+
+    fn start_input_queue(&mut self, queue_id: Option<i32>) {
+        if let Some(queue_id) = queue_id {
             let seq = self.seq.as_mut().unwrap();
             let _ = seq.control_queue(queue_id, EventType::Start, 0, None);
             let _ = seq.drain_output();
@@ -267,7 +639,7 @@ impl MidiInput {
     }
 
     pub fn connect<F, T: Send>(
-        mut self,
+        self,
         port: &MidiInputPort,
         port_name: &str,
         callback: F,
@@ -276,22 +648,112 @@ impl MidiInput {
     where
         F: FnMut(u64, &[u8], &mut T) + Send + 'static,
     {
-        let trigger_fds = match self.init_trigger() {
-            Ok(fds) => fds,
-            Err(()) => {
-                return Err(ConnectError::other(
-                    "could not create communication pipe for ALSA handler",
-                    self,
-                ));
+        self.connect_internal(
+            None,
+            &[port],
+            port_name,
+            without_source(callback),
+            None,
+            data,
+        )
+    }
+
+    pub fn connect_with_source<F, T: Send>(
+        self,
+        port: &MidiInputPort,
+        port_name: &str,
+        mut callback: F,
+        data: T,
+    ) -> Result<MidiInputConnection<T>, ConnectError<Self>>
+    where
+        F: FnMut(u64, &[u8], SourceId, &mut T) + Send + 'static,
+    {
+        let callback = Box::new(move |timestamp, message: &[u8], addr, data: &mut T| {
+            callback(timestamp, message, SourceId { addr }, data)
+        });
+        self.connect_internal(None, &[port], port_name, callback, None, data)
+    }
+
+    pub fn connect_multi<F, T: Send>(
+        self,
+        ports: &[&MidiInputPort],
+        port_name: &str,
+        mut callback: F,
+        data: T,
+    ) -> Result<MidiInputConnection<T>, ConnectError<Self>>
+    where
+        F: FnMut(u64, &[u8], usize, &mut T) + Send + 'static,
+    {
+        let senders: Vec<Addr> = ports.iter().map(|port| port.addr).collect();
+        let callback = Box::new(move |timestamp, message: &[u8], source, data: &mut T| {
+            // Messages from ports that are subscribed from elsewhere have no index
+            if let Some(index) = senders.iter().position(|&addr| addr == source) {
+                callback(timestamp, message, index, data)
             }
-        };
+        });
+        self.connect_internal(None, ports, port_name, callback, None, data)
+    }
 
-        let queue_id = self.init_queue();
+    #[allow(dead_code)] // only used with the `unsafe-raw` feature
+    pub fn connect_raw<F, T: Send>(
+        self,
+        port: &MidiInputPort,
+        port_name: &str,
+        callback: F,
+        data: T,
+    ) -> Result<MidiInputConnection<T>, ConnectError<Self>>
+    where
+        F: FnMut(&RawEvent<'_>, &mut T) + Send + 'static,
+    {
+        let raw_callback = Some(Box::new(callback) as RawCallback<T>);
+        self.connect_internal(
+            None,
+            &[port],
+            port_name,
+            Box::new(|_, _, _, _| {}),
+            raw_callback,
+            data,
+        )
+    }
 
-        let src_pinfo = match self.seq.as_ref().unwrap().get_any_port_info(port.addr) {
-            Ok(p) => p,
-            Err(_) => return Err(ConnectError::new(ConnectErrorKind::InvalidPort, self)),
-        };
+    pub fn connect_on<F, T: Send>(
+        self,
+        executor: &dyn InputExecutor,
+        port: &MidiInputPort,
+        port_name: &str,
+        callback: F,
+        data: T,
+    ) -> Result<MidiInputConnection<T>, ConnectError<Self>>
+    where
+        F: FnMut(u64, &[u8], &mut T) + Send + 'static,
+    {
+        self.connect_internal(
+            Some(executor),
+            &[port],
+            port_name,
+            without_source(callback),
+            None,
+            data,
+        )
+    }
+
+    fn connect_internal<T: Send>(
+        mut self,
+        executor: Option<&dyn InputExecutor>,
+        ports: &[&MidiInputPort],
+        port_name: &str,
+        callback: InputCallback<T>,
+        raw_callback: Option<RawCallback<T>>,
+        data: T,
+    ) -> Result<MidiInputConnection<T>, ConnectError<Self>> {
+        let seq = self.seq.as_ref().unwrap();
+        if ports.is_empty()
+            || ports
+                .iter()
+                .any(|port| seq.get_any_port_info(port.addr).is_err())
+        {
+            return Err(ConnectError::new(ConnectErrorKind::InvalidPort, self));
+        }
 
         let c_port_name = match CString::new(port_name) {
             Ok(c_port_name) => c_port_name,
@@ -303,9 +765,24 @@ impl MidiInput {
             }
         };
 
+        let trigger_fds = match self.init_trigger() {
+            Ok(fds) => fds,
+            Err(()) => {
+                return Err(ConnectError::other(
+                    "could not create communication pipe for ALSA handler",
+                    self,
+                ));
+            }
+        };
+
+        let queue_id = self.init_queue();
+        // Without the queue, no timestamps can be taken although they have been requested
+        let timestamps_degraded = queue_id.is_none() && uses_queue(self.compat, self.timestamping);
+
         let vport = match self.create_port(&c_port_name, queue_id) {
             Ok(vp) => vp,
             Err(_) => {
+                self.release_connect_resources(trigger_fds, queue_id, None);
                 return Err(ConnectError::other(
                     "could not create ALSA input port",
                     self,
@@ -313,44 +790,66 @@ impl MidiInput {
             }
         };
 
-        // Make subscription
-        let sub = PortSubscribe::empty().unwrap();
-        sub.set_sender(src_pinfo.addr());
-        sub.set_dest(Addr {
-            client: self.seq.as_ref().unwrap().client_id().unwrap(),
-            port: vport,
-        });
-        if self.seq.as_ref().unwrap().subscribe_port(&sub).is_err() {
-            return Err(ConnectError::other(
-                "could not create ALSA input subscription",
-                self,
-            ));
+        // Make subscriptions (one for every port)
+        let mut subscriptions = Vec::with_capacity(ports.len());
+        for port in ports {
+            let sub = PortSubscribe::empty().unwrap();
+            sub.set_sender(port.addr);
+            sub.set_dest(Addr {
+                client: self.seq.as_ref().unwrap().client_id().unwrap(),
+                port: vport,
+            });
+            if self.seq.as_ref().unwrap().subscribe_port(&sub).is_err() {
+                for sub in &subscriptions {
+                    let _ = self.unsubscribe(sub);
+                }
+                self.release_connect_resources(trigger_fds, queue_id, Some(vport));
+                return Err(ConnectError::other(
+                    "could not create ALSA input subscription",
+                    self,
+                ));
+            }
+            subscriptions.push(sub);
         }
-        let subscription = sub;
 
         // Start the input queue
         self.start_input_queue(queue_id);
 
+        let client_id = self.seq.as_ref().unwrap().client_id().unwrap();
+
         // Start our MIDI input thread.
+        let raw_tap = Arc::new(Mutex::new(None));
+        let subscription_callback = Arc::new(Mutex::new(None));
+        let clear_requested = Arc::new(AtomicBool::new(false));
+        let filtered_count = Arc::new(AtomicU64::new(0));
         let handler_data = HandlerData {
             ignore_flags: self.ignore_flags,
+            sysex_mode: self.sysex_mode,
+            max_sysex_size: self.max_sysex_size,
+            compat: self.compat,
+            timestamping: self.timestamping,
             seq: self.seq.take().unwrap(),
             trigger_rcv_fd: trigger_fds[0],
-            callback: Box::new(callback),
-            queue_id: queue_id,
+            callback,
+            raw_callback,
+            raw_tap: raw_tap.clone(),
+            subscription_callback: subscription_callback.clone(),
+            clear_requested: clear_requested.clone(),
+            filtered_count: filtered_count.clone(),
+            queue_id,
+            timestamps_degraded,
+            pending: Vec::new(),
         };
 
-        let threadbuilder = Builder::new();
         let name = format!("midir ALSA input handler (port '{}')", port_name);
-        let threadbuilder = threadbuilder.name(name);
-        let thread = match threadbuilder.spawn(move || {
-            let mut d = data;
-            let h = handle_input(handler_data, &mut d);
-            (h, d) // return both the handler data and the user data
-        }) {
-            Ok(handle) => handle,
-            Err(_) => {
-                //unsafe { snd_seq_unsubscribe_port(self.seq.as_mut_ptr(), sub.as_ptr()) };
+        let thread = match HandlerThread::start(handler_data, data, executor, Some(name)) {
+            Ok(thread) => thread,
+            Err(failed) => {
+                self.seq = Some(failed.0.seq);
+                for sub in &subscriptions {
+                    let _ = self.unsubscribe(sub);
+                }
+                self.release_connect_resources(trigger_fds, queue_id, Some(vport));
                 return Err(ConnectError::other(
                     "could not start ALSA input handler thread",
                     self,
@@ -359,10 +858,17 @@ impl MidiInput {
         };
 
         Ok(MidiInputConnection {
-            subscription: Some(subscription),
+            subscriptions,
             thread: Some(thread),
-            vport: vport,
+            vport,
+            client_id,
             trigger_send_fd: trigger_fds[1],
+            trigger_rcv_fd: trigger_fds[0],
+            raw_tap,
+            subscription_callback,
+            clear_requested,
+            filtered_count,
+            timestamps_degraded,
         })
     }
 
@@ -375,6 +881,16 @@ impl MidiInput {
     where
         F: FnMut(u64, &[u8], &mut T) + Send + 'static,
     {
+        let c_port_name = match CString::new(port_name) {
+            Ok(c_port_name) => c_port_name,
+            Err(_) => {
+                return Err(ConnectError::other(
+                    "port_name must not contain null bytes",
+                    self,
+                ))
+            }
+        };
+
         let trigger_fds = match self.init_trigger() {
             Ok(fds) => fds,
             Err(()) => {
@@ -386,20 +902,13 @@ impl MidiInput {
         };
 
         let queue_id = self.init_queue();
-
-        let c_port_name = match CString::new(port_name) {
-            Ok(c_port_name) => c_port_name,
-            Err(_) => {
-                return Err(ConnectError::other(
-                    "port_name must not contain null bytes",
-                    self,
-                ))
-            }
-        };
+        // Without the queue, no timestamps can be taken although they have been requested
+        let timestamps_degraded = queue_id.is_none() && uses_queue(self.compat, self.timestamping);
 
         let vport = match self.create_port(&c_port_name, queue_id) {
             Ok(vp) => vp,
             Err(_) => {
+                self.release_connect_resources(trigger_fds, queue_id, None);
                 return Err(ConnectError::other(
                     "could not create ALSA input port",
                     self,
@@ -410,24 +919,37 @@ impl MidiInput {
         // Start the input queue
         self.start_input_queue(queue_id);
 
+        let client_id = self.seq.as_ref().unwrap().client_id().unwrap();
+
         // Start our MIDI input thread.
+        let raw_tap = Arc::new(Mutex::new(None));
+        let subscription_callback = Arc::new(Mutex::new(None));
+        let clear_requested = Arc::new(AtomicBool::new(false));
+        let filtered_count = Arc::new(AtomicU64::new(0));
         let handler_data = HandlerData {
             ignore_flags: self.ignore_flags,
+            sysex_mode: self.sysex_mode,
+            max_sysex_size: self.max_sysex_size,
+            compat: self.compat,
+            timestamping: self.timestamping,
             seq: self.seq.take().unwrap(),
             trigger_rcv_fd: trigger_fds[0],
-            callback: Box::new(callback),
-            queue_id: queue_id,
+            callback: without_source(callback),
+            raw_callback: None,
+            raw_tap: raw_tap.clone(),
+            subscription_callback: subscription_callback.clone(),
+            clear_requested: clear_requested.clone(),
+            filtered_count: filtered_count.clone(),
+            queue_id,
+            timestamps_degraded,
+            pending: Vec::new(),
         };
 
-        let threadbuilder = Builder::new();
-        let thread = match threadbuilder.spawn(move || {
-            let mut d = data;
-            let h = handle_input(handler_data, &mut d);
-            (h, d) // return both the handler data and the user data
-        }) {
-            Ok(handle) => handle,
-            Err(_) => {
-                //unsafe { snd_seq_unsubscribe_port(self.seq.as_mut_ptr(), sub.as_ptr()) };
+        let thread = match HandlerThread::start(handler_data, data, None, None) {
+            Ok(thread) => thread,
+            Err(failed) => {
+                self.seq = Some(failed.0.seq);
+                self.release_connect_resources(trigger_fds, queue_id, Some(vport));
                 return Err(ConnectError::other(
                     "could not start ALSA input handler thread",
                     self,
@@ -436,29 +958,136 @@ impl MidiInput {
         };
 
         Ok(MidiInputConnection {
-            subscription: None,
+            subscriptions: Vec::new(),
             thread: Some(thread),
-            vport: vport,
+            vport,
+            client_id,
             trigger_send_fd: trigger_fds[1],
+            trigger_rcv_fd: trigger_fds[0],
+            raw_tap,
+            subscription_callback,
+            clear_requested,
+            filtered_count,
+            timestamps_degraded,
         })
     }
 }
 
 impl<T> MidiInputConnection<T> {
-    pub fn close(mut self) -> (MidiInput, T) {
-        let (handler_data, user_data) = self.close_internal();
+    pub fn backend_name(&self) -> &'static str {
+        "ALSA"
+    }
+
+    pub fn set_raw_tap(&mut self, tap: Option<RawTap>) {
+        *self.raw_tap.lock().unwrap() = tap;
+    }
+
+    pub fn on_subscription_change(
+        &mut self,
+        callback: Option<SubscriptionCallback>,
+    ) -> Result<(), ConnectErrorKind> {
+        *self.subscription_callback.lock().unwrap() = callback;
+        Ok(())
+    }
+
+    pub fn timestamps_degraded(&self) -> bool {
+        self.timestamps_degraded
+    }
+
+    pub fn set_close_timeout(&mut self, _timeout: Option<std::time::Duration>) {
+        // Closing only waits for our own handler thread, which does not depend on a driver
+    }
+
+    pub fn filtered_count(&self) -> u64 {
+        self.filtered_count.load(Ordering::Relaxed)
+    }
+
+    pub fn callback_thread_id(&self) -> Option<std::thread::ThreadId> {
+        self.thread.as_ref().and_then(HandlerThread::thread_id)
+    }
+
+    pub fn clear_input(&mut self) {
+        // The sequencer handle is owned by the handler thread, so we ask it to
+        // drop the pending input and wake it up (`true` keeps it running).
+        self.clear_requested.store(true, Ordering::SeqCst);
+        let _res = unsafe {
+            libc::write(
+                self.trigger_send_fd,
+                &true as *const bool as *const _,
+                mem::size_of::<bool>() as libc::size_t,
+            )
+        };
+    }
+
+    pub fn subscribe_from(&mut self, sender: (i32, i32)) -> Result<(), ConnectErrorKind> {
+        // The sequencer handle of this connection is owned by the handler thread,
+        // so the subscription is made from a separate one (like `aconnect` does)
+        let seq = Seq::open(None, None, false)
+            .map_err(|_| ConnectErrorKind::Other("could not open ALSA sequencer"))?;
+        let sender = Addr {
+            client: sender.0,
+            port: sender.1,
+        };
+        if seq.get_any_port_info(sender).is_err() {
+            return Err(ConnectErrorKind::InvalidPort);
+        }
+        let sub = PortSubscribe::empty().unwrap();
+        sub.set_sender(sender);
+        sub.set_dest(Addr {
+            client: self.client_id,
+            port: self.vport,
+        });
+        seq.subscribe_port(&sub)
+            .map_err(|_| ConnectErrorKind::Other("could not create ALSA input subscription"))
+    }
+
+    pub fn unsubscribe_from(&mut self, sender: (i32, i32)) -> Result<(), ConnectErrorKind> {
+        let seq = Seq::open(None, None, false)
+            .map_err(|_| ConnectErrorKind::Other("could not open ALSA sequencer"))?;
+        let sender = Addr {
+            client: sender.0,
+            port: sender.1,
+        };
+        let dest = Addr {
+            client: self.client_id,
+            port: self.vport,
+        };
+        seq.unsubscribe_port(sender, dest)
+            .map_err(|_| ConnectErrorKind::Other("could not remove ALSA input subscription"))
+    }
+
+    pub fn close(self) -> (MidiInput, T) {
+        let (input, data, _) = self.close_draining();
+        (input, data)
+    }
+
+    pub fn close_draining(mut self) -> (MidiInput, T, Vec<(u64, Vec<u8>)>) {
+        // The handler delivers all events that are pending before it stops, so only a sysex
+        // message whose end has not arrived yet can be left over
+        let (mut handler_data, user_data) = match self.close_internal() {
+            Some(data) => data,
+            None => panic!("ALSA input handler panicked"),
+        };
+        let pending = mem::take(&mut handler_data.pending);
 
         (
             MidiInput {
                 ignore_flags: handler_data.ignore_flags,
+                sysex_mode: handler_data.sysex_mode,
+                max_sysex_size: handler_data.max_sysex_size,
+                compat: handler_data.compat,
+                timestamping: handler_data.timestamping,
                 seq: Some(handler_data.seq),
             },
             user_data,
+            pending,
         )
     }
 
-    /// This must only be called if the handler thread has not yet been shut down
-    fn close_internal(&mut self) -> (HandlerData<T>, T) {
+    /// This must only be called if the handler thread has not yet been shut down.
+    /// Returns `None` if the handler loop did not return the handler data and the
+    /// user data (because it panicked).
+    fn close_internal(&mut self) -> Option<(HandlerData<T>, T)> {
         // Request the thread to stop.
         let _res = unsafe {
             libc::write(
@@ -471,19 +1100,20 @@ impl<T> MidiInputConnection<T> {
         let thread = self.thread.take().unwrap();
         // Join the thread to get the handler_data back
         let (handler_data, user_data) = match thread.join() {
-            Ok(data) => data,
-            // TODO: handle this more gracefully?
-            Err(e) => {
-                if let Some(e) = e.downcast_ref::<&'static str>() {
-                    panic!("Error when joining ALSA thread: {}", e);
-                } else {
-                    panic!("Unknown error when joining ALSA thread: {:?}", e);
+            Some(data) => data,
+            None => {
+                // The sequencer handle has been dropped together with the handler data, which
+                // closed our client, so its port, queue and subscriptions are gone already
+                unsafe {
+                    libc::close(self.trigger_rcv_fd);
+                    libc::close(self.trigger_send_fd);
                 }
+                return None;
             }
         };
 
         // TODO: find out why snd_seq_unsubscribe_port takes a long time if there was not yet any input message
-        if let Some(ref subscription) = self.subscription {
+        for subscription in &self.subscriptions {
             let _ = handler_data
                 .seq
                 .unsubscribe_port(subscription.get_sender(), subscription.get_dest());
@@ -496,26 +1126,27 @@ impl<T> MidiInputConnection<T> {
         }
 
         // Stop and free the input queue
-        if !cfg!(feature = "avoid_timestamping") {
+        if let Some(queue_id) = handler_data.queue_id {
             let _ = handler_data
                 .seq
-                .control_queue(handler_data.queue_id, EventType::Stop, 0, None);
+                .control_queue(queue_id, EventType::Stop, 0, None);
             let _ = handler_data.seq.drain_output();
-            let _ = handler_data.seq.free_queue(handler_data.queue_id);
+            let _ = handler_data.seq.free_queue(queue_id);
         }
 
         // Delete the port
         let _ = handler_data.seq.delete_port(self.vport);
 
-        (handler_data, user_data)
+        Some((handler_data, user_data))
     }
 }
 
 impl<T> Drop for MidiInputConnection<T> {
     fn drop(&mut self) {
-        // Use `self.thread` as a flag whether the connection has already been dropped
+        // Use `self.thread` as a flag whether the connection has already been dropped.
+        // The handler data is not needed here, so it doesn't matter if it has been lost.
         if self.thread.is_some() {
-            self.close_internal();
+            let _ = self.close_internal();
         }
     }
 }
@@ -533,6 +1164,10 @@ impl MidiOutputPort {
     pub fn id(&self) -> String {
         format!("{}:{}", self.addr.client, self.addr.port)
     }
+
+    pub fn from_id(id: &str) -> Option<Self> {
+        helpers::parse_addr(id).map(|addr| MidiOutputPort { addr })
+    }
 }
 
 pub struct MidiOutputConnection {
@@ -540,30 +1175,55 @@ pub struct MidiOutputConnection {
     vport: i32,
     coder: helpers::EventEncoder,
     subscription: Option<PortSubscribe>,
+    queue_id: Option<i32>, // an output queue is only allocated for scheduled messages
 }
 
 impl MidiOutput {
     pub fn new(client_name: &str) -> Result<Self, InitError> {
         let seq = match Seq::open(None, Some(Direction::Playback), true) {
             Ok(s) => s,
-            Err(_) => {
-                return Err(InitError);
-            }
+            Err(err) => return Err(InitError::with_source(err)),
         };
 
-        let c_client_name = CString::new(client_name).map_err(|_| InitError)?;
-        seq.set_client_name(&c_client_name).map_err(|_| InitError)?;
+        let c_client_name = CString::new(client_name)
+            .map_err(|_| InitError::new("the client name contains a null byte"))?;
+        seq.set_client_name(&c_client_name)
+            .map_err(InitError::with_source)?;
 
         Ok(MidiOutput { seq: Some(seq) })
     }
 
+    pub fn reject_duplicate_virtual_names(&mut self, _reject: bool) {
+        // port names are always qualified by the client, so there are no duplicates
+    }
+
+    pub fn supports_virtual_ports(&self) -> bool {
+        true
+    }
+
+    pub fn set_client_name(&mut self, name: &str) -> Result<(), ConnectErrorKind> {
+        let c_name =
+            CString::new(name).map_err(|_| ConnectErrorKind::Other("invalid client name"))?;
+        self.seq
+            .as_ref()
+            .unwrap()
+            .set_client_name(&c_name)
+            .map_err(|_| ConnectErrorKind::Other("could not rename ALSA client"))
+    }
+
+    #[cfg(not(feature = "jack"))]
     pub(crate) fn ports_internal(&self) -> Vec<crate::common::MidiOutputPort> {
+        self.ports()
+            .into_iter()
+            .map(|imp| crate::common::MidiOutputPort { imp })
+            .collect()
+    }
+
+    pub(crate) fn ports(&self) -> Vec<MidiOutputPort> {
         helpers::get_ports(
             self.seq.as_ref().unwrap(),
             PortCap::WRITE | PortCap::SUBS_WRITE,
-            |p| crate::common::MidiOutputPort {
-                imp: MidiOutputPort { addr: p.addr() },
-            },
+            |p| MidiOutputPort { addr: p.addr() },
         )
     }
 
@@ -578,6 +1238,51 @@ impl MidiOutput {
         helpers::get_port_name(self.seq.as_ref().unwrap(), port.addr)
     }
 
+    pub fn watch(
+        &self,
+        callback: Box<dyn FnMut(DeviceEvent<MidiOutputPort>) + Send>,
+    ) -> Result<PortWatcher, InitError> {
+        PortWatcher::start(
+            PortCap::WRITE | PortCap::SUBS_WRITE,
+            |addr| MidiOutputPort { addr },
+            callback,
+        )
+    }
+
+    pub fn port_metadata(
+        &self,
+        port: &MidiOutputPort,
+    ) -> Result<crate::PortMetadata, PortInfoError> {
+        // ALSA only knows the names of clients and ports
+        helpers::get_port_name(self.seq.as_ref().unwrap(), port.addr)?;
+        Ok(crate::PortMetadata::default())
+    }
+
+    pub fn hw_info(&self, port: &MidiOutputPort) -> Option<(i32, i32)> {
+        helpers::get_hw_info(self.seq.as_ref().unwrap(), port.addr)
+    }
+
+    pub fn device_name(&self, port: &MidiOutputPort) -> Option<String> {
+        helpers::get_client_name(self.seq.as_ref().unwrap(), port.addr)
+    }
+
+    pub fn is_connectable(&self, port: &MidiOutputPort) -> bool {
+        helpers::is_connectable(self.seq.as_ref().unwrap(), port.addr, QuerySubsType::WRITE)
+    }
+
+    pub fn is_synth_port(&self, port: &MidiOutputPort) -> bool {
+        match self.seq.as_ref().unwrap().get_any_port_info(port.addr) {
+            Ok(pinfo) => pinfo
+                .get_type()
+                .intersects(PortType::SYNTH | PortType::SYNTHESIZER),
+            Err(_) => false,
+        }
+    }
+
+    pub fn is_sibling_port(&self, input: &MidiInputPort, output: &MidiOutputPort) -> bool {
+        input.addr.client == output.addr.client
+    }
+
     pub fn connect(
         mut self,
         port: &MidiOutputPort,
@@ -622,6 +1327,7 @@ impl MidiOutput {
         sub.set_time_update(true);
         sub.set_time_real(true);
         if self.seq.as_ref().unwrap().subscribe_port(&sub).is_err() {
+            let _ = self.seq.as_ref().unwrap().delete_port(vport);
             return Err(ConnectError::other(
                 "could not create ALSA output subscription",
                 self,
@@ -630,9 +1336,10 @@ impl MidiOutput {
 
         Ok(MidiOutputConnection {
             seq: self.seq.take(),
-            vport: vport,
+            vport,
             coder: helpers::EventEncoder::new(INITIAL_CODER_BUFFER_SIZE as u32),
             subscription: Some(sub),
+            queue_id: None,
         })
     }
 
@@ -666,14 +1373,33 @@ impl MidiOutput {
 
         Ok(MidiOutputConnection {
             seq: self.seq.take(),
-            vport: vport,
+            vport,
             coder: helpers::EventEncoder::new(INITIAL_CODER_BUFFER_SIZE as u32),
             subscription: None,
+            queue_id: None,
         })
     }
 }
 
 impl MidiOutputConnection {
+    pub fn backend_name(&self) -> &'static str {
+        "ALSA"
+    }
+
+    pub fn supports_sysex(&self) -> Option<bool> {
+        // The sequencer accepts sysex for every port, but does not know whether the device does
+        None
+    }
+
+    pub fn max_sysex_size(&self) -> Option<usize> {
+        None
+    }
+
+    pub fn set_running_status(&mut self, enable: bool) -> Result<(), ConnectErrorKind> {
+        self.coder.get_wrapped().enable_running_status(enable);
+        Ok(())
+    }
+
     pub fn close(mut self) -> MidiOutput {
         self.close_internal();
 
@@ -682,14 +1408,38 @@ impl MidiOutputConnection {
         }
     }
 
+    pub fn send_all(&mut self, messages: &[&[u8]]) -> Result<(), SendError> {
+        for message in messages {
+            self.send(message)?;
+        }
+        Ok(())
+    }
+
     pub fn send(&mut self, message: &[u8]) -> Result<(), SendError> {
+        self.send_internal(message, None)
+    }
+
+    pub fn send_at(&mut self, message: &[u8], delay: std::time::Duration) -> Result<(), SendError> {
+        self.send_internal(message, Some(delay))
+    }
+
+    fn send_internal(
+        &mut self,
+        message: &[u8],
+        delay: Option<std::time::Duration>,
+    ) -> Result<(), SendError> {
         let nbytes = message.len();
-        assert!(nbytes <= u32::max_value() as usize);
+        assert!(nbytes <= u32::MAX as usize);
 
-        if nbytes > self.coder.get_buffer_size() as usize {
-            if self.coder.resize_buffer(nbytes as u32).is_err() {
-                return Err(SendError::Other("could not resize ALSA encoding buffer"));
-            }
+        let queue_id = match delay {
+            Some(_) => Some(self.output_queue()?),
+            None => None,
+        };
+
+        if nbytes > self.coder.get_buffer_size() as usize
+            && self.coder.resize_buffer(nbytes as u32).is_err()
+        {
+            return Err(SendError::Other("could not resize ALSA encoding buffer"));
         }
 
         let mut ev = match self.coder.get_wrapped().encode(message) {
@@ -699,7 +1449,12 @@ impl MidiOutputConnection {
 
         ev.set_source(self.vport);
         ev.set_subs();
-        ev.set_direct();
+        match (queue_id, delay) {
+            (Some(queue_id), Some(delay)) => ev.schedule_real(queue_id, true, delay),
+            // Direct events bypass the queue and can therefore not be reordered
+            // by scheduling: they are delivered in the order they are sent.
+            _ => ev.set_direct(),
+        }
 
         // Send the event.
         if self
@@ -716,12 +1471,40 @@ impl MidiOutputConnection {
         Ok(())
     }
 
+    /// The queue that scheduled messages are sent through, which is allocated
+    /// and started when the first scheduled message is sent.
+    fn output_queue(&mut self) -> Result<i32, SendError> {
+        if let Some(queue_id) = self.queue_id {
+            return Ok(queue_id);
+        }
+        let seq = self.seq.as_mut().unwrap();
+        let queue_id = seq
+            .alloc_named_queue(c"midir output queue")
+            .map_err(|_| SendError::Other("could not allocate ALSA output queue"))?;
+        if seq
+            .control_queue(queue_id, EventType::Start, 0, None)
+            .is_err()
+        {
+            let _ = seq.free_queue(queue_id);
+            return Err(SendError::Other("could not start ALSA output queue"));
+        }
+        let _ = seq.drain_output();
+        self.queue_id = Some(queue_id);
+        Ok(queue_id)
+    }
+
     fn close_internal(&mut self) {
         let seq = self.seq.as_mut().unwrap();
         if let Some(ref subscription) = self.subscription {
             let _ = seq.unsubscribe_port(subscription.get_sender(), subscription.get_dest());
         }
         let _ = seq.delete_port(self.vport);
+        // Messages that are still scheduled are dropped with the queue
+        if let Some(queue_id) = self.queue_id.take() {
+            let _ = seq.control_queue(queue_id, EventType::Stop, 0, None);
+            let _ = seq.drain_output();
+            let _ = seq.free_queue(queue_id);
+        }
     }
 }
 
@@ -733,22 +1516,165 @@ impl Drop for MidiOutputConnection {
     }
 }
 
+const INVALID_POLLFD: libc::pollfd = libc::pollfd {
+    fd: -1,
+    events: 0,
+    revents: 0,
+};
+
+/// Watches for ports that appear or disappear, using a separate sequencer client that
+/// is subscribed to the announce port of the system client. The kernel sends an event
+/// to that port whenever a port is created, changed or deleted.
+pub struct PortWatcher {
+    trigger_send_fd: i32,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl PortWatcher {
+    fn start<P: Send + 'static>(
+        capability: PortCap,
+        port: fn(Addr) -> P,
+        mut callback: Box<dyn FnMut(DeviceEvent<P>) + Send>,
+    ) -> Result<Self, InitError> {
+        let seq =
+            Seq::open(None, Some(Direction::Capture), true).map_err(InitError::with_source)?;
+        seq.set_client_name(c"midir port watcher")
+            .map_err(InitError::with_source)?;
+
+        let mut pinfo = PortInfo::empty().map_err(InitError::with_source)?;
+        // Without `SUBS_WRITE`, the port is not listed as an output, and others cannot subscribe
+        pinfo.set_capability(PortCap::WRITE | PortCap::NO_EXPORT);
+        pinfo.set_type(PortType::APPLICATION);
+        pinfo.set_name(c"midir port watcher");
+        seq.create_port(&pinfo).map_err(InitError::with_source)?;
+
+        let sub = PortSubscribe::empty().map_err(InitError::with_source)?;
+        sub.set_sender(Addr::system_announce());
+        sub.set_dest(Addr {
+            client: seq.client_id().map_err(InitError::with_source)?,
+            port: pinfo.get_port(),
+        });
+        seq.subscribe_port(&sub).map_err(InitError::with_source)?;
+
+        // Ports that are created from now on are announced, so this doesn't miss any
+        let mut known: HashSet<Addr> = helpers::get_ports(&seq, capability, |p| p.addr())
+            .into_iter()
+            .collect();
+
+        let mut trigger_fds = [-1, -1];
+        if unsafe { libc::pipe(trigger_fds.as_mut_ptr()) } == -1 {
+            return Err(InitError::new(
+                "could not create communication pipe for ALSA port watcher",
+            ));
+        }
+        let trigger_rcv_fd = trigger_fds[0];
+
+        let thread = Builder::new()
+            .name("midir ALSA port watcher".to_owned())
+            .spawn(move || {
+                use alsa::PollDescriptors;
+
+                let poll_desc_info = (&seq, Some(Direction::Capture));
+                let mut poll_fds = vec![INVALID_POLLFD; poll_desc_info.count() + 1];
+                poll_fds[0] = libc::pollfd {
+                    fd: trigger_rcv_fd,
+                    events: libc::POLLIN,
+                    revents: 0,
+                };
+                poll_desc_info.fill(&mut poll_fds[1..]).unwrap();
+
+                let mut seq_input = seq.input();
+                loop {
+                    if let Ok(0) = seq_input.event_input_pending(true) {
+                        // Anything that is written to the pipe stops the thread
+                        if helpers::poll(&mut poll_fds, -1) >= 0
+                            && poll_fds[0].revents & libc::POLLIN != 0
+                        {
+                            break;
+                        }
+                        continue;
+                    }
+                    let ev = match seq_input.event_input() {
+                        Ok(ev) => ev,
+                        Err(_) => continue,
+                    };
+                    let Some(addr) = ev.get_data::<Addr>() else {
+                        continue;
+                    };
+                    match ev.get_type() {
+                        // A change of the capabilities can make a port (un)available
+                        EventType::PortStart | EventType::PortChange => {
+                            let listed = seq
+                                .get_any_port_info(addr)
+                                .is_ok_and(|p| helpers::is_listed(&p, capability));
+                            if listed && known.insert(addr) {
+                                callback(DeviceEvent::Added(port(addr)));
+                            } else if !listed && known.remove(&addr) {
+                                callback(DeviceEvent::Removed(port(addr)));
+                            }
+                        }
+                        EventType::PortExit if known.remove(&addr) => {
+                            callback(DeviceEvent::Removed(port(addr)));
+                        }
+                        _ => {}
+                    }
+                }
+                unsafe {
+                    libc::close(trigger_rcv_fd);
+                }
+            });
+
+        match thread {
+            Ok(thread) => Ok(PortWatcher {
+                trigger_send_fd: trigger_fds[1],
+                thread: Some(thread),
+            }),
+            Err(err) => {
+                // The closure (and with it the client) has been dropped
+                unsafe {
+                    libc::close(trigger_fds[0]);
+                    libc::close(trigger_fds[1]);
+                }
+                Err(InitError::with_source(err))
+            }
+        }
+    }
+}
+
+impl Drop for PortWatcher {
+    fn drop(&mut self) {
+        let _res = unsafe {
+            libc::write(
+                self.trigger_send_fd,
+                &false as *const bool as *const _,
+                mem::size_of::<bool>() as libc::size_t,
+            )
+        };
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        unsafe {
+            libc::close(self.trigger_send_fd);
+        }
+    }
+}
+
 fn handle_input<T>(mut data: HandlerData<T>, user_data: &mut T) -> HandlerData<T> {
     use alsa::seq::Connect;
     use alsa::PollDescriptors;
     use libc::pollfd;
 
-    const INVALID_POLLFD: pollfd = pollfd {
-        fd: -1,
-        events: 0,
-        revents: 0,
-    };
+    let _scope = crate::CallbackScope::enter();
 
     let mut continue_sysex: bool = false;
+    // Whether the rest of the current sysex message is discarded, because it is too long
+    let mut oversized = false;
 
     // ALSA documentation says:
     // The required buffer size for a sequencer event it as most 12 bytes, except for System Exclusive events (which we handle separately)
     let mut buffer = [0; 12];
+    // System real-time messages that are interleaved with the segment of a sysex message
+    let mut interleaved = Vec::new();
 
     let mut coder = helpers::EventDecoder::new(false);
 
@@ -763,6 +1689,7 @@ fn handle_input<T>(mut data: HandlerData<T>, user_data: &mut T) -> HandlerData<T
     poll_desc_info.fill(&mut poll_fds[1..]).unwrap();
 
     let mut message = MidiMessage::new();
+    let started = Instant::now();
 
     {
         // open scope where we can borrow data.seq
@@ -770,6 +1697,14 @@ fn handle_input<T>(mut data: HandlerData<T>, user_data: &mut T) -> HandlerData<T
 
         let mut do_input = true;
         while do_input {
+            if data.clear_requested.swap(false, Ordering::SeqCst) {
+                // Discard everything that is buffered, including a partial sysex message
+                let _ = seq_input.drop_input();
+                message.bytes.clear();
+                continue_sysex = false;
+                oversized = false;
+            }
+
             if let Ok(0) = seq_input.event_input_pending(true) {
                 // No data pending
                 if helpers::poll(&mut poll_fds, -1) >= 0 {
@@ -778,7 +1713,7 @@ fn handle_input<T>(mut data: HandlerData<T>, user_data: &mut T) -> HandlerData<T
                         let _res = unsafe {
                             libc::read(
                                 poll_fds[0].fd,
-                                mem::transmute(&mut do_input),
+                                &mut do_input as *mut bool as *mut libc::c_void,
                                 mem::size_of::<bool>() as libc::size_t,
                             )
                         };
@@ -834,12 +1769,26 @@ fn handle_input<T>(mut data: HandlerData<T>, user_data: &mut T) -> HandlerData<T
                 }
             };
 
-            let do_decode = match ev.get_type() {
+            if let Some(ref mut raw_callback) = data.raw_callback {
+                raw_callback(&RawEvent { event: &ev }, user_data);
+                continue;
+            }
+
+            // A raw tap receives all messages, including those that are ignored
+            let mut raw_tap = data.raw_tap.lock().unwrap();
+
+            let (do_decode, ignored) = match ev.get_type() {
                 EventType::PortSubscribed => {
                     if cfg!(debug) {
                         println!("Notice from handle_input: ALSA port connection made!")
                     };
-                    false
+                    if let Some(ref mut callback) = *data.subscription_callback.lock().unwrap() {
+                        let connect = ev.get_data::<Connect>().unwrap();
+                        callback(SubscriptionEvent::Subscribed {
+                            sender: (connect.sender.client, connect.sender.port),
+                        });
+                    }
+                    (false, false)
                 }
                 EventType::PortUnsubscribed => {
                     if cfg!(debug) {
@@ -857,58 +1806,165 @@ fn handle_input<T>(mut data: HandlerData<T>, user_data: &mut T) -> HandlerData<T
                             connect.dest.port
                         );
                     }
-                    false
+                    if let Some(ref mut callback) = *data.subscription_callback.lock().unwrap() {
+                        let connect = ev.get_data::<Connect>().unwrap();
+                        callback(SubscriptionEvent::Unsubscribed {
+                            sender: (connect.sender.client, connect.sender.port),
+                        });
+                    }
+                    (false, false)
                 }
                 EventType::Qframe => {
                     // MIDI time code
-                    !ignore_flags.contains(Ignore::Time)
+                    (true, ignore_flags.contains(Ignore::Time))
                 }
                 EventType::Tick => {
                     // 0xF9 ... MIDI timing tick
-                    !ignore_flags.contains(Ignore::Time)
+                    (true, ignore_flags.contains(Ignore::Time))
                 }
                 EventType::Clock => {
                     // 0xF8 ... MIDI timing (clock) tick
-                    !ignore_flags.contains(Ignore::Time)
+                    (true, ignore_flags.contains(Ignore::Time))
                 }
                 EventType::Sensing => {
                     // Active sensing
-                    !ignore_flags.contains(Ignore::ActiveSense)
+                    (true, ignore_flags.contains(Ignore::ActiveSense))
                 }
                 EventType::Sysex => {
-                    if !ignore_flags.contains(Ignore::Sysex) {
-                        // Directly copy the data from the external buffer to our message
-                        message.bytes.extend_from_slice(ev.get_ext().unwrap());
-                        continue_sysex = *message.bytes.last().unwrap() != 0xF7;
+                    let mut ignored = ignore_flags.contains(Ignore::Sysex);
+                    let ext = ev.get_ext().unwrap();
+                    // Directly copy the data from the external buffer to our message
+                    // (if we're not ignoring it), but not the real-time messages in it
+                    let sysex = ((!ignored || raw_tap.is_some()) && !oversized)
+                        .then_some(&mut message.bytes);
+                    crate::split_sysex_segment(ext, sysex, |status| interleaved.push(status));
+                    continue_sysex = !crate::is_last_sysex_segment(ext);
+                    if data.sysex_mode == SysexMode::Complete
+                        && data
+                            .max_sysex_size
+                            .is_some_and(|max| message.bytes.len() > max)
+                    {
+                        // Drop the message instead of letting the buffer grow without bound
+                        message.bytes.clear();
+                        oversized = true;
                     }
-                    false // don't ever decode sysex messages (it would unnecessarily copy the message content to another buffer)
+                    if oversized {
+                        ignored = true;
+                        oversized = continue_sysex;
+                    }
+                    (false, ignored) // don't ever decode sysex messages (it would unnecessarily copy the message content to another buffer)
                 }
-                _ => true,
+                _ => (true, false),
             };
 
+            // Count every ignored message once (sysex messages with their last segment)
+            if ignored
+                && (ev.get_type() != EventType::Sysex
+                    || ev.get_ext().is_some_and(crate::is_last_sysex_segment))
+            {
+                data.filtered_count.fetch_add(1, Ordering::Relaxed);
+            }
+
             // NOTE: SysEx messages have already been "decoded" at this point!
-            if do_decode {
-                if let Ok(nbytes) = coder.get_wrapped().decode(&mut buffer, &mut ev) {
-                    if nbytes > 0 {
-                        message.bytes.extend_from_slice(&buffer[0..nbytes]);
-                    }
+            // Other messages are decoded into their own buffer, so that they are passed
+            // on separately when they arrive between the segments of a sysex message.
+            let is_sysex = ev.get_type() == EventType::Sysex;
+            let mut nbytes = 0;
+            if do_decode && (!ignored || raw_tap.is_some()) {
+                if let Ok(n) = coder.get_wrapped().decode(&mut buffer, &mut ev) {
+                    nbytes = n;
                 }
             }
 
-            if message.bytes.len() == 0 || continue_sysex {
+            // In streaming mode, segments of a sysex message are passed on as they arrive
+            let streaming = data.sysex_mode == SysexMode::Streaming;
+            let sysex_ready =
+                is_sysex && !message.bytes.is_empty() && (!continue_sysex || streaming);
+            if nbytes == 0 && !sysex_ready && interleaved.is_empty() {
                 continue;
             }
 
             // Calculate the time stamp:
             // Use the ALSA sequencer event time data.
             // (thanks to Pedro Lopez-Cabanillas!).
-            let alsa_time = ev.get_time().unwrap();
-            let secs = alsa_time.as_secs();
-            let nsecs = alsa_time.subsec_nanos();
+            // This is the absolute (real) time of the input queue, not a delta to the
+            // previous event. Deltas are computed in `common` (see `TimestampMode::Relative`).
+            // Without a queue (see `uses_queue`), events don't carry a real time stamp,
+            // so the time of reception relative to the start of the handler is used,
+            // unless timestamping has been disabled explicitly or the queue could not
+            // be allocated (see `init_queue`).
+            message.timestamp = if data.timestamping && !data.timestamps_degraded {
+                let alsa_time = ev.get_time().unwrap_or_else(|| started.elapsed());
+                Timestamp::from_alsa(alsa_time.as_secs() as u32, alsa_time.subsec_nanos())
+                    .as_nanos()
+            } else {
+                0
+            };
+            let source = ev.get_source();
+
+            // Real-time messages that interrupted the sysex message are passed on first
+            for status in interleaved.drain(..) {
+                let status_ignored = ignore_flags.contains_realtime(status);
+                if status_ignored {
+                    data.filtered_count.fetch_add(1, Ordering::Relaxed);
+                }
+                if let Some(ref mut tap) = *raw_tap {
+                    tap(message.timestamp, &[status]);
+                }
+                if !status_ignored {
+                    (data.callback)(message.timestamp, &[status], source, user_data);
+                }
+            }
+
+            let bytes = if is_sysex {
+                if !sysex_ready {
+                    continue;
+                }
+                &message.bytes[..]
+            } else {
+                &buffer[..nbytes]
+            };
+            if let Some(ref mut tap) = *raw_tap {
+                tap(message.timestamp, bytes);
+            }
+            drop(raw_tap);
+            if !ignored {
+                (data.callback)(message.timestamp, bytes, source, user_data);
+            }
 
-            message.timestamp = (secs as u64 * 1_000_000) + (nsecs as u64 / 1_000);
-            (data.callback)(message.timestamp, &message.bytes, user_data);
+            if is_sysex && continue_sysex {
+                // The segment has been streamed, so start collecting the next one
+                message.bytes.clear();
+            }
         }
     } // close scope where data.seq is borrowed
+
+    if continue_sysex && !message.bytes.is_empty() {
+        // Keep the part of a sysex message that has been received so far (see `close_draining`)
+        data.pending.push((message.timestamp, message.bytes));
+    }
     data // return data back to thread owner
 }
+
+#[cfg(test)]
+mod tests {
+    use super::helpers::format_port_name;
+    use crate::PortInfoError;
+    use alsa::seq::PortInfo;
+
+    #[test]
+    fn test_format_port_name() {
+        let mut pinfo = PortInfo::empty().unwrap();
+        pinfo.set_name(c"Port");
+        assert_eq!(
+            format_port_name(Ok("Client"), &pinfo),
+            Ok("Client:Port 0:0".to_owned())
+        );
+
+        pinfo.set_name(c"Port \xFF");
+        assert_eq!(
+            format_port_name(Ok("Client"), &pinfo),
+            Err(PortInfoError::CannotRetrievePortName)
+        );
+    }
+}
@@ -1,7 +1,9 @@
 use std::sync::{Arc, Mutex};
 
 use crate::errors::*;
-use crate::{Ignore, MidiMessage};
+use crate::{
+    DeviceEvent, EarlyMessagePolicy, Ignore, InputExecutor, MidiMessage, RawTap, SysexMode,
+};
 
 use coremidi::*;
 
@@ -9,13 +11,49 @@ mod external {
     #[link(name = "CoreAudio", kind = "framework")]
     extern "C" {
         pub fn AudioConvertHostTimeToNanos(inHostTime: u64) -> u64;
+        pub fn AudioConvertNanosToHostTime(inNanos: u64) -> u64;
         pub fn AudioGetCurrentHostTime() -> u64;
     }
 }
 
+pub(crate) fn host_time_to_nanos(host_time: u64) -> u64 {
+    unsafe { external::AudioConvertHostTimeToNanos(host_time) }
+}
+
+pub fn restart() -> Result<(), InitError> {
+    // Asks the MIDI server to rescan the drivers and rebuild its device list
+    coremidi::restart().map_err(|status| {
+        InitError::new(format!(
+            "could not restart the CoreMIDI server (OSStatus {})",
+            status
+        ))
+    })
+}
+
+/// Endpoints of devices inherit the driver owner property from their device, while
+/// virtual endpoints (which are created by applications) don't belong to a device.
+fn is_virtual_endpoint(endpoint: &Object) -> bool {
+    let driver_owner: Result<String, _> = Properties::driver_owner().value_from(endpoint);
+    driver_owner.is_err()
+}
+
+/// Endpoints inherit the manufacturer and model properties from their device (if any).
+fn endpoint_metadata(endpoint: &Object) -> crate::PortMetadata {
+    let manufacturer: Result<String, _> = Properties::manufacturer().value_from(endpoint);
+    let model: Result<String, _> = Properties::model().value_from(endpoint);
+    crate::PortMetadata {
+        manufacturer: manufacturer.ok(),
+        product: model.ok(),
+        serial: None,
+    }
+}
+
 pub struct MidiInput {
     client: Client,
     ignore_flags: Ignore,
+    sysex_mode: SysexMode,
+    max_sysex_size: Option<usize>,
+    reject_duplicate_names: bool,
 }
 
 #[derive(Clone)]
@@ -31,6 +69,32 @@ impl MidiInputPort {
             .unwrap_or(0)
             .to_string()
     }
+
+    pub fn from_id(id: &str) -> Option<Self> {
+        // There is no way to get an endpoint by its unique ID, so the present ones are searched
+        let unique_id: u32 = id.parse().ok()?;
+        Sources
+            .into_iter()
+            .find(|source| source.unique_id() == Some(unique_id))
+            .map(|source| MidiInputPort {
+                source: Arc::new(source),
+            })
+    }
+
+    pub fn is_virtual(&self) -> bool {
+        is_virtual_endpoint(&self.source)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SourceId {
+    unique_id: u32,
+}
+
+impl SourceId {
+    pub fn port_id(&self) -> Option<String> {
+        Some(self.unique_id.to_string())
+    }
 }
 
 impl PartialEq for MidiInputPort {
@@ -50,8 +114,14 @@ impl MidiInput {
             Ok(cl) => Ok(MidiInput {
                 client: cl,
                 ignore_flags: Ignore::None,
+                sysex_mode: SysexMode::Complete,
+                max_sysex_size: None,
+                reject_duplicate_names: false,
             }),
-            Err(_) => Err(InitError),
+            Err(status) => Err(InitError::new(format!(
+                "could not create a CoreMIDI client (OSStatus {})",
+                status
+            ))),
         }
     }
 
@@ -70,6 +140,54 @@ impl MidiInput {
         self.ignore_flags = flags;
     }
 
+    pub fn ignored_flags(&self) -> Ignore {
+        self.ignore_flags
+    }
+
+    pub fn set_sysex_mode(&mut self, mode: SysexMode) {
+        self.sysex_mode = mode;
+    }
+
+    pub fn set_timestamping(&mut self, _enable: bool) {
+        // Timestamps are provided by the system without extra cost
+    }
+
+    pub fn timestamping(&self) -> bool {
+        true
+    }
+
+    pub fn set_backend_option(&mut self, _key: &str, _value: &str) -> Result<(), ConnectErrorKind> {
+        // There are no options for this backend (yet)
+        Ok(())
+    }
+
+    pub fn raw_timestamp_fn(&self) -> fn(u64) -> u64 {
+        // Convert back to the host time of the packet (this might differ from the original by a tick)
+        |nanos| unsafe { external::AudioConvertNanosToHostTime(nanos) }
+    }
+
+    pub fn set_max_sysex_size(&mut self, max: Option<usize>) {
+        self.max_sysex_size = max;
+    }
+
+    pub fn set_early_message_policy(&mut self, _policy: EarlyMessagePolicy) {
+        // The source is connected synchronously in `connect`, so no message can arrive early
+    }
+
+    pub fn reject_duplicate_virtual_names(&mut self, reject: bool) {
+        self.reject_duplicate_names = reject;
+    }
+
+    pub fn supports_virtual_ports(&self) -> bool {
+        true
+    }
+
+    pub fn set_client_name(&mut self, name: &str) -> Result<(), ConnectErrorKind> {
+        Properties::name()
+            .set_value(&self.client, name)
+            .map_err(|_| ConnectErrorKind::Other("could not rename CoreMIDI client"))
+    }
+
     pub fn port_count(&self) -> usize {
         Sources::count()
     }
@@ -81,11 +199,66 @@ impl MidiInput {
         }
     }
 
-    fn handle_input<T>(packets: &PacketList, handler_data: &mut HandlerData<T>) {
+    pub fn watch(
+        &self,
+        callback: Box<dyn FnMut(DeviceEvent<MidiInputPort>) + Send>,
+    ) -> Result<PortWatcher, InitError> {
+        PortWatcher::start(
+            || {
+                Sources
+                    .into_iter()
+                    .filter_map(|s| {
+                        let id = s.unique_id()?;
+                        Some((
+                            id,
+                            MidiInputPort {
+                                source: Arc::new(s),
+                            },
+                        ))
+                    })
+                    .collect()
+            },
+            callback,
+        )
+    }
+
+    pub fn port_metadata(
+        &self,
+        port: &MidiInputPort,
+    ) -> Result<crate::PortMetadata, PortInfoError> {
+        Ok(endpoint_metadata(&port.source))
+    }
+
+    pub fn device_name(&self, port: &MidiInputPort) -> Option<String> {
+        // The display name is shared by the endpoints of an entity (see `is_sibling_port`)
+        port.source.display_name()
+    }
+
+    pub fn is_connectable(&self, port: &MidiInputPort) -> bool {
+        // Endpoints of devices that are currently disconnected are marked as offline
+        let offline: Result<bool, _> = Properties::offline().value_from(&port.source);
+        !offline.unwrap_or(false)
+    }
+
+    fn handle_input<T>(packets: &PacketList, source: usize, handler_data: &mut HandlerData<T>) {
+        let _scope = crate::CallbackScope::enter();
         let continue_sysex = &mut handler_data.continue_sysex;
+        let oversized = &mut handler_data.oversized;
+        let max_sysex_size = handler_data.max_sysex_size;
+        let filtered_count = &mut handler_data.filtered_count;
         let ignore = handler_data.ignore_flags;
+        let streaming = handler_data.sysex_mode == SysexMode::Streaming;
         let message = &mut handler_data.message;
+        let raw_tap = &mut handler_data.raw_tap;
         let data = &mut handler_data.user_data.as_mut().unwrap();
+        if let Some(ref mut raw_callback) = handler_data.raw_callback {
+            for p in packets.iter() {
+                raw_callback(&RawEvent { packet: p }, data);
+            }
+            return;
+        }
+        // System real-time messages that are interleaved with a sysex message
+        let mut interleaved = Vec::new();
         for p in packets.iter() {
             let pdata = p.data();
             if pdata.len() == 0 {
@@ -99,33 +272,27 @@ impl MidiInput {
             }
 
             if !*continue_sysex {
-                message.timestamp =
-                    unsafe { external::AudioConvertHostTimeToNanos(timestamp) } as u64 / 1000;
+                message.timestamp = host_time_to_nanos(timestamp);
             }
 
-            let mut cur_byte = 0;
+            // A sysex message always extends to the end of the packet, so its
+            // start is remembered and it is handled after the other messages
+            let mut sysex_start = None;
             if *continue_sysex {
                 // We have a continuing, segmented sysex message.
-                if !ignore.contains(Ignore::Sysex) {
-                    // If we're not ignoring sysex messages, copy the entire packet.
-                    message.bytes.extend_from_slice(pdata);
-                }
-                *continue_sysex = pdata[pdata.len() - 1] != 0xF7;
-
-                if !ignore.contains(Ignore::Sysex) && !*continue_sysex {
-                    // If we reached the end of the sysex, invoke the user callback
-                    (handler_data.callback)(message.timestamp, &message.bytes, data);
-                    message.bytes.clear();
-                }
+                sysex_start = Some(0);
             } else {
+                let mut cur_byte = 0;
                 while cur_byte < pdata.len() {
                     // We are expecting that the next byte in the packet is a status byte.
                     let status = pdata[cur_byte];
                     if status & 0x80 == 0 {
                         break;
                     }
-                    // Determine the number of bytes in the MIDI message.
+                    // Determine the number of bytes in the MIDI message
+                    // and whether it is filtered out by the ignore flags.
                     let size;
+                    let mut ignored = false;
                     if status < 0xC0 {
                         size = 3;
                     } else if status < 0xE0 {
@@ -134,55 +301,106 @@ impl MidiInput {
                         size = 3;
                     } else if status == 0xF0 {
                         // A MIDI sysex
-                        if ignore.contains(Ignore::Sysex) {
-                            size = 0;
-                            cur_byte = pdata.len();
-                        } else {
-                            size = pdata.len() - cur_byte;
-                        }
-                        *continue_sysex = pdata[pdata.len() - 1] != 0xF7;
+                        sysex_start = Some(cur_byte);
+                        break;
                     } else if status == 0xF1 {
                         // A MIDI time code message
-                        if ignore.contains(Ignore::Time) {
-                            size = 0;
-                            cur_byte += 2;
-                        } else {
-                            size = 2;
-                        }
+                        ignored = ignore.contains(Ignore::Time);
+                        size = 2;
                     } else if status == 0xF2 {
                         size = 3;
                     } else if status == 0xF3 {
                         size = 2;
-                    } else if status == 0xF8 && ignore.contains(Ignore::Time) {
-                        // A MIDI timing tick message and we're ignoring it.
-                        size = 0;
-                        cur_byte += 1;
-                    } else if status == 0xFE && ignore.contains(Ignore::ActiveSense) {
-                        // A MIDI active sensing message and we're ignoring it.
-                        size = 0;
-                        cur_byte += 1;
                     } else {
+                        // Any other system message (e.g. timing clock or active sensing)
+                        ignored = ignore.contains_realtime(status);
                         size = 1;
                     }
+                    if ignored {
+                        *filtered_count += 1;
+                    }
 
-                    // Copy the MIDI data to our vector.
-                    if size > 0 {
+                    // Pass the MIDI data on directly.
+                    if !ignored || raw_tap.is_some() {
                         let message_bytes = &pdata[cur_byte..(cur_byte + size)];
-                        if !*continue_sysex {
-                            // This is either a non-sysex message or a non-segmented sysex message
-                            (handler_data.callback)(message.timestamp, message_bytes, data);
-                            message.bytes.clear();
-                        } else {
-                            // This is the beginning of a segmented sysex message
-                            message.bytes.extend_from_slice(message_bytes);
+                        if let Some(ref mut tap) = *raw_tap {
+                            tap(message.timestamp, message_bytes);
+                        }
+                        if !ignored {
+                            (handler_data.callback)(message.timestamp, message_bytes, source, data);
                         }
-                        cur_byte += size;
                     }
+                    cur_byte += size;
+                }
+            }
+
+            let segment = match sysex_start {
+                Some(start) => &pdata[start..],
+                None => continue,
+            };
+            let mut ignored = ignore.contains(Ignore::Sysex);
+            *continue_sysex = !crate::is_last_sysex_segment(segment);
+
+            // If we're not ignoring sysex messages (or need to pass them to the
+            // raw tap), copy the segment, except for interleaved real-time messages.
+            let sysex =
+                ((!ignored || raw_tap.is_some()) && !*oversized).then_some(&mut message.bytes);
+            crate::split_sysex_segment(segment, sysex, |status| interleaved.push(status));
+            if !streaming && max_sysex_size.is_some_and(|max| message.bytes.len() > max) {
+                // Drop the message instead of letting the buffer grow without bound
+                message.bytes.clear();
+                *oversized = true;
+            }
+            if *oversized {
+                ignored = true;
+                *oversized = *continue_sysex;
+            }
+            if ignored && !*continue_sysex {
+                // Segmented sysex messages are counted with their last segment
+                *filtered_count += 1;
+            }
+            for status in interleaved.drain(..) {
+                let status_ignored = ignore.contains_realtime(status);
+                if status_ignored {
+                    *filtered_count += 1;
+                }
+                if let Some(ref mut tap) = *raw_tap {
+                    tap(message.timestamp, &[status]);
+                }
+                if !status_ignored {
+                    (handler_data.callback)(message.timestamp, &[status], source, data);
                 }
             }
+
+            // Pass the message on when it is complete, or every segment as it
+            // arrives if it is streamed
+            if (!*continue_sysex || streaming) && !message.bytes.is_empty() {
+                if let Some(ref mut tap) = *raw_tap {
+                    tap(message.timestamp, &message.bytes);
+                }
+                if !ignored {
+                    (handler_data.callback)(message.timestamp, &message.bytes, source, data);
+                }
+                message.bytes.clear();
+            }
         }
     }
 
+    pub fn connect_on<F, T: Send + 'static>(
+        self,
+        _executor: &dyn InputExecutor,
+        port: &MidiInputPort,
+        port_name: &str,
+        callback: F,
+        data: T,
+    ) -> Result<MidiInputConnection<T>, ConnectError<MidiInput>>
+    where
+        F: FnMut(u64, &[u8], &mut T) + Send + 'static,
+    {
+        // Input is delivered by callbacks from CoreMIDI, so there is no handler loop to run
+        self.connect(port, port_name, callback, data)
+    }
+
     pub fn connect<F, T: Send + 'static>(
         self,
         port: &MidiInputPort,
@@ -193,29 +411,110 @@ impl MidiInput {
     where
         F: FnMut(u64, &[u8], &mut T) + Send + 'static,
     {
+        self.connect_internal(&[port], port_name, without_source(callback), None, data)
+    }
+
+    pub fn connect_with_source<F, T: Send + 'static>(
+        self,
+        port: &MidiInputPort,
+        port_name: &str,
+        mut callback: F,
+        data: T,
+    ) -> Result<MidiInputConnection<T>, ConnectError<MidiInput>>
+    where
+        F: FnMut(u64, &[u8], SourceId, &mut T) + Send + 'static,
+    {
+        // There is only one source, so its ID is known in advance
+        let source = SourceId {
+            unique_id: port.source.unique_id().unwrap_or(0),
+        };
+        let callback = Box::new(move |timestamp, message: &[u8], _, data: &mut T| {
+            callback(timestamp, message, source, data)
+        });
+        self.connect_internal(&[port], port_name, callback, None, data)
+    }
+
+    pub fn connect_multi<F, T: Send + 'static>(
+        self,
+        ports: &[&MidiInputPort],
+        port_name: &str,
+        callback: F,
+        data: T,
+    ) -> Result<MidiInputConnection<T>, ConnectError<MidiInput>>
+    where
+        F: FnMut(u64, &[u8], usize, &mut T) + Send + 'static,
+    {
+        self.connect_internal(ports, port_name, Box::new(callback), None, data)
+    }
+
+    #[allow(dead_code)] // only used with the `unsafe-raw` feature
+    pub fn connect_raw<F, T: Send + 'static>(
+        self,
+        port: &MidiInputPort,
+        port_name: &str,
+        callback: F,
+        data: T,
+    ) -> Result<MidiInputConnection<T>, ConnectError<MidiInput>>
+    where
+        F: FnMut(&RawEvent<'_>, &mut T) + Send + 'static,
+    {
+        let raw_callback = Some(Box::new(callback) as RawCallback<T>);
+        self.connect_internal(
+            &[port],
+            port_name,
+            Box::new(|_, _, _, _| {}),
+            raw_callback,
+            data,
+        )
+    }
+
+    fn connect_internal<T: Send + 'static>(
+        self,
+        ports: &[&MidiInputPort],
+        port_name: &str,
+        callback: InputCallback<T>,
+        raw_callback: Option<RawCallback<T>>,
+        data: T,
+    ) -> Result<MidiInputConnection<T>, ConnectError<MidiInput>> {
+        if ports.is_empty() {
+            return Err(ConnectError::new(ConnectErrorKind::InvalidPort, self));
+        }
         let handler_data = Arc::new(Mutex::new(HandlerData {
             message: MidiMessage::new(),
             ignore_flags: self.ignore_flags,
+            sysex_mode: self.sysex_mode,
+            max_sysex_size: self.max_sysex_size,
             continue_sysex: false,
-            callback: Box::new(callback),
+            oversized: false,
+            filtered_count: 0,
+            callback,
+            raw_callback,
+            raw_tap: None,
             user_data: Some(data),
         }));
-        let handler_data2 = handler_data.clone();
-        let iport = match self.client.input_port(port_name, move |packets| {
-            MidiInput::handle_input(packets, &mut *handler_data2.lock().unwrap())
-        }) {
-            Ok(p) => p,
-            Err(_) => return Err(ConnectError::other("error creating MIDI input port", self)),
-        };
-        if let Err(_) = iport.connect_source(&port.source) {
-            return Err(ConnectError::other(
-                "error connecting MIDI input port",
-                self,
-            ));
+        // The read callback of a port does not tell which source a packet comes from,
+        // so every source is connected to its own port (all sharing the handler data)
+        let mut iports = Vec::with_capacity(ports.len());
+        for (index, port) in ports.iter().enumerate() {
+            let handler_data2 = handler_data.clone();
+            let iport = match self.client.input_port(port_name, move |packets| {
+                MidiInput::handle_input(packets, index, &mut *handler_data2.lock().unwrap())
+            }) {
+                Ok(p) => p,
+                Err(_) => return Err(ConnectError::other("error creating MIDI input port", self)),
+            };
+            if let Err(_) = iport.connect_source(&port.source) {
+                return Err(ConnectError::other(
+                    "error connecting MIDI input port",
+                    self,
+                ));
+            }
+            iports.push(iport);
         }
         Ok(MidiInputConnection {
             client: self.client,
-            details: InputConnectionDetails::Explicit(iport),
+            reject_duplicate_names: self.reject_duplicate_names,
+            details: InputConnectionDetails::Explicit(iports),
             handler_data: handler_data,
         })
     }
@@ -229,22 +528,40 @@ impl MidiInput {
     where
         F: FnMut(u64, &[u8], &mut T) + Send + 'static,
     {
+        if self.reject_duplicate_names
+            && Destinations
+                .into_iter()
+                .any(|d| d.name().as_deref() == Some(port_name))
+        {
+            return Err(ConnectError::other(
+                "virtual port name already exists",
+                self,
+            ));
+        }
+
         let handler_data = Arc::new(Mutex::new(HandlerData {
             message: MidiMessage::new(),
             ignore_flags: self.ignore_flags,
+            sysex_mode: self.sysex_mode,
+            max_sysex_size: self.max_sysex_size,
             continue_sysex: false,
-            callback: Box::new(callback),
+            oversized: false,
+            filtered_count: 0,
+            callback: without_source(callback),
+            raw_callback: None,
+            raw_tap: None,
             user_data: Some(data),
         }));
         let handler_data2 = handler_data.clone();
         let vrt = match self.client.virtual_destination(port_name, move |packets| {
-            MidiInput::handle_input(packets, &mut *handler_data2.lock().unwrap())
+            MidiInput::handle_input(packets, 0, &mut *handler_data2.lock().unwrap())
         }) {
             Ok(p) => p,
             Err(_) => return Err(ConnectError::other("error creating MIDI input port", self)),
         };
         Ok(MidiInputConnection {
             client: self.client,
+            reject_duplicate_names: self.reject_duplicate_names,
             details: InputConnectionDetails::Virtual(vrt),
             handler_data: handler_data,
         })
@@ -252,13 +569,63 @@ impl MidiInput {
 }
 
 enum InputConnectionDetails {
-    Explicit(InputPort),
+    Explicit(Vec<InputPort>), // one for each source
     Virtual(VirtualDestination),
 }
 
+/// Watches for ports that appear or disappear, using the notifications (`MIDINotifyProc`)
+/// of a separate client. CoreMIDI delivers them on the run loop of the thread that
+/// started watching, which is the main thread in most applications.
+pub struct PortWatcher {
+    _client: Client,
+}
+
+impl PortWatcher {
+    /// `endpoints` lists the present endpoints with their unique IDs. The removed ones are
+    /// no longer valid when the notification arrives, so the ones that have been listed
+    /// before are reported.
+    fn start<P: Clone + Send + 'static>(
+        endpoints: fn() -> Vec<(u32, P)>,
+        mut callback: Box<dyn FnMut(DeviceEvent<P>) + Send>,
+    ) -> Result<Self, InitError> {
+        let mut known = endpoints();
+        let notify = move |notification: &Notification| {
+            // Endpoints of devices also appear when their device or entity is added, so the
+            // notifications are only taken as a hint to look for changes
+            if !matches!(
+                notification,
+                Notification::SetupChanged
+                    | Notification::ObjectAdded(_)
+                    | Notification::ObjectRemoved(_)
+            ) {
+                return;
+            }
+            let present = endpoints();
+            for (id, port) in &known {
+                if !present.iter().any(|(present_id, _)| present_id == id) {
+                    callback(DeviceEvent::Removed(port.clone()));
+                }
+            }
+            for (id, port) in &present {
+                if !known.iter().any(|(known_id, _)| known_id == id) {
+                    callback(DeviceEvent::Added(port.clone()));
+                }
+            }
+            known = present;
+        };
+        match Client::new_with_notifications("midir port watcher", notify) {
+            Ok(client) => Ok(PortWatcher { _client: client }),
+            Err(status) => Err(InitError::new(format!(
+                "could not create a CoreMIDI client (OSStatus {})",
+                status
+            ))),
+        }
+    }
+}
+
 pub struct MidiInputConnection<T> {
     client: Client,
-    #[allow(dead_code)]
+    reject_duplicate_names: bool,
     details: InputConnectionDetails,
     // TODO: get rid of Arc & Mutex?
     //       synchronization is required because the borrow checker does not
@@ -268,14 +635,71 @@ pub struct MidiInputConnection<T> {
 }
 
 impl<T> MidiInputConnection<T> {
+    pub fn backend_name(&self) -> &'static str {
+        "CoreMIDI"
+    }
+
+    pub fn set_raw_tap(&mut self, tap: Option<RawTap>) {
+        self.handler_data.lock().unwrap().raw_tap = tap;
+    }
+
+    pub fn set_port_name(&mut self, name: &str) -> Result<(), ConnectErrorKind> {
+        let rename = |object: &Object| {
+            Properties::name()
+                .set_value(object, name)
+                .map_err(|_| ConnectErrorKind::Other("could not rename CoreMIDI port"))
+        };
+        match self.details {
+            InputConnectionDetails::Explicit(ref ports) => {
+                ports.iter().try_for_each(|port| rename(port))
+            }
+            InputConnectionDetails::Virtual(ref endpoint) => rename(endpoint),
+        }
+    }
+
+    pub fn set_close_timeout(&mut self, _timeout: Option<std::time::Duration>) {
+        // Disposing of CoreMIDI ports does not block on the driver
+    }
+
+    pub fn clear_input(&mut self) {
+        // CoreMIDI does not queue input for us, so only a partially received
+        // sysex message can be pending.
+        let mut handler_data = self.handler_data.lock().unwrap();
+        handler_data.message.bytes.clear();
+        handler_data.continue_sysex = false;
+    }
+
+    pub fn filtered_count(&self) -> u64 {
+        self.handler_data.lock().unwrap().filtered_count
+    }
+
+    pub fn callback_thread_id(&self) -> Option<std::thread::ThreadId> {
+        None // The callback is invoked on a thread of CoreMIDI
+    }
+
     pub fn close(self) -> (MidiInput, T) {
+        let (input, data, _) = self.close_draining();
+        (input, data)
+    }
+
+    pub fn close_draining(self) -> (MidiInput, T, Vec<(u64, Vec<u8>)>) {
         let mut handler_data_locked = self.handler_data.lock().unwrap();
+        // Only a sysex message whose end has not arrived yet can be left over
+        let mut pending = Vec::new();
+        if handler_data_locked.continue_sysex && !handler_data_locked.message.bytes.is_empty() {
+            let message = &mut handler_data_locked.message;
+            pending.push((message.timestamp, std::mem::take(&mut message.bytes)));
+        }
         (
             MidiInput {
                 client: self.client,
                 ignore_flags: handler_data_locked.ignore_flags,
+                sysex_mode: handler_data_locked.sysex_mode,
+                max_sysex_size: handler_data_locked.max_sysex_size,
+                reject_duplicate_names: self.reject_duplicate_names,
             },
             handler_data_locked.user_data.take().unwrap(),
+            pending,
         )
     }
 }
@@ -288,13 +712,43 @@ impl<T> MidiInputConnection<T> {
 struct HandlerData<T> {
     message: MidiMessage,
     ignore_flags: Ignore,
+    sysex_mode: SysexMode,
+    max_sysex_size: Option<usize>,
     continue_sysex: bool,
-    callback: Box<dyn FnMut(u64, &[u8], &mut T) + Send>,
+    // Whether the rest of the current sysex message is discarded, because it is too long
+    oversized: bool,
+    filtered_count: u64,
+    callback: InputCallback<T>,
+    // Receives the packets instead of `callback` if the connection has been made with `connect_raw`
+    raw_callback: Option<RawCallback<T>>,
+    raw_tap: Option<RawTap>,
     user_data: Option<T>,
 }
 
+type RawCallback<T> = Box<dyn FnMut(&RawEvent<'_>, &mut T) + Send>;
+
+// The index of the source that a message has been received from is passed on
+// (see `connect_multi`), which is always 0 for virtual ports
+type InputCallback<T> = Box<dyn FnMut(u64, &[u8], usize, &mut T) + Send>;
+
+fn without_source<F, T>(mut callback: F) -> InputCallback<T>
+where
+    F: FnMut(u64, &[u8], &mut T) + Send + 'static,
+{
+    Box::new(move |timestamp, message, _, data| callback(timestamp, message, data))
+}
+
+/// A packet as it has been received from CoreMIDI (see `MidiInput::connect_raw`).
+#[non_exhaustive]
+#[allow(dead_code)] // only read by users of the `unsafe-raw` feature
+pub struct RawEvent<'a> {
+    /// The packet, with its host timestamp and data as sent by the source.
+    pub packet: &'a Packet,
+}
+
 pub struct MidiOutput {
     client: Client,
+    reject_duplicate_names: bool,
 }
 
 #[derive(Clone)]
@@ -310,6 +764,21 @@ impl MidiOutputPort {
             .unwrap_or(0)
             .to_string()
     }
+
+    pub fn from_id(id: &str) -> Option<Self> {
+        // There is no way to get an endpoint by its unique ID, so the present ones are searched
+        let unique_id: u32 = id.parse().ok()?;
+        Destinations
+            .into_iter()
+            .find(|dest| dest.unique_id() == Some(unique_id))
+            .map(|dest| MidiOutputPort {
+                dest: Arc::new(dest),
+            })
+    }
+
+    pub fn is_virtual(&self) -> bool {
+        is_virtual_endpoint(&self.dest)
+    }
 }
 
 impl PartialEq for MidiOutputPort {
@@ -326,11 +795,31 @@ impl PartialEq for MidiOutputPort {
 impl MidiOutput {
     pub fn new(client_name: &str) -> Result<Self, InitError> {
         match Client::new(client_name) {
-            Ok(cl) => Ok(MidiOutput { client: cl }),
-            Err(_) => Err(InitError),
+            Ok(cl) => Ok(MidiOutput {
+                client: cl,
+                reject_duplicate_names: false,
+            }),
+            Err(status) => Err(InitError::new(format!(
+                "could not create a CoreMIDI client (OSStatus {})",
+                status
+            ))),
         }
     }
 
+    pub fn reject_duplicate_virtual_names(&mut self, reject: bool) {
+        self.reject_duplicate_names = reject;
+    }
+
+    pub fn supports_virtual_ports(&self) -> bool {
+        true
+    }
+
+    pub fn set_client_name(&mut self, name: &str) -> Result<(), ConnectErrorKind> {
+        Properties::name()
+            .set_value(&self.client, name)
+            .map_err(|_| ConnectErrorKind::Other("could not rename CoreMIDI client"))
+    }
+
     pub(crate) fn ports_internal(&self) -> Vec<crate::common::MidiOutputPort> {
         Destinations
             .into_iter()
@@ -351,6 +840,55 @@ impl MidiOutput {
         }
     }
 
+    pub fn watch(
+        &self,
+        callback: Box<dyn FnMut(DeviceEvent<MidiOutputPort>) + Send>,
+    ) -> Result<PortWatcher, InitError> {
+        PortWatcher::start(
+            || {
+                Destinations
+                    .into_iter()
+                    .filter_map(|d| {
+                        let id = d.unique_id()?;
+                        Some((id, MidiOutputPort { dest: Arc::new(d) }))
+                    })
+                    .collect()
+            },
+            callback,
+        )
+    }
+
+    pub fn port_metadata(
+        &self,
+        port: &MidiOutputPort,
+    ) -> Result<crate::PortMetadata, PortInfoError> {
+        Ok(endpoint_metadata(&port.dest))
+    }
+
+    pub fn device_name(&self, port: &MidiOutputPort) -> Option<String> {
+        // The display name is shared by the endpoints of an entity (see `is_sibling_port`)
+        port.dest.display_name()
+    }
+
+    pub fn is_connectable(&self, port: &MidiOutputPort) -> bool {
+        // Endpoints of devices that are currently disconnected are marked as offline
+        let offline: Result<bool, _> = Properties::offline().value_from(&port.dest);
+        !offline.unwrap_or(false)
+    }
+
+    pub fn is_synth_port(&self, _port: &MidiOutputPort) -> bool {
+        false // CoreMIDI does not distinguish synthesizers from other ports
+    }
+
+    pub fn is_sibling_port(&self, input: &MidiInputPort, output: &MidiOutputPort) -> bool {
+        // The `coremidi` crate does not expose the entity of an endpoint, so this
+        // compares display names, which are shared by the endpoints of an entity
+        match (input.source.display_name(), output.dest.display_name()) {
+            (Some(input_name), Some(output_name)) => input_name == output_name,
+            _ => false,
+        }
+    }
+
     pub fn connect(
         self,
         port: &MidiOutputPort,
@@ -362,7 +900,9 @@ impl MidiOutput {
         };
         Ok(MidiOutputConnection {
             client: self.client,
+            reject_duplicate_names: self.reject_duplicate_names,
             details: OutputConnectionDetails::Explicit(oport, port.dest.clone()),
+            packets: PacketBuffer::with_capacity(PACKET_BUFFER_CAPACITY),
         })
     }
 
@@ -370,6 +910,17 @@ impl MidiOutput {
         self,
         port_name: &str,
     ) -> Result<MidiOutputConnection, ConnectError<MidiOutput>> {
+        if self.reject_duplicate_names
+            && Sources
+                .into_iter()
+                .any(|s| s.name().as_deref() == Some(port_name))
+        {
+            return Err(ConnectError::other(
+                "virtual port name already exists",
+                self,
+            ));
+        }
+
         let vrt = match self.client.virtual_source(port_name) {
             Ok(p) => p,
             Err(_) => {
@@ -381,7 +932,9 @@ impl MidiOutput {
         };
         Ok(MidiOutputConnection {
             client: self.client,
+            reject_duplicate_names: self.reject_duplicate_names,
             details: OutputConnectionDetails::Virtual(vrt),
+            packets: PacketBuffer::with_capacity(PACKET_BUFFER_CAPACITY),
         })
     }
 }
@@ -391,16 +944,55 @@ enum OutputConnectionDetails {
     Virtual(VirtualSource),
 }
 
+/// The initial capacity (in bytes, including headers) of the packet buffer of an
+/// output connection, which is enough for any message except long SysEx messages.
+const PACKET_BUFFER_CAPACITY: usize = 256;
+
 pub struct MidiOutputConnection {
     client: Client,
+    reject_duplicate_names: bool,
     details: OutputConnectionDetails,
+    // Reused by `send`, so that sending does not allocate (unless the buffer has
+    // to grow for a message that is longer than any message sent before)
+    packets: PacketBuffer,
 }
 
 impl MidiOutputConnection {
+    pub fn backend_name(&self) -> &'static str {
+        "CoreMIDI"
+    }
+
+    pub fn supports_sysex(&self) -> Option<bool> {
+        // There is no endpoint property for this (only the transmission speed of sysex)
+        None
+    }
+
+    pub fn max_sysex_size(&self) -> Option<usize> {
+        None
+    }
+
+    pub fn set_port_name(&mut self, name: &str) -> Result<(), ConnectErrorKind> {
+        let object: &Object = match self.details {
+            OutputConnectionDetails::Explicit(ref port, _) => port,
+            OutputConnectionDetails::Virtual(ref endpoint) => endpoint,
+        };
+        Properties::name()
+            .set_value(object, name)
+            .map_err(|_| ConnectErrorKind::Other("could not rename CoreMIDI port"))
+    }
+
     pub fn close(self) -> MidiOutput {
         MidiOutput {
             client: self.client,
+            reject_duplicate_names: self.reject_duplicate_names,
+        }
+    }
+
+    pub fn send_all(&mut self, messages: &[&[u8]]) -> Result<(), SendError> {
+        for message in messages {
+            self.send(message)?;
         }
+        Ok(())
     }
 
     pub fn send(&mut self, message: &[u8]) -> Result<(), SendError> {
@@ -409,13 +1001,28 @@ impl MidiOutputConnection {
         } else {
             0
         };
-        let packets = PacketBuffer::new(send_time, message);
+        self.send_with_host_time(message, send_time)
+    }
+
+    pub fn send_at(&mut self, message: &[u8], delay: std::time::Duration) -> Result<(), SendError> {
+        // The packet timestamp is the host time at which the message is to be sent
+        let send_time = unsafe {
+            external::AudioGetCurrentHostTime()
+                + external::AudioConvertNanosToHostTime(delay.as_nanos() as u64)
+        };
+        self.send_with_host_time(message, send_time)
+    }
+
+    fn send_with_host_time(&mut self, message: &[u8], send_time: u64) -> Result<(), SendError> {
+        self.packets.clear();
+        self.packets.push_data(send_time, message);
+        let packets = &self.packets;
         match self.details {
             OutputConnectionDetails::Explicit(ref port, ref dest) => port
-                .send(&dest, &packets)
+                .send(dest, packets)
                 .map_err(|_| SendError::Other("error sending MIDI message to port")),
             OutputConnectionDetails::Virtual(ref vrt) => vrt
-                .received(&packets)
+                .received(packets)
                 .map_err(|_| SendError::Other("error sending MIDI to virtual destinations")),
         }
     }
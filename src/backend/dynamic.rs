@@ -0,0 +1,805 @@
+//! Runtime selection between the ALSA and JACK backends on Linux.
+//!
+//! If the `jack` feature is enabled on Linux, both backends are compiled and
+//! every object wraps the implementation of the backend it was created with.
+//! JACK is used by default (as it used to be the only backend with that
+//! feature), ALSA can be selected with `new_with_backend`.
+
+use std::num::NonZeroU32;
+
+use super::{alsa, jack};
+use crate::errors::*;
+use crate::os::unix::alsa::{Compat, SubscriptionCallback};
+use crate::os::unix::jack::{JackConnectOptions, JackStartOptions, LatencyMode};
+use crate::{Backend, DeviceEvent, EarlyMessagePolicy, Ignore, InputExecutor, RawTap, SysexMode};
+
+/// Calls the same method on the implementation of either backend.
+macro_rules! dispatch {
+    ($value:expr, $imp:ident => $e:expr) => {
+        match $value {
+            Self::Alsa($imp) => $e,
+            Self::Jack($imp) => $e,
+        }
+    };
+}
+
+/// Rewraps the backend-specific object that is returned with a connection error.
+fn map_connect_error<I, O>(err: ConnectError<I>, f: impl FnOnce(I) -> O) -> ConnectError<O> {
+    let kind = err.kind();
+    ConnectError::new(kind, f(err.into_inner()))
+}
+
+#[derive(Clone, PartialEq)]
+pub enum MidiInputPort {
+    Alsa(alsa::MidiInputPort),
+    Jack(jack::MidiInputPort),
+}
+
+impl MidiInputPort {
+    pub fn id(&self) -> String {
+        dispatch!(self, p => p.id())
+    }
+
+    pub fn from_id(id: &str) -> Option<Self> {
+        // ALSA IDs are numeric `client:port` pairs, while JACK IDs are port names
+        alsa::MidiInputPort::from_id(id)
+            .map(MidiInputPort::Alsa)
+            .or_else(|| jack::MidiInputPort::from_id(id).map(MidiInputPort::Jack))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SourceId {
+    Alsa(alsa::SourceId),
+    Jack(jack::SourceId),
+}
+
+impl SourceId {
+    pub fn port_id(&self) -> Option<String> {
+        dispatch!(self, s => s.port_id())
+    }
+}
+
+/// Watching stops when the watcher of the backend is dropped.
+pub enum PortWatcher {
+    Alsa { _watcher: alsa::PortWatcher },
+    Jack { _watcher: jack::PortWatcher },
+}
+
+pub enum MidiInput {
+    Alsa(alsa::MidiInput),
+    Jack(jack::MidiInput),
+}
+
+impl MidiInput {
+    pub fn new(client_name: &str) -> Result<Self, InitError> {
+        Self::new_with_backend(Backend::Jack, client_name)
+    }
+
+    pub fn new_with_backend(backend: Backend, client_name: &str) -> Result<Self, InitError> {
+        match backend {
+            Backend::Alsa => alsa::MidiInput::new(client_name).map(MidiInput::Alsa),
+            Backend::Jack => jack::MidiInput::new(client_name).map(MidiInput::Jack),
+        }
+    }
+
+    pub fn new_with_options(
+        client_name: &str,
+        options: JackStartOptions,
+    ) -> Result<Self, InitError> {
+        jack::MidiInput::new_with_options(client_name, options).map(MidiInput::Jack)
+    }
+
+    pub fn backend(&self) -> Backend {
+        match self {
+            MidiInput::Alsa(_) => Backend::Alsa,
+            MidiInput::Jack(_) => Backend::Jack,
+        }
+    }
+
+    pub fn ignore(&mut self, flags: Ignore) {
+        dispatch!(self, imp => imp.ignore(flags))
+    }
+
+    pub fn ignored_flags(&self) -> Ignore {
+        dispatch!(self, imp => imp.ignored_flags())
+    }
+
+    pub fn set_sysex_mode(&mut self, mode: SysexMode) {
+        dispatch!(self, imp => imp.set_sysex_mode(mode))
+    }
+
+    pub fn set_max_sysex_size(&mut self, max: Option<usize>) {
+        dispatch!(self, imp => imp.set_max_sysex_size(max))
+    }
+
+    pub fn set_timestamping(&mut self, enable: bool) {
+        dispatch!(self, imp => imp.set_timestamping(enable))
+    }
+
+    pub fn timestamping(&self) -> bool {
+        dispatch!(self, imp => imp.timestamping())
+    }
+
+    pub fn set_backend_option(&mut self, key: &str, value: &str) -> Result<(), ConnectErrorKind> {
+        dispatch!(self, imp => imp.set_backend_option(key, value))
+    }
+
+    pub fn raw_timestamp_fn(&self) -> fn(u64) -> u64 {
+        dispatch!(self, imp => imp.raw_timestamp_fn())
+    }
+
+    pub fn set_compat(&mut self, compat: Compat) {
+        if let MidiInput::Alsa(imp) = self {
+            imp.set_compat(compat)
+        }
+    }
+
+    pub fn set_frame_timestamps(&mut self, enable: bool) {
+        if let MidiInput::Jack(imp) = self {
+            imp.set_frame_timestamps(enable)
+        }
+    }
+
+    pub fn frame_rate(&self) -> Option<NonZeroU32> {
+        match self {
+            MidiInput::Jack(imp) => imp.frame_rate(),
+            MidiInput::Alsa(_) => None,
+        }
+    }
+
+    pub fn set_connect_options(&mut self, options: JackConnectOptions) {
+        if let MidiInput::Jack(imp) = self {
+            imp.set_connect_options(options)
+        }
+    }
+
+    pub fn set_early_message_policy(&mut self, policy: EarlyMessagePolicy) {
+        dispatch!(self, imp => imp.set_early_message_policy(policy))
+    }
+
+    pub fn reject_duplicate_virtual_names(&mut self, reject: bool) {
+        dispatch!(self, imp => imp.reject_duplicate_virtual_names(reject))
+    }
+
+    pub fn supports_virtual_ports(&self) -> bool {
+        dispatch!(self, imp => imp.supports_virtual_ports())
+    }
+
+    pub fn set_client_name(&mut self, name: &str) -> Result<(), ConnectErrorKind> {
+        dispatch!(self, imp => imp.set_client_name(name))
+    }
+
+    pub(crate) fn ports_internal(&self) -> Vec<crate::common::MidiInputPort> {
+        match self {
+            MidiInput::Alsa(imp) => imp
+                .ports()
+                .into_iter()
+                .map(|p| crate::common::MidiInputPort {
+                    imp: MidiInputPort::Alsa(p),
+                })
+                .collect(),
+            MidiInput::Jack(imp) => imp
+                .ports()
+                .into_iter()
+                .map(|p| crate::common::MidiInputPort {
+                    imp: MidiInputPort::Jack(p),
+                })
+                .collect(),
+        }
+    }
+
+    pub fn port_count(&self) -> usize {
+        dispatch!(self, imp => imp.port_count())
+    }
+
+    pub fn port_name(&self, port: &MidiInputPort) -> Result<String, PortInfoError> {
+        match (self, port) {
+            (MidiInput::Alsa(imp), MidiInputPort::Alsa(port)) => imp.port_name(port),
+            (MidiInput::Jack(imp), MidiInputPort::Jack(port)) => imp.port_name(port),
+            _ => Err(PortInfoError::InvalidPort),
+        }
+    }
+
+    pub fn watch(
+        &self,
+        mut callback: Box<dyn FnMut(DeviceEvent<MidiInputPort>) + Send>,
+    ) -> Result<PortWatcher, InitError> {
+        match self {
+            MidiInput::Alsa(imp) => imp
+                .watch(Box::new(move |event| {
+                    callback(event.map(MidiInputPort::Alsa))
+                }))
+                .map(|watcher| PortWatcher::Alsa { _watcher: watcher }),
+            MidiInput::Jack(imp) => imp
+                .watch(Box::new(move |event| {
+                    callback(event.map(MidiInputPort::Jack))
+                }))
+                .map(|watcher| PortWatcher::Jack { _watcher: watcher }),
+        }
+    }
+
+    pub fn port_metadata(
+        &self,
+        port: &MidiInputPort,
+    ) -> Result<crate::PortMetadata, PortInfoError> {
+        match (self, port) {
+            (MidiInput::Alsa(imp), MidiInputPort::Alsa(port)) => imp.port_metadata(port),
+            (MidiInput::Jack(imp), MidiInputPort::Jack(port)) => imp.port_metadata(port),
+            _ => Err(PortInfoError::InvalidPort),
+        }
+    }
+
+    pub fn device_name(&self, port: &MidiInputPort) -> Option<String> {
+        match (self, port) {
+            (MidiInput::Alsa(imp), MidiInputPort::Alsa(port)) => imp.device_name(port),
+            (MidiInput::Jack(imp), MidiInputPort::Jack(port)) => imp.device_name(port),
+            _ => None,
+        }
+    }
+
+    pub fn is_connectable(&self, port: &MidiInputPort) -> bool {
+        match (self, port) {
+            (MidiInput::Alsa(imp), MidiInputPort::Alsa(port)) => imp.is_connectable(port),
+            (MidiInput::Jack(imp), MidiInputPort::Jack(port)) => imp.is_connectable(port),
+            _ => true,
+        }
+    }
+
+    pub fn hw_info(&self, port: &MidiInputPort) -> Option<(i32, i32)> {
+        match (self, port) {
+            (MidiInput::Alsa(imp), MidiInputPort::Alsa(port)) => imp.hw_info(port),
+            _ => None,
+        }
+    }
+
+    pub fn connect<F, T: Send>(
+        self,
+        port: &MidiInputPort,
+        port_name: &str,
+        callback: F,
+        data: T,
+    ) -> Result<MidiInputConnection<T>, ConnectError<MidiInput>>
+    where
+        F: FnMut(u64, &[u8], &mut T) + Send + 'static,
+    {
+        match (self, port) {
+            (MidiInput::Alsa(imp), MidiInputPort::Alsa(port)) => imp
+                .connect(port, port_name, callback, data)
+                .map(MidiInputConnection::Alsa)
+                .map_err(|e| map_connect_error(e, MidiInput::Alsa)),
+            (MidiInput::Jack(imp), MidiInputPort::Jack(port)) => imp
+                .connect(port, port_name, callback, data)
+                .map(MidiInputConnection::Jack)
+                .map_err(|e| map_connect_error(e, MidiInput::Jack)),
+            (this, _) => Err(ConnectError::new(ConnectErrorKind::InvalidPort, this)),
+        }
+    }
+
+    pub fn connect_with_source<F, T: Send>(
+        self,
+        port: &MidiInputPort,
+        port_name: &str,
+        mut callback: F,
+        data: T,
+    ) -> Result<MidiInputConnection<T>, ConnectError<MidiInput>>
+    where
+        F: FnMut(u64, &[u8], SourceId, &mut T) + Send + 'static,
+    {
+        match (self, port) {
+            (MidiInput::Alsa(imp), MidiInputPort::Alsa(port)) => imp
+                .connect_with_source(
+                    port,
+                    port_name,
+                    move |timestamp, message, source, data| {
+                        callback(timestamp, message, SourceId::Alsa(source), data)
+                    },
+                    data,
+                )
+                .map(MidiInputConnection::Alsa)
+                .map_err(|e| map_connect_error(e, MidiInput::Alsa)),
+            (MidiInput::Jack(imp), MidiInputPort::Jack(port)) => imp
+                .connect_with_source(
+                    port,
+                    port_name,
+                    move |timestamp, message, source, data| {
+                        callback(timestamp, message, SourceId::Jack(source), data)
+                    },
+                    data,
+                )
+                .map(MidiInputConnection::Jack)
+                .map_err(|e| map_connect_error(e, MidiInput::Jack)),
+            (this, _) => Err(ConnectError::new(ConnectErrorKind::InvalidPort, this)),
+        }
+    }
+
+    pub fn connect_multi<F, T: Send>(
+        self,
+        ports: &[&MidiInputPort],
+        port_name: &str,
+        callback: F,
+        data: T,
+    ) -> Result<MidiInputConnection<T>, ConnectError<MidiInput>>
+    where
+        F: FnMut(u64, &[u8], usize, &mut T) + Send + 'static,
+    {
+        match self {
+            MidiInput::Alsa(imp) => {
+                let ports: Option<Vec<_>> = ports
+                    .iter()
+                    .map(|port| match port {
+                        MidiInputPort::Alsa(port) => Some(port),
+                        _ => None,
+                    })
+                    .collect();
+                match ports {
+                    Some(ports) => imp
+                        .connect_multi(&ports, port_name, callback, data)
+                        .map(MidiInputConnection::Alsa)
+                        .map_err(|e| map_connect_error(e, MidiInput::Alsa)),
+                    None => Err(ConnectError::new(
+                        ConnectErrorKind::InvalidPort,
+                        MidiInput::Alsa(imp),
+                    )),
+                }
+            }
+            MidiInput::Jack(imp) => {
+                let ports: Option<Vec<_>> = ports
+                    .iter()
+                    .map(|port| match port {
+                        MidiInputPort::Jack(port) => Some(port),
+                        _ => None,
+                    })
+                    .collect();
+                match ports {
+                    Some(ports) => imp
+                        .connect_multi(&ports, port_name, callback, data)
+                        .map(MidiInputConnection::Jack)
+                        .map_err(|e| map_connect_error(e, MidiInput::Jack)),
+                    None => Err(ConnectError::new(
+                        ConnectErrorKind::InvalidPort,
+                        MidiInput::Jack(imp),
+                    )),
+                }
+            }
+        }
+    }
+
+    pub fn connect_on<F, T: Send>(
+        self,
+        executor: &dyn InputExecutor,
+        port: &MidiInputPort,
+        port_name: &str,
+        callback: F,
+        data: T,
+    ) -> Result<MidiInputConnection<T>, ConnectError<MidiInput>>
+    where
+        F: FnMut(u64, &[u8], &mut T) + Send + 'static,
+    {
+        match (self, port) {
+            (MidiInput::Alsa(imp), MidiInputPort::Alsa(port)) => imp
+                .connect_on(executor, port, port_name, callback, data)
+                .map(MidiInputConnection::Alsa)
+                .map_err(|e| map_connect_error(e, MidiInput::Alsa)),
+            (MidiInput::Jack(imp), MidiInputPort::Jack(port)) => imp
+                .connect_on(executor, port, port_name, callback, data)
+                .map(MidiInputConnection::Jack)
+                .map_err(|e| map_connect_error(e, MidiInput::Jack)),
+            (this, _) => Err(ConnectError::new(ConnectErrorKind::InvalidPort, this)),
+        }
+    }
+
+    pub fn create_virtual<F, T: Send>(
+        self,
+        port_name: &str,
+        callback: F,
+        data: T,
+    ) -> Result<MidiInputConnection<T>, ConnectError<Self>>
+    where
+        F: FnMut(u64, &[u8], &mut T) + Send + 'static,
+    {
+        match self {
+            MidiInput::Alsa(imp) => imp
+                .create_virtual(port_name, callback, data)
+                .map(MidiInputConnection::Alsa)
+                .map_err(|e| map_connect_error(e, MidiInput::Alsa)),
+            MidiInput::Jack(imp) => imp
+                .create_virtual(port_name, callback, data)
+                .map(MidiInputConnection::Jack)
+                .map_err(|e| map_connect_error(e, MidiInput::Jack)),
+        }
+    }
+}
+
+pub enum MidiInputConnection<T: 'static> {
+    Alsa(alsa::MidiInputConnection<T>),
+    Jack(jack::MidiInputConnection<T>),
+}
+
+impl<T> MidiInputConnection<T> {
+    pub fn backend_name(&self) -> &'static str {
+        dispatch!(self, imp => imp.backend_name())
+    }
+
+    pub fn set_latency_range(
+        &mut self,
+        mode: LatencyMode,
+        min: u32,
+        max: u32,
+    ) -> Result<(), ConnectErrorKind> {
+        match self {
+            MidiInputConnection::Alsa(_) => {
+                Err(ConnectErrorKind::Other("not a JACK input connection"))
+            }
+            MidiInputConnection::Jack(imp) => imp.set_latency_range(mode, min, max),
+        }
+    }
+
+    pub fn latency_range(&self, mode: LatencyMode) -> Result<(u32, u32), ConnectErrorKind> {
+        match self {
+            MidiInputConnection::Alsa(_) => {
+                Err(ConnectErrorKind::Other("not a JACK input connection"))
+            }
+            MidiInputConnection::Jack(imp) => imp.latency_range(mode),
+        }
+    }
+
+    pub fn set_raw_tap(&mut self, tap: Option<RawTap>) {
+        dispatch!(self, imp => imp.set_raw_tap(tap))
+    }
+
+    pub fn clear_input(&mut self) {
+        dispatch!(self, imp => imp.clear_input())
+    }
+
+    pub fn filtered_count(&self) -> u64 {
+        dispatch!(self, imp => imp.filtered_count())
+    }
+
+    pub fn callback_thread_id(&self) -> Option<std::thread::ThreadId> {
+        dispatch!(self, imp => imp.callback_thread_id())
+    }
+
+    pub fn set_close_timeout(&mut self, timeout: Option<std::time::Duration>) {
+        dispatch!(self, imp => imp.set_close_timeout(timeout))
+    }
+
+    pub fn subscribe_from(&mut self, sender: (i32, i32)) -> Result<(), ConnectErrorKind> {
+        match self {
+            MidiInputConnection::Alsa(imp) => imp.subscribe_from(sender),
+            MidiInputConnection::Jack(_) => {
+                Err(ConnectErrorKind::Other("not an ALSA input connection"))
+            }
+        }
+    }
+
+    pub fn unsubscribe_from(&mut self, sender: (i32, i32)) -> Result<(), ConnectErrorKind> {
+        match self {
+            MidiInputConnection::Alsa(imp) => imp.unsubscribe_from(sender),
+            MidiInputConnection::Jack(_) => {
+                Err(ConnectErrorKind::Other("not an ALSA input connection"))
+            }
+        }
+    }
+
+    pub fn on_subscription_change(
+        &mut self,
+        callback: Option<SubscriptionCallback>,
+    ) -> Result<(), ConnectErrorKind> {
+        match self {
+            MidiInputConnection::Alsa(imp) => imp.on_subscription_change(callback),
+            MidiInputConnection::Jack(_) => {
+                Err(ConnectErrorKind::Other("not an ALSA input connection"))
+            }
+        }
+    }
+
+    pub fn timestamps_degraded(&self) -> bool {
+        match self {
+            MidiInputConnection::Alsa(imp) => imp.timestamps_degraded(),
+            MidiInputConnection::Jack(_) => false,
+        }
+    }
+
+    pub fn close(self) -> (MidiInput, T) {
+        match self {
+            MidiInputConnection::Alsa(imp) => {
+                let (imp, data) = imp.close();
+                (MidiInput::Alsa(imp), data)
+            }
+            MidiInputConnection::Jack(imp) => {
+                let (imp, data) = imp.close();
+                (MidiInput::Jack(imp), data)
+            }
+        }
+    }
+
+    pub fn close_draining(self) -> (MidiInput, T, Vec<(u64, Vec<u8>)>) {
+        match self {
+            MidiInputConnection::Alsa(imp) => {
+                let (imp, data, pending) = imp.close_draining();
+                (MidiInput::Alsa(imp), data, pending)
+            }
+            MidiInputConnection::Jack(imp) => {
+                let (imp, data, pending) = imp.close_draining();
+                (MidiInput::Jack(imp), data, pending)
+            }
+        }
+    }
+}
+
+#[derive(Clone, PartialEq)]
+pub enum MidiOutputPort {
+    Alsa(alsa::MidiOutputPort),
+    Jack(jack::MidiOutputPort),
+}
+
+impl MidiOutputPort {
+    pub fn id(&self) -> String {
+        dispatch!(self, p => p.id())
+    }
+
+    pub fn from_id(id: &str) -> Option<Self> {
+        // ALSA IDs are numeric `client:port` pairs, while JACK IDs are port names
+        alsa::MidiOutputPort::from_id(id)
+            .map(MidiOutputPort::Alsa)
+            .or_else(|| jack::MidiOutputPort::from_id(id).map(MidiOutputPort::Jack))
+    }
+}
+
+pub enum MidiOutput {
+    Alsa(alsa::MidiOutput),
+    Jack(jack::MidiOutput),
+}
+
+impl MidiOutput {
+    pub fn new(client_name: &str) -> Result<Self, InitError> {
+        Self::new_with_backend(Backend::Jack, client_name)
+    }
+
+    pub fn new_with_backend(backend: Backend, client_name: &str) -> Result<Self, InitError> {
+        match backend {
+            Backend::Alsa => alsa::MidiOutput::new(client_name).map(MidiOutput::Alsa),
+            Backend::Jack => jack::MidiOutput::new(client_name).map(MidiOutput::Jack),
+        }
+    }
+
+    pub fn new_with_options(
+        client_name: &str,
+        options: JackStartOptions,
+    ) -> Result<Self, InitError> {
+        jack::MidiOutput::new_with_options(client_name, options).map(MidiOutput::Jack)
+    }
+
+    pub fn backend(&self) -> Backend {
+        match self {
+            MidiOutput::Alsa(_) => Backend::Alsa,
+            MidiOutput::Jack(_) => Backend::Jack,
+        }
+    }
+
+    pub fn reject_duplicate_virtual_names(&mut self, reject: bool) {
+        dispatch!(self, imp => imp.reject_duplicate_virtual_names(reject))
+    }
+
+    pub fn set_connect_options(&mut self, options: JackConnectOptions) {
+        if let MidiOutput::Jack(imp) = self {
+            imp.set_connect_options(options)
+        }
+    }
+
+    pub fn supports_virtual_ports(&self) -> bool {
+        dispatch!(self, imp => imp.supports_virtual_ports())
+    }
+
+    pub fn set_client_name(&mut self, name: &str) -> Result<(), ConnectErrorKind> {
+        dispatch!(self, imp => imp.set_client_name(name))
+    }
+
+    pub(crate) fn ports_internal(&self) -> Vec<crate::common::MidiOutputPort> {
+        match self {
+            MidiOutput::Alsa(imp) => imp
+                .ports()
+                .into_iter()
+                .map(|p| crate::common::MidiOutputPort {
+                    imp: MidiOutputPort::Alsa(p),
+                })
+                .collect(),
+            MidiOutput::Jack(imp) => imp
+                .ports()
+                .into_iter()
+                .map(|p| crate::common::MidiOutputPort {
+                    imp: MidiOutputPort::Jack(p),
+                })
+                .collect(),
+        }
+    }
+
+    pub fn port_count(&self) -> usize {
+        dispatch!(self, imp => imp.port_count())
+    }
+
+    pub fn port_name(&self, port: &MidiOutputPort) -> Result<String, PortInfoError> {
+        match (self, port) {
+            (MidiOutput::Alsa(imp), MidiOutputPort::Alsa(port)) => imp.port_name(port),
+            (MidiOutput::Jack(imp), MidiOutputPort::Jack(port)) => imp.port_name(port),
+            _ => Err(PortInfoError::InvalidPort),
+        }
+    }
+
+    pub fn watch(
+        &self,
+        mut callback: Box<dyn FnMut(DeviceEvent<MidiOutputPort>) + Send>,
+    ) -> Result<PortWatcher, InitError> {
+        match self {
+            MidiOutput::Alsa(imp) => imp
+                .watch(Box::new(move |event| {
+                    callback(event.map(MidiOutputPort::Alsa))
+                }))
+                .map(|watcher| PortWatcher::Alsa { _watcher: watcher }),
+            MidiOutput::Jack(imp) => imp
+                .watch(Box::new(move |event| {
+                    callback(event.map(MidiOutputPort::Jack))
+                }))
+                .map(|watcher| PortWatcher::Jack { _watcher: watcher }),
+        }
+    }
+
+    pub fn port_metadata(
+        &self,
+        port: &MidiOutputPort,
+    ) -> Result<crate::PortMetadata, PortInfoError> {
+        match (self, port) {
+            (MidiOutput::Alsa(imp), MidiOutputPort::Alsa(port)) => imp.port_metadata(port),
+            (MidiOutput::Jack(imp), MidiOutputPort::Jack(port)) => imp.port_metadata(port),
+            _ => Err(PortInfoError::InvalidPort),
+        }
+    }
+
+    pub fn device_name(&self, port: &MidiOutputPort) -> Option<String> {
+        match (self, port) {
+            (MidiOutput::Alsa(imp), MidiOutputPort::Alsa(port)) => imp.device_name(port),
+            (MidiOutput::Jack(imp), MidiOutputPort::Jack(port)) => imp.device_name(port),
+            _ => None,
+        }
+    }
+
+    pub fn is_connectable(&self, port: &MidiOutputPort) -> bool {
+        match (self, port) {
+            (MidiOutput::Alsa(imp), MidiOutputPort::Alsa(port)) => imp.is_connectable(port),
+            (MidiOutput::Jack(imp), MidiOutputPort::Jack(port)) => imp.is_connectable(port),
+            _ => true,
+        }
+    }
+
+    pub fn hw_info(&self, port: &MidiOutputPort) -> Option<(i32, i32)> {
+        match (self, port) {
+            (MidiOutput::Alsa(imp), MidiOutputPort::Alsa(port)) => imp.hw_info(port),
+            _ => None,
+        }
+    }
+
+    pub fn is_synth_port(&self, port: &MidiOutputPort) -> bool {
+        match (self, port) {
+            (MidiOutput::Alsa(imp), MidiOutputPort::Alsa(port)) => imp.is_synth_port(port),
+            (MidiOutput::Jack(imp), MidiOutputPort::Jack(port)) => imp.is_synth_port(port),
+            _ => false,
+        }
+    }
+
+    pub fn is_sibling_port(&self, input: &MidiInputPort, output: &MidiOutputPort) -> bool {
+        match (self, input, output) {
+            (MidiOutput::Alsa(imp), MidiInputPort::Alsa(input), MidiOutputPort::Alsa(output)) => {
+                imp.is_sibling_port(input, output)
+            }
+            (MidiOutput::Jack(imp), MidiInputPort::Jack(input), MidiOutputPort::Jack(output)) => {
+                imp.is_sibling_port(input, output)
+            }
+            _ => false,
+        }
+    }
+
+    pub fn connect(
+        self,
+        port: &MidiOutputPort,
+        port_name: &str,
+    ) -> Result<MidiOutputConnection, ConnectError<MidiOutput>> {
+        match (self, port) {
+            (MidiOutput::Alsa(imp), MidiOutputPort::Alsa(port)) => imp
+                .connect(port, port_name)
+                .map(MidiOutputConnection::Alsa)
+                .map_err(|e| map_connect_error(e, MidiOutput::Alsa)),
+            (MidiOutput::Jack(imp), MidiOutputPort::Jack(port)) => imp
+                .connect(port, port_name)
+                .map(MidiOutputConnection::Jack)
+                .map_err(|e| map_connect_error(e, MidiOutput::Jack)),
+            (this, _) => Err(ConnectError::new(ConnectErrorKind::InvalidPort, this)),
+        }
+    }
+
+    pub fn create_virtual(
+        self,
+        port_name: &str,
+    ) -> Result<MidiOutputConnection, ConnectError<MidiOutput>> {
+        match self {
+            MidiOutput::Alsa(imp) => imp
+                .create_virtual(port_name)
+                .map(MidiOutputConnection::Alsa)
+                .map_err(|e| map_connect_error(e, MidiOutput::Alsa)),
+            MidiOutput::Jack(imp) => imp
+                .create_virtual(port_name)
+                .map(MidiOutputConnection::Jack)
+                .map_err(|e| map_connect_error(e, MidiOutput::Jack)),
+        }
+    }
+}
+
+pub enum MidiOutputConnection {
+    Alsa(alsa::MidiOutputConnection),
+    Jack(jack::MidiOutputConnection),
+}
+
+impl MidiOutputConnection {
+    pub fn backend_name(&self) -> &'static str {
+        dispatch!(self, imp => imp.backend_name())
+    }
+
+    pub fn supports_sysex(&self) -> Option<bool> {
+        dispatch!(self, imp => imp.supports_sysex())
+    }
+
+    pub fn max_sysex_size(&self) -> Option<usize> {
+        dispatch!(self, imp => imp.max_sysex_size())
+    }
+
+    pub fn set_latency_range(
+        &mut self,
+        mode: LatencyMode,
+        min: u32,
+        max: u32,
+    ) -> Result<(), ConnectErrorKind> {
+        match self {
+            MidiOutputConnection::Alsa(_) => {
+                Err(ConnectErrorKind::Other("not a JACK output connection"))
+            }
+            MidiOutputConnection::Jack(imp) => imp.set_latency_range(mode, min, max),
+        }
+    }
+
+    pub fn latency_range(&self, mode: LatencyMode) -> Result<(u32, u32), ConnectErrorKind> {
+        match self {
+            MidiOutputConnection::Alsa(_) => {
+                Err(ConnectErrorKind::Other("not a JACK output connection"))
+            }
+            MidiOutputConnection::Jack(imp) => imp.latency_range(mode),
+        }
+    }
+
+    pub fn close(self) -> MidiOutput {
+        match self {
+            MidiOutputConnection::Alsa(imp) => MidiOutput::Alsa(imp.close()),
+            MidiOutputConnection::Jack(imp) => MidiOutput::Jack(imp.close()),
+        }
+    }
+
+    pub fn send(&mut self, message: &[u8]) -> Result<(), SendError> {
+        dispatch!(self, imp => imp.send(message))
+    }
+
+    pub fn send_at(&mut self, message: &[u8], delay: std::time::Duration) -> Result<(), SendError> {
+        dispatch!(self, imp => imp.send_at(message, delay))
+    }
+
+    pub fn send_all(&mut self, messages: &[&[u8]]) -> Result<(), SendError> {
+        dispatch!(self, imp => imp.send_all(messages))
+    }
+
+    pub fn set_running_status(&mut self, enable: bool) -> Result<(), ConnectErrorKind> {
+        match self {
+            MidiOutputConnection::Alsa(imp) => imp.set_running_status(enable),
+            MidiOutputConnection::Jack(_) => {
+                Err(ConnectErrorKind::Other("not an ALSA output connection"))
+            }
+        }
+    }
+}
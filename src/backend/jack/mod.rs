@@ -1,14 +1,24 @@
-use jack_sys::jack_nframes_t;
-use libc::c_void;
-
-use std::ffi::CString;
+use jack_sys::{
+    jack_latency_callback_mode_t, jack_nframes_t, jack_port_id_t, JackCaptureLatency,
+    JackNameNotUnique, JackPlaybackLatency, JackServerError, JackServerFailed, JackVersionError,
+};
+use libc::{c_int, c_void};
+
+use std::ffi::{CStr, CString};
+use std::num::NonZeroU32;
+use std::sync::{mpsc, Mutex};
+use std::thread::{self, JoinHandle};
 use std::{mem, slice};
 
 mod wrappers;
 use self::wrappers::*;
 
 use crate::errors::*;
-use crate::{Ignore, MidiMessage};
+use crate::os::unix::jack::{JackConnectOptions, JackStartOptions, LatencyMode};
+use crate::time::Timestamp;
+use crate::{
+    DeviceEvent, EarlyMessagePolicy, Ignore, InputExecutor, MidiMessage, RawTap, SysexMode,
+};
 
 const OUTPUT_RINGBUFFER_SIZE: usize = 16384;
 
@@ -16,11 +26,134 @@ struct InputHandlerData<T> {
     port: Option<MidiPort>,
     ignore_flags: Ignore,
     callback: Box<dyn FnMut(u64, &[u8], &mut T) + Send>,
+    raw_tap: Mutex<Option<RawTap>>,
+    // Set if messages are timestamped with frame times instead of microseconds
+    frame_clock: Option<FrameClock>,
     user_data: Option<T>,
 }
 
+fn input_ports(client: &Client) -> Vec<MidiInputPort> {
+    let ports = client.get_midi_ports(PortFlags::PortIsOutput);
+    let mut result = Vec::with_capacity(ports.count());
+    for i in 0..ports.count() {
+        result.push(MidiInputPort {
+            name: ports.get_c_name(i).into(),
+        })
+    }
+    result
+}
+
+fn output_ports(client: &Client) -> Vec<MidiOutputPort> {
+    let ports = client.get_midi_ports(PortFlags::PortIsInput);
+    let mut result = Vec::with_capacity(ports.count());
+    for i in 0..ports.count() {
+        result.push(MidiOutputPort {
+            name: ports.get_c_name(i).into(),
+        })
+    }
+    result
+}
+
+enum WatchEvent {
+    Changed,
+    Stop,
+}
+
+/// Watches for ports that appear or disappear, using the port registration callback of
+/// a separate client. Ports must not be listed in that callback, so it only notifies a
+/// helper thread, which compares the ports with the ones it has listed before.
+pub struct PortWatcher {
+    events: mpsc::Sender<WatchEvent>,
+    thread: Option<JoinHandle<()>>,
+    // Used by the registration callback, so this must live until the client is closed
+    _callback_events: Box<mpsc::Sender<WatchEvent>>,
+}
+
+impl PortWatcher {
+    fn start<P: Clone + PartialEq + Send + 'static>(
+        ports: fn(&Client) -> Vec<P>,
+        mut callback: Box<dyn FnMut(DeviceEvent<P>) + Send>,
+    ) -> Result<Self, InitError> {
+        let mut client = open_client("midir port watcher", JackStartOptions::default())?;
+        let (events, received) = mpsc::channel();
+        let callback_events = Box::new(events.clone());
+        client.set_port_registration_callback(
+            handle_port_registration,
+            &*callback_events as *const _ as *mut c_void,
+        );
+        client.activate();
+
+        let mut known = ports(&client);
+        let thread = thread::Builder::new()
+            .name("midir JACK port watcher".to_owned())
+            .spawn(move || {
+                while let Ok(WatchEvent::Changed) = received.recv() {
+                    let present = ports(&client);
+                    for port in &known {
+                        if !present.contains(port) {
+                            callback(DeviceEvent::Removed(port.clone()));
+                        }
+                    }
+                    for port in &present {
+                        if !known.contains(port) {
+                            callback(DeviceEvent::Added(port.clone()));
+                        }
+                    }
+                    known = present;
+                }
+                // The client is closed here, so the registration callback is no longer invoked
+            })
+            .map_err(InitError::with_source)?;
+
+        Ok(PortWatcher {
+            events,
+            thread: Some(thread),
+            _callback_events: callback_events,
+        })
+    }
+}
+
+impl Drop for PortWatcher {
+    fn drop(&mut self) {
+        let _ = self.events.send(WatchEvent::Stop);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+extern "C" fn handle_port_registration(_port: jack_port_id_t, _register: c_int, arg: *mut c_void) {
+    let events = unsafe { &*(arg as *const mpsc::Sender<WatchEvent>) };
+    let _ = events.send(WatchEvent::Changed);
+}
+
+/// Opens a client, describing why this failed if it did.
+fn open_client(client_name: &str, options: JackStartOptions) -> Result<Client, InitError> {
+    let open_options = if options.start_server {
+        JackOpenOptions::empty()
+    } else {
+        JackOpenOptions::NoStartServer
+    };
+    Client::open(client_name, open_options).map_err(|status| {
+        let reason = if status & JackServerFailed != 0 {
+            "could not connect to the JACK server (is it running?)"
+        } else if status & JackNameNotUnique != 0 {
+            "the JACK client name is already taken"
+        } else if status & JackVersionError != 0 {
+            "the JACK server uses a different protocol version"
+        } else if status & JackServerError != 0 {
+            "could not communicate with the JACK server"
+        } else {
+            "could not open a JACK client"
+        };
+        InitError::new(reason)
+    })
+}
+
 pub struct MidiInput {
     ignore_flags: Ignore,
+    frame_timestamps: bool,
+    connect_options: JackConnectOptions,
     client: Option<Client>,
 }
 
@@ -33,24 +166,45 @@ impl MidiInputPort {
     pub fn id(&self) -> String {
         self.name.to_string_lossy().to_string()
     }
+
+    pub fn from_id(id: &str) -> Option<Self> {
+        // The ID is the full name of the port, which is all that is needed to connect
+        CString::new(id).ok().map(|name| MidiInputPort { name })
+    }
+}
+
+// Messages can't be attributed to a sending port by this backend
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SourceId;
+
+impl SourceId {
+    pub fn port_id(&self) -> Option<String> {
+        None
+    }
 }
 
 pub struct MidiInputConnection<T> {
     handler_data: Box<InputHandlerData<T>>,
+    // The options of the `MidiInput` that is returned by `close`
+    connect_options: JackConnectOptions,
     client: Option<Client>,
 }
 
 impl MidiInput {
     pub fn new(client_name: &str) -> Result<Self, InitError> {
-        let client = match Client::open(client_name, JackOpenOptions::NoStartServer) {
-            Ok(c) => c,
-            Err(_) => {
-                return Err(InitError);
-            } // TODO: maybe add message that Jack server might not be running
-        };
+        Self::new_with_options(client_name, JackStartOptions::default())
+    }
+
+    pub fn new_with_options(
+        client_name: &str,
+        options: JackStartOptions,
+    ) -> Result<Self, InitError> {
+        let client = open_client(client_name, options)?;
 
         Ok(MidiInput {
             ignore_flags: Ignore::None,
+            frame_timestamps: false,
+            connect_options: JackConnectOptions::default(),
             client: Some(client),
         })
     }
@@ -59,21 +213,92 @@ impl MidiInput {
         self.ignore_flags = flags;
     }
 
-    pub(crate) fn ports_internal(&self) -> Vec<crate::common::MidiInputPort> {
-        let ports = self
-            .client
-            .as_ref()
-            .unwrap()
-            .get_midi_ports(PortFlags::PortIsOutput);
-        let mut result = Vec::with_capacity(ports.count());
-        for i in 0..ports.count() {
-            result.push(crate::common::MidiInputPort {
-                imp: MidiInputPort {
-                    name: ports.get_c_name(i).into(),
-                },
-            })
+    pub fn ignored_flags(&self) -> Ignore {
+        self.ignore_flags
+    }
+
+    pub fn set_sysex_mode(&mut self, _mode: SysexMode) {
+        // JACK always delivers complete sysex messages, so there is nothing to reassemble or stream
+    }
+
+    pub fn set_timestamping(&mut self, _enable: bool) {
+        // Timestamps are provided by the system without extra cost
+    }
+
+    pub fn timestamping(&self) -> bool {
+        true
+    }
+
+    pub fn set_backend_option(&mut self, key: &str, _value: &str) -> Result<(), ConnectErrorKind> {
+        match key {
+            // The server is chosen when the client is opened, and the bindings don't support
+            // passing its name to `jack_client_open`, which takes it as a variadic argument
+            "jack.server_name" => Err(ConnectErrorKind::Other(
+                "the JACK server can only be chosen with the JACK_DEFAULT_SERVER environment variable",
+            )),
+            _ => Ok(()),
         }
-        result
+    }
+
+    pub fn raw_timestamp_fn(&self) -> fn(u64) -> u64 {
+        if self.frame_timestamps {
+            // Frame times are passed on as they are
+            |frames| frames
+        } else {
+            // JACK times (`jack_time_t`) are in microseconds
+            |nanos| nanos / 1_000
+        }
+    }
+
+    pub fn set_frame_timestamps(&mut self, enable: bool) {
+        self.frame_timestamps = enable;
+    }
+
+    /// The sample rate of the server if timestamps are frame times.
+    pub fn frame_rate(&self) -> Option<NonZeroU32> {
+        match self.client {
+            Some(ref client) if self.frame_timestamps => NonZeroU32::new(client.sample_rate()),
+            _ => None,
+        }
+    }
+
+    pub fn set_connect_options(&mut self, options: JackConnectOptions) {
+        self.connect_options = options;
+    }
+
+    pub fn set_max_sysex_size(&mut self, _max: Option<usize>) {
+        // Sysex messages are not reassembled, so there is no buffer that could grow
+    }
+
+    pub fn set_early_message_policy(&mut self, _policy: EarlyMessagePolicy) {
+        // The port is connected synchronously in `connect`, so no message can arrive early
+    }
+
+    pub fn reject_duplicate_virtual_names(&mut self, _reject: bool) {
+        // port names are always qualified by the client, so there are no duplicates
+    }
+
+    pub fn supports_virtual_ports(&self) -> bool {
+        true
+    }
+
+    pub fn set_client_name(&mut self, _name: &str) -> Result<(), ConnectErrorKind> {
+        // JACK client names are fixed when the client is opened
+        Err(ConnectErrorKind::Other(
+            "renaming the client is not supported by this backend",
+        ))
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub(crate) fn ports_internal(&self) -> Vec<crate::common::MidiInputPort> {
+        self.ports()
+            .into_iter()
+            .map(|imp| crate::common::MidiInputPort { imp })
+            .collect()
+    }
+
+    pub(crate) fn ports(&self) -> Vec<MidiInputPort> {
+        input_ports(self.client.as_ref().unwrap())
     }
 
     pub fn port_count(&self) -> usize {
@@ -88,14 +313,51 @@ impl MidiInput {
         Ok(port.name.to_string_lossy().into())
     }
 
-    fn activate_callback<F, T: Send>(&mut self, callback: F, data: T) -> Box<InputHandlerData<T>>
+    pub fn watch(
+        &self,
+        callback: Box<dyn FnMut(DeviceEvent<MidiInputPort>) + Send>,
+    ) -> Result<PortWatcher, InitError> {
+        PortWatcher::start(input_ports, callback)
+    }
+
+    pub fn port_metadata(
+        &self,
+        port: &MidiInputPort,
+    ) -> Result<crate::PortMetadata, PortInfoError> {
+        if !self.ports().contains(port) {
+            return Err(PortInfoError::InvalidPort);
+        }
+        // JACK ports only have names (and aliases)
+        Ok(crate::PortMetadata::default())
+    }
+
+    pub fn device_name(&self, port: &MidiInputPort) -> Option<String> {
+        client_name(&port.name)
+    }
+
+    pub fn is_connectable(&self, _port: &MidiInputPort) -> bool {
+        true // JACK ports accept any number of connections
+    }
+
+    /// Start processing with the given (already registered) port, so that the
+    /// process callback never runs before the port exists.
+    fn activate_callback<F, T: Send>(
+        &mut self,
+        port: MidiPort,
+        callback: F,
+        data: T,
+    ) -> Box<InputHandlerData<T>>
     where
         F: FnMut(u64, &[u8], &mut T) + Send + 'static,
     {
         let handler_data = Box::new(InputHandlerData {
-            port: None,
+            port: Some(port),
             ignore_flags: self.ignore_flags,
             callback: Box::new(callback),
+            raw_tap: Mutex::new(None),
+            frame_clock: self
+                .frame_timestamps
+                .then(|| self.client.as_ref().unwrap().frame_clock()),
             user_data: Some(data),
         });
 
@@ -109,6 +371,64 @@ impl MidiInput {
         handler_data
     }
 
+    pub fn connect_on<F, T: Send>(
+        self,
+        _executor: &dyn InputExecutor,
+        port: &MidiInputPort,
+        port_name: &str,
+        callback: F,
+        data: T,
+    ) -> Result<MidiInputConnection<T>, ConnectError<MidiInput>>
+    where
+        F: FnMut(u64, &[u8], &mut T) + Send + 'static,
+    {
+        // Input is delivered by callbacks from JACK, so there is no handler loop to run
+        self.connect(port, port_name, callback, data)
+    }
+
+    pub fn connect_with_source<F, T: Send>(
+        self,
+        port: &MidiInputPort,
+        port_name: &str,
+        mut callback: F,
+        data: T,
+    ) -> Result<MidiInputConnection<T>, ConnectError<MidiInput>>
+    where
+        F: FnMut(u64, &[u8], SourceId, &mut T) + Send + 'static,
+    {
+        self.connect(
+            port,
+            port_name,
+            move |timestamp, message, data| callback(timestamp, message, SourceId, data),
+            data,
+        )
+    }
+
+    pub fn connect_multi<F, T: Send>(
+        self,
+        ports: &[&MidiInputPort],
+        port_name: &str,
+        mut callback: F,
+        data: T,
+    ) -> Result<MidiInputConnection<T>, ConnectError<MidiInput>>
+    where
+        F: FnMut(u64, &[u8], usize, &mut T) + Send + 'static,
+    {
+        match ports {
+            [port] => self.connect(
+                port,
+                port_name,
+                move |timestamp, message, data| callback(timestamp, message, 0, data),
+                data,
+            ),
+            [] => Err(ConnectError::new(ConnectErrorKind::InvalidPort, self)),
+            _ => Err(ConnectError::other(
+                "connecting to several ports is not supported by this backend",
+                self,
+            )),
+        }
+    }
+
     pub fn connect<F, T: Send>(
         mut self,
         port: &MidiInputPort,
@@ -119,8 +439,6 @@ impl MidiInput {
     where
         F: FnMut(u64, &[u8], &mut T) + Send + 'static,
     {
-        let mut handler_data = self.activate_callback(callback, data);
-
         // Create port ...
         let dest_port = match self
             .client
@@ -134,20 +452,26 @@ impl MidiInput {
             }
         };
 
-        // ... and connect it to the output
-        if let Err(_) = self
-            .client
-            .as_mut()
-            .unwrap()
-            .connect(&port.name, dest_port.get_name())
+        // ... start processing (ports can only be connected by active clients) ...
+        let mut handler_data = self.activate_callback(dest_port, callback, data);
+
+        // ... and connect it to the output (unless this is left to the user)
+        let dest_port = handler_data.port.as_ref().unwrap();
+        if self.connect_options.auto_connect
+            && self
+                .client
+                .as_mut()
+                .unwrap()
+                .connect(&port.name, dest_port.get_name())
+                .is_err()
         {
+            deactivate(self.client.as_mut().unwrap(), &mut handler_data.port);
             return Err(ConnectError::new(ConnectErrorKind::InvalidPort, self));
         }
 
-        handler_data.port = Some(dest_port);
-
         Ok(MidiInputConnection {
             handler_data: handler_data,
+            connect_options: self.connect_options,
             client: self.client.take(),
         })
     }
@@ -161,8 +485,6 @@ impl MidiInput {
     where
         F: FnMut(u64, &[u8], &mut T) + Send + 'static,
     {
-        let mut handler_data = self.activate_callback(callback, data);
-
         // Create port
         let port = match self
             .client
@@ -176,16 +498,58 @@ impl MidiInput {
             }
         };
 
-        handler_data.port = Some(port);
+        let handler_data = self.activate_callback(port, callback, data);
 
         Ok(MidiInputConnection {
             handler_data: handler_data,
+            connect_options: self.connect_options,
             client: self.client.take(),
         })
     }
 }
 
 impl<T> MidiInputConnection<T> {
+    pub fn backend_name(&self) -> &'static str {
+        "JACK"
+    }
+
+    pub fn set_latency_range(
+        &mut self,
+        mode: LatencyMode,
+        min: u32,
+        max: u32,
+    ) -> Result<(), ConnectErrorKind> {
+        let port = self.handler_data.port.as_ref().unwrap();
+        port.set_latency_range(latency_mode(mode), min, max);
+        self.client.as_mut().unwrap().recompute_total_latencies();
+        Ok(())
+    }
+
+    pub fn latency_range(&self, mode: LatencyMode) -> Result<(u32, u32), ConnectErrorKind> {
+        let port = self.handler_data.port.as_ref().unwrap();
+        Ok(port.get_latency_range(latency_mode(mode)))
+    }
+
+    pub fn set_raw_tap(&mut self, tap: Option<RawTap>) {
+        *self.handler_data.raw_tap.lock().unwrap() = tap;
+    }
+
+    pub fn set_close_timeout(&mut self, _timeout: Option<std::time::Duration>) {
+        // Closing only deactivates the JACK client, which does not depend on a driver
+    }
+
+    pub fn clear_input(&mut self) {
+        // Input is passed on from within the process callback, so nothing is ever pending
+    }
+
+    pub fn filtered_count(&self) -> u64 {
+        0 // The ignore flags are not applied to JACK input, so nothing is ever filtered
+    }
+
+    pub fn callback_thread_id(&self) -> Option<std::thread::ThreadId> {
+        None // The callback is invoked on the process thread of the JACK server
+    }
+
     pub fn close(mut self) -> (MidiInput, T) {
         self.close_internal();
 
@@ -193,15 +557,21 @@ impl<T> MidiInputConnection<T> {
             MidiInput {
                 client: self.client.take(),
                 ignore_flags: self.handler_data.ignore_flags,
+                frame_timestamps: self.handler_data.frame_clock.is_some(),
+                connect_options: self.connect_options,
             },
             self.handler_data.user_data.take().unwrap(),
         )
     }
 
+    pub fn close_draining(self) -> (MidiInput, T, Vec<(u64, Vec<u8>)>) {
+        // Messages are passed on in the process callback in which they arrive, nothing is buffered
+        let (input, data) = self.close();
+        (input, data, Vec::new())
+    }
+
     fn close_internal(&mut self) {
-        let port = self.handler_data.port.take().unwrap();
-        self.client.as_mut().unwrap().unregister_midi_port(port);
-        self.client.as_mut().unwrap().deactivate();
+        deactivate(self.client.as_mut().unwrap(), &mut self.handler_data.port);
     }
 }
 
@@ -213,10 +583,31 @@ impl<T> Drop for MidiInputConnection<T> {
     }
 }
 
+fn latency_mode(mode: LatencyMode) -> jack_latency_callback_mode_t {
+    match mode {
+        LatencyMode::Capture => JackCaptureLatency,
+        LatencyMode::Playback => JackPlaybackLatency,
+    }
+}
+
+/// Stop processing and unregister the port of a connection. The client is
+/// deactivated first, so that the process callback never runs without the port.
+fn deactivate(client: &mut Client, port: &mut Option<MidiPort>) {
+    client.deactivate();
+    client.unregister_midi_port(port.take().unwrap());
+}
+
+/// JACK port names are qualified by the client name ("client:port").
+fn client_name(port_name: &CStr) -> Option<String> {
+    let name = port_name.to_string_lossy();
+    name.split_once(':').map(|(client, _)| client.to_owned())
+}
+
 extern "C" fn handle_input<T>(nframes: jack_nframes_t, arg: *mut c_void) -> i32 {
     let data: &mut InputHandlerData<T> = unsafe { &mut *(arg as *mut InputHandlerData<T>) };
+    let _scope = crate::CallbackScope::enter();
 
-    // Is port created?
+    // The port exists as long as the client is active (see `activate_callback`)
     if let Some(ref port) = data.port {
         let buff = port.get_midi_buffer(nframes);
 
@@ -237,7 +628,17 @@ extern "C" fn handle_input<T>(nframes: jack_nframes_t, arg: *mut c_void) -> i32
                     .push(unsafe { *event.buffer.offset(i as isize) });
             }
 
-            message.timestamp = Client::get_time(); // this is in microseconds
+            message.timestamp = match data.frame_clock {
+                Some(clock) => clock.last_frame_time() as u64 + event.time as u64,
+                None => Timestamp::from_jack_micros(Client::get_time()).as_nanos(),
+            };
+
+            // Never block the realtime thread: skip the tap if it is being replaced
+            if let Ok(mut raw_tap) = data.raw_tap.try_lock() {
+                if let Some(ref mut tap) = *raw_tap {
+                    tap(message.timestamp, &message.bytes);
+                }
+            }
             (data.callback)(
                 message.timestamp,
                 &message.bytes,
@@ -256,6 +657,7 @@ struct OutputHandlerData {
 }
 
 pub struct MidiOutput {
+    connect_options: JackConnectOptions,
     client: Option<Client>,
 }
 
@@ -268,42 +670,66 @@ impl MidiOutputPort {
     pub fn id(&self) -> String {
         self.name.to_string_lossy().to_string()
     }
+
+    pub fn from_id(id: &str) -> Option<Self> {
+        // The ID is the full name of the port, which is all that is needed to connect
+        CString::new(id).ok().map(|name| MidiOutputPort { name })
+    }
 }
 
 pub struct MidiOutputConnection {
     handler_data: Box<OutputHandlerData>,
+    // The options of the `MidiOutput` that is returned by `close`
+    connect_options: JackConnectOptions,
     client: Option<Client>,
 }
 
 impl MidiOutput {
     pub fn new(client_name: &str) -> Result<Self, InitError> {
-        let client = match Client::open(client_name, JackOpenOptions::NoStartServer) {
-            Ok(c) => c,
-            Err(_) => {
-                return Err(InitError);
-            } // TODO: maybe add message that Jack server might not be running
-        };
+        Self::new_with_options(client_name, JackStartOptions::default())
+    }
+
+    pub fn new_with_options(
+        client_name: &str,
+        options: JackStartOptions,
+    ) -> Result<Self, InitError> {
+        let client = open_client(client_name, options)?;
 
         Ok(MidiOutput {
+            connect_options: JackConnectOptions::default(),
             client: Some(client),
         })
     }
 
+    pub fn set_connect_options(&mut self, options: JackConnectOptions) {
+        self.connect_options = options;
+    }
+
+    pub fn reject_duplicate_virtual_names(&mut self, _reject: bool) {
+        // port names are always qualified by the client, so there are no duplicates
+    }
+
+    pub fn supports_virtual_ports(&self) -> bool {
+        true
+    }
+
+    pub fn set_client_name(&mut self, _name: &str) -> Result<(), ConnectErrorKind> {
+        // JACK client names are fixed when the client is opened
+        Err(ConnectErrorKind::Other(
+            "renaming the client is not supported by this backend",
+        ))
+    }
+
+    #[cfg(not(target_os = "linux"))]
     pub(crate) fn ports_internal(&self) -> Vec<crate::common::MidiOutputPort> {
-        let ports = self
-            .client
-            .as_ref()
-            .unwrap()
-            .get_midi_ports(PortFlags::PortIsInput);
-        let mut result = Vec::with_capacity(ports.count());
-        for i in 0..ports.count() {
-            result.push(crate::common::MidiOutputPort {
-                imp: MidiOutputPort {
-                    name: ports.get_c_name(i).into(),
-                },
-            })
-        }
-        result
+        self.ports()
+            .into_iter()
+            .map(|imp| crate::common::MidiOutputPort { imp })
+            .collect()
+    }
+
+    pub(crate) fn ports(&self) -> Vec<MidiOutputPort> {
+        output_ports(self.client.as_ref().unwrap())
     }
 
     pub fn port_count(&self) -> usize {
@@ -318,9 +744,45 @@ impl MidiOutput {
         Ok(port.name.to_string_lossy().into())
     }
 
-    fn activate_callback(&mut self) -> Box<OutputHandlerData> {
+    pub fn watch(
+        &self,
+        callback: Box<dyn FnMut(DeviceEvent<MidiOutputPort>) + Send>,
+    ) -> Result<PortWatcher, InitError> {
+        PortWatcher::start(output_ports, callback)
+    }
+
+    pub fn port_metadata(
+        &self,
+        port: &MidiOutputPort,
+    ) -> Result<crate::PortMetadata, PortInfoError> {
+        if !self.ports().contains(port) {
+            return Err(PortInfoError::InvalidPort);
+        }
+        // JACK ports only have names (and aliases)
+        Ok(crate::PortMetadata::default())
+    }
+
+    pub fn device_name(&self, port: &MidiOutputPort) -> Option<String> {
+        client_name(&port.name)
+    }
+
+    pub fn is_connectable(&self, _port: &MidiOutputPort) -> bool {
+        true // JACK ports accept any number of connections
+    }
+
+    pub fn is_synth_port(&self, _port: &MidiOutputPort) -> bool {
+        false // JACK does not distinguish synthesizers from other ports
+    }
+
+    pub fn is_sibling_port(&self, input: &MidiInputPort, output: &MidiOutputPort) -> bool {
+        client_name(&input.name) == client_name(&output.name)
+    }
+
+    /// Start processing with the given (already registered) port, so that the
+    /// process callback never runs before the port exists.
+    fn activate_callback(&mut self, port: MidiPort) -> Box<OutputHandlerData> {
         let handler_data = Box::new(OutputHandlerData {
-            port: None,
+            port: Some(port),
             buff_size: Ringbuffer::new(OUTPUT_RINGBUFFER_SIZE),
             buff_message: Ringbuffer::new(OUTPUT_RINGBUFFER_SIZE),
         });
@@ -340,8 +802,6 @@ impl MidiOutput {
         port: &MidiOutputPort,
         port_name: &str,
     ) -> Result<MidiOutputConnection, ConnectError<MidiOutput>> {
-        let mut handler_data = self.activate_callback();
-
         // Create port ...
         let source_port = match self
             .client
@@ -355,20 +815,26 @@ impl MidiOutput {
             }
         };
 
-        // ... and connect it to the input
-        if let Err(_) = self
-            .client
-            .as_mut()
-            .unwrap()
-            .connect(source_port.get_name(), &port.name)
+        // ... start processing (ports can only be connected by active clients) ...
+        let mut handler_data = self.activate_callback(source_port);
+
+        // ... and connect it to the input (unless this is left to the user)
+        let source_port = handler_data.port.as_ref().unwrap();
+        if self.connect_options.auto_connect
+            && self
+                .client
+                .as_mut()
+                .unwrap()
+                .connect(source_port.get_name(), &port.name)
+                .is_err()
         {
+            deactivate(self.client.as_mut().unwrap(), &mut handler_data.port);
             return Err(ConnectError::new(ConnectErrorKind::InvalidPort, self));
         }
 
-        handler_data.port = Some(source_port);
-
         Ok(MidiOutputConnection {
             handler_data: handler_data,
+            connect_options: self.connect_options,
             client: self.client.take(),
         })
     }
@@ -377,8 +843,6 @@ impl MidiOutput {
         mut self,
         port_name: &str,
     ) -> Result<MidiOutputConnection, ConnectError<Self>> {
-        let mut handler_data = self.activate_callback();
-
         // Create port
         let port = match self
             .client
@@ -392,19 +856,74 @@ impl MidiOutput {
             }
         };
 
-        handler_data.port = Some(port);
+        let handler_data = self.activate_callback(port);
 
         Ok(MidiOutputConnection {
             handler_data: handler_data,
+            connect_options: self.connect_options,
             client: self.client.take(),
         })
     }
 }
 
 impl MidiOutputConnection {
+    pub fn backend_name(&self) -> &'static str {
+        "JACK"
+    }
+
+    pub fn supports_sysex(&self) -> Option<bool> {
+        // JACK ports carry arbitrary MIDI data and have no notion of the device's capabilities
+        None
+    }
+
+    pub fn max_sysex_size(&self) -> Option<usize> {
+        None
+    }
+
+    pub fn set_latency_range(
+        &mut self,
+        mode: LatencyMode,
+        min: u32,
+        max: u32,
+    ) -> Result<(), ConnectErrorKind> {
+        let port = self.handler_data.port.as_ref().unwrap();
+        port.set_latency_range(latency_mode(mode), min, max);
+        self.client.as_mut().unwrap().recompute_total_latencies();
+        Ok(())
+    }
+
+    pub fn latency_range(&self, mode: LatencyMode) -> Result<(u32, u32), ConnectErrorKind> {
+        let port = self.handler_data.port.as_ref().unwrap();
+        Ok(port.get_latency_range(latency_mode(mode)))
+    }
+
+    pub fn send_all(&mut self, messages: &[&[u8]]) -> Result<(), SendError> {
+        for message in messages {
+            self.send(message)?;
+        }
+        Ok(())
+    }
+
+    pub fn send_at(
+        &mut self,
+        _message: &[u8],
+        _delay: std::time::Duration,
+    ) -> Result<(), SendError> {
+        // The process callback sends all queued messages at the start of the next cycle
+        Err(SendError::Other("scheduled send not supported"))
+    }
+
     pub fn send(&mut self, message: &[u8]) -> Result<(), SendError> {
         let nbytes = message.len();
 
+        // Never write a partial message (or a size without its message), because that
+        // would corrupt every message that follows
+        if self.handler_data.buff_message.get_write_space() < nbytes
+            || self.handler_data.buff_size.get_write_space() < mem::size_of_val(&nbytes)
+        {
+            return Err(SendError::Other("JACK output buffer is full"));
+        }
+
         // Write full message to buffer
         let written = self.handler_data.buff_message.write(message);
         debug_assert!(
@@ -429,14 +948,13 @@ impl MidiOutputConnection {
         self.close_internal();
 
         MidiOutput {
+            connect_options: self.connect_options,
             client: self.client.take(),
         }
     }
 
     fn close_internal(&mut self) {
-        let port = self.handler_data.port.take().unwrap();
-        self.client.as_mut().unwrap().unregister_midi_port(port);
-        self.client.as_mut().unwrap().deactivate();
+        deactivate(self.client.as_mut().unwrap(), &mut self.handler_data.port);
     }
 }
 
@@ -450,14 +968,17 @@ impl Drop for MidiOutputConnection {
 
 extern "C" fn handle_output(nframes: jack_nframes_t, arg: *mut c_void) -> i32 {
     let data: &mut OutputHandlerData = unsafe { mem::transmute(arg) };
+    let _scope = crate::CallbackScope::enter();
 
-    // Is port created?
+    // The port exists as long as the client is active (see `activate_callback`)
     if let Some(ref port) = data.port {
         let mut space: usize = 0;
 
         let mut buff = port.get_midi_buffer(nframes);
         buff.clear();
 
+        // All events are written at frame 0, which JACK keeps in the order they were
+        // reserved, so messages are delivered in the order they were sent
         while data.buff_size.get_read_space() > 0 {
             let read = data
                 .buff_size
@@ -4,16 +4,19 @@ use std::ffi::{CStr, CString};
 use std::ops::Index;
 use std::{ptr, slice, str};
 
-use libc::{c_void, size_t};
+use libc::{c_int, c_void, size_t};
 
 use jack_sys::{
     jack_activate, jack_client_close, jack_client_open, jack_client_t, jack_connect,
-    jack_deactivate, jack_free, jack_get_ports, jack_get_time, jack_midi_clear_buffer,
-    jack_midi_data_t, jack_midi_event_get, jack_midi_event_reserve, jack_midi_event_t,
-    jack_midi_get_event_count, jack_nframes_t, jack_port_get_buffer, jack_port_name,
-    jack_port_register, jack_port_t, jack_port_unregister, jack_ringbuffer_create,
-    jack_ringbuffer_free, jack_ringbuffer_read, jack_ringbuffer_read_space, jack_ringbuffer_t,
-    jack_ringbuffer_write, jack_set_process_callback,
+    jack_deactivate, jack_free, jack_get_ports, jack_get_sample_rate, jack_get_time,
+    jack_last_frame_time, jack_latency_callback_mode_t, jack_latency_range_t,
+    jack_midi_clear_buffer, jack_midi_data_t, jack_midi_event_get, jack_midi_event_reserve,
+    jack_midi_event_t, jack_midi_get_event_count, jack_nframes_t, jack_port_get_buffer,
+    jack_port_get_latency_range, jack_port_id_t, jack_port_name, jack_port_register,
+    jack_port_set_latency_range, jack_port_t, jack_port_unregister, jack_recompute_total_latencies,
+    jack_ringbuffer_create, jack_ringbuffer_free, jack_ringbuffer_read, jack_ringbuffer_read_space,
+    jack_ringbuffer_t, jack_ringbuffer_write, jack_ringbuffer_write_space,
+    jack_set_port_registration_callback, jack_set_process_callback, jack_status_t,
 };
 
 pub const JACK_DEFAULT_MIDI_TYPE: &[u8] = b"8 bit raw midi\0";
@@ -40,24 +43,50 @@ bitflags! {
 // TODO: hide this type
 pub type ProcessCallback = extern "C" fn(nframes: jack_nframes_t, arg: *mut c_void) -> i32;
 
+pub type PortRegistrationCallback =
+    extern "C" fn(port: jack_port_id_t, register: c_int, arg: *mut c_void);
+
 pub struct Client {
     p: *mut jack_client_t,
 }
 
 unsafe impl Send for Client {}
 
+/// Gives the process callback access to the frame time of the client.
+#[derive(Clone, Copy)]
+pub struct FrameClock(*const jack_client_t);
+
+unsafe impl Send for FrameClock {}
+
+impl FrameClock {
+    /// The frame time at the start of the current cycle (only valid in the process callback).
+    pub fn last_frame_time(&self) -> u32 {
+        unsafe { jack_last_frame_time(self.0) }
+    }
+}
+
 impl Client {
     pub fn get_time() -> u64 {
         unsafe { jack_get_time() }
     }
 
-    pub fn open(name: &str, options: JackOpenOptions) -> Result<Client, ()> {
+    pub fn sample_rate(&self) -> u32 {
+        unsafe { jack_get_sample_rate(self.p) as u32 }
+    }
+
+    pub fn frame_clock(&self) -> FrameClock {
+        FrameClock(self.p)
+    }
+
+    /// Opens a client, or returns the status flags that JACK reports if this fails.
+    pub fn open(name: &str, options: JackOpenOptions) -> Result<Client, jack_status_t> {
         let c_name = CString::new(name)
             .ok()
             .expect("client name must not contain null bytes");
-        let result = unsafe { jack_client_open(c_name.as_ptr(), options.bits(), ptr::null_mut()) };
+        let mut status: jack_status_t = 0;
+        let result = unsafe { jack_client_open(c_name.as_ptr(), options.bits(), &mut status) };
         if result.is_null() {
-            Err(())
+            Err(status)
         } else {
             Ok(Client { p: result })
         }
@@ -119,6 +148,11 @@ impl Client {
         unsafe { jack_deactivate(self.p) };
     }
 
+    /// Tell the server to propagate changed port latencies through the graph.
+    pub fn recompute_total_latencies(&mut self) {
+        unsafe { jack_recompute_total_latencies(self.p) };
+    }
+
     /// The code in the supplied function must be suitable for real-time
     /// execution. That means that it cannot call functions that might block
     /// for a long time. This includes all I/O functions (disk, TTY, network),
@@ -128,6 +162,16 @@ impl Client {
         unsafe { jack_set_process_callback(self.p, Some(callback), data) };
     }
 
+    /// The callback is invoked on a notification thread of the client whenever a port
+    /// is registered or unregistered. It must be set before the client is activated.
+    pub fn set_port_registration_callback(
+        &mut self,
+        callback: PortRegistrationCallback,
+        data: *mut c_void,
+    ) {
+        unsafe { jack_set_port_registration_callback(self.p, Some(callback), data) };
+    }
+
     pub fn connect(&mut self, source_port: &CStr, destination_port: &CStr) -> Result<(), ()> {
         let rc = unsafe { jack_connect(self.p, source_port.as_ptr(), destination_port.as_ptr()) };
         if rc == 0 {
@@ -201,6 +245,17 @@ impl MidiPort {
         let buf = unsafe { jack_port_get_buffer(self.p, nframes) };
         MidiBuffer { p: buf }
     }
+
+    pub fn get_latency_range(&self, mode: jack_latency_callback_mode_t) -> (u32, u32) {
+        let mut range = jack_latency_range_t::default();
+        unsafe { jack_port_get_latency_range(self.p, mode, &mut range) };
+        (range.min, range.max)
+    }
+
+    pub fn set_latency_range(&self, mode: jack_latency_callback_mode_t, min: u32, max: u32) {
+        let mut range = jack_latency_range_t { min, max };
+        unsafe { jack_port_set_latency_range(self.p, mode, &mut range) };
+    }
 }
 
 pub struct MidiBuffer {
@@ -245,6 +300,10 @@ impl Ringbuffer {
         unsafe { jack_ringbuffer_read_space(self.p) as usize }
     }
 
+    pub fn get_write_space(&self) -> usize {
+        unsafe { jack_ringbuffer_write_space(self.p) as usize }
+    }
+
     pub fn read(&mut self, destination: *mut u8, count: usize) -> usize {
         let bytes_read =
             unsafe { jack_ringbuffer_read(self.p, destination as *mut _, count as size_t) };
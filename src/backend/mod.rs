@@ -1,6 +1,6 @@
 // This module is not public
 
-// TODO: improve feature selection (make sure that there is always exactly one implementation, or enable dynamic backend selection)
+// TODO: improve feature selection (make sure that there is always exactly one implementation)
 // TODO: allow to disable build dependency on ALSA
 
 #[cfg(all(target_os = "windows", not(feature = "winrt")))]
@@ -23,17 +23,45 @@ mod coremidi;
 #[cfg(all(target_os = "ios", not(feature = "jack")))]
 pub use self::coremidi::*;
 
-#[cfg(all(target_os = "linux", not(feature = "jack")))]
+#[cfg(target_os = "linux")]
 mod alsa;
 #[cfg(all(target_os = "linux", not(feature = "jack")))]
 pub use self::alsa::*;
 
 #[cfg(all(feature = "jack", not(target_os = "windows")))]
 mod jack;
-#[cfg(all(feature = "jack", not(target_os = "windows")))]
+#[cfg(all(feature = "jack", not(target_os = "windows"), not(target_os = "linux")))]
 pub use self::jack::*;
 
+// On Linux, the `jack` feature makes both ALSA and JACK available, selected at runtime
+#[cfg(all(target_os = "linux", feature = "jack"))]
+mod dynamic;
+#[cfg(all(target_os = "linux", feature = "jack"))]
+pub use self::dynamic::*;
+
 #[cfg(target_arch = "wasm32")]
 mod webmidi;
 #[cfg(target_arch = "wasm32")]
 pub use self::webmidi::*;
+
+#[cfg(not(all(target_os = "windows", feature = "winrt")))]
+pub fn enumerate_all() -> (crate::MidiInputPorts, crate::MidiOutputPorts) {
+    const CLIENT_NAME: &str = "midir enumeration";
+    let inputs = MidiInput::new(CLIENT_NAME)
+        .map(|imp| imp.ports_internal())
+        .unwrap_or_default();
+    let outputs = MidiOutput::new(CLIENT_NAME)
+        .map(|imp| imp.ports_internal())
+        .unwrap_or_default();
+    (inputs, outputs)
+}
+
+// The other backends have nothing to reset and query the system anew for every port
+// listing, so it is only checked that the backend can still be initialized
+#[cfg(not(any(
+    all(any(target_os = "macos", target_os = "ios"), not(feature = "jack")),
+    target_arch = "wasm32"
+)))]
+pub fn restart() -> Result<(), crate::InitError> {
+    MidiInput::new("midir restart").map(|_| ())
+}
@@ -7,13 +7,20 @@
 use js_sys::{Map, Promise, Uint8Array};
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsCast;
-use web_sys::{MidiAccess, MidiMessageEvent, MidiOptions};
+use web_sys::{
+    MidiAccess, MidiConnectionEvent, MidiMessageEvent, MidiOptions, MidiPortDeviceState,
+    MidiPortType,
+};
 
 use std::cell::RefCell;
+use std::collections::HashSet;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
 
 use crate::errors::*;
-use crate::Ignore;
+use crate::time::Timestamp;
+use crate::{DeviceEvent, EarlyMessagePolicy, Ignore, InputExecutor, RawTap, SysexMode};
 
 thread_local! {
     static STATIC : RefCell<Static> = RefCell::new(Static::new());
@@ -90,6 +97,30 @@ impl Static {
     }
 }
 
+/// Waits until the MIDI access has been granted or denied, requesting it if that has not
+/// happened yet.
+pub async fn wait_for_access() {
+    let request = STATIC.with(|s| {
+        let mut s = s.borrow_mut();
+        s.request_midi_access(true);
+        s.request.clone()
+    });
+    if let Some(request) = request {
+        // The request resolves after `on_ok` or `on_err` has stored the outcome
+        let _ = wasm_bindgen_futures::JsFuture::from(request).await;
+    }
+}
+
+pub fn restart() -> Result<(), InitError> {
+    STATIC.with(|s| {
+        let mut s = s.borrow_mut();
+        // Drop the current access object, so that the device list is requested anew
+        s.access = None;
+        s.request_midi_access(true);
+    });
+    Ok(())
+}
+
 #[derive(Clone, PartialEq)]
 pub struct MidiInputPort {
     input: web_sys::MidiInput,
@@ -99,20 +130,59 @@ impl MidiInputPort {
     pub fn id(&self) -> String {
         self.input.id()
     }
+
+    pub fn from_id(id: &str) -> Option<Self> {
+        // Ports are objects of the MIDI access, so it must have been granted already
+        STATIC.with(|s| {
+            let s = s.borrow();
+            let inputs: Map = s.access.as_ref()?.inputs().unchecked_into();
+            let input = inputs.get(&JsValue::from_str(id));
+            input.dyn_into().ok().map(|input| MidiInputPort { input })
+        })
+    }
+}
+
+// Messages can't be attributed to a sending port by this backend
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SourceId;
+
+impl SourceId {
+    pub fn port_id(&self) -> Option<String> {
+        None
+    }
 }
 
 pub struct MidiInput {
     ignore_flags: Ignore,
+    early_message_policy: EarlyMessagePolicy,
+    // The Web MIDI API has no notion of clients, so the name is only kept for the user
+    client_name: String,
 }
 
 impl MidiInput {
-    pub fn new(_client_name: &str) -> Result<Self, InitError> {
+    pub fn new(client_name: &str) -> Result<Self, InitError> {
         STATIC.with(|_| {});
         Ok(MidiInput {
             ignore_flags: Ignore::None,
+            early_message_policy: EarlyMessagePolicy::default(),
+            client_name: client_name.to_owned(),
         })
     }
 
+    pub fn client_name(&self) -> &str {
+        &self.client_name
+    }
+
+    pub fn supports_virtual_ports(&self) -> bool {
+        // The Web MIDI API gives access to the devices of the system only
+        false
+    }
+
+    pub fn set_client_name(&mut self, name: &str) -> Result<(), ConnectErrorKind> {
+        self.client_name = name.to_owned();
+        Ok(())
+    }
+
     pub(crate) fn ports_internal(&self) -> Vec<crate::common::MidiInputPort> {
         STATIC.with(|s| {
             let mut v = Vec::new();
@@ -135,6 +205,40 @@ impl MidiInput {
         self.ignore_flags = flags;
     }
 
+    pub fn ignored_flags(&self) -> Ignore {
+        self.ignore_flags
+    }
+
+    pub fn set_sysex_mode(&mut self, _mode: SysexMode) {
+        // Web MIDI always delivers complete sysex messages, so there is nothing to reassemble or stream
+    }
+
+    pub fn set_timestamping(&mut self, _enable: bool) {
+        // Timestamps are provided by the system without extra cost
+    }
+
+    pub fn timestamping(&self) -> bool {
+        true
+    }
+
+    pub fn set_backend_option(&mut self, _key: &str, _value: &str) -> Result<(), ConnectErrorKind> {
+        // There are no options for this backend (yet)
+        Ok(())
+    }
+
+    pub fn raw_timestamp_fn(&self) -> fn(u64) -> u64 {
+        // `DOMHighResTimeStamp` is in (fractional) milliseconds, of which only whole ones are kept
+        |nanos| nanos / 1_000_000
+    }
+
+    pub fn set_max_sysex_size(&mut self, _max: Option<usize>) {
+        // Sysex messages are not reassembled, so there is no buffer that could grow
+    }
+
+    pub fn set_early_message_policy(&mut self, policy: EarlyMessagePolicy) {
+        self.early_message_policy = policy;
+    }
+
     pub fn port_count(&self) -> usize {
         STATIC.with(|s| {
             let s = s.borrow();
@@ -149,6 +253,97 @@ impl MidiInput {
         Ok(port.input.name().unwrap_or_else(|| port.input.id()))
     }
 
+    pub fn watch(
+        &self,
+        callback: Box<dyn FnMut(DeviceEvent<MidiInputPort>) + Send>,
+    ) -> Result<PortWatcher, InitError> {
+        PortWatcher::start(
+            MidiPortType::Input,
+            |access| access.inputs().unchecked_into(),
+            |port| MidiInputPort {
+                input: port.unchecked_into(),
+            },
+            callback,
+        )
+    }
+
+    pub fn port_metadata(
+        &self,
+        port: &MidiInputPort,
+    ) -> Result<crate::PortMetadata, PortInfoError> {
+        // The Web MIDI API has no model or serial number, the name is that of the port
+        Ok(crate::PortMetadata {
+            manufacturer: port.input.manufacturer().filter(|name| !name.is_empty()),
+            ..Default::default()
+        })
+    }
+
+    pub fn device_name(&self, port: &MidiInputPort) -> Option<String> {
+        port.input.name()
+    }
+
+    pub fn is_connectable(&self, port: &MidiInputPort) -> bool {
+        port.input.state() != MidiPortDeviceState::Disconnected
+    }
+
+    pub fn connect_on<F, T: Send + 'static>(
+        self,
+        _executor: &dyn InputExecutor,
+        port: &MidiInputPort,
+        port_name: &str,
+        callback: F,
+        data: T,
+    ) -> Result<MidiInputConnection<T>, ConnectError<MidiInput>>
+    where
+        F: FnMut(u64, &[u8], &mut T) + Send + 'static,
+    {
+        // Input is delivered by callbacks from the browser, so there is no handler loop to run
+        self.connect(port, port_name, callback, data)
+    }
+
+    pub fn connect_with_source<F, T: Send + 'static>(
+        self,
+        port: &MidiInputPort,
+        port_name: &str,
+        mut callback: F,
+        data: T,
+    ) -> Result<MidiInputConnection<T>, ConnectError<MidiInput>>
+    where
+        F: FnMut(u64, &[u8], SourceId, &mut T) + Send + 'static,
+    {
+        self.connect(
+            port,
+            port_name,
+            move |timestamp, message, data| callback(timestamp, message, SourceId, data),
+            data,
+        )
+    }
+
+    pub fn connect_multi<F, T: Send + 'static>(
+        self,
+        ports: &[&MidiInputPort],
+        port_name: &str,
+        mut callback: F,
+        data: T,
+    ) -> Result<MidiInputConnection<T>, ConnectError<MidiInput>>
+    where
+        F: FnMut(u64, &[u8], usize, &mut T) + Send + 'static,
+    {
+        match ports {
+            [port] => self.connect(
+                port,
+                port_name,
+                move |timestamp, message, data| callback(timestamp, message, 0, data),
+                data,
+            ),
+            [] => Err(ConnectError::new(ConnectErrorKind::InvalidPort, self)),
+            _ => Err(ConnectError::other(
+                "connecting to several ports is not supported by this backend",
+                self,
+            )),
+        }
+    }
+
     pub fn connect<F, T: Send + 'static>(
         self,
         port: &MidiInputPort,
@@ -160,30 +355,59 @@ impl MidiInput {
         F: FnMut(u64, &[u8], &mut T) + Send + 'static,
     {
         let input = port.input.clone();
-        let _ = input.open(); // NOTE: asyncronous!
 
         let ignore_flags = self.ignore_flags;
+        let early_message_policy = self.early_message_policy;
         let user_data = Arc::new(Mutex::new(Some(data)));
+        let raw_tap: Arc<Mutex<Option<RawTap>>> = Arc::new(Mutex::new(None));
+        let filtered_count = Arc::new(AtomicU64::new(0));
 
-        let closure = {
+        let deliver: Rc<RefCell<dyn FnMut(u64, &[u8])>> = {
             let user_data = user_data.clone();
+            let raw_tap = raw_tap.clone();
+            let filtered_count = filtered_count.clone();
 
-            let closure = Closure::wrap(Box::new(move |event: MidiMessageEvent| {
-                let time = (event.time_stamp() * 1000.0) as u64; // ms -> us
-                let buffer = event.data().unwrap();
+            Rc::new(RefCell::new(move |time: u64, message: &[u8]| {
+                if let Some(ref mut tap) = *raw_tap.lock().unwrap() {
+                    tap(time, message);
+                }
 
-                let status = buffer[0];
+                let status = message[0];
                 if !(status == 0xF0 && ignore_flags.contains(Ignore::Sysex)
                     || status == 0xF1 && ignore_flags.contains(Ignore::Time)
                     || status == 0xF8 && ignore_flags.contains(Ignore::Time)
                     || status == 0xFE && ignore_flags.contains(Ignore::ActiveSense))
                 {
-                    callback(
-                        time,
-                        &buffer[..],
-                        user_data.lock().unwrap().as_mut().unwrap(),
-                    );
+                    callback(time, message, user_data.lock().unwrap().as_mut().unwrap());
+                } else {
+                    filtered_count.fetch_add(1, Ordering::Relaxed);
                 }
+            }))
+        };
+
+        // Messages that arrive before the port has been opened (which happens asynchronously)
+        // are collected here, unless they are delivered right away
+        let early_messages = Rc::new(RefCell::new(match early_message_policy {
+            EarlyMessagePolicy::Deliver => None,
+            EarlyMessagePolicy::Buffer | EarlyMessagePolicy::Drop => Some(Vec::new()),
+        }));
+
+        let closure = {
+            let deliver = deliver.clone();
+            let early_messages = early_messages.clone();
+
+            let closure = Closure::wrap(Box::new(move |event: MidiMessageEvent| {
+                let _scope = crate::CallbackScope::enter();
+                let time = Timestamp::from_webmidi_millis(event.time_stamp()).as_nanos();
+                let buffer = event.data().unwrap();
+
+                if let Some(ref mut early_messages) = *early_messages.borrow_mut() {
+                    if early_message_policy == EarlyMessagePolicy::Buffer {
+                        early_messages.push((time, buffer));
+                    }
+                    return;
+                }
+                (deliver.borrow_mut())(time, &buffer[..]);
             }) as Box<dyn FnMut(MidiMessageEvent)>);
 
             input.set_onmidimessage(Some(closure.as_ref().unchecked_ref()));
@@ -191,36 +415,159 @@ impl MidiInput {
             closure
         };
 
+        let on_open = Closure::once({
+            let early_messages = early_messages.clone();
+            move |_: JsValue| {
+                let buffered = early_messages.borrow_mut().take();
+                for (time, message) in buffered.into_iter().flatten() {
+                    (deliver.borrow_mut())(time, &message[..]);
+                }
+            }
+        });
+        // The promise is also rejected if the port can't be opened, but then no messages arrive
+        let _ = input.open().then(&on_open);
+
         Ok(MidiInputConnection {
             ignore_flags,
+            early_message_policy,
+            client_name: self.client_name,
             input,
             user_data,
+            raw_tap,
+            filtered_count,
+            early_messages,
             closure,
+            on_open,
         })
     }
 }
 
+/// Watches for ports that appear or disappear, using the `statechange` event of the
+/// MIDI access. The listener is added with `addEventListener` rather than as the
+/// `onstatechange` handler, so that several watchers don't replace each other.
+/// Watching stops when this is dropped.
+pub struct PortWatcher {
+    access: MidiAccess,
+    listener: Closure<dyn FnMut(MidiConnectionEvent)>,
+}
+
+impl PortWatcher {
+    fn start<P: 'static>(
+        port_type: MidiPortType,
+        ports: fn(&MidiAccess) -> Map,
+        port: fn(web_sys::MidiPort) -> P,
+        mut callback: Box<dyn FnMut(DeviceEvent<P>) + Send>,
+    ) -> Result<Self, InitError> {
+        let access = STATIC
+            .with(|s| s.borrow().access.clone())
+            .ok_or_else(|| InitError::new("the MIDI access has not been granted (yet)"))?;
+
+        let mut connected = HashSet::new();
+        ports(&access).for_each(&mut |value, _| {
+            let value: web_sys::MidiPort = value.unchecked_into();
+            if value.state() == MidiPortDeviceState::Connected {
+                connected.insert(value.id());
+            }
+        });
+        let listener = Closure::wrap(Box::new(move |event: MidiConnectionEvent| {
+            let Some(changed) = event.port() else {
+                return;
+            };
+            if changed.type_() != port_type {
+                return;
+            }
+            // The event is also dispatched when a port is opened or closed
+            if changed.state() == MidiPortDeviceState::Connected {
+                if connected.insert(changed.id()) {
+                    callback(DeviceEvent::Added(port(changed)));
+                }
+            } else if connected.remove(&changed.id()) {
+                callback(DeviceEvent::Removed(port(changed)));
+            }
+        }) as Box<dyn FnMut(MidiConnectionEvent)>);
+        access
+            .add_event_listener_with_callback("statechange", listener.as_ref().unchecked_ref())
+            .map_err(|_| InitError::new("could not listen for MIDI state changes"))?;
+
+        Ok(PortWatcher { access, listener })
+    }
+}
+
+impl Drop for PortWatcher {
+    fn drop(&mut self) {
+        let _ = self.access.remove_event_listener_with_callback(
+            "statechange",
+            self.listener.as_ref().unchecked_ref(),
+        );
+    }
+}
+
 pub struct MidiInputConnection<T> {
     ignore_flags: Ignore,
+    early_message_policy: EarlyMessagePolicy,
+    client_name: String,
     input: web_sys::MidiInput,
     user_data: Arc<Mutex<Option<T>>>,
+    raw_tap: Arc<Mutex<Option<RawTap>>>,
+    filtered_count: Arc<AtomicU64>,
+    early_messages: Rc<RefCell<Option<Vec<(u64, Vec<u8>)>>>>,
     #[allow(dead_code)] // Must be kept alive until we decide to unregister from input
     closure: Closure<dyn FnMut(MidiMessageEvent)>,
+    #[allow(dead_code)] // Must be kept alive until the port has been opened
+    on_open: Closure<dyn FnMut(JsValue)>,
 }
 
 impl<T> MidiInputConnection<T> {
+    pub fn backend_name(&self) -> &'static str {
+        "Web MIDI"
+    }
+
+    pub fn set_raw_tap(&mut self, tap: Option<RawTap>) {
+        *self.raw_tap.lock().unwrap() = tap;
+    }
+
+    pub fn clear_input(&mut self) {
+        // The browser dispatches an event for every message, so nothing is buffered on our side
+    }
+
+    pub fn filtered_count(&self) -> u64 {
+        self.filtered_count.load(Ordering::Relaxed)
+    }
+
+    pub fn callback_thread_id(&self) -> Option<std::thread::ThreadId> {
+        None // The callback is invoked on the main thread of the browser
+    }
+
     pub fn close(self) -> (MidiInput, T) {
+        let (input, data, _) = self.close_draining();
+        (input, data)
+    }
+
+    pub fn close_draining(self) -> (MidiInput, T, Vec<(u64, Vec<u8>)>) {
         let Self {
             ignore_flags,
+            early_message_policy,
+            client_name,
             input,
             user_data,
+            early_messages,
             ..
         } = self;
 
         input.set_onmidimessage(None);
         let mut user_data = user_data.lock().unwrap();
-
-        (MidiInput { ignore_flags }, user_data.take().unwrap())
+        // Messages are only buffered until the port has been opened
+        let pending = early_messages.borrow_mut().take().unwrap_or_default();
+
+        (
+            MidiInput {
+                ignore_flags,
+                early_message_policy,
+                client_name,
+            },
+            user_data.take().unwrap(),
+            pending,
+        )
     }
 }
 
@@ -233,14 +580,45 @@ impl MidiOutputPort {
     pub fn id(&self) -> String {
         self.output.id()
     }
+
+    pub fn from_id(id: &str) -> Option<Self> {
+        // Ports are objects of the MIDI access, so it must have been granted already
+        STATIC.with(|s| {
+            let s = s.borrow();
+            let outputs: Map = s.access.as_ref()?.outputs().unchecked_into();
+            let output = outputs.get(&JsValue::from_str(id));
+            output
+                .dyn_into()
+                .ok()
+                .map(|output| MidiOutputPort { output })
+        })
+    }
 }
 
-pub struct MidiOutput {}
+pub struct MidiOutput {
+    client_name: String,
+}
 
 impl MidiOutput {
-    pub fn new(_client_name: &str) -> Result<Self, InitError> {
+    pub fn new(client_name: &str) -> Result<Self, InitError> {
         STATIC.with(|_| {});
-        Ok(MidiOutput {})
+        Ok(MidiOutput {
+            client_name: client_name.to_owned(),
+        })
+    }
+
+    pub fn client_name(&self) -> &str {
+        &self.client_name
+    }
+
+    pub fn supports_virtual_ports(&self) -> bool {
+        // The Web MIDI API gives access to the devices of the system only
+        false
+    }
+
+    pub fn set_client_name(&mut self, name: &str) -> Result<(), ConnectErrorKind> {
+        self.client_name = name.to_owned();
+        Ok(())
     }
 
     pub(crate) fn ports_internal(&self) -> Vec<crate::common::MidiOutputPort> {
@@ -273,10 +651,53 @@ impl MidiOutput {
         })
     }
 
+    pub fn watch(
+        &self,
+        callback: Box<dyn FnMut(DeviceEvent<MidiOutputPort>) + Send>,
+    ) -> Result<PortWatcher, InitError> {
+        PortWatcher::start(
+            MidiPortType::Output,
+            |access| access.outputs().unchecked_into(),
+            |port| MidiOutputPort {
+                output: port.unchecked_into(),
+            },
+            callback,
+        )
+    }
+
     pub fn port_name(&self, port: &MidiOutputPort) -> Result<String, PortInfoError> {
         Ok(port.output.name().unwrap_or_else(|| port.output.id()))
     }
 
+    pub fn port_metadata(
+        &self,
+        port: &MidiOutputPort,
+    ) -> Result<crate::PortMetadata, PortInfoError> {
+        // The Web MIDI API has no model or serial number, the name is that of the port
+        Ok(crate::PortMetadata {
+            manufacturer: port.output.manufacturer().filter(|name| !name.is_empty()),
+            ..Default::default()
+        })
+    }
+
+    pub fn device_name(&self, port: &MidiOutputPort) -> Option<String> {
+        port.output.name()
+    }
+
+    pub fn is_connectable(&self, port: &MidiOutputPort) -> bool {
+        port.output.state() != MidiPortDeviceState::Disconnected
+    }
+
+    pub fn is_synth_port(&self, _port: &MidiOutputPort) -> bool {
+        false // Web MIDI does not distinguish synthesizers from other ports
+    }
+
+    pub fn is_sibling_port(&self, input: &MidiInputPort, output: &MidiOutputPort) -> bool {
+        input.input.name().is_some()
+            && input.input.name() == output.output.name()
+            && input.input.manufacturer() == output.output.manufacturer()
+    }
+
     pub fn connect(
         self,
         port: &MidiOutputPort,
@@ -284,19 +705,52 @@ impl MidiOutput {
     ) -> Result<MidiOutputConnection, ConnectError<MidiOutput>> {
         let _ = port.output.open(); // NOTE: asyncronous!
         Ok(MidiOutputConnection {
+            client_name: self.client_name,
             output: port.output.clone(),
         })
     }
 }
 
 pub struct MidiOutputConnection {
+    client_name: String,
     output: web_sys::MidiOutput,
 }
 
 impl MidiOutputConnection {
+    pub fn backend_name(&self) -> &'static str {
+        "Web MIDI"
+    }
+
+    pub fn supports_sysex(&self) -> Option<bool> {
+        // Sending sysex fails unless the access has been granted with sysex permission
+        STATIC.with(|s| s.borrow().access.as_ref().map(|a| a.sysex_enabled()))
+    }
+
+    pub fn max_sysex_size(&self) -> Option<usize> {
+        None
+    }
+
     pub fn close(self) -> MidiOutput {
         let _ = self.output.close(); // NOTE: asyncronous!
-        MidiOutput {}
+        MidiOutput {
+            client_name: self.client_name,
+        }
+    }
+
+    pub async fn close_async(self) -> MidiOutput {
+        // The promise is rejected if the port cannot be closed, but then there is nothing
+        // left to do for us either
+        let _ = wasm_bindgen_futures::JsFuture::from(self.output.close()).await;
+        MidiOutput {
+            client_name: self.client_name,
+        }
+    }
+
+    pub fn send_all(&mut self, messages: &[&[u8]]) -> Result<(), SendError> {
+        for message in messages {
+            self.send(message)?;
+        }
+        Ok(())
     }
 
     pub fn send(&mut self, message: &[u8]) -> Result<(), SendError> {
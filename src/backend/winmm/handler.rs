@@ -1,14 +1,24 @@
 use std::io::{stderr, Write};
+use std::sync::atomic::Ordering;
 use std::{mem, slice};
 
 use windows::Win32::Media::Audio::{midiInAddBuffer, HMIDIIN, MIDIHDR};
 use windows::Win32::Media::{MMSYSERR_NOERROR, MM_MIM_DATA, MM_MIM_LONGDATA, MM_MIM_LONGERROR};
 
-use crate::Ignore;
+use crate::time::Timestamp;
+use crate::{Ignore, SysexMode};
 
 use super::{DWORD, DWORD_PTR, UINT};
 
-use super::HandlerData;
+use super::{HandlerData, RawEvent};
+
+/// Passes the user data to `f`, unless it has been taken back because shutting
+/// down the port has timed out (see `MidiInputConnection::shut_down`).
+fn with_user_data<T>(user_data: &parking_lot::Mutex<Option<T>>, f: impl FnOnce(&mut T)) {
+    if let Some(user_data) = user_data.lock().as_mut() {
+        f(user_data);
+    }
+}
 
 pub extern "system" fn handle_input<T>(
     _: HMIDIIN,
@@ -25,9 +35,33 @@ pub extern "system" fn handle_input<T>(
     }
 
     let data: &mut HandlerData<T> = unsafe { &mut *(instance_ptr as *mut HandlerData<T>) };
+    let _scope = crate::CallbackScope::enter();
+
+    if let Some(ref mut raw_callback) = data.raw_callback {
+        let header = if input_status == MM_MIM_DATA {
+            None
+        } else {
+            Some(unsafe { &*(midi_message as *const MIDIHDR) })
+        };
+        let event = RawEvent {
+            status: input_status,
+            message: midi_message,
+            timestamp,
+            header,
+        };
+        with_user_data(&data.user_data, |user_data| raw_callback(&event, user_data));
+        if let Some(sysex) = header {
+            requeue_sysex_buffer(data, sysex);
+        }
+        return;
+    }
 
     // Calculate time stamp.
-    data.message.timestamp = timestamp as u64 * 1000; // milliseconds -> microseconds
+    let timestamp = Timestamp::from_winmm_millis(timestamp).as_nanos();
+
+    // A raw tap receives all messages, including those that are ignored
+    let mut raw_tap = data.raw_tap.lock();
+    let mut ignored = false;
 
     if input_status == MM_MIM_DATA {
         // Channel or system message
@@ -45,81 +79,193 @@ pub extern "system" fn handle_input<T>(
         } else if status < 0xF0 {
             3
         } else if status == 0xF1 {
-            if data.ignore_flags.contains(Ignore::Time) {
-                return;
-            } else {
-                2
-            }
+            // A MIDI time code message
+            ignored = data.ignore_flags.contains(Ignore::Time);
+            2
         } else if status == 0xF2 {
             3
         } else if status == 0xF3 {
             2
-        } else if status == 0xF8 && (data.ignore_flags.contains(Ignore::Time)) {
-            // A MIDI timing tick message and we're ignoring it.
-            return;
-        } else if status == 0xFE && (data.ignore_flags.contains(Ignore::ActiveSense)) {
-            // A MIDI active sensing message and we're ignoring it.
-            return;
+        } else if status == 0xF8 {
+            // A MIDI timing tick message
+            ignored = data.ignore_flags.contains(Ignore::Time);
+            1
+        } else if status == 0xFE {
+            // A MIDI active sensing message
+            ignored = data.ignore_flags.contains(Ignore::ActiveSense);
+            1
         } else {
             1
         };
 
-        // Copy bytes to our MIDI message.
+        if ignored {
+            data.filtered_count.fetch_add(1, Ordering::Relaxed);
+            if raw_tap.is_none() {
+                return;
+            }
+        }
+
+        // Short messages are passed on directly from the packed DWORD, so they
+        // never contain bytes of a previous message or of a pending sysex message.
         let ptr = (&midi_message) as *const DWORD_PTR as *const u8;
         let bytes: &[u8] = unsafe { slice::from_raw_parts(ptr, nbytes as usize) };
-        data.message.bytes.extend_from_slice(bytes);
-    } else {
-        // Sysex message (MIM_LONGDATA or MIM_LONGERROR)
-        let sysex = unsafe { &*(midi_message as *const MIDIHDR) };
-        if !data.ignore_flags.contains(Ignore::Sysex) && input_status != MM_MIM_LONGERROR {
-            // Sysex message and we're not ignoring it
-            let bytes: &[u8] =
-                unsafe { slice::from_raw_parts(sysex.lpData.0, sysex.dwBytesRecorded as usize) };
-            data.message.bytes.extend_from_slice(bytes);
-            // TODO: If sysex messages are longer than MIDIR_SYSEX_BUFFER_SIZE, they
-            //       are split in chunks. We could reassemble a single message.
+        if let Some(ref mut tap) = *raw_tap {
+            tap(timestamp, bytes);
         }
+        drop(raw_tap);
+        if !ignored {
+            with_user_data(&data.user_data, |user_data| {
+                (data.callback)(timestamp, bytes, user_data)
+            });
+        }
+        return;
+    }
 
-        // The WinMM API requires that the sysex buffer be requeued after
-        // input of each sysex message.  Even if we are ignoring sysex
-        // messages, we still need to requeue the buffer in case the user
-        // decides to not ignore sysex messages in the future.  However,
-        // it seems that WinMM calls this function with an empty sysex
-        // buffer when an application closes and in this case, we should
-        // avoid requeueing it, else the computer suddenly reboots after
-        // one or two minutes.
-        if (unsafe { *data.sysex_buffer.0[sysex.dwUser] }).dwBytesRecorded > 0 {
-            //if ( sysex->dwBytesRecorded > 0 ) {
-            let in_handle = data.in_handle.as_ref().unwrap().0.lock();
-            let result = unsafe {
-                midiInAddBuffer(
-                    *in_handle,
-                    data.sysex_buffer.0[sysex.dwUser],
-                    mem::size_of::<MIDIHDR>() as u32,
-                )
-            };
-            drop(in_handle);
-            if result != MMSYSERR_NOERROR {
-                let _ = writeln!(
-                    stderr(),
-                    "\nError in handle_input: Requeuing WinMM input sysex buffer failed.\n"
-                );
-            }
-
-            if data.ignore_flags.contains(Ignore::Sysex) {
-                return;
-            }
+    // Sysex message (MIM_LONGDATA or MIM_LONGERROR)
+    let sysex = unsafe { &*(midi_message as *const MIDIHDR) };
+    let chunk: &[u8] =
+        unsafe { slice::from_raw_parts(sysex.lpData.0, sysex.dwBytesRecorded as usize) };
+    ignored = data.ignore_flags.contains(Ignore::Sysex);
+    let mut complete = false;
+    // Real-time messages that are interleaved with the sysex data are passed on separately
+    let mut interleaved = Vec::new();
+    if input_status == MM_MIM_LONGERROR {
+        // The message has been corrupted, so drop what has been received of it
+        data.message.bytes.clear();
+        data.oversized = false;
+    } else if !chunk.is_empty() {
+        let last = crate::is_last_sysex_segment(chunk);
+        if chunk.first() == Some(&0xF0) {
+            // A new message starts, even if the previous one was too long
+            data.oversized = false;
+        }
+        if (!ignored || raw_tap.is_some()) && !data.oversized {
+            // Sysex message and we're not ignoring it (or it is passed to the raw tap)
+            complete = append_sysex_chunk(&mut data.message.bytes, chunk, |status| {
+                interleaved.push(status)
+            });
         } else {
-            return;
+            crate::split_sysex_segment(chunk, None, |status| interleaved.push(status));
         }
+        if data.sysex_mode == SysexMode::Complete
+            && data
+                .max_sysex_size
+                .is_some_and(|max| data.message.bytes.len() > max)
+        {
+            // Drop the message instead of letting the buffer grow without bound
+            data.message.bytes.clear();
+            data.oversized = true;
+        }
+        if data.oversized {
+            ignored = true;
+            data.oversized = !last;
+        }
+        if ignored && last {
+            data.filtered_count.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    // Even if we are ignoring sysex messages, we still need to requeue the
+    // buffer in case the user decides to not ignore sysex messages in the future.
+    if !requeue_sysex_buffer(data, sysex) {
+        return;
+    }
+
+    for status in interleaved {
+        let status_ignored = data.ignore_flags.contains_realtime(status);
+        if status_ignored {
+            data.filtered_count.fetch_add(1, Ordering::Relaxed);
+        }
+        if let Some(ref mut tap) = *raw_tap {
+            tap(timestamp, &[status]);
+        }
+        if !status_ignored {
+            with_user_data(&data.user_data, |user_data| {
+                (data.callback)(timestamp, &[status], user_data)
+            });
+        }
+    }
+
+    // Messages longer than MIDIR_SYSEX_BUFFER_SIZE arrive in several buffers. They are
+    // collected until the last one arrives, unless the segments should be streamed.
+    let streaming = data.sysex_mode == SysexMode::Streaming;
+    if data.message.bytes.is_empty() || (!complete && !streaming) {
+        return;
+    }
+
+    data.message.timestamp = timestamp;
+    if let Some(ref mut tap) = *raw_tap {
+        tap(data.message.timestamp, &data.message.bytes);
     }
+    drop(raw_tap);
 
-    (data.callback)(
-        data.message.timestamp,
-        &data.message.bytes,
-        data.user_data.as_mut().unwrap(),
-    );
+    if !ignored {
+        with_user_data(&data.user_data, |user_data| {
+            (data.callback)(data.message.timestamp, &data.message.bytes, user_data)
+        });
+    }
 
-    // Clear the vector for the next input message.
+    // Clear the vector for the next segment or message.
     data.message.bytes.clear();
 }
+
+/// The WinMM API requires that the sysex buffer be requeued after input of
+/// each sysex message. However, it seems that WinMM calls the handler with an
+/// empty sysex buffer when an application closes and in this case, we should
+/// avoid requeueing it, else the computer suddenly reboots after one or two
+/// minutes. Returns whether the buffer has been requeued.
+fn requeue_sysex_buffer<T>(data: &HandlerData<T>, sysex: &MIDIHDR) -> bool {
+    if (unsafe { *data.sysex_buffer.0[sysex.dwUser] }).dwBytesRecorded > 0 {
+        let in_handle = data.in_handle.as_ref().unwrap().0.lock();
+        let result = unsafe {
+            midiInAddBuffer(
+                *in_handle,
+                data.sysex_buffer.0[sysex.dwUser],
+                mem::size_of::<MIDIHDR>() as u32,
+            )
+        };
+        drop(in_handle);
+        if result != MMSYSERR_NOERROR {
+            let _ = writeln!(
+                stderr(),
+                "\nError in handle_input: Requeuing WinMM input sysex buffer failed.\n"
+            );
+        }
+        true
+    } else {
+        false
+    }
+}
+
+/// Appends a buffer of sysex data to the message that is being received and
+/// returns whether the message is complete. If a new message starts before
+/// the previous one has been completed, the incomplete message is dropped.
+/// Interleaved real-time messages are passed to `realtime` instead.
+fn append_sysex_chunk<F: FnMut(u8)>(message: &mut Vec<u8>, chunk: &[u8], realtime: F) -> bool {
+    if chunk.first() == Some(&0xF0) {
+        message.clear();
+    }
+    crate::split_sysex_segment(chunk, Some(message), realtime);
+    crate::is_last_sysex_segment(chunk)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::winmm::MIDIR_SYSEX_BUFFER_SIZE;
+
+    #[test]
+    fn test_long_sysex() {
+        let mut sysex = vec![0xF0];
+        sysex.extend((0..MIDIR_SYSEX_BUFFER_SIZE * 2).map(|i| (i % 0x80) as u8));
+        sysex.push(0xF7);
+
+        let mut message = vec![0xF0, 0x01]; // incomplete message, dropped
+        let mut chunks = sysex.chunks(MIDIR_SYSEX_BUFFER_SIZE).peekable();
+        while let Some(chunk) = chunks.next() {
+            let complete = append_sysex_chunk(&mut message, chunk, |_| unreachable!());
+            assert_eq!(complete, chunks.peek().is_none());
+        }
+        assert_eq!(message, sysex);
+    }
+}
@@ -2,10 +2,12 @@ use parking_lot::ReentrantMutex as Mutex;
 use std::alloc::{alloc, dealloc, Layout};
 use std::ffi::OsString;
 use std::io::{stderr, Write};
-use std::mem::MaybeUninit;
+use std::mem::{ManuallyDrop, MaybeUninit};
 use std::os::windows::ffi::OsStringExt;
 use std::ptr::null_mut;
-use std::thread::sleep;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc;
+use std::thread::{self, sleep};
 use std::time::Duration;
 use std::{mem, ptr, slice};
 
@@ -15,8 +17,8 @@ use windows::Win32::Media::Audio::{
     midiInPrepareHeader, midiInReset, midiInStart, midiInStop, midiInUnprepareHeader, midiOutClose,
     midiOutGetDevCapsW, midiOutGetNumDevs, midiOutLongMsg, midiOutMessage, midiOutOpen,
     midiOutPrepareHeader, midiOutReset, midiOutShortMsg, midiOutUnprepareHeader, CALLBACK_FUNCTION,
-    CALLBACK_NULL, HMIDIIN, HMIDIOUT, MIDIERR_NOTREADY, MIDIERR_STILLPLAYING, MIDIHDR, MIDIINCAPSW,
-    MIDIOUTCAPSW,
+    CALLBACK_NULL, HMIDIIN, HMIDIOUT, MHDR_INQUEUE, MIDIERR_NOTREADY, MIDIERR_STILLPLAYING,
+    MIDIHDR, MIDIINCAPSW, MIDIOUTCAPSW, MOD_MAPPER, MOD_MIDIPORT,
 };
 use windows::Win32::Media::Multimedia::{DRV_QUERYDEVICEINTERFACE, DRV_QUERYDEVICEINTERFACESIZE};
 use windows::Win32::Media::{MMSYSERR_ALLOCATED, MMSYSERR_BADDEVICEID, MMSYSERR_NOERROR};
@@ -35,7 +37,9 @@ type DWORD = u32;
 type DWORD_PTR = usize;
 
 use crate::errors::*;
-use crate::{Ignore, MidiMessage};
+use crate::{
+    DeviceEvent, EarlyMessagePolicy, Ignore, InputExecutor, MidiMessage, RawTap, SysexMode,
+};
 
 mod handler;
 
@@ -54,9 +58,29 @@ fn from_wide_ptr(ptr: *const u16, max_len: usize) -> OsString {
     }
 }
 
+/// An error when querying a port by its number, which is not exposed
+/// because the public API only deals with port objects.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PortNumberError {
+    OutOfRange,
+    CannotRetrievePortName,
+}
+
+impl From<PortNumberError> for PortInfoError {
+    fn from(err: PortNumberError) -> Self {
+        match err {
+            // The port that had this number is gone
+            PortNumberError::OutOfRange => PortInfoError::InvalidPort,
+            PortNumberError::CannotRetrievePortName => PortInfoError::CannotRetrievePortName,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct MidiInput {
     ignore_flags: Ignore,
+    sysex_mode: SysexMode,
+    max_sysex_size: Option<usize>,
 }
 
 #[derive(Clone)]
@@ -69,6 +93,23 @@ impl MidiInputPort {
     pub fn id(&self) -> String {
         String::from_utf16_lossy(&self.interface_id)
     }
+
+    pub fn from_id(id: &str) -> Option<Self> {
+        // Ports are opened by their number, so the port has to be present to find it
+        (0..Self::count())
+            .filter_map(|i| Self::from_port_number(i).ok())
+            .find(|port| String::from_utf16_lossy(&port.interface_id) == id)
+    }
+}
+
+// Messages can't be attributed to a sending port by this backend
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SourceId;
+
+impl SourceId {
+    pub fn port_id(&self) -> Option<String> {
+        None
+    }
 }
 
 impl PartialEq for MidiInputPort {
@@ -77,8 +118,17 @@ impl PartialEq for MidiInputPort {
     }
 }
 
+/// WinMM has no notifications for ports that appear or disappear, so watching
+/// them always fails (see `MidiInput::watch`).
+pub enum PortWatcher {}
+
 pub struct MidiInputConnection<T> {
-    handler_data: Box<HandlerData<T>>,
+    // Not dropped if shutting down the port times out, because the driver might still use it
+    handler_data: ManuallyDrop<Box<HandlerData<T>>>,
+    close_timeout: Option<Duration>,
+    // Whether the port has been given up on because shutting it down timed out
+    abandoned: bool,
+    receiving: bool,
 }
 
 impl MidiInputPort {
@@ -86,7 +136,7 @@ impl MidiInputPort {
         unsafe { midiInGetNumDevs() }
     }
 
-    fn interface_id(port_number: UINT) -> Result<Box<[u16]>, PortInfoError> {
+    fn interface_id(port_number: UINT) -> Result<Box<[u16]>, PortNumberError> {
         let mut buffer_size: ULONG = 0;
         let result = unsafe {
             midiInMessage(
@@ -97,9 +147,9 @@ impl MidiInputPort {
             )
         };
         if result == MMSYSERR_BADDEVICEID {
-            return Err(PortInfoError::PortNumberOutOfRange);
+            return Err(PortNumberError::OutOfRange);
         } else if result != MMSYSERR_NOERROR {
-            return Err(PortInfoError::CannotRetrievePortName);
+            return Err(PortNumberError::CannotRetrievePortName);
         }
         let mut buffer = Vec::<u16>::with_capacity(buffer_size as usize / 2);
         unsafe {
@@ -110,9 +160,9 @@ impl MidiInputPort {
                 buffer_size as DWORD_PTR,
             );
             if result == MMSYSERR_BADDEVICEID {
-                return Err(PortInfoError::PortNumberOutOfRange);
+                return Err(PortNumberError::OutOfRange);
             } else if result != MMSYSERR_NOERROR {
-                return Err(PortInfoError::CannotRetrievePortName);
+                return Err(PortNumberError::CannotRetrievePortName);
             }
             buffer.set_len(buffer_size as usize / 2);
         }
@@ -120,7 +170,7 @@ impl MidiInputPort {
         Ok(buffer.into_boxed_slice())
     }
 
-    fn name(port_number: UINT) -> Result<String, PortInfoError> {
+    fn name(port_number: UINT) -> Result<String, PortNumberError> {
         let mut device_caps: MaybeUninit<MIDIINCAPSW> = MaybeUninit::uninit();
         let result = unsafe {
             midiInGetDevCapsW(
@@ -130,9 +180,9 @@ impl MidiInputPort {
             )
         };
         if result == MMSYSERR_BADDEVICEID {
-            return Err(PortInfoError::PortNumberOutOfRange);
+            return Err(PortNumberError::OutOfRange);
         } else if result != MMSYSERR_NOERROR {
-            return Err(PortInfoError::CannotRetrievePortName);
+            return Err(PortNumberError::CannotRetrievePortName);
         }
         let device_caps = unsafe { device_caps.assume_init() };
         let pname_ptr: *const [u16; 32] = std::ptr::addr_of!(device_caps.szPname);
@@ -142,7 +192,7 @@ impl MidiInputPort {
         Ok(output)
     }
 
-    fn from_port_number(port_number: UINT) -> Result<Self, PortInfoError> {
+    fn from_port_number(port_number: UINT) -> Result<Self, PortNumberError> {
         Ok(MidiInputPort {
             name: Self::name(port_number)?,
             interface_id: Self::interface_id(port_number)?,
@@ -182,14 +232,45 @@ struct HandlerData<T> {
     sysex_buffer: SysexBuffer,
     in_handle: Option<MidiInHandle>,
     ignore_flags: Ignore,
+    sysex_mode: SysexMode,
+    max_sysex_size: Option<usize>,
+    // Whether the rest of the current sysex message is discarded, because it is too long
+    oversized: bool,
+    filtered_count: AtomicU64,
     callback: Box<dyn FnMut(u64, &[u8], &mut T) + Send + 'static>,
-    user_data: Option<T>,
+    // Receives the messages instead of `callback` if the connection has been made with `connect_raw`
+    raw_callback: Option<RawCallback<T>>,
+    raw_tap: parking_lot::Mutex<Option<RawTap>>,
+    // Locked while it is passed to the callback, so that it can be taken back safely
+    // if shutting down the port times out and the driver might still call the handler
+    user_data: parking_lot::Mutex<Option<T>>,
+}
+
+type RawCallback<T> = Box<dyn FnMut(&RawEvent<'_>, &mut T) + Send + 'static>;
+
+/// A message as it has been passed to the `MidiInProc` callback by WinMM
+/// (see `MidiInput::connect_raw`).
+#[non_exhaustive]
+#[allow(dead_code)] // only read by users of the `unsafe-raw` feature
+pub struct RawEvent<'a> {
+    /// The kind of message (`MIM_DATA`, `MIM_LONGDATA` or `MIM_LONGERROR`).
+    pub status: u32,
+    /// The first parameter: the packed short message for `MIM_DATA`, or a
+    /// pointer to `header` otherwise.
+    pub message: usize,
+    /// The time since the port has been opened, in milliseconds.
+    pub timestamp: u32,
+    /// The sysex buffer for `MIM_LONGDATA` and `MIM_LONGERROR`. It is requeued
+    /// after the callback has returned.
+    pub header: Option<&'a MIDIHDR>,
 }
 
 impl MidiInput {
     pub fn new(_client_name: &str) -> Result<Self, InitError> {
         Ok(MidiInput {
             ignore_flags: Ignore::None,
+            sysex_mode: SysexMode::Complete,
+            max_sysex_size: None,
         })
     }
 
@@ -197,6 +278,52 @@ impl MidiInput {
         self.ignore_flags = flags;
     }
 
+    pub fn ignored_flags(&self) -> Ignore {
+        self.ignore_flags
+    }
+
+    pub fn set_sysex_mode(&mut self, mode: SysexMode) {
+        self.sysex_mode = mode;
+    }
+
+    pub fn set_timestamping(&mut self, _enable: bool) {
+        // Timestamps are provided by the system without extra cost
+    }
+
+    pub fn timestamping(&self) -> bool {
+        true
+    }
+
+    pub fn set_backend_option(&mut self, _key: &str, _value: &str) -> Result<(), ConnectErrorKind> {
+        // There are no options for this backend (yet)
+        Ok(())
+    }
+
+    pub fn raw_timestamp_fn(&self) -> fn(u64) -> u64 {
+        // WinMM timestamps are in milliseconds since the port has been opened
+        |nanos| nanos / 1_000_000
+    }
+
+    pub fn set_max_sysex_size(&mut self, max: Option<usize>) {
+        self.max_sysex_size = max;
+    }
+
+    pub fn set_early_message_policy(&mut self, _policy: EarlyMessagePolicy) {
+        // The device is opened synchronously in `connect`, so no message can arrive early
+    }
+
+    pub fn supports_virtual_ports(&self) -> bool {
+        // WinMM can only open ports of installed drivers
+        false
+    }
+
+    pub fn set_client_name(&mut self, _name: &str) -> Result<(), ConnectErrorKind> {
+        // WinMM has no notion of clients
+        Err(ConnectErrorKind::Other(
+            "renaming the client is not supported by this backend",
+        ))
+    }
+
     pub(crate) fn ports_internal(&self) -> Vec<crate::common::MidiInputPort> {
         let count = MidiInputPort::count();
         let mut result = Vec::with_capacity(count as usize);
@@ -218,16 +345,129 @@ impl MidiInput {
         Ok(port.name.clone())
     }
 
+    pub fn watch(
+        &self,
+        _callback: Box<dyn FnMut(DeviceEvent<MidiInputPort>) + Send>,
+    ) -> Result<PortWatcher, InitError> {
+        Err(InitError::new(
+            "WinMM does not report when ports appear or disappear",
+        ))
+    }
+
+    pub fn port_metadata(
+        &self,
+        port: &MidiInputPort,
+    ) -> Result<crate::PortMetadata, PortInfoError> {
+        port.current_port_number()
+            .ok_or(PortInfoError::InvalidPort)?;
+        // The device capabilities only contain numeric manufacturer and product IDs
+        Ok(crate::PortMetadata::default())
+    }
+
+    pub fn device_name(&self, port: &MidiInputPort) -> Option<String> {
+        // WinMM has no notion of devices, ports of the same device share their name
+        Some(port.name.clone())
+    }
+
+    pub fn is_connectable(&self, _port: &MidiInputPort) -> bool {
+        // Whether another application has opened the device can only be found out by opening it
+        true
+    }
+
+    pub fn connect_on<F, T: Send>(
+        self,
+        _executor: &dyn InputExecutor,
+        port: &MidiInputPort,
+        port_name: &str,
+        callback: F,
+        data: T,
+    ) -> Result<MidiInputConnection<T>, ConnectError<MidiInput>>
+    where
+        F: FnMut(u64, &[u8], &mut T) + Send + 'static,
+    {
+        // Input is delivered by callbacks from WinMM, so there is no handler loop to run
+        self.connect(port, port_name, callback, data)
+    }
+
+    pub fn connect_with_source<F, T: Send>(
+        self,
+        port: &MidiInputPort,
+        port_name: &str,
+        mut callback: F,
+        data: T,
+    ) -> Result<MidiInputConnection<T>, ConnectError<MidiInput>>
+    where
+        F: FnMut(u64, &[u8], SourceId, &mut T) + Send + 'static,
+    {
+        self.connect(
+            port,
+            port_name,
+            move |timestamp, message, data| callback(timestamp, message, SourceId, data),
+            data,
+        )
+    }
+
+    pub fn connect_multi<F, T: Send>(
+        self,
+        ports: &[&MidiInputPort],
+        port_name: &str,
+        mut callback: F,
+        data: T,
+    ) -> Result<MidiInputConnection<T>, ConnectError<MidiInput>>
+    where
+        F: FnMut(u64, &[u8], usize, &mut T) + Send + 'static,
+    {
+        match ports {
+            [port] => self.connect(
+                port,
+                port_name,
+                move |timestamp, message, data| callback(timestamp, message, 0, data),
+                data,
+            ),
+            [] => Err(ConnectError::new(ConnectErrorKind::InvalidPort, self)),
+            _ => Err(ConnectError::other(
+                "connecting to several ports is not supported by this backend",
+                self,
+            )),
+        }
+    }
+
     pub fn connect<F, T: Send>(
         self,
         port: &MidiInputPort,
-        _port_name: &str,
+        port_name: &str,
         callback: F,
         data: T,
     ) -> Result<MidiInputConnection<T>, ConnectError<MidiInput>>
     where
         F: FnMut(u64, &[u8], &mut T) + Send + 'static,
     {
+        self.connect_internal(port, port_name, Box::new(callback), None, data)
+    }
+
+    #[allow(dead_code)] // only used with the `unsafe-raw` feature
+    pub fn connect_raw<F, T: Send>(
+        self,
+        port: &MidiInputPort,
+        port_name: &str,
+        callback: F,
+        data: T,
+    ) -> Result<MidiInputConnection<T>, ConnectError<MidiInput>>
+    where
+        F: FnMut(&RawEvent<'_>, &mut T) + Send + 'static,
+    {
+        let raw_callback = Some(Box::new(callback) as RawCallback<T>);
+        self.connect_internal(port, port_name, Box::new(|_, _, _| {}), raw_callback, data)
+    }
+
+    fn connect_internal<T: Send>(
+        self,
+        port: &MidiInputPort,
+        _port_name: &str,
+        callback: Box<dyn FnMut(u64, &[u8], &mut T) + Send + 'static>,
+        raw_callback: Option<RawCallback<T>>,
+        data: T,
+    ) -> Result<MidiInputConnection<T>, ConnectError<MidiInput>> {
         let port_number = match port.current_port_number() {
             Some(p) => p,
             None => return Err(ConnectError::new(ConnectErrorKind::InvalidPort, self)),
@@ -238,8 +478,14 @@ impl MidiInput {
             sysex_buffer: SysexBuffer([null_mut(); MIDIR_SYSEX_BUFFER_COUNT]),
             in_handle: None,
             ignore_flags: self.ignore_flags,
-            callback: Box::new(callback),
-            user_data: Some(data),
+            sysex_mode: self.sysex_mode,
+            max_sysex_size: self.max_sysex_size,
+            oversized: false,
+            filtered_count: AtomicU64::new(0),
+            callback,
+            raw_callback,
+            raw_tap: parking_lot::Mutex::new(None),
+            user_data: parking_lot::Mutex::new(Some(data)),
         });
 
         let mut in_handle: MaybeUninit<HMIDIIN> = MaybeUninit::uninit();
@@ -332,63 +578,235 @@ impl MidiInput {
             ));
         }
 
-        Ok(MidiInputConnection { handler_data })
+        Ok(MidiInputConnection {
+            handler_data: ManuallyDrop::new(handler_data),
+            close_timeout: None,
+            abandoned: false,
+            receiving: true,
+        })
     }
 }
 
 impl<T> MidiInputConnection<T> {
-    pub fn close(mut self) -> (MidiInput, T) {
-        self.close_internal();
+    pub fn backend_name(&self) -> &'static str {
+        "WinMM"
+    }
+
+    pub fn set_raw_tap(&mut self, tap: Option<RawTap>) {
+        *self.handler_data.raw_tap.lock() = tap;
+    }
+
+    pub fn filtered_count(&self) -> u64 {
+        self.handler_data.filtered_count.load(Ordering::Relaxed)
+    }
+
+    pub fn callback_thread_id(&self) -> Option<std::thread::ThreadId> {
+        None // The callback is invoked on a thread of the WinMM driver
+    }
 
+    pub fn clear_input(&mut self) {
+        // Borrow the fields separately, so that the message can be cleared while holding the lock
+        let handler_data = &mut **self.handler_data;
+        let in_handle_lock = handler_data.in_handle.as_ref().unwrap().0.lock();
+
+        // Reset returns all pending sysex buffers to the callback, which requeues
+        // those that contain data. The others have to be requeued here.
+        unsafe { midiInReset(*in_handle_lock) };
+        // Drop a sysex message that has only been received partially
+        handler_data.message.bytes.clear();
+        handler_data.oversized = false;
+        for i in 0..MIDIR_SYSEX_BUFFER_COUNT {
+            let buffer = handler_data.sysex_buffer.0[i];
+            if unsafe { (*buffer).dwFlags } & MHDR_INQUEUE != 0 {
+                continue;
+            }
+            let result = unsafe {
+                midiInAddBuffer(*in_handle_lock, buffer, mem::size_of::<MIDIHDR>() as u32)
+            };
+            if result != MMSYSERR_NOERROR {
+                let _ = writeln!(
+                    stderr(),
+                    "Warning: Requeuing Windows MM input sysex buffer failed."
+                );
+            }
+        }
+        if self.receiving {
+            unsafe { midiInStart(*in_handle_lock) };
+        }
+    }
+
+    pub fn pause(&mut self) -> Result<(), ConnectErrorKind> {
+        if !self.receiving {
+            return Ok(());
+        }
+        let handler_data = &mut **self.handler_data;
+        let in_handle_lock = handler_data.in_handle.as_ref().unwrap().0.lock();
+        if unsafe { midiInStop(*in_handle_lock) } != MMSYSERR_NOERROR {
+            return Err(ConnectErrorKind::Other(
+                "could not stop Windows MM MIDI input port",
+            ));
+        }
+        // Don't continue a sysex message with data received after resuming
+        handler_data.message.bytes.clear();
+        handler_data.oversized = false;
+        self.receiving = false;
+        Ok(())
+    }
+
+    pub fn resume(&mut self) -> Result<(), ConnectErrorKind> {
+        if self.receiving {
+            return Ok(());
+        }
+        let in_handle_lock = self.handler_data.in_handle.as_ref().unwrap().0.lock();
+        if unsafe { midiInStart(*in_handle_lock) } != MMSYSERR_NOERROR {
+            return Err(ConnectErrorKind::Other(
+                "could not start Windows MM MIDI input port",
+            ));
+        }
+        self.receiving = true;
+        Ok(())
+    }
+
+    pub fn is_receiving(&self) -> bool {
+        self.receiving
+    }
+
+    pub fn close(self) -> (MidiInput, T) {
+        let (input, data, _) = self.close_draining();
+        (input, data)
+    }
+
+    pub fn close_draining(mut self) -> (MidiInput, T, Vec<(u64, Vec<u8>)>) {
+        let mut pending = Vec::new();
+        // If the port has been abandoned, the handler might still be using the message
+        if self.shut_down() {
+            // `midiInReset` returns the sysex buffers to the handler first, so their data is
+            // included in a sysex message whose end has not arrived yet, which is all that
+            // can be left over
+            let message = &mut self.handler_data.message;
+            if !message.bytes.is_empty() {
+                pending.push((message.timestamp, mem::take(&mut message.bytes)));
+            }
+        }
         (
             MidiInput {
                 ignore_flags: self.handler_data.ignore_flags,
+                sysex_mode: self.handler_data.sysex_mode,
+                max_sysex_size: self.handler_data.max_sysex_size,
             },
-            self.handler_data.user_data.take().unwrap(),
+            self.handler_data.user_data.lock().take().unwrap(),
+            pending,
         )
     }
 
+    pub fn set_close_timeout(&mut self, timeout: Option<Duration>) {
+        self.close_timeout = timeout;
+    }
+
+    /// Shuts down the port, giving up after the close timeout (if there is one).
+    /// Returns `false` if the port has been abandoned because of this.
+    fn shut_down(&mut self) -> bool {
+        let closed = match self.close_timeout {
+            Some(timeout) => self.close_internal_timeout(timeout),
+            None => {
+                self.close_internal();
+                true
+            }
+        };
+        self.abandoned = !closed;
+        closed
+    }
+
     fn close_internal(&mut self) {
-        // for information about this lock, see https://groups.google.com/forum/#!topic/mididev/6OUjHutMpEo
-        let in_handle_lock = self.handler_data.in_handle.as_ref().unwrap().0.lock();
+        close_port(
+            self.handler_data.in_handle.as_ref().unwrap(),
+            &self.handler_data.sysex_buffer,
+        );
+    }
 
-        // TODO: Call both reset and stop here? The difference seems to be that
-        //       reset "returns all pending input buffers to the callback function"
-        unsafe {
-            midiInReset(*in_handle_lock);
-            midiInStop(*in_handle_lock);
+    /// Shuts down the port on a separate thread, because some drivers hang in `midiInReset`.
+    /// Returns `false` if this did not finish within `timeout`.
+    fn close_internal_timeout(&mut self, timeout: Duration) -> bool {
+        struct Shutdown(*const MidiInHandle, SysexBuffer);
+        unsafe impl Send for Shutdown {}
+
+        let shutdown = Shutdown(
+            self.handler_data.in_handle.as_ref().unwrap(),
+            SysexBuffer(self.handler_data.sysex_buffer.0),
+        );
+        let (tx, rx) = mpsc::channel();
+        let spawned = thread::Builder::new()
+            .name("midir WinMM input shutdown".into())
+            .spawn(move || {
+                let shutdown = shutdown;
+                // The handler data is leaked if we give up waiting, so the handle stays valid
+                close_port(unsafe { &*shutdown.0 }, &shutdown.1);
+                let _ = tx.send(());
+            });
+        if spawned.is_err() {
+            self.close_internal();
+            return true;
         }
 
-        for i in 0..MIDIR_SYSEX_BUFFER_COUNT {
-            let result;
-            unsafe {
-                result = midiInUnprepareHeader(
-                    *in_handle_lock,
-                    self.handler_data.sysex_buffer.0[i],
-                    mem::size_of::<MIDIHDR>() as u32,
-                );
-                dealloc(
-                    (*self.handler_data.sysex_buffer.0[i]).lpData.0 as *mut _,
-                    Layout::from_size_align_unchecked(MIDIR_SYSEX_BUFFER_SIZE, 1),
-                );
-                // recreate the Box so that it will be dropped/deallocated at the end of this scope
-                let _ = Box::from_raw(self.handler_data.sysex_buffer.0[i]);
-            }
+        if rx.recv_timeout(timeout).is_ok() {
+            true
+        } else {
+            let _ = writeln!(
+                stderr(),
+                "Warning: Shutting down Windows MM input port timed out, leaving it open."
+            );
+            false
+        }
+    }
+}
 
-            if result != MMSYSERR_NOERROR {
-                let _ = writeln!(stderr(), "Warning: Ignoring error shutting down Windows MM input port (UnprepareHeader).");
-            }
+fn close_port(in_handle: &MidiInHandle, sysex_buffer: &SysexBuffer) {
+    // for information about this lock, see https://groups.google.com/forum/#!topic/mididev/6OUjHutMpEo
+    let in_handle_lock = in_handle.0.lock();
+
+    // TODO: Call both reset and stop here? The difference seems to be that
+    //       reset "returns all pending input buffers to the callback function"
+    unsafe {
+        midiInReset(*in_handle_lock);
+        midiInStop(*in_handle_lock);
+    }
+
+    for i in 0..MIDIR_SYSEX_BUFFER_COUNT {
+        let result;
+        unsafe {
+            result = midiInUnprepareHeader(
+                *in_handle_lock,
+                sysex_buffer.0[i],
+                mem::size_of::<MIDIHDR>() as u32,
+            );
+            dealloc(
+                (*sysex_buffer.0[i]).lpData.0 as *mut _,
+                Layout::from_size_align_unchecked(MIDIR_SYSEX_BUFFER_SIZE, 1),
+            );
+            // recreate the Box so that it will be dropped/deallocated at the end of this scope
+            let _ = Box::from_raw(sysex_buffer.0[i]);
         }
 
-        unsafe { midiInClose(*in_handle_lock) };
+        if result != MMSYSERR_NOERROR {
+            let _ = writeln!(
+                stderr(),
+                "Warning: Ignoring error shutting down Windows MM input port (UnprepareHeader)."
+            );
+        }
     }
+
+    unsafe { midiInClose(*in_handle_lock) };
 }
 
 impl<T> Drop for MidiInputConnection<T> {
     fn drop(&mut self) {
         // If user_data has been emptied, we know that we already have closed the connection
-        if self.handler_data.user_data.is_some() {
-            self.close_internal()
+        if self.handler_data.user_data.lock().is_some() {
+            self.shut_down();
+        }
+        // An abandoned port might still be used by the driver, so its handler data is leaked
+        if !self.abandoned {
+            unsafe { ManuallyDrop::drop(&mut self.handler_data) };
         }
     }
 }
@@ -406,6 +824,13 @@ impl MidiOutputPort {
     pub fn id(&self) -> String {
         String::from_utf16_lossy(&self.interface_id)
     }
+
+    pub fn from_id(id: &str) -> Option<Self> {
+        // Ports are opened by their number, so the port has to be present to find it
+        (0..Self::count())
+            .filter_map(|i| Self::from_port_number(i).ok())
+            .find(|port| String::from_utf16_lossy(&port.interface_id) == id)
+    }
 }
 
 impl PartialEq for MidiOutputPort {
@@ -425,7 +850,7 @@ impl MidiOutputPort {
         unsafe { midiOutGetNumDevs() }
     }
 
-    fn interface_id(port_number: UINT) -> Result<Box<[u16]>, PortInfoError> {
+    fn interface_id(port_number: UINT) -> Result<Box<[u16]>, PortNumberError> {
         let mut buffer_size: ULONG = 0;
         let result = unsafe {
             midiOutMessage(
@@ -436,9 +861,9 @@ impl MidiOutputPort {
             )
         };
         if result == MMSYSERR_BADDEVICEID {
-            return Err(PortInfoError::PortNumberOutOfRange);
+            return Err(PortNumberError::OutOfRange);
         } else if result != MMSYSERR_NOERROR {
-            return Err(PortInfoError::CannotRetrievePortName);
+            return Err(PortNumberError::CannotRetrievePortName);
         }
         let mut buffer = Vec::<u16>::with_capacity(buffer_size as usize / 2);
         unsafe {
@@ -449,9 +874,9 @@ impl MidiOutputPort {
                 buffer_size as DWORD_PTR,
             );
             if result == MMSYSERR_BADDEVICEID {
-                return Err(PortInfoError::PortNumberOutOfRange);
+                return Err(PortNumberError::OutOfRange);
             } else if result != MMSYSERR_NOERROR {
-                return Err(PortInfoError::CannotRetrievePortName);
+                return Err(PortNumberError::CannotRetrievePortName);
             }
             buffer.set_len(buffer_size as usize / 2);
         }
@@ -459,7 +884,7 @@ impl MidiOutputPort {
         Ok(buffer.into_boxed_slice())
     }
 
-    fn name(port_number: UINT) -> Result<String, PortInfoError> {
+    fn name(port_number: UINT) -> Result<String, PortNumberError> {
         let mut device_caps: MaybeUninit<MIDIOUTCAPSW> = MaybeUninit::uninit();
         let result = unsafe {
             midiOutGetDevCapsW(
@@ -469,9 +894,9 @@ impl MidiOutputPort {
             )
         };
         if result == MMSYSERR_BADDEVICEID {
-            return Err(PortInfoError::PortNumberOutOfRange);
+            return Err(PortNumberError::OutOfRange);
         } else if result != MMSYSERR_NOERROR {
-            return Err(PortInfoError::CannotRetrievePortName);
+            return Err(PortNumberError::CannotRetrievePortName);
         }
         let device_caps = unsafe { device_caps.assume_init() };
         let pname_ptr: *const [u16; 32] = std::ptr::addr_of!(device_caps.szPname);
@@ -481,7 +906,24 @@ impl MidiOutputPort {
         Ok(output)
     }
 
-    fn from_port_number(port_number: UINT) -> Result<Self, PortInfoError> {
+    fn is_synth(port_number: UINT) -> bool {
+        let mut device_caps: MaybeUninit<MIDIOUTCAPSW> = MaybeUninit::uninit();
+        let result = unsafe {
+            midiOutGetDevCapsW(
+                port_number as usize,
+                device_caps.as_mut_ptr(),
+                mem::size_of::<MIDIOUTCAPSW>() as u32,
+            )
+        };
+        if result != MMSYSERR_NOERROR {
+            return false;
+        }
+        let device_caps = unsafe { device_caps.assume_init() };
+        let technology = device_caps.wTechnology as u32;
+        technology != MOD_MIDIPORT && technology != MOD_MAPPER
+    }
+
+    fn from_port_number(port_number: UINT) -> Result<Self, PortNumberError> {
         Ok(MidiOutputPort {
             name: Self::name(port_number)?,
             interface_id: Self::interface_id(port_number)?,
@@ -510,6 +952,18 @@ impl MidiOutput {
         Ok(MidiOutput)
     }
 
+    pub fn supports_virtual_ports(&self) -> bool {
+        // WinMM can only open ports of installed drivers
+        false
+    }
+
+    pub fn set_client_name(&mut self, _name: &str) -> Result<(), ConnectErrorKind> {
+        // WinMM has no notion of clients
+        Err(ConnectErrorKind::Other(
+            "renaming the client is not supported by this backend",
+        ))
+    }
+
     pub(crate) fn ports_internal(&self) -> Vec<crate::common::MidiOutputPort> {
         let count = MidiOutputPort::count();
         let mut result = Vec::with_capacity(count as usize);
@@ -531,6 +985,44 @@ impl MidiOutput {
         Ok(port.name.clone())
     }
 
+    pub fn watch(
+        &self,
+        _callback: Box<dyn FnMut(DeviceEvent<MidiOutputPort>) + Send>,
+    ) -> Result<PortWatcher, InitError> {
+        Err(InitError::new(
+            "WinMM does not report when ports appear or disappear",
+        ))
+    }
+
+    pub fn port_metadata(
+        &self,
+        port: &MidiOutputPort,
+    ) -> Result<crate::PortMetadata, PortInfoError> {
+        port.current_port_number()
+            .ok_or(PortInfoError::InvalidPort)?;
+        // The device capabilities only contain numeric manufacturer and product IDs
+        Ok(crate::PortMetadata::default())
+    }
+
+    pub fn device_name(&self, port: &MidiOutputPort) -> Option<String> {
+        // WinMM has no notion of devices, ports of the same device share their name
+        Some(port.name.clone())
+    }
+
+    pub fn is_connectable(&self, _port: &MidiOutputPort) -> bool {
+        // Whether another application has opened the device can only be found out by opening it
+        true
+    }
+
+    pub fn is_synth_port(&self, port: &MidiOutputPort) -> bool {
+        port.current_port_number()
+            .map_or(false, MidiOutputPort::is_synth)
+    }
+
+    pub fn is_sibling_port(&self, input: &MidiInputPort, output: &MidiOutputPort) -> bool {
+        input.name == output.name
+    }
+
     pub fn connect(
         self,
         port: &MidiOutputPort,
@@ -568,11 +1060,40 @@ impl MidiOutput {
 }
 
 impl MidiOutputConnection {
+    pub fn backend_name(&self) -> &'static str {
+        "WinMM"
+    }
+
+    pub fn supports_sysex(&self) -> Option<bool> {
+        // The device capabilities (`MIDIOUTCAPS`) do not include sysex support
+        None
+    }
+
+    pub fn max_sysex_size(&self) -> Option<usize> {
+        None
+    }
+
     pub fn close(self) -> MidiOutput {
         // The actual closing is done by the implementation of Drop
         MidiOutput // In this API this is a noop
     }
 
+    pub fn send_all(&mut self, messages: &[&[u8]]) -> Result<(), SendError> {
+        for message in messages {
+            self.send(message)?;
+        }
+        Ok(())
+    }
+
+    pub fn send_at(
+        &mut self,
+        _message: &[u8],
+        _delay: std::time::Duration,
+    ) -> Result<(), SendError> {
+        // WinMM sends every message immediately
+        Err(SendError::Other("scheduled send not supported"))
+    }
+
     pub fn send(&mut self, message: &[u8]) -> Result<(), SendError> {
         let nbytes = message.len();
         if nbytes == 0 {
@@ -1,17 +1,218 @@
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 
 use crate::errors::*;
-use crate::Ignore;
+use crate::time::Timestamp;
+use crate::{DeviceEvent, EarlyMessagePolicy, Ignore, InputExecutor, RawTap, SysexMode};
 
-use windows::core::HSTRING;
+use windows::core::{IInspectable, Interface, HSTRING};
 
 use windows::{
-    Devices::Enumeration::DeviceInformation,
+    Devices::Enumeration::{
+        DeviceInformation, DeviceInformationCollection, DeviceInformationKind,
+        DeviceInformationUpdate, DeviceWatcher,
+    },
     Devices::Midi::*,
-    Foundation::{EventRegistrationToken, TypedEventHandler},
+    Foundation::Collections::IIterable,
+    Foundation::{EventRegistrationToken, IAsyncOperation, IPropertyValue, TypedEventHandler},
     Storage::Streams::{DataReader, DataWriter},
 };
 
+/// Finds all devices that match the given selector. This fails with "access
+/// denied" if the app lacks the capability to access MIDI devices.
+fn find_all_devices(selector: &HSTRING) -> windows::core::Result<DeviceInformationCollection> {
+    DeviceInformation::FindAllAsyncAqsFilter(selector)?.get()
+}
+
+const CONTAINER_ID_PROPERTY: &str = "System.Devices.ContainerId";
+const MANUFACTURER_PROPERTY: &str = "System.Devices.Manufacturer";
+const MODEL_PROPERTY: &str = "System.Devices.ModelName";
+
+/// Looks up the manufacturer and model of the device that the port with the given ID
+/// belongs to. These are properties of the device container, not of the port (which is
+/// a device interface), so the container is looked up by its ID first.
+fn device_metadata(id: &HSTRING) -> Result<crate::PortMetadata, PortInfoError> {
+    fn property_names(names: &[&str]) -> windows::core::Result<IIterable<HSTRING>> {
+        let names: Vec<HSTRING> = names.iter().map(|&name| HSTRING::from(name)).collect();
+        IIterable::try_from(names)
+    }
+
+    fn property(info: &DeviceInformation, name: &str) -> Option<IPropertyValue> {
+        let value = info.Properties().ok()?.Lookup(&HSTRING::from(name)).ok()?;
+        value.cast().ok()
+    }
+
+    let names = property_names(&[CONTAINER_ID_PROPERTY])
+        .map_err(|_| PortInfoError::CannotRetrievePortName)?;
+    let port_info = DeviceInformation::CreateFromIdAsyncAdditionalProperties(id, &names)
+        .and_then(|op| op.get())
+        .map_err(|_| PortInfoError::InvalidPort)?;
+    let container_id =
+        match property(&port_info, CONTAINER_ID_PROPERTY).and_then(|value| value.GetGuid().ok()) {
+            // Container IDs are formatted like `{xxxxxxxx-xxxx-xxxx-xxxx-xxxxxxxxxxxx}`
+            Some(guid) => HSTRING::from(format!("{{{:?}}}", guid)),
+            None => return Ok(crate::PortMetadata::default()),
+        };
+    let container_info = property_names(&[MANUFACTURER_PROPERTY, MODEL_PROPERTY])
+        .and_then(|names| {
+            DeviceInformation::CreateFromIdAsyncWithKindAndAdditionalProperties(
+                &container_id,
+                &names,
+                DeviceInformationKind::DeviceContainer,
+            )
+        })
+        .and_then(|op| op.get())
+        .ok();
+    let string_property = |name| {
+        let value = property(container_info.as_ref()?, name)?;
+        value.GetString().ok().map(|s| s.to_string())
+    };
+    Ok(crate::PortMetadata {
+        manufacturer: string_property(MANUFACTURER_PROPERTY),
+        product: string_property(MODEL_PROPERTY),
+        serial: None,
+    })
+}
+
+pub fn enumerate_all() -> (crate::MidiInputPorts, crate::MidiOutputPorts) {
+    type Enumeration = windows::core::Result<IAsyncOperation<DeviceInformationCollection>>;
+
+    fn find_all(selector: windows::core::Result<HSTRING>) -> Enumeration {
+        selector.and_then(|selector| DeviceInformation::FindAllAsyncAqsFilter(&selector))
+    }
+
+    fn device_ids(enumeration: Enumeration) -> Vec<HSTRING> {
+        enumeration
+            .and_then(|enumeration| enumeration.get())
+            .map(|collection| {
+                collection
+                    .into_iter()
+                    .filter_map(|device_info| device_info.Id().ok())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    // Start both enumerations before waiting for either, so that they run concurrently
+    let inputs = find_all(MidiInPort::GetDeviceSelector());
+    let outputs = find_all(MidiOutPort::GetDeviceSelector());
+    (
+        device_ids(inputs)
+            .into_iter()
+            .map(|id| crate::common::MidiInputPort {
+                imp: MidiInputPort { id },
+            })
+            .collect(),
+        device_ids(outputs)
+            .into_iter()
+            .map(|id| crate::common::MidiOutputPort {
+                imp: MidiOutputPort { id },
+            })
+            .collect(),
+    )
+}
+
+/// Whether a received message is filtered out by the ignore flags. WinRT delivers
+/// every message on its own, so only its status byte has to be checked, which is
+/// done the same way as by the backends that split the incoming data themselves.
+fn is_ignored(ignore: Ignore, message: &[u8]) -> bool {
+    match message.first() {
+        Some(0xF0) => ignore.contains(Ignore::Sysex),
+        Some(0xF1) => ignore.contains(Ignore::Time),
+        Some(&status) => ignore.contains_realtime(status),
+        None => false,
+    }
+}
+
+/// Passes a message that has been received by WinRT on to the callback, reassembling
+/// sysex messages that arrive in several segments.
+fn handle_message<T>(timestamp: u64, message_bytes: &[u8], handler_data: &mut HandlerData<T>) {
+    let ignore = handler_data.ignore_flags;
+    let data = &mut handler_data.user_data.as_mut().unwrap();
+    // Large sysex messages arrive in several segments, of which only the first starts
+    // with 0xF0. Any other status byte (except for real-time messages) ends the message.
+    let status = message_bytes[0];
+    if handler_data.continue_sysex && (0x80..0xF8).contains(&status) && status != 0xF7 {
+        handler_data.continue_sysex = false;
+        handler_data.sysex.clear();
+    }
+    if status < 0x80 && !handler_data.continue_sysex {
+        // Stray data bytes, e.g. the rest of an interrupted sysex message, are dropped
+        return;
+    }
+    if status != 0xF0 && !handler_data.continue_sysex {
+        if let Some(ref mut tap) = handler_data.raw_tap {
+            tap(timestamp, message_bytes);
+        }
+        if !is_ignored(ignore, message_bytes) {
+            (handler_data.callback)(timestamp, message_bytes, data);
+        } else {
+            handler_data.filtered_count += 1;
+        }
+        return;
+    }
+
+    if status == 0xF0 {
+        // An unterminated message that is still in the buffer is dropped
+        handler_data.sysex.clear();
+        handler_data.sysex_timestamp = timestamp;
+        handler_data.oversized = false;
+    }
+    let streaming = handler_data.sysex_mode == SysexMode::Streaming;
+    let mut ignored = ignore.contains(Ignore::Sysex);
+    handler_data.continue_sysex = !crate::is_last_sysex_segment(message_bytes);
+
+    // If we're not ignoring sysex messages (or need to pass them to the raw tap),
+    // append the segment, except for interleaved real-time messages
+    let sysex = ((!ignored || handler_data.raw_tap.is_some()) && !handler_data.oversized)
+        .then_some(&mut handler_data.sysex);
+    let filtered_count = &mut handler_data.filtered_count;
+    let raw_tap = &mut handler_data.raw_tap;
+    let callback = &mut handler_data.callback;
+    crate::split_sysex_segment(message_bytes, sysex, |status| {
+        let status_ignored = ignore.contains_realtime(status);
+        if status_ignored {
+            *filtered_count += 1;
+        }
+        if let Some(ref mut tap) = *raw_tap {
+            tap(timestamp, &[status]);
+        }
+        if !status_ignored {
+            callback(timestamp, &[status], data);
+        }
+    });
+    if !streaming
+        && handler_data
+            .max_sysex_size
+            .is_some_and(|max| handler_data.sysex.len() > max)
+    {
+        // Drop the message instead of letting the buffer grow without bound
+        handler_data.sysex.clear();
+        handler_data.oversized = true;
+    }
+    if handler_data.oversized {
+        ignored = true;
+        handler_data.oversized = handler_data.continue_sysex;
+    }
+    if ignored && !handler_data.continue_sysex {
+        // Segmented sysex messages are counted with their last segment
+        handler_data.filtered_count += 1;
+    }
+
+    // Pass the message on when it is complete, or every segment as it
+    // arrives if it is streamed
+    if (!handler_data.continue_sysex || streaming) && !handler_data.sysex.is_empty() {
+        let timestamp = handler_data.sysex_timestamp;
+        if let Some(ref mut tap) = handler_data.raw_tap {
+            tap(timestamp, &handler_data.sysex);
+        }
+        if !ignored {
+            (handler_data.callback)(timestamp, &handler_data.sysex, data);
+        }
+        handler_data.sysex.clear();
+    }
+}
+
 #[derive(Clone, PartialEq)]
 pub struct MidiInputPort {
     id: HSTRING,
@@ -21,19 +222,44 @@ impl MidiInputPort {
     pub fn id(&self) -> String {
         self.id.to_string_lossy()
     }
+
+    pub fn from_id(id: &str) -> Option<Self> {
+        // The ID is the device ID, which is all that is needed to open the port
+        Some(MidiInputPort {
+            id: HSTRING::from(id),
+        })
+    }
+}
+
+// Messages can't be attributed to a sending port by this backend
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SourceId;
+
+impl SourceId {
+    pub fn port_id(&self) -> Option<String> {
+        None
+    }
 }
 
 pub struct MidiInput {
     selector: HSTRING,
     ignore_flags: Ignore,
+    sysex_mode: SysexMode,
+    max_sysex_size: Option<usize>,
 }
 
 impl MidiInput {
     pub fn new(_client_name: &str) -> Result<Self, InitError> {
-        let device_selector = MidiInPort::GetDeviceSelector().map_err(|_| InitError)?;
+        let device_selector = MidiInPort::GetDeviceSelector().map_err(InitError::with_source)?;
+        // Enumerate once, so that an app that is not allowed to access MIDI devices
+        // (e.g. a packaged app without the required capability) fails here, instead
+        // of silently finding no ports later
+        find_all_devices(&device_selector).map_err(InitError::with_source)?;
         Ok(MidiInput {
             selector: device_selector,
             ignore_flags: Ignore::None,
+            sysex_mode: SysexMode::Complete,
+            max_sysex_size: None,
         })
     }
 
@@ -41,33 +267,77 @@ impl MidiInput {
         self.ignore_flags = flags;
     }
 
+    pub fn ignored_flags(&self) -> Ignore {
+        self.ignore_flags
+    }
+
+    pub fn set_sysex_mode(&mut self, mode: SysexMode) {
+        self.sysex_mode = mode;
+    }
+
+    pub fn set_timestamping(&mut self, _enable: bool) {
+        // Timestamps are provided by the system without extra cost
+    }
+
+    pub fn timestamping(&self) -> bool {
+        true
+    }
+
+    pub fn set_backend_option(&mut self, _key: &str, _value: &str) -> Result<(), ConnectErrorKind> {
+        // There are no options for this backend (yet)
+        Ok(())
+    }
+
+    pub fn raw_timestamp_fn(&self) -> fn(u64) -> u64 {
+        // `TimeSpan` is in ticks of 100 nanoseconds
+        |nanos| nanos / 100
+    }
+
+    pub fn set_max_sysex_size(&mut self, max: Option<usize>) {
+        self.max_sysex_size = max;
+    }
+
+    pub fn set_early_message_policy(&mut self, _policy: EarlyMessagePolicy) {
+        // `connect` waits until the port has been opened before it registers the handler,
+        // so no message can arrive early
+    }
+
+    pub fn supports_virtual_ports(&self) -> bool {
+        // WinRT can only open ports of devices
+        false
+    }
+
+    pub fn set_client_name(&mut self, _name: &str) -> Result<(), ConnectErrorKind> {
+        // WinRT has no notion of clients
+        Err(ConnectErrorKind::Other(
+            "renaming the client is not supported by this backend",
+        ))
+    }
+
     pub(crate) fn ports_internal(&self) -> Vec<crate::common::MidiInputPort> {
-        let device_collection = DeviceInformation::FindAllAsyncAqsFilter(&self.selector)
-            .unwrap()
-            .get()
-            .expect("FindAllAsyncAqsFilter failed");
-        let count = device_collection.Size().expect("Size failed") as usize;
-        let mut result = Vec::with_capacity(count);
-        for device_info in device_collection.into_iter() {
-            let device_id = device_info.Id().expect("Id failed");
-            result.push(crate::common::MidiInputPort {
+        let device_collection = match find_all_devices(&self.selector) {
+            Ok(device_collection) => device_collection,
+            Err(_) => return Vec::new(),
+        };
+        device_collection
+            .into_iter()
+            .filter_map(|device_info| device_info.Id().ok())
+            .map(|device_id| crate::common::MidiInputPort {
                 imp: MidiInputPort { id: device_id },
-            });
-        }
-        result
+            })
+            .collect()
     }
 
     pub fn port_count(&self) -> usize {
-        let device_collection = DeviceInformation::FindAllAsyncAqsFilter(&self.selector)
-            .unwrap()
-            .get()
-            .expect("FindAllAsyncAqsFilter failed");
-        device_collection.Size().expect("Size failed") as usize
+        find_all_devices(&self.selector)
+            .and_then(|device_collection| device_collection.Size())
+            .map_or(0, |size| size as usize)
     }
 
     pub fn port_name(&self, port: &MidiInputPort) -> Result<String, PortInfoError> {
+        // Failing to start the lookup is a problem of the runtime, not of the port
         let device_info_async = DeviceInformation::CreateFromIdAsync(&port.id)
-            .map_err(|_| PortInfoError::InvalidPort)?;
+            .map_err(|_| PortInfoError::CannotRetrievePortName)?;
         let device_info = device_info_async
             .get()
             .map_err(|_| PortInfoError::InvalidPort)?;
@@ -77,28 +347,104 @@ impl MidiInput {
         Ok(device_name.to_string())
     }
 
+    pub fn watch(
+        &self,
+        callback: Box<dyn FnMut(DeviceEvent<MidiInputPort>) + Send>,
+    ) -> Result<PortWatcher, InitError> {
+        PortWatcher::start(&self.selector, |id| MidiInputPort { id }, callback)
+    }
+
+    pub fn port_metadata(
+        &self,
+        port: &MidiInputPort,
+    ) -> Result<crate::PortMetadata, PortInfoError> {
+        device_metadata(&port.id)
+    }
+
+    pub fn device_name(&self, port: &MidiInputPort) -> Option<String> {
+        // The device name is shared by the input and output ports of a device
+        self.port_name(port).ok()
+    }
+
+    pub fn is_connectable(&self, _port: &MidiInputPort) -> bool {
+        true // WinRT does not report whether a device is in use without opening it
+    }
+
+    /// Reads the timestamp (in nanoseconds) and the bytes of a received message.
+    fn read_message(args: &MidiMessageReceivedEventArgs) -> windows::core::Result<(u64, Vec<u8>)> {
+        let message = args.Message()?;
+        let timestamp = Timestamp::from_winrt_ticks(message.Timestamp()?.Duration).as_nanos();
+        let buffer = message.RawData()?;
+        let mut message_bytes = vec![0; buffer.Length()? as usize];
+        DataReader::FromBuffer(&buffer)?.ReadBytes(&mut message_bytes)?;
+        Ok((timestamp, message_bytes))
+    }
+
     fn handle_input<T>(args: &MidiMessageReceivedEventArgs, handler_data: &mut HandlerData<T>) {
-        let ignore = handler_data.ignore_flags;
-        let data = &mut handler_data.user_data.as_mut().unwrap();
-        let message = args.Message().expect("Message failed");
-        let timestamp = message.Timestamp().expect("Timestamp failed").Duration as u64 / 10;
-        let buffer = message.RawData().expect("RawData failed");
-        let length = buffer.Length().expect("Length failed") as usize;
-        let data_reader = DataReader::FromBuffer(&buffer).expect("FromBuffer failed");
-        let mut message_bytes = vec![0; length];
-        data_reader
-            .ReadBytes(&mut message_bytes)
-            .expect("ReadBytes failed");
-
-        // The first byte in the message is the status
-        let status = message_bytes[0];
-
-        if !(status == 0xF0 && ignore.contains(Ignore::Sysex)
-            || status == 0xF1 && ignore.contains(Ignore::Time)
-            || status == 0xF8 && ignore.contains(Ignore::Time)
-            || status == 0xFE && ignore.contains(Ignore::ActiveSense))
-        {
-            (handler_data.callback)(timestamp, &message_bytes, data);
+        let _scope = crate::CallbackScope::enter();
+        // Messages that can't be read are dropped, since panicking here would abort the process
+        if let Ok((timestamp, message_bytes)) = Self::read_message(args) {
+            if !message_bytes.is_empty() {
+                handle_message(timestamp, &message_bytes, handler_data);
+            }
+        }
+    }
+
+    pub fn connect_on<F, T: Send + 'static>(
+        self,
+        _executor: &dyn InputExecutor,
+        port: &MidiInputPort,
+        port_name: &str,
+        callback: F,
+        data: T,
+    ) -> Result<MidiInputConnection<T>, ConnectError<MidiInput>>
+    where
+        F: FnMut(u64, &[u8], &mut T) + Send + 'static,
+    {
+        // Input is delivered by callbacks from WinRT, so there is no handler loop to run
+        self.connect(port, port_name, callback, data)
+    }
+
+    pub fn connect_with_source<F, T: Send + 'static>(
+        self,
+        port: &MidiInputPort,
+        port_name: &str,
+        mut callback: F,
+        data: T,
+    ) -> Result<MidiInputConnection<T>, ConnectError<MidiInput>>
+    where
+        F: FnMut(u64, &[u8], SourceId, &mut T) + Send + 'static,
+    {
+        self.connect(
+            port,
+            port_name,
+            move |timestamp, message, data| callback(timestamp, message, SourceId, data),
+            data,
+        )
+    }
+
+    pub fn connect_multi<F, T: Send + 'static>(
+        self,
+        ports: &[&MidiInputPort],
+        port_name: &str,
+        mut callback: F,
+        data: T,
+    ) -> Result<MidiInputConnection<T>, ConnectError<MidiInput>>
+    where
+        F: FnMut(u64, &[u8], usize, &mut T) + Send + 'static,
+    {
+        match ports {
+            [port] => self.connect(
+                port,
+                port_name,
+                move |timestamp, message, data| callback(timestamp, message, 0, data),
+                data,
+            ),
+            [] => Err(ConnectError::new(ConnectErrorKind::InvalidPort, self)),
+            _ => Err(ConnectError::other(
+                "connecting to several ports is not supported by this backend",
+                self,
+            )),
         }
     }
 
@@ -122,7 +468,15 @@ impl MidiInput {
 
         let handler_data = Arc::new(Mutex::new(HandlerData {
             ignore_flags: self.ignore_flags,
+            sysex_mode: self.sysex_mode,
+            max_sysex_size: self.max_sysex_size,
+            sysex: Vec::new(),
+            sysex_timestamp: 0,
+            continue_sysex: false,
+            oversized: false,
+            filtered_count: 0,
             callback: Box::new(callback),
+            raw_tap: None,
             user_data: Some(data),
         }));
         let handler_data2 = handler_data.clone();
@@ -130,21 +484,26 @@ impl MidiInput {
         type Handler = TypedEventHandler<MidiInPort, MidiMessageReceivedEventArgs>;
         let handler = Handler::new(
             move |_sender, args: &Option<MidiMessageReceivedEventArgs>| {
-                MidiInput::handle_input(
-                    args.as_ref()
-                        .expect("MidiMessageReceivedEventArgs were null"),
-                    &mut *handler_data2.lock().unwrap(),
-                );
+                if let Some(args) = args {
+                    MidiInput::handle_input(args, &mut *handler_data2.lock().unwrap());
+                }
                 Ok(())
             },
         );
-        let event_token = in_port
-            .MessageReceived(&handler)
-            .expect("MessageReceived failed");
+        let event_token = match in_port.MessageReceived(&handler) {
+            Ok(event_token) => event_token,
+            Err(_) => {
+                return Err(ConnectError::other(
+                    "could not register the MIDI message handler",
+                    self,
+                ))
+            }
+        };
 
         Ok(MidiInputConnection {
             port: RtMidiInPort(in_port),
             event_token,
+            selector: self.selector,
             handler_data,
         })
     }
@@ -153,9 +512,92 @@ impl MidiInput {
 struct RtMidiInPort(MidiInPort);
 unsafe impl Send for RtMidiInPort {}
 
+/// Watches for ports that appear or disappear, using a `DeviceWatcher` with the
+/// device selector of the ports. Watching stops when this is dropped.
+pub struct PortWatcher {
+    watcher: DeviceWatcher,
+    added_token: EventRegistrationToken,
+    removed_token: EventRegistrationToken,
+}
+
+impl PortWatcher {
+    fn start<P: 'static>(
+        selector: &HSTRING,
+        port: fn(HSTRING) -> P,
+        callback: Box<dyn FnMut(DeviceEvent<P>) + Send>,
+    ) -> Result<Self, InitError> {
+        let watcher =
+            DeviceInformation::CreateWatcherAqsFilter(selector).map_err(InitError::with_source)?;
+        let callback = Arc::new(Mutex::new(callback));
+        // The devices that are already present are reported as added until the
+        // enumeration is complete, which must not be passed on
+        let enumerated = Arc::new(AtomicBool::new(false));
+
+        let added_callback = callback.clone();
+        let added_enumerated = enumerated.clone();
+        let added_token = watcher
+            .Added(&TypedEventHandler::<DeviceWatcher, DeviceInformation>::new(
+                move |_watcher, info: &Option<DeviceInformation>| {
+                    if let Some(info) = info {
+                        if added_enumerated.load(Ordering::SeqCst) {
+                            (*added_callback.lock().unwrap())(DeviceEvent::Added(port(info.Id()?)));
+                        }
+                    }
+                    Ok(())
+                },
+            ))
+            .map_err(InitError::with_source)?;
+        let removed_token = watcher
+            .Removed(
+                &TypedEventHandler::<DeviceWatcher, DeviceInformationUpdate>::new(
+                    move |_watcher, update: &Option<DeviceInformationUpdate>| {
+                        if let Some(update) = update {
+                            (*callback.lock().unwrap())(DeviceEvent::Removed(port(update.Id()?)));
+                        }
+                        Ok(())
+                    },
+                ),
+            )
+            .map_err(InitError::with_source)?;
+        // Without a handler for updates, devices that are added later are not reported
+        watcher
+            .Updated(
+                &TypedEventHandler::<DeviceWatcher, DeviceInformationUpdate>::new(
+                    |_watcher, _update: &Option<DeviceInformationUpdate>| Ok(()),
+                ),
+            )
+            .map_err(InitError::with_source)?;
+        watcher
+            .EnumerationCompleted(&TypedEventHandler::<DeviceWatcher, IInspectable>::new(
+                move |_watcher, _args: &Option<IInspectable>| {
+                    enumerated.store(true, Ordering::SeqCst);
+                    Ok(())
+                },
+            ))
+            .map_err(InitError::with_source)?;
+        watcher.Start().map_err(InitError::with_source)?;
+
+        Ok(PortWatcher {
+            watcher,
+            added_token,
+            removed_token,
+        })
+    }
+}
+
+impl Drop for PortWatcher {
+    fn drop(&mut self) {
+        let _ = self.watcher.RemoveAdded(self.added_token);
+        let _ = self.watcher.RemoveRemoved(self.removed_token);
+        let _ = self.watcher.Stop();
+    }
+}
+
 pub struct MidiInputConnection<T> {
     port: RtMidiInPort,
     event_token: EventRegistrationToken,
+    // The device selector of the `MidiInput` that is returned by `close`
+    selector: HSTRING,
     // TODO: get rid of Arc & Mutex?
     //       synchronization is required because the borrow checker does not
     //       know that the callback we're in here is never called concurrently
@@ -164,19 +606,58 @@ pub struct MidiInputConnection<T> {
 }
 
 impl<T> MidiInputConnection<T> {
+    pub fn backend_name(&self) -> &'static str {
+        "WinRT"
+    }
+
+    pub fn set_raw_tap(&mut self, tap: Option<RawTap>) {
+        self.handler_data.lock().unwrap().raw_tap = tap;
+    }
+
+    pub fn set_close_timeout(&mut self, _timeout: Option<std::time::Duration>) {
+        // TODO: closing a WinRT port might also block, but there is no way to abandon it
+    }
+
+    pub fn clear_input(&mut self) {
+        // WinRT raises an event for every message, so nothing is buffered on our side
+    }
+
+    pub fn filtered_count(&self) -> u64 {
+        self.handler_data.lock().unwrap().filtered_count
+    }
+
+    pub fn callback_thread_id(&self) -> Option<std::thread::ThreadId> {
+        None // The callback is invoked on a thread of the WinRT thread pool
+    }
+
     pub fn close(self) -> (MidiInput, T) {
         let _ = self.port.0.RemoveMessageReceived(self.event_token);
-        self.port.0.Close().expect("failed to close MidiInput");
-        let device_selector = MidiInPort::GetDeviceSelector().expect("GetDeviceSelector failed"); // probably won't ever fail here, because it worked previously
+        let _ = self.port.0.Close();
         let mut handler_data_locked = self.handler_data.lock().unwrap();
         (
             MidiInput {
-                selector: device_selector,
+                selector: self.selector,
                 ignore_flags: handler_data_locked.ignore_flags,
+                sysex_mode: handler_data_locked.sysex_mode,
+                max_sysex_size: handler_data_locked.max_sysex_size,
             },
             handler_data_locked.user_data.take().unwrap(),
         )
     }
+
+    pub fn close_draining(self) -> (MidiInput, T, Vec<(u64, Vec<u8>)>) {
+        // Only a sysex message whose end has not arrived yet can be left over
+        let mut pending = Vec::new();
+        {
+            let mut handler_data_locked = self.handler_data.lock().unwrap();
+            if handler_data_locked.continue_sysex && !handler_data_locked.sysex.is_empty() {
+                let timestamp = handler_data_locked.sysex_timestamp;
+                pending.push((timestamp, std::mem::take(&mut handler_data_locked.sysex)));
+            }
+        }
+        let (input, data) = self.close();
+        (input, data, pending)
+    }
 }
 
 /// This is all the data that is stored on the heap as long as a connection
@@ -186,7 +667,17 @@ impl<T> MidiInputConnection<T> {
 /// offsets after monomorphization.
 struct HandlerData<T> {
     ignore_flags: Ignore,
+    sysex_mode: SysexMode,
+    max_sysex_size: Option<usize>,
+    // The segments of the sysex message that is currently received
+    sysex: Vec<u8>,
+    // The timestamp of the first segment
+    sysex_timestamp: u64,
+    continue_sysex: bool,
+    oversized: bool,
+    filtered_count: u64,
     callback: Box<dyn FnMut(u64, &[u8], &mut T) + Send>,
+    raw_tap: Option<RawTap>,
     user_data: Option<T>,
 }
 
@@ -199,6 +690,13 @@ impl MidiOutputPort {
     pub fn id(&self) -> String {
         self.id.to_string_lossy()
     }
+
+    pub fn from_id(id: &str) -> Option<Self> {
+        // The ID is the device ID, which is all that is needed to open the port
+        Some(MidiOutputPort {
+            id: HSTRING::from(id),
+        })
+    }
 }
 
 pub struct MidiOutput {
@@ -207,39 +705,52 @@ pub struct MidiOutput {
 
 impl MidiOutput {
     pub fn new(_client_name: &str) -> Result<Self, InitError> {
-        let device_selector = MidiOutPort::GetDeviceSelector().map_err(|_| InitError)?;
+        let device_selector = MidiOutPort::GetDeviceSelector().map_err(InitError::with_source)?;
+        // Enumerate once, so that an app that is not allowed to access MIDI devices
+        // (e.g. a packaged app without the required capability) fails here, instead
+        // of silently finding no ports later
+        find_all_devices(&device_selector).map_err(InitError::with_source)?;
         Ok(MidiOutput {
             selector: device_selector,
         })
     }
 
+    pub fn supports_virtual_ports(&self) -> bool {
+        // WinRT can only open ports of devices
+        false
+    }
+
+    pub fn set_client_name(&mut self, _name: &str) -> Result<(), ConnectErrorKind> {
+        // WinRT has no notion of clients
+        Err(ConnectErrorKind::Other(
+            "renaming the client is not supported by this backend",
+        ))
+    }
+
     pub(crate) fn ports_internal(&self) -> Vec<crate::common::MidiOutputPort> {
-        let device_collection = DeviceInformation::FindAllAsyncAqsFilter(&self.selector)
-            .unwrap()
-            .get()
-            .expect("FindAllAsyncAqsFilter failed");
-        let count = device_collection.Size().expect("Size failed") as usize;
-        let mut result = Vec::with_capacity(count);
-        for device_info in device_collection.into_iter() {
-            let device_id = device_info.Id().expect("Id failed");
-            result.push(crate::common::MidiOutputPort {
+        let device_collection = match find_all_devices(&self.selector) {
+            Ok(device_collection) => device_collection,
+            Err(_) => return Vec::new(),
+        };
+        device_collection
+            .into_iter()
+            .filter_map(|device_info| device_info.Id().ok())
+            .map(|device_id| crate::common::MidiOutputPort {
                 imp: MidiOutputPort { id: device_id },
-            });
-        }
-        result
+            })
+            .collect()
     }
 
     pub fn port_count(&self) -> usize {
-        let device_collection = DeviceInformation::FindAllAsyncAqsFilter(&self.selector)
-            .unwrap()
-            .get()
-            .expect("FindAllAsyncAqsFilter failed");
-        device_collection.Size().expect("Size failed") as usize
+        find_all_devices(&self.selector)
+            .and_then(|device_collection| device_collection.Size())
+            .map_or(0, |size| size as usize)
     }
 
     pub fn port_name(&self, port: &MidiOutputPort) -> Result<String, PortInfoError> {
+        // Failing to start the lookup is a problem of the runtime, not of the port
         let device_info_async = DeviceInformation::CreateFromIdAsync(&port.id)
-            .map_err(|_| PortInfoError::InvalidPort)?;
+            .map_err(|_| PortInfoError::CannotRetrievePortName)?;
         let device_info = device_info_async
             .get()
             .map_err(|_| PortInfoError::InvalidPort)?;
@@ -249,6 +760,49 @@ impl MidiOutput {
         Ok(device_name.to_string())
     }
 
+    pub fn watch(
+        &self,
+        callback: Box<dyn FnMut(DeviceEvent<MidiOutputPort>) + Send>,
+    ) -> Result<PortWatcher, InitError> {
+        PortWatcher::start(&self.selector, |id| MidiOutputPort { id }, callback)
+    }
+
+    pub fn port_metadata(
+        &self,
+        port: &MidiOutputPort,
+    ) -> Result<crate::PortMetadata, PortInfoError> {
+        device_metadata(&port.id)
+    }
+
+    pub fn device_name(&self, port: &MidiOutputPort) -> Option<String> {
+        // The device name is shared by the input and output ports of a device
+        self.port_name(port).ok()
+    }
+
+    pub fn is_connectable(&self, _port: &MidiOutputPort) -> bool {
+        true // WinRT does not report whether a device is in use without opening it
+    }
+
+    pub fn is_synth_port(&self, port: &MidiOutputPort) -> bool {
+        DeviceInformation::CreateFromIdAsync(&port.id)
+            .and_then(|device_info_async| device_info_async.get())
+            .and_then(|device_info| MidiSynthesizer::IsSynthesizer(&device_info))
+            .unwrap_or(false)
+    }
+
+    pub fn is_sibling_port(&self, input: &MidiInputPort, output: &MidiOutputPort) -> bool {
+        let device_name = |id: &HSTRING| {
+            DeviceInformation::CreateFromIdAsync(id)
+                .and_then(|device_info_async| device_info_async.get())
+                .and_then(|device_info| device_info.Name())
+                .ok()
+        };
+        match (device_name(&input.id), device_name(&output.id)) {
+            (Some(input_name), Some(output_name)) => input_name == output_name,
+            _ => false,
+        }
+    }
+
     pub fn connect(
         self,
         port: &MidiOutputPort,
@@ -261,31 +815,71 @@ impl MidiOutput {
             },
             Err(_) => return Err(ConnectError::new(ConnectErrorKind::InvalidPort, self)),
         };
-        Ok(MidiOutputConnection { port: out_port })
+        let writer = match DataWriter::new() {
+            Ok(writer) => writer,
+            Err(_) => return Err(ConnectError::other("could not create DataWriter", self)),
+        };
+        Ok(MidiOutputConnection {
+            port: out_port,
+            writer,
+            selector: self.selector,
+        })
     }
 }
 
 pub struct MidiOutputConnection {
     port: IMidiOutPort,
+    // Reused for all messages, since detaching its buffer leaves it ready for the next one
+    writer: DataWriter,
+    // The device selector of the `MidiOutput` that is returned by `close`
+    selector: HSTRING,
 }
 
 unsafe impl Send for MidiOutputConnection {}
 
 impl MidiOutputConnection {
+    pub fn backend_name(&self) -> &'static str {
+        "WinRT"
+    }
+
+    pub fn supports_sysex(&self) -> Option<bool> {
+        // `MidiOutPort` does not expose any capabilities of the device
+        None
+    }
+
+    pub fn max_sysex_size(&self) -> Option<usize> {
+        None
+    }
+
     pub fn close(self) -> MidiOutput {
-        self.port.Close().expect("failed to close MidiOutput");
-        let device_selector = MidiOutPort::GetDeviceSelector().expect("GetDeviceSelector failed"); // probably won't ever fail here, because it worked previously
+        let _ = self.port.Close();
         MidiOutput {
-            selector: device_selector,
+            selector: self.selector,
         }
     }
 
+    pub fn send_at(
+        &mut self,
+        _message: &[u8],
+        _delay: std::time::Duration,
+    ) -> Result<(), SendError> {
+        // WinRT sends every buffer immediately, the timestamp of a message is ignored
+        Err(SendError::Other("scheduled send not supported"))
+    }
+
     pub fn send(&mut self, message: &[u8]) -> Result<(), SendError> {
-        let data_writer = DataWriter::new().unwrap();
-        data_writer
-            .WriteBytes(message)
-            .map_err(|_| SendError::Other("WriteBytes failed"))?;
-        let buffer = data_writer
+        self.send_all(&[message])
+    }
+
+    pub fn send_all(&mut self, messages: &[&[u8]]) -> Result<(), SendError> {
+        // All messages are sent in one buffer, which saves a round trip per message
+        for message in messages {
+            self.writer
+                .WriteBytes(message)
+                .map_err(|_| SendError::Other("WriteBytes failed"))?;
+        }
+        let buffer = self
+            .writer
             .DetachBuffer()
             .map_err(|_| SendError::Other("DetachBuffer failed"))?;
         self.port
@@ -294,3 +888,109 @@ impl MidiOutputConnection {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_ignored() {
+        let messages: [&[u8]; 8] = [
+            &[0x90, 0x3C, 0x7F],
+            &[0xF0, 0x7E, 0x01, 0xF7],
+            &[0xF1, 0x21],
+            &[0xF2, 0x00, 0x10],
+            &[0xF8],
+            &[0xF9],
+            &[0xFA],
+            &[0xFE],
+        ];
+        let filtered = |ignore| {
+            messages
+                .iter()
+                .map(|message| is_ignored(ignore, message))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(filtered(Ignore::None), [false; 8]);
+        assert_eq!(
+            filtered(Ignore::Sysex),
+            [false, true, false, false, false, false, false, false]
+        );
+        assert_eq!(
+            filtered(Ignore::Time),
+            [false, false, true, false, true, true, false, false]
+        );
+        assert_eq!(
+            filtered(Ignore::ActiveSense),
+            [false, false, false, false, false, false, false, true]
+        );
+        assert_eq!(
+            filtered(Ignore::All),
+            [false, true, true, false, true, true, false, true]
+        );
+        assert!(!is_ignored(Ignore::All, &[]));
+    }
+
+    fn handler_data(sysex_mode: SysexMode) -> HandlerData<Vec<(u64, Vec<u8>)>> {
+        HandlerData {
+            ignore_flags: Ignore::Time,
+            sysex_mode,
+            max_sysex_size: None,
+            sysex: Vec::new(),
+            sysex_timestamp: 0,
+            continue_sysex: false,
+            oversized: false,
+            filtered_count: 0,
+            callback: Box::new(|timestamp, message, received: &mut Vec<_>| {
+                received.push((timestamp, message.to_vec()))
+            }),
+            raw_tap: None,
+            user_data: Some(Vec::new()),
+        }
+    }
+
+    #[test]
+    fn test_sysex_reassembly() {
+        let segments: [&[u8]; 3] = [
+            &[0xF0, 0x7E, 0x01],
+            &[0x02, 0xF8, 0xFA, 0x03],
+            &[0x04, 0xF7],
+        ];
+
+        let mut data = handler_data(SysexMode::Complete);
+        for (timestamp, segment) in segments.iter().enumerate() {
+            handle_message(timestamp as u64, segment, &mut data);
+        }
+        handle_message(3, &[0x90, 0x3C, 0x7F], &mut data);
+        assert_eq!(
+            data.user_data.unwrap(),
+            [
+                (1, vec![0xFA]),
+                (0, vec![0xF0, 0x7E, 0x01, 0x02, 0x03, 0x04, 0xF7]),
+                (3, vec![0x90, 0x3C, 0x7F]),
+            ]
+        );
+        assert_eq!(data.filtered_count, 1);
+
+        let mut data = handler_data(SysexMode::Streaming);
+        for segment in segments {
+            handle_message(0, segment, &mut data);
+        }
+        assert_eq!(
+            data.user_data.unwrap(),
+            [
+                (0, vec![0xF0, 0x7E, 0x01]),
+                (0, vec![0xFA]),
+                (0, vec![0x02, 0x03]),
+                (0, vec![0x04, 0xF7]),
+            ]
+        );
+
+        // A message that is interrupted by another one is dropped
+        let mut data = handler_data(SysexMode::Complete);
+        handle_message(0, &[0xF0, 0x01], &mut data);
+        handle_message(1, &[0x90, 0x3C, 0x7F], &mut data);
+        handle_message(2, &[0x02, 0xF7], &mut data);
+        assert_eq!(data.user_data.unwrap(), [(1, vec![0x90, 0x3C, 0x7F])]);
+    }
+}
@@ -0,0 +1,383 @@
+//! Basic support for MIDI Capability Inquiry (MIDI-CI) discovery.
+//!
+//! MIDI-CI devices identify themselves with a random 28-bit MUID and report
+//! their identity and supported profiles in response to universal sysex
+//! inquiries. This module can build the inquiries and parse the replies,
+//! which is enough to take part in MIDI-CI using any input and output
+//! connection. `discover` performs the complete handshake over a pair of
+//! ports.
+//!
+//! Only the discovery and profile inquiry messages of MIDI-CI version 1.1
+//! are supported (devices implementing newer versions must reply to them).
+
+use std::error::Error;
+use std::fmt;
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::mpsc;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::{Duration, Instant};
+
+use crate::{ConnectErrorKind, SendError};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::{MidiInput, MidiInputPort, MidiOutputConnection};
+
+/// The MUID that addresses all devices.
+pub const BROADCAST_MUID: u32 = 0x0FFF_FFFF;
+
+const UNIVERSAL_NON_REALTIME: u8 = 0x7E;
+const TO_WHOLE_PORT: u8 = 0x7F;
+const SUB_ID_CI: u8 = 0x0D;
+const DISCOVERY: u8 = 0x70;
+const DISCOVERY_REPLY: u8 = 0x71;
+const PROFILE_INQUIRY: u8 = 0x20;
+const PROFILE_INQUIRY_REPLY: u8 = 0x21;
+const CI_VERSION: u8 = 0x01;
+const CATEGORY_PROFILE_CONFIGURATION: u8 = 0x04;
+const HEADER_LEN: usize = 14;
+
+/// The identity of a MIDI-CI device (as in the MIDI identity reply).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DeviceIdentity {
+    /// The sysex manufacturer ID (one-byte IDs are followed by two zeros).
+    pub manufacturer: [u8; 3],
+    /// The device family (14 bits).
+    pub family: u16,
+    /// The model number within the family (14 bits).
+    pub model: u16,
+    /// The software revision level.
+    pub version: [u8; 4],
+}
+
+/// The five-byte identifier of a MIDI-CI profile.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ProfileId(pub [u8; 5]);
+
+/// A parsed MIDI-CI reply (see `parse`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CiReply {
+    /// A device answered a discovery inquiry.
+    Discovery {
+        /// The MUID of the responding device.
+        source: u32,
+        /// The MUID the reply is addressed to.
+        destination: u32,
+        /// The identity of the responding device.
+        identity: DeviceIdentity,
+        /// The MIDI-CI categories supported by the device (a bit field).
+        categories: u8,
+        /// The largest sysex message the device can receive.
+        max_sysex_size: u32,
+    },
+    /// A device answered a profile inquiry.
+    Profiles {
+        /// The MUID of the responding device.
+        source: u32,
+        /// The MUID the reply is addressed to.
+        destination: u32,
+        /// The profiles that are currently enabled.
+        enabled: Vec<ProfileId>,
+        /// The profiles that are supported, but currently disabled.
+        disabled: Vec<ProfileId>,
+    },
+}
+
+/// A device that has been found by `discover`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Responder {
+    /// The MUID of the device.
+    pub muid: u32,
+    /// The identity of the device.
+    pub identity: DeviceIdentity,
+    /// The enabled and disabled profiles of the device (empty if the device
+    /// does not support profile configuration or did not reply in time).
+    pub profiles: Vec<ProfileId>,
+}
+
+fn push_7bit(message: &mut Vec<u8>, value: u32, bytes: usize) {
+    for i in 0..bytes {
+        message.push(((value >> (7 * i)) & 0x7F) as u8);
+    }
+}
+
+fn read_7bit(data: &[u8]) -> u32 {
+    data.iter()
+        .enumerate()
+        .fold(0, |value, (i, &b)| value | ((b as u32 & 0x7F) << (7 * i)))
+}
+
+fn header(sub_id: u8, source: u32, destination: u32) -> Vec<u8> {
+    let mut message = vec![
+        0xF0,
+        UNIVERSAL_NON_REALTIME,
+        TO_WHOLE_PORT,
+        SUB_ID_CI,
+        sub_id,
+        CI_VERSION,
+    ];
+    push_7bit(&mut message, source, 4);
+    push_7bit(&mut message, destination, 4);
+    message
+}
+
+fn push_identity(message: &mut Vec<u8>, identity: &DeviceIdentity) {
+    message.extend_from_slice(&identity.manufacturer);
+    push_7bit(message, identity.family as u32, 2);
+    push_7bit(message, identity.model as u32, 2);
+    message.extend_from_slice(&identity.version);
+}
+
+/// Build a discovery inquiry, which is broadcast to all devices.
+/// `muid` is the (random) MUID of the sender, `max_sysex_size` the
+/// largest sysex message it can receive.
+pub fn discovery_message(muid: u32, identity: &DeviceIdentity, max_sysex_size: u32) -> Vec<u8> {
+    let mut message = header(DISCOVERY, muid, BROADCAST_MUID);
+    push_identity(&mut message, identity);
+    message.push(CATEGORY_PROFILE_CONFIGURATION);
+    push_7bit(&mut message, max_sysex_size, 4);
+    message.push(0xF7);
+    message
+}
+
+/// Build a profile inquiry from `muid` to the device with the MUID `destination`.
+pub fn profile_inquiry_message(muid: u32, destination: u32) -> Vec<u8> {
+    let mut message = header(PROFILE_INQUIRY, muid, destination);
+    message.push(0xF7);
+    message
+}
+
+/// Parse a MIDI-CI reply. Returns `None` for any other message, including
+/// malformed replies.
+pub fn parse(message: &[u8]) -> Option<CiReply> {
+    if message.len() < HEADER_LEN + 1
+        || message[0] != 0xF0
+        || message[1] != UNIVERSAL_NON_REALTIME
+        || message[3] != SUB_ID_CI
+        || message[message.len() - 1] != 0xF7
+    {
+        return None;
+    }
+    let source = read_7bit(&message[6..10]);
+    let destination = read_7bit(&message[10..14]);
+    let payload = &message[HEADER_LEN..message.len() - 1];
+    match message[4] {
+        DISCOVERY_REPLY => {
+            if payload.len() < 16 {
+                return None;
+            }
+            Some(CiReply::Discovery {
+                source,
+                destination,
+                identity: DeviceIdentity {
+                    manufacturer: [payload[0], payload[1], payload[2]],
+                    family: read_7bit(&payload[3..5]) as u16,
+                    model: read_7bit(&payload[5..7]) as u16,
+                    version: [payload[7], payload[8], payload[9], payload[10]],
+                },
+                categories: payload[11],
+                max_sysex_size: read_7bit(&payload[12..16]),
+            })
+        }
+        PROFILE_INQUIRY_REPLY => {
+            let (enabled, rest) = read_profiles(payload)?;
+            let (disabled, _) = read_profiles(rest)?;
+            Some(CiReply::Profiles {
+                source,
+                destination,
+                enabled,
+                disabled,
+            })
+        }
+        _ => None,
+    }
+}
+
+fn read_profiles(data: &[u8]) -> Option<(Vec<ProfileId>, &[u8])> {
+    if data.len() < 2 {
+        return None;
+    }
+    let count = read_7bit(&data[0..2]) as usize;
+    let end = 2 + count * 5;
+    if data.len() < end {
+        return None;
+    }
+    let profiles = data[2..end]
+        .chunks(5)
+        .map(|id| ProfileId([id[0], id[1], id[2], id[3], id[4]]))
+        .collect();
+    Some((profiles, &data[end..]))
+}
+
+/// An error that can occur during `discover`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum DiscoveryError {
+    /// Connecting to the input port failed.
+    Connect(ConnectErrorKind),
+    /// Sending an inquiry failed.
+    Send(SendError),
+}
+
+impl Error for DiscoveryError {}
+
+impl fmt::Display for DiscoveryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            DiscoveryError::Connect(ref kind) => kind.fmt(f),
+            DiscoveryError::Send(ref err) => err.fmt(f),
+        }
+    }
+}
+
+/// Perform a MIDI-CI discovery handshake: broadcast a discovery inquiry on
+/// `conn_out`, collect the replies arriving on the input port `port` for
+/// `timeout`, and then ask every responding device for its profiles
+/// (waiting up to `timeout` again).
+///
+/// `muid` identifies this application and should be chosen randomly
+/// (below `0x0FFF_FF00`). `midi_in` must not ignore sysex messages.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn discover(
+    midi_in: MidiInput,
+    port: &MidiInputPort,
+    conn_out: &mut MidiOutputConnection,
+    muid: u32,
+    identity: &DeviceIdentity,
+    timeout: Duration,
+) -> Result<Vec<Responder>, DiscoveryError> {
+    let (tx, rx) = mpsc::channel();
+    let conn_in = midi_in
+        .connect(
+            port,
+            "midir MIDI-CI discovery",
+            move |_, message, _| {
+                if let Some(reply) = parse(message) {
+                    let _ = tx.send(reply);
+                }
+            },
+            (),
+        )
+        .map_err(|e| DiscoveryError::Connect(e.kind()))?;
+
+    let receive_until = |deadline: Instant, f: &mut dyn FnMut(CiReply)| {
+        while let Ok(reply) = rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            f(reply);
+        }
+    };
+
+    conn_out
+        .send(&discovery_message(muid, identity, 512))
+        .map_err(DiscoveryError::Send)?;
+    let mut responders = Vec::new();
+    let mut profile_support = Vec::new();
+    receive_until(Instant::now() + timeout, &mut |reply| {
+        if let CiReply::Discovery {
+            source,
+            destination,
+            identity,
+            categories,
+            ..
+        } = reply
+        {
+            if destination == muid && !responders.iter().any(|r: &Responder| r.muid == source) {
+                responders.push(Responder {
+                    muid: source,
+                    identity,
+                    profiles: Vec::new(),
+                });
+                profile_support.push(categories & CATEGORY_PROFILE_CONFIGURATION != 0);
+            }
+        }
+    });
+
+    let mut inquiries = 0;
+    for (responder, supported) in responders.iter().zip(profile_support) {
+        if supported {
+            conn_out
+                .send(&profile_inquiry_message(muid, responder.muid))
+                .map_err(DiscoveryError::Send)?;
+            inquiries += 1;
+        }
+    }
+    if inquiries > 0 {
+        receive_until(Instant::now() + timeout, &mut |reply| {
+            if let CiReply::Profiles {
+                source,
+                destination,
+                enabled,
+                disabled,
+            } = reply
+            {
+                if destination != muid {
+                    return;
+                }
+                if let Some(responder) = responders.iter_mut().find(|r| r.muid == source) {
+                    responder.profiles = enabled.into_iter().chain(disabled).collect();
+                }
+            }
+        });
+    }
+
+    conn_in.close();
+    Ok(responders)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reply(sub_id: u8, source: u32, destination: u32, payload: &[u8]) -> Vec<u8> {
+        let mut message = header(sub_id, source, destination);
+        message.extend_from_slice(payload);
+        message.push(0xF7);
+        message
+    }
+
+    #[test]
+    fn test_discovery() {
+        let identity = DeviceIdentity {
+            manufacturer: [0x7D, 0, 0],
+            family: 0x1234,
+            model: 0x0101,
+            version: [1, 2, 3, 4],
+        };
+        let message = discovery_message(0x0123_4567, &identity, 512);
+        assert_eq!(&message[..6], &[0xF0, 0x7E, 0x7F, 0x0D, 0x70, 0x01]);
+        assert_eq!(read_7bit(&message[6..10]), 0x0123_4567);
+        assert_eq!(read_7bit(&message[10..14]), BROADCAST_MUID);
+
+        // A reply has the same layout as the inquiry
+        let mut message = message;
+        message[4] = DISCOVERY_REPLY;
+        assert_eq!(
+            parse(&message),
+            Some(CiReply::Discovery {
+                source: 0x0123_4567,
+                destination: BROADCAST_MUID,
+                identity,
+                categories: CATEGORY_PROFILE_CONFIGURATION,
+                max_sysex_size: 512,
+            })
+        );
+    }
+
+    #[test]
+    fn test_profiles() {
+        let payload = [1, 0, 0x7E, 0x40, 1, 1, 0, 1, 0, 0x7E, 0x41, 1, 1, 0];
+        assert_eq!(
+            parse(&reply(PROFILE_INQUIRY_REPLY, 5, 6, &payload)),
+            Some(CiReply::Profiles {
+                source: 5,
+                destination: 6,
+                enabled: vec![ProfileId([0x7E, 0x40, 1, 1, 0])],
+                disabled: vec![ProfileId([0x7E, 0x41, 1, 1, 0])],
+            })
+        );
+
+        // Truncated list of profiles
+        assert_eq!(
+            parse(&reply(PROFILE_INQUIRY_REPLY, 5, 6, &payload[..8])),
+            None
+        );
+        assert_eq!(parse(&[0xF0, 0x7E, 0x7F, 0x06, 0x01, 0xF7]), None);
+    }
+}
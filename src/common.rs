@@ -4,10 +4,29 @@ use backend::{
     MidiInput as MidiInputImpl, MidiInputConnection as MidiInputConnectionImpl,
     MidiInputPort as MidiInputPortImpl, MidiOutput as MidiOutputImpl,
     MidiOutputConnection as MidiOutputConnectionImpl, MidiOutputPort as MidiOutputPortImpl,
+    SourceId as SourceIdImpl,
 };
 use errors::*;
 
-use crate::{backend, errors, Ignore, InitError};
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::sync::mpsc;
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::{Arc, Mutex};
+use std::thread::ThreadId;
+#[cfg(not(target_arch = "wasm32"))]
+use std::thread::{self, JoinHandle};
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::{Duration, Instant};
+
+use crate::parse::ChannelVoiceEvent;
+#[cfg(all(target_os = "linux", feature = "jack"))]
+use crate::Backend;
+use crate::{
+    backend, errors, EarlyMessagePolicy, Ignore, InitError, SysexMode, TimestampMode, TimestampUnit,
+};
 
 /// Trait that abstracts over input and output ports.
 pub trait MidiIO {
@@ -27,6 +46,180 @@ pub trait MidiIO {
     /// An error will be returned when the port is no longer valid
     /// (e.g. the respective device has been disconnected).
     fn port_name(&self, port: &Self::Port) -> Result<String, PortInfoError>;
+
+    /// Get information about the device of a specified MIDI input or output
+    /// port, e.g. to show it in a device picker.
+    ///
+    /// CoreMIDI reports the manufacturer and model of the device, WinRT those
+    /// of the device container and Web MIDI only the manufacturer. ALSA, JACK
+    /// and WinMM have no such information, so all fields are `None` there.
+    /// None of the backends currently reports a serial number.
+    ///
+    /// An error will be returned when the port is no longer valid, if the
+    /// backend can detect this.
+    fn port_metadata(&self, port: &Self::Port) -> Result<PortMetadata, PortInfoError>;
+
+    /// Get all MIDI input or output ports together with their names.
+    ///
+    /// The name of each port is retrieved once. Ports whose name can't be
+    /// retrieved (e.g. because the device has been disconnected since the
+    /// ports were enumerated) are left out.
+    fn ports_with_names(&self) -> Vec<(Self::Port, String)> {
+        self.ports()
+            .into_iter()
+            .filter_map(|port| {
+                let name = self.port_name(&port).ok()?;
+                Some((port, name))
+            })
+            .collect()
+    }
+}
+
+/// Describes which port should be chosen when opening a connection in
+/// a single step using `MidiInput::open` or `MidiOutput::open`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PortSelector {
+    /// The port at the given position in the list returned by `ports`.
+    Index(usize),
+    /// The port with the given unique identifier (see `MidiInputPort::id`
+    /// and `MidiOutputPort::id`).
+    Id(String),
+    /// The first port whose name contains the given string.
+    NameContains(String),
+    /// The first available port.
+    Default,
+}
+
+/// Describes how the name of a port is compared to the pattern passed to
+/// `MidiInput::find_port_by_name` or `MidiOutput::find_port_by_name`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatchMode {
+    /// The name must be equal to the pattern.
+    Exact,
+    /// The name must be equal to the pattern, ignoring case.
+    CaseInsensitive,
+    /// The name must contain the pattern (case-sensitive).
+    Substring,
+}
+
+impl MatchMode {
+    fn matches(self, name: &str, pattern: &str) -> bool {
+        match self {
+            MatchMode::Exact => name == pattern,
+            MatchMode::CaseInsensitive => name.to_lowercase() == pattern.to_lowercase(),
+            MatchMode::Substring => name.contains(pattern),
+        }
+    }
+}
+
+fn find_port_by_name<IO: MidiIO>(midi_io: &IO, pattern: &str, mode: MatchMode) -> Option<IO::Port> {
+    midi_io.ports().into_iter().find(|port| {
+        midi_io
+            .port_name(port)
+            .is_ok_and(|name| mode.matches(&name, pattern))
+    })
+}
+
+fn select_port<IO: MidiIO>(
+    midi_io: &IO,
+    selector: &PortSelector,
+    id: impl Fn(&IO::Port) -> String,
+) -> Option<IO::Port> {
+    let mut ports = midi_io.ports().into_iter();
+    match *selector {
+        PortSelector::Index(index) => ports.nth(index),
+        PortSelector::Id(ref port_id) => ports.find(|p| id(p) == *port_id),
+        PortSelector::NameContains(ref name) => ports.find(|p| {
+            midi_io
+                .port_name(p)
+                .map(|n| n.contains(name.as_str()))
+                .unwrap_or(false)
+        }),
+        PortSelector::Default => ports.next(),
+    }
+}
+
+/// Get all input and output ports at once, e.g. for a device picker that
+/// shows both. This is equivalent to calling `ports` on a new `MidiInput` and
+/// `MidiOutput`, but backends with expensive asynchronous enumeration (WinRT)
+/// perform both enumerations concurrently.
+///
+/// If the backend cannot be initialized, the respective list is empty.
+pub fn enumerate_all() -> (MidiInputPorts, MidiOutputPorts) {
+    backend::enumerate_all()
+}
+
+/// Reinitialize the MIDI subsystem of the platform, e.g. after the device
+/// list has become stale or a driver has been installed.
+///
+/// On macOS and iOS this asks the CoreMIDI server to rescan its drivers
+/// (`MIDIRestart`), on the Web MIDI backend the MIDI access is requested
+/// again. The other backends query the system anew whenever ports are
+/// listed, so there it is only checked that the backend can still be
+/// initialized.
+///
+/// Existing connections and port objects may be invalidated by a restart,
+/// so they should be closed before and looked up again afterwards.
+pub fn restart() -> Result<(), InitError> {
+    backend::restart()
+}
+
+/// A device with all of its input and output ports (see `MidiInput::devices`).
+#[derive(Debug, Clone)]
+pub struct Device {
+    /// The name of the device.
+    pub name: String,
+    /// The input ports that belong to the device.
+    pub inputs: Vec<MidiInputPort>,
+    /// The output ports that belong to the device.
+    pub outputs: Vec<MidiOutputPort>,
+}
+
+/// Information about the device that a port belongs to, as far as the
+/// backend knows it (see `MidiIO::port_metadata`). Fields that are not
+/// available are `None`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PortMetadata {
+    /// The manufacturer of the device.
+    pub manufacturer: Option<String>,
+    /// The product name or model of the device.
+    pub product: Option<String>,
+    /// The serial number of the device.
+    pub serial: Option<String>,
+}
+
+/// Get the device with the given name from `devices`, adding it if necessary.
+fn device_entry(devices: &mut Vec<Device>, name: String) -> &mut Device {
+    match devices.iter().position(|device| device.name == name) {
+        Some(index) => &mut devices[index],
+        None => {
+            devices.push(Device {
+                name,
+                inputs: Vec::new(),
+                outputs: Vec::new(),
+            });
+            devices.last_mut().unwrap()
+        }
+    }
+}
+
+/// Displays the name of a port, or its ID if the name can't be retrieved
+/// (see `MidiInputPort::display_with` and `MidiOutputPort::display_with`).
+struct PortDisplay<'a, IO: MidiIO> {
+    midi_io: &'a IO,
+    port: &'a IO::Port,
+}
+
+impl<IO: MidiIO> fmt::Display for PortDisplay<'_, IO>
+where
+    IO::Port: fmt::Display,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.midi_io.port_name(self.port) {
+            Ok(name) => f.write_str(&name),
+            Err(_) => self.port.fmt(f),
+        }
+    }
 }
 
 /// An object representing a single input port.
@@ -47,30 +240,476 @@ impl MidiInputPort {
     pub fn id(&self) -> String {
         self.imp.id()
     }
+
+    /// Construct a port object from an identifier returned by `id` (e.g. one
+    /// that has been stored in the settings of an application), without
+    /// enumerating the ports first. Whether the port actually exists is
+    /// checked when connecting to it.
+    ///
+    /// On ALSA, JACK and WinRT the identifier is all that is needed to
+    /// connect. On CoreMIDI and WinMM, the port has to be present, and on
+    /// Web MIDI, access to MIDI devices has to be granted already, otherwise
+    /// `None` is returned (as it is for identifiers that are malformed).
+    pub fn from_id(id: &str) -> Option<MidiInputPort> {
+        MidiInputPortImpl::from_id(id).map(|imp| MidiInputPort { imp })
+    }
+
+    /// Get an object that displays the name of this port, as returned by
+    /// `MidiInput::port_name`, or its ID if the name can't be retrieved.
+    pub fn display_with<'a>(&'a self, midi_in: &'a MidiInput) -> impl fmt::Display + 'a {
+        PortDisplay {
+            midi_io: midi_in,
+            port: self,
+        }
+    }
+}
+
+/// Shows the ID of the port (see `MidiInputPort::id`).
+impl fmt::Debug for MidiInputPort {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MidiInputPort")
+            .field("id", &self.id())
+            .finish()
+    }
+}
+
+/// Displays the ID of the port (see `id`). Use `display_with` to show its name.
+impl fmt::Display for MidiInputPort {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.id())
+    }
 }
 
 /// A collection of input ports.
 pub type MidiInputPorts = Vec<MidiInputPort>;
 
+/// Identifies the port that a message has been sent from, as passed to the
+/// callback of `MidiInput::connect_with_source`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SourceId {
+    imp: SourceIdImpl,
+}
+
+impl SourceId {
+    /// Get the ID of the port that has sent the message, in the format of
+    /// `MidiInputPort::id`, so that it can be compared with the IDs of known
+    /// ports or passed to `MidiInputPort::from_id`.
+    ///
+    /// On ALSA, this is the address (`client:port`) of the sender, which may
+    /// also be a port that has been subscribed to the connection from
+    /// elsewhere. On CoreMIDI, it is the unique ID of the source. All other
+    /// backends can't tell where a message comes from, so `None` is returned.
+    pub fn port_id(&self) -> Option<String> {
+        self.imp.port_id()
+    }
+}
+
 /// An instance of `MidiInput` is required for anything related to MIDI input.
 /// Create one with `MidiInput::new`.
 pub struct MidiInput {
     //ignore_flags: Ignore
     imp: MidiInputImpl,
+    settings: InputSettings,
+}
+
+/// The settings of a `MidiInput` that are applied here instead of by the
+/// backend. They are moved to each connection and back when it is closed.
+#[derive(Default)]
+struct InputSettings {
+    timestamp_unit: TimestampUnit,
+    dedup_window: Option<std::time::Duration>,
+    timestamp_mode: TimestampMode,
+    ports_generation: PortsGeneration,
+    /// The sample rate of the JACK server if the backend reports frame times
+    /// instead of nanoseconds (see `JackInput::set_frame_timestamps`).
+    frame_rate: Option<std::num::NonZeroU32>,
+    /// Whether the backend has been told not to timestamp messages (see
+    /// `MidiInput::set_timestamping`), so that the dedup window is ignored.
+    untimestamped: bool,
+}
+
+impl InputSettings {
+    /// Returns a function that converts the timestamps of the backend into
+    /// the configured unit. Frame times are passed on unchanged, since they
+    /// are not nanoseconds.
+    fn timestamp_converter(&self) -> impl Fn(u64) -> u64 + Copy + Send + 'static {
+        let unit = self.timestamp_unit;
+        let frames = self.frame_rate.is_some();
+        move |nanos| {
+            if frames {
+                nanos
+            } else {
+                unit.convert_nanos(nanos)
+            }
+        }
+    }
+
+    /// Wraps the `callback` of a connection, so that it receives timestamps
+    /// according to these settings (while the nanoseconds reported by the
+    /// backend are passed on as well), and calls the `listeners` after it.
+    /// Repeated messages are dropped here if deduplication is enabled.
+    fn wrap_callback<S, F, T>(
+        &self,
+        mut callback: F,
+        listeners: Arc<Mutex<Listeners>>,
+    ) -> impl FnMut(u64, &[u8], S, &mut T) + Send + 'static
+    where
+        S: Copy + PartialEq + Send + 'static,
+        F: FnMut(u64, u64, &[u8], S, &mut T) + Send + 'static,
+    {
+        let convert = self.timestamp_converter();
+        let mode = self.timestamp_mode;
+        // The window is measured in the same ticks as the timestamps of the backend
+        let dedup_window = match self.dedup_window {
+            Some(_) if self.untimestamped => None,
+            Some(window) => Some(match self.frame_rate {
+                Some(rate) => (window.as_nanos() * rate.get() as u128 / 1_000_000_000) as u64,
+                None => window.as_nanos() as u64,
+            }),
+            None => None,
+        };
+        // The last message that has been delivered and is compared with the next ones
+        let mut last_kept = None;
+        let mut last_message = Vec::new();
+        let mut last_delivered = None;
+        move |nanos, message, source, data| {
+            // System real-time messages (e.g. Timing Clock) are repeated by design
+            let realtime = matches!(message, [0xF8..=0xFF]);
+            if let (Some(window), false) = (dedup_window, realtime) {
+                let repeated = last_kept.is_some_and(|(last, last_source): (u64, S)| {
+                    source == last_source
+                        && message == &last_message[..]
+                        && nanos.saturating_sub(last) < window
+                });
+                if repeated {
+                    return;
+                }
+                last_kept = Some((nanos, source));
+                last_message.clear();
+                last_message.extend_from_slice(message);
+            }
+            let timestamp = match mode {
+                TimestampMode::Absolute => convert(nanos),
+                TimestampMode::Relative => {
+                    let delta = last_delivered.map_or(0, |last| nanos.saturating_sub(last));
+                    last_delivered = Some(nanos);
+                    convert(delta)
+                }
+            };
+            callback(timestamp, nanos, message, source, data);
+            // Never block the thread of the callback (which is a realtime thread with JACK):
+            // skip the listeners while they are being added or removed
+            if let Ok(mut listeners) = listeners.try_lock() {
+                for (_, listener) in listeners.0.iter_mut() {
+                    listener(timestamp, message);
+                }
+            }
+        }
+    }
+}
+
+/// Turns the result of connecting with the backend into that of `MidiInput`,
+/// moving the `settings` into the connection, or back into the `MidiInput`
+/// if connecting has failed.
+fn finish_connect<T>(
+    settings: InputSettings,
+    result: Result<MidiInputConnectionImpl<T>, ConnectError<MidiInputImpl>>,
+    listeners: Arc<Mutex<Listeners>>,
+    port: ConnectedPort,
+) -> Result<MidiInputConnection<T>, ConnectError<MidiInput>> {
+    match result {
+        Ok(imp) => Ok(MidiInputConnection {
+            imp,
+            settings,
+            listeners,
+            next_listener_id: 0,
+            port,
+            #[cfg(not(target_arch = "wasm32"))]
+            opened_at: Instant::now(),
+        }),
+        Err(err) => {
+            let kind = err.kind();
+            Err(ConnectError::new(
+                kind,
+                MidiInput {
+                    imp: err.into_inner(),
+                    settings,
+                },
+            ))
+        }
+    }
 }
 
 impl MidiInput {
     /// Creates a new `MidiInput` object that is required for any MIDI input functionality.
     pub fn new(client_name: &str) -> Result<Self, InitError> {
-        MidiInputImpl::new(client_name).map(|imp| MidiInput { imp })
+        MidiInputImpl::new(client_name).map(|imp| MidiInput {
+            imp,
+            settings: InputSettings::default(),
+        })
+    }
+
+    /// Creates a new `MidiInput` object that uses the given backend.
+    /// `new` uses JACK in this configuration.
+    #[cfg(all(target_os = "linux", feature = "jack"))]
+    pub fn new_with_backend(backend: Backend, client_name: &str) -> Result<Self, InitError> {
+        MidiInputImpl::new_with_backend(backend, client_name).map(|imp| MidiInput {
+            imp,
+            settings: InputSettings::default(),
+        })
+    }
+
+    /// Creates a new `MidiInput` object that uses the JACK backend with the
+    /// given options, e.g. to start the JACK server if it is not running
+    /// (`new` never does this). If this fails, the `InitError` tells why.
+    #[cfg(all(feature = "jack", unix))]
+    pub fn new_with_options(
+        client_name: &str,
+        options: crate::os::unix::jack::JackStartOptions,
+    ) -> Result<Self, InitError> {
+        MidiInputImpl::new_with_options(client_name, options).map(|imp| MidiInput {
+            imp,
+            settings: InputSettings::default(),
+        })
+    }
+
+    /// Get the backend that this `MidiInput` object uses.
+    #[cfg(all(target_os = "linux", feature = "jack"))]
+    pub fn backend(&self) -> Backend {
+        self.imp.backend()
+    }
+
+    /// Creates a new `MidiInput` object and immediately connects to the port
+    /// chosen by `selector`. This combines `new`, `ports` and `connect` in a
+    /// single call, using `client_name` also as the name of the connection.
+    ///
+    /// See `connect` for the meaning of `callback` and `data`.
+    pub fn open<F, T: Send>(
+        client_name: &str,
+        selector: PortSelector,
+        callback: F,
+        data: T,
+    ) -> Result<MidiInputConnection<T>, OpenError>
+    where
+        F: FnMut(u64, &[u8], &mut T) + Send + 'static,
+    {
+        let midi_in = MidiInput::new(client_name).map_err(OpenError::Init)?;
+        let port =
+            select_port(&midi_in, &selector, MidiInputPort::id).ok_or(OpenError::NoMatchingPort)?;
+        midi_in
+            .connect(&port, client_name, callback, data)
+            .map_err(|e| OpenError::Connect(e.kind()))
+    }
+
+    /// Change the client name that was passed to `new`, i.e. the name under
+    /// which this object appears to other applications (e.g. in `aconnect -l`
+    /// on ALSA or as the CoreMIDI client name).
+    ///
+    /// The client itself is kept, so other applications see the same client
+    /// (with the same client ID on ALSA) under its new name, and connections
+    /// opened afterwards belong to the renamed client. Since `connect`
+    /// consumes this object, its own connections must be closed first (which
+    /// returns it); connections of other `MidiInput` or `MidiOutput` objects
+    /// are not affected, because each of them is a separate client.
+    ///
+    /// This is only supported on ALSA, CoreMIDI and WebMIDI (where the name
+    /// is only used by `client_name`). All other backends return a
+    /// `ConnectErrorKind::Other` error saying that renaming is not supported.
+    pub fn set_client_name(&mut self, name: &str) -> Result<(), ConnectErrorKind> {
+        self.imp.set_client_name(name)
+    }
+
+    /// Whether the backend can create virtual ports (see `os::unix::VirtualInput`),
+    /// so that portable code can decide at runtime whether to offer them.
+    ///
+    /// This is the case for ALSA, CoreMIDI and JACK, but not for WinMM, WinRT
+    /// and WebMIDI (where `VirtualInput` is not available at all).
+    pub fn supports_virtual_ports(&self) -> bool {
+        self.imp.supports_virtual_ports()
     }
 
     /// Set flags to decide what kind of messages should be ignored (i.e., filtered out)
     /// by this `MidiInput`. By default, no messages are ignored.
+    ///
+    /// System real-time messages (e.g. timing clock) may be sent in the middle
+    /// of a SysEx message. They are always passed on as separate messages
+    /// (before the SysEx message that they interrupted) and are subject to
+    /// their own flags, independently of `Ignore::Sysex`.
     pub fn ignore(&mut self, flags: Ignore) {
         self.imp.ignore(flags);
     }
 
+    /// Get the flags that decide what kind of messages are currently ignored
+    /// by this `MidiInput` (see `ignore`).
+    pub fn ignored_flags(&self) -> Ignore {
+        self.imp.ignored_flags()
+    }
+
+    /// Set whether SysEx messages that arrive in several segments are passed
+    /// to the callback as one complete message (the default) or segment by
+    /// segment (see `SysexMode`). Streaming avoids buffering large SysEx dumps
+    /// in memory.
+    ///
+    /// This only makes a difference on ALSA, CoreMIDI, WinMM and WinRT, since
+    /// the other backends receive SysEx messages in one piece.
+    pub fn set_sysex_mode(&mut self, mode: SysexMode) {
+        self.imp.set_sysex_mode(mode)
+    }
+
+    /// Set whether connections opened afterwards timestamp incoming messages
+    /// (enabled by default).
+    ///
+    /// This only makes a difference on ALSA, where timestamping requires a
+    /// queue of the sequencer. If disabled, no queue is allocated and the
+    /// timestamp passed to the callback is always 0, which lowers the overhead
+    /// for applications that don't need timing. The other backends get the
+    /// timestamps from the system, so this setting is ignored there.
+    pub fn set_timestamping(&mut self, enable: bool) {
+        self.imp.set_timestamping(enable);
+        self.settings.untimestamped = !self.imp.timestamping();
+    }
+
+    /// Set a backend-specific option that does not warrant a method of its
+    /// own. Options that are not recognized by the backend in use are
+    /// ignored, but an error is returned if the value of a recognized option
+    /// is not valid or can't be applied.
+    ///
+    /// The recognized options are:
+    ///
+    /// * ALSA: `"alsa.input_pool_size"`, the number of events that the input
+    ///   pool of the sequencer client can hold (see `snd_seq_set_client_pool_input`)
+    /// * JACK: `"jack.server_name"` always returns an error, because the
+    ///   server can't be changed once the client has been opened. Set the
+    ///   `JACK_DEFAULT_SERVER` environment variable before creating the
+    ///   `MidiInput` instead.
+    ///
+    /// No other backend has any options yet.
+    pub fn set_backend_option(&mut self, key: &str, value: &str) -> Result<(), ConnectErrorKind> {
+        self.imp.set_backend_option(key, value)
+    }
+
+    /// Set the maximum size in bytes of SysEx messages that are reassembled
+    /// from several segments by connections opened afterwards (unlimited by
+    /// default). This protects against a faulty device that never terminates
+    /// a SysEx message, which would otherwise be buffered without bound.
+    ///
+    /// A message that exceeds the limit is dropped (the rest of it is
+    /// discarded until it ends) and counted by
+    /// `MidiInputConnection::filtered_count`.
+    ///
+    /// This only applies to ALSA, CoreMIDI, WinMM and WinRT in
+    /// `SysexMode::Complete`, since messages are not buffered in streaming mode
+    /// and the other backends receive SysEx messages in one piece.
+    pub fn set_max_sysex_size(&mut self, max: Option<usize>) {
+        self.imp.set_max_sysex_size(max)
+    }
+
+    /// Set what happens to messages that arrive before a port has been opened
+    /// completely by connections opened afterwards (see `EarlyMessagePolicy`).
+    ///
+    /// This only makes a difference on Web MIDI, where ports are opened
+    /// asynchronously. All other backends have opened the port when `connect`
+    /// returns (WinRT waits for the asynchronous operation to complete).
+    pub fn set_early_message_policy(&mut self, policy: EarlyMessagePolicy) {
+        self.imp.set_early_message_policy(policy)
+    }
+
+    /// Set the unit of the timestamps that are passed to the callback of
+    /// connections opened afterwards (microseconds by default).
+    ///
+    /// The precision of the timestamps depends on the backend: ALSA and
+    /// CoreMIDI provide nanoseconds, WinRT provides 100 nanoseconds (which is
+    /// only fully preserved with `TimestampUnit::Nanos`), while e.g. WinMM
+    /// only provides milliseconds.
+    ///
+    /// The unit has no effect while JACK frame timestamps are enabled (see
+    /// `JackInput::set_frame_timestamps`), since frame times are passed on
+    /// unchanged.
+    pub fn set_timestamp_unit(&mut self, unit: TimestampUnit) {
+        self.settings.timestamp_unit = unit;
+    }
+
+    /// Set whether the timestamps that are passed to the callback (and to
+    /// listeners) of connections opened afterwards are absolute or relative
+    /// to the previous message (see `TimestampMode`). They are absolute by
+    /// default.
+    ///
+    /// Relative timestamps are computed by midir, so this is supported by all
+    /// backends. The timestamps passed to a raw tap, the raw timestamps of
+    /// `connect_with_raw_time` and those returned by
+    /// `MidiInputConnection::close_draining` are always absolute.
+    pub fn set_timestamp_mode(&mut self, mode: TimestampMode) {
+        self.settings.timestamp_mode = mode;
+    }
+
+    /// Suppress messages that are byte-identical to the previous message that
+    /// has been delivered and arrive within the given `window` after it, for
+    /// connections opened afterwards. Pass `None` to disable this again (the
+    /// default).
+    ///
+    /// This is a workaround for devices and drivers that send messages twice
+    /// (e.g. a Note Off), not a fix for the cause. It can also drop messages
+    /// that have been repeated intentionally, so the window should be kept
+    /// short. System real-time messages (e.g. Timing Clock or Active Sensing)
+    /// are never suppressed, since they are repeated by design.
+    ///
+    /// The window is measured with the timestamps of the messages, so it is
+    /// ignored if timestamping is disabled (see `set_timestamping`). With
+    /// JACK frame timestamps, the window is converted into frames using the
+    /// sample rate of the server.
+    pub fn set_dedup_window(&mut self, window: Option<std::time::Duration>) {
+        self.settings.dedup_window = window;
+    }
+
+    /// Wraps a callback so that it receives timestamps in the configured unit
+    /// (backends always report nanoseconds) and passes every message on to
+    /// the listeners of the connection.
+    fn wrap_callback<F, T>(
+        &self,
+        mut callback: F,
+        listeners: Arc<Mutex<Listeners>>,
+    ) -> impl FnMut(u64, &[u8], &mut T) + Send + 'static
+    where
+        F: FnMut(u64, &[u8], &mut T) + Send + 'static,
+    {
+        self.wrap_callback_with_nanos(
+            move |timestamp, _, message, data| callback(timestamp, message, data),
+            listeners,
+        )
+    }
+
+    /// Like `wrap_callback`, but also passes the timestamp in nanoseconds (as
+    /// reported by the backend) to the `callback`.
+    fn wrap_callback_with_nanos<F, T>(
+        &self,
+        mut callback: F,
+        listeners: Arc<Mutex<Listeners>>,
+    ) -> impl FnMut(u64, &[u8], &mut T) + Send + 'static
+    where
+        F: FnMut(u64, u64, &[u8], &mut T) + Send + 'static,
+    {
+        let mut wrapped = self.wrap_callback_with_source(
+            move |timestamp, nanos, message, (), data| callback(timestamp, nanos, message, data),
+            listeners,
+        );
+        move |nanos, message, data| wrapped(nanos, message, (), data)
+    }
+
+    /// Like `wrap_callback_with_nanos`, but also passes on the source of each
+    /// message (see `connect_multi`). Messages from different sources are never
+    /// considered to be repetitions of each other.
+    fn wrap_callback_with_source<S, F, T>(
+        &self,
+        callback: F,
+        listeners: Arc<Mutex<Listeners>>,
+    ) -> impl FnMut(u64, &[u8], S, &mut T) + Send + 'static
+    where
+        S: Copy + PartialEq + Send + 'static,
+        F: FnMut(u64, u64, &[u8], S, &mut T) + Send + 'static,
+    {
+        self.settings.wrap_callback(callback, listeners)
+    }
+
     /// Get a collection of all MIDI input ports that *midir* can connect to.
     /// The resulting vector contains one object per port, which you can use to
     /// query metadata about the port or connect to it in order to receive
@@ -79,11 +718,78 @@ impl MidiInput {
         self.imp.ports_internal()
     }
 
+    /// Get the available MIDI input ports, without those that are known to be
+    /// unavailable, e.g. because another application has an exclusive
+    /// connection (ALSA) or because the device is offline (CoreMIDI) or
+    /// disconnected (WebMIDI). The ports are not opened to find this out, so
+    /// connecting to one of the returned ports may still fail. Ports for which
+    /// this can't be determined (e.g. on WinMM) are always included.
+    pub fn connectable_ports(&self) -> MidiInputPorts {
+        self.ports()
+            .into_iter()
+            .filter(|port| self.imp.is_connectable(&port.imp))
+            .collect()
+    }
+
+    /// Get a collection of all MIDI input ports like `ports`, but on Web MIDI,
+    /// wait until the browser has granted access to MIDI devices first
+    /// (requesting it if necessary). Before that, `ports` returns no ports.
+    ///
+    /// On all other backends, the ports are available right away.
+    pub async fn ports_async(&self) -> MidiInputPorts {
+        #[cfg(target_arch = "wasm32")]
+        backend::wait_for_access().await;
+        self.ports()
+    }
+
+    /// Get the available MIDI input ports in a stable order, which does not
+    /// change when ports are enumerated again, so that lists shown to users
+    /// don't jump around (unlike `ports`, whose order depends on the backend
+    /// and may change when devices are connected).
+    ///
+    /// The ports are sorted by the name of their device, then by their own
+    /// name and finally by their unique identifier.
+    pub fn ports_sorted(&self) -> MidiInputPorts {
+        let mut ports = self.ports();
+        ports.sort_by_cached_key(|port| {
+            (
+                self.imp.device_name(&port.imp).unwrap_or_default(),
+                self.port_name(port).unwrap_or_default(),
+                port.id(),
+            )
+        });
+        ports
+    }
+
     /// Get the number of available MIDI input ports that *midir* can connect to.
     pub fn port_count(&self) -> usize {
         self.imp.port_count()
     }
 
+    /// Get a number that changes whenever the set of available MIDI input
+    /// ports has changed since the previous call, so that an application can
+    /// poll a single integer instead of comparing the results of `ports`.
+    ///
+    /// The number starts at 0 and is incremented if the IDs of the ports
+    /// differ from those at the time of the previous call. This still lists
+    /// the ports, but avoids allocating and comparing port objects in the
+    /// application. Changes that are undone before the next call (e.g. a
+    /// device that is unplugged and plugged in again) are not noticed.
+    pub fn ports_generation(&self) -> u64 {
+        self.settings
+            .ports_generation
+            .update(self.ports().iter().map(MidiInputPort::id))
+    }
+
+    /// Get the client name that was passed to `new` (or `set_client_name`).
+    ///
+    /// The Web MIDI API has no notion of client names, so the name is not
+    /// passed on to the browser and this is the only place where it appears.
+    #[cfg(target_arch = "wasm32")]
+    pub fn client_name(&self) -> &str {
+        self.imp.client_name()
+    }
+
     /// Get the name of a specified MIDI input port.
     ///
     /// An error will be returned when the port is no longer valid
@@ -92,15 +798,151 @@ impl MidiInput {
         self.imp.port_name(&port.imp)
     }
 
+    /// Get information about the device of a specified MIDI input port
+    /// (see `MidiIO::port_metadata`).
+    pub fn port_metadata(&self, port: &MidiInputPort) -> Result<PortMetadata, PortInfoError> {
+        self.imp.port_metadata(&port.imp)
+    }
+
+    /// Get all MIDI input ports together with their names, leaving out the
+    /// ports whose name can't be retrieved (see `MidiIO::ports_with_names`).
+    pub fn ports_with_names(&self) -> Vec<(MidiInputPort, String)> {
+        <Self as MidiIO>::ports_with_names(self)
+    }
+
+    /// Get all input and output ports grouped by the device they belong to,
+    /// e.g. to present them in a device picker. Devices are listed in the
+    /// order of their first port.
+    ///
+    /// Ports are grouped by the name of their device: on ALSA and JACK this is
+    /// the name of the client, on WebMIDI the port name and on the other
+    /// backends the name that is shared by the ports of a device (see
+    /// `MidiOutput::find_sibling_port`). Therefore, several identical devices
+    /// may end up as a single `Device`.
+    ///
+    /// A separate `MidiOutput` (using the same backend) is created to list the
+    /// output ports. If that fails, all devices are listed without outputs.
+    pub fn devices(&self) -> Vec<Device> {
+        const CLIENT_NAME: &str = "midir enumeration";
+        let mut devices = Vec::new();
+        for port in self.ports() {
+            let name = self
+                .imp
+                .device_name(&port.imp)
+                .or_else(|| self.port_name(&port).ok());
+            if let Some(name) = name {
+                device_entry(&mut devices, name).inputs.push(port);
+            }
+        }
+        #[cfg(all(target_os = "linux", feature = "jack"))]
+        let midi_out = MidiOutput::new_with_backend(self.backend(), CLIENT_NAME);
+        #[cfg(not(all(target_os = "linux", feature = "jack")))]
+        let midi_out = MidiOutput::new(CLIENT_NAME);
+        if let Ok(midi_out) = midi_out {
+            for port in midi_out.ports() {
+                let name = midi_out
+                    .imp
+                    .device_name(&port.imp)
+                    .or_else(|| midi_out.port_name(&port).ok());
+                if let Some(name) = name {
+                    device_entry(&mut devices, name).outputs.push(port);
+                }
+            }
+        }
+        devices
+    }
+
     /// Get a MIDI input port by its unique identifier.
     pub fn find_port_by_id(&self, id: String) -> Option<MidiInputPort> {
         self.ports().into_iter().find(|port| port.id() == id)
     }
 
+    /// Get a MIDI input port by its name, which is compared to `pattern`
+    /// according to `mode`. If several ports match, the first one in the
+    /// order of `ports` is returned. Ports whose name can't be retrieved
+    /// never match.
+    pub fn find_port_by_name(&self, pattern: &str, mode: MatchMode) -> Option<MidiInputPort> {
+        find_port_by_name(self, pattern, mode)
+    }
+
+    /// Get the MIDI input ports that have been created by a client (i.e. an
+    /// application or a device driver) whose name contains `name_substr`,
+    /// e.g. `"FluidSynth"`. This is useful for diagnostics and routing in
+    /// setups with several applications.
+    ///
+    /// On ALSA and JACK, this is the name of the sequencer or JACK client that
+    /// owns the port. On CoreMIDI, the name of the device or application that
+    /// the port belongs to is used instead.
+    #[cfg(unix)]
+    pub fn ports_by_client(&self, name_substr: &str) -> Vec<MidiInputPort> {
+        self.ports()
+            .into_iter()
+            .filter(|port| {
+                self.imp
+                    .device_name(&port.imp)
+                    .is_some_and(|name| name.contains(name_substr))
+            })
+            .collect()
+    }
+
+    /// Get the default MIDI input port. None of the backends has a notion of
+    /// a system default input, so this is the first available port.
+    pub fn default_port(&self) -> Option<MidiInputPort> {
+        self.ports().into_iter().next()
+    }
+
+    /// Invoke `callback` whenever `default_port` would return a different
+    /// port than before (e.g. because a device has been connected or
+    /// disconnected), until the returned `DefaultPortWatcher` is dropped.
+    ///
+    /// A separate `MidiInput` is created with the given `client_name`, which
+    /// is polled in the given `interval` on a background thread, because
+    /// the backends don't provide notifications for this.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn watch_default_port<F>(
+        client_name: &str,
+        interval: Duration,
+        callback: F,
+    ) -> Result<DefaultPortWatcher, InitError>
+    where
+        F: FnMut(Option<MidiInputPort>) + Send + 'static,
+    {
+        let midi_in = MidiInput::new(client_name)?;
+        Ok(DefaultPortWatcher::spawn(
+            move || midi_in.default_port(),
+            MidiInputPort::id,
+            interval,
+            callback,
+        ))
+    }
+
+    /// Invoke `callback` whenever a MIDI input port appears or disappears
+    /// (e.g. because a device has been connected or disconnected), until the
+    /// returned `PortWatcher` is dropped.
+    ///
+    /// This uses the notifications of the backend: the announcements of the
+    /// system client on ALSA, the port registration callback on JACK, the
+    /// `MIDINotifyProc` on CoreMIDI, a `DeviceWatcher` on WinRT and the
+    /// `statechange` event on Web MIDI. WinMM doesn't report when ports appear
+    /// or disappear, so an error is returned there. The callback is invoked on
+    /// a thread of the backend (on CoreMIDI, the run loop of the thread that
+    /// called `watch`), and the port that is passed with `DeviceEvent::Removed`
+    /// may no longer be used to query its name.
+    pub fn watch<F>(&self, mut callback: F) -> Result<PortWatcher, InitError>
+    where
+        F: FnMut(DeviceEvent<MidiInputPort>) + Send + 'static,
+    {
+        let imp = self.imp.watch(Box::new(move |event| {
+            callback(event.map(|imp| MidiInputPort { imp }))
+        }))?;
+        Ok(PortWatcher { _imp: imp })
+    }
+
     /// Connect to a specified MIDI input port in order to receive messages.
     /// For each incoming MIDI message, the provided `callback` function will
     /// be called. The first parameter of the callback function is a timestamp
-    /// (in microseconds) designating the time since some unspecified point in
+    /// (in microseconds, unless changed with `set_timestamp_unit`) designating
+    /// the time since some unspecified point in
     /// the past (which will not change during the lifetime of a
     /// `MidiInputConnection`). The second parameter contains the actual bytes
     /// of the MIDI message.
@@ -127,76 +969,764 @@ impl MidiInput {
     where
         F: FnMut(u64, &[u8], &mut T) + Send + 'static,
     {
-        match self.imp.connect(&port.imp, port_name, callback, data) {
-            Ok(imp) => Ok(MidiInputConnection { imp }),
-            Err(imp) => {
-                let kind = imp.kind();
-                Err(ConnectError::new(
-                    kind,
-                    MidiInput {
-                        imp: imp.into_inner(),
-                    },
-                ))
+        let listeners = Arc::new(Mutex::new(Listeners(Vec::new())));
+        let callback = self.wrap_callback(callback, listeners.clone());
+        let connected_port = ConnectedPort::new(port.id(), self.port_name(port).ok());
+        let result = self.imp.connect(&port.imp, port_name, callback, data);
+        finish_connect(self.settings, result, listeners, connected_port)
+    }
+
+    /// Connect to a specified MIDI input port like `connect`, but also pass
+    /// the port that each message has been sent from to the `callback` (as
+    /// its third argument), e.g. to route messages by device.
+    ///
+    /// See `SourceId::port_id` for the backends that can identify the source.
+    /// Messages that are received from different sources are never suppressed
+    /// as repetitions of each other (see `set_dedup_window`).
+    pub fn connect_with_source<F, T: Send>(
+        self,
+        port: &MidiInputPort,
+        port_name: &str,
+        mut callback: F,
+        data: T,
+    ) -> Result<MidiInputConnection<T>, ConnectError<MidiInput>>
+    where
+        F: FnMut(u64, &[u8], SourceId, &mut T) + Send + 'static,
+    {
+        let listeners = Arc::new(Mutex::new(Listeners(Vec::new())));
+        let callback = self.wrap_callback_with_source(
+            move |timestamp, _, message, imp, data| {
+                callback(timestamp, message, SourceId { imp }, data)
+            },
+            listeners.clone(),
+        );
+        let connected_port = ConnectedPort::new(port.id(), self.port_name(port).ok());
+        let result = self
+            .imp
+            .connect_with_source(&port.imp, port_name, callback, data);
+        finish_connect(self.settings, result, listeners, connected_port)
+    }
+
+    /// Connect to several MIDI input ports at once, merging the messages from
+    /// all of them into a single stream that is passed to the `callback`. In
+    /// addition to the arguments of the callback of `connect`, it receives the
+    /// index (within `ports`) of the port that each message comes from.
+    ///
+    /// This is supported by ALSA (which subscribes one port of this client to
+    /// all of the given ports) and CoreMIDI. All other backends can only
+    /// connect to a single port here and return an error otherwise. Passing
+    /// no ports at all is an error (`ConnectErrorKind::InvalidPort`).
+    ///
+    /// SysEx messages that arrive in several segments must not be sent by two
+    /// ports at the same time, because the segments are not reassembled per
+    /// port. With ALSA, messages from ports that have been subscribed to the
+    /// port of this connection from elsewhere (e.g. with `aconnect`) are
+    /// dropped, since they have no index.
+    pub fn connect_multi<F, T: Send>(
+        self,
+        ports: &[&MidiInputPort],
+        port_name: &str,
+        mut callback: F,
+        data: T,
+    ) -> Result<MidiInputConnection<T>, ConnectError<MidiInput>>
+    where
+        F: FnMut(u64, &[u8], usize, &mut T) + Send + 'static,
+    {
+        let connected_port = match ports.first() {
+            Some(port) => ConnectedPort::new(port.id(), self.port_name(port).ok()),
+            None => return Err(ConnectError::new(ConnectErrorKind::InvalidPort, self)),
+        };
+        let listeners = Arc::new(Mutex::new(Listeners(Vec::new())));
+        let callback = self.wrap_callback_with_source(
+            move |timestamp, _, message, source, data| callback(timestamp, message, source, data),
+            listeners.clone(),
+        );
+        let imp_ports: Vec<_> = ports.iter().map(|port| &port.imp).collect();
+        let result = self
+            .imp
+            .connect_multi(&imp_ports, port_name, callback, data);
+        finish_connect(self.settings, result, listeners, connected_port)
+    }
+
+    /// Connect to a specified MIDI input port like `connect`, but pass the
+    /// timestamp of each message to the `callback` twice: first in the unit
+    /// set with `set_timestamp_unit` (as `connect` does), then in the unit of
+    /// the backend, for correlation with other tools of the platform:
+    ///
+    /// * ALSA: nanoseconds of the real time of the input queue
+    /// * CoreMIDI: host time (ticks of `mach_absolute_time`)
+    /// * JACK: microseconds of `jack_get_time`
+    /// * WinMM: milliseconds since the port has been opened
+    /// * WinRT: ticks of 100 nanoseconds since the port has been created
+    /// * Web MIDI: whole milliseconds of `performance.now()`
+    ///
+    /// See the `time` module for the reference points of the timestamps.
+    pub fn connect_with_raw_time<F, T: Send>(
+        self,
+        port: &MidiInputPort,
+        port_name: &str,
+        mut callback: F,
+        data: T,
+    ) -> Result<MidiInputConnection<T>, ConnectError<MidiInput>>
+    where
+        F: FnMut(u64, u64, &[u8], &mut T) + Send + 'static,
+    {
+        let listeners = Arc::new(Mutex::new(Listeners(Vec::new())));
+        let to_raw = self.imp.raw_timestamp_fn();
+        let callback = self.wrap_callback_with_nanos(
+            move |timestamp, nanos, message, data| {
+                callback(timestamp, to_raw(nanos), message, data)
+            },
+            listeners.clone(),
+        );
+        let connected_port = ConnectedPort::new(port.id(), self.port_name(port).ok());
+        let result = self.imp.connect(&port.imp, port_name, callback, data);
+        finish_connect(self.settings, result, listeners, connected_port)
+    }
+
+    /// Connect to a specified MIDI input port like `connect`, but instead of
+    /// handling messages in a callback, queue them so that they can be polled
+    /// from the returned `MidiInputRx` (e.g. once per frame of a game loop).
+    ///
+    /// Each message is copied into a newly allocated `Vec` before it is
+    /// queued, so this costs one allocation per message. The messages are
+    /// received in the order in which they arrived. Messages that are not
+    /// polled stay queued for as long as the connection is open.
+    pub fn connect_rx(
+        self,
+        port: &MidiInputPort,
+        port_name: &str,
+    ) -> Result<MidiInputRx, ConnectError<MidiInput>> {
+        let (sender, receiver) = mpsc::channel();
+        let conn = self.connect(
+            port,
+            port_name,
+            |stamp, message, sender: &mut mpsc::Sender<(u64, Vec<u8>)>| {
+                // The receiver lives as long as the connection, so this can't fail
+                let _ = sender.send((stamp, message.to_vec()));
+            },
+            sender,
+        )?;
+        Ok(MidiInputRx { conn, receiver })
+    }
+
+    /// Connect to a specified MIDI input port like `connect`, but pass each
+    /// incoming message to the `callback` as a decoded `ChannelVoiceEvent`
+    /// instead of raw bytes. Messages that are not channel voice messages are
+    /// passed as `ChannelVoiceEvent::Other`.
+    ///
+    /// Decoding happens in place, so no allocations are made beyond those of
+    /// the backend for receiving the message.
+    pub fn connect_events<F>(
+        self,
+        port: &MidiInputPort,
+        port_name: &str,
+        mut callback: F,
+    ) -> Result<MidiInputConnection<()>, ConnectError<MidiInput>>
+    where
+        F: FnMut(u64, ChannelVoiceEvent<'_>) + Send + 'static,
+    {
+        self.connect(
+            port,
+            port_name,
+            move |stamp, message, _| callback(stamp, ChannelVoiceEvent::parse(message)),
+            (),
+        )
+    }
+
+    /// Connect to a specified MIDI input port like `connect`, but pass each
+    /// incoming message to the `callback` as a `wmidi::MidiMessage`.
+    ///
+    /// SysEx messages are passed as `wmidi::MidiMessage::SysEx`, which borrows
+    /// the data without the leading `0xF0` and the trailing `0xF7`. Messages
+    /// that `wmidi` can't parse are dropped, which includes the segments of
+    /// SysEx messages if `SysexMode::Streaming` has been set.
+    #[cfg(feature = "wmidi")]
+    pub fn connect_wmidi<F, T: Send>(
+        self,
+        port: &MidiInputPort,
+        port_name: &str,
+        mut callback: F,
+        data: T,
+    ) -> Result<MidiInputConnection<T>, ConnectError<MidiInput>>
+    where
+        F: FnMut(u64, wmidi::MidiMessage<'_>, &mut T) + Send + 'static,
+    {
+        self.connect(
+            port,
+            port_name,
+            move |stamp, message, data| {
+                if let Ok(message) = wmidi::MidiMessage::try_from(message) {
+                    callback(stamp, message, data);
+                }
+            },
+            data,
+        )
+    }
+
+    /// Connect to a specified MIDI input port like `connect`, but pass the
+    /// events to the `callback` as they have been received from the backend,
+    /// without any processing (see `os::raw` for what they contain on each
+    /// platform).
+    ///
+    /// Since no messages are decoded, the ignore flags, the sysex and
+    /// timestamp settings and the listeners of the connection have no effect.
+    #[cfg(all(
+        feature = "unsafe-raw",
+        not(feature = "jack"),
+        any(
+            target_os = "linux",
+            target_os = "macos",
+            target_os = "ios",
+            all(target_os = "windows", not(feature = "winrt"))
+        )
+    ))]
+    pub fn connect_raw<F, T: Send + 'static>(
+        self,
+        port: &MidiInputPort,
+        port_name: &str,
+        callback: F,
+        data: T,
+    ) -> Result<MidiInputConnection<T>, ConnectError<MidiInput>>
+    where
+        F: FnMut(&crate::os::raw::RawEvent<'_>, &mut T) + Send + 'static,
+    {
+        let listeners = Arc::new(Mutex::new(Listeners(Vec::new())));
+        let connected_port = ConnectedPort::new(port.id(), self.port_name(port).ok());
+        let result = self.imp.connect_raw(&port.imp, port_name, callback, data);
+        finish_connect(self.settings, result, listeners, connected_port)
+    }
+
+    /// Connect to a specified MIDI input port like `connect`, but run the
+    /// input handler loop using the given `executor` instead of spawning a
+    /// dedicated thread for it.
+    ///
+    /// Only the ALSA backend runs its own handler loop; on all other backends
+    /// input is delivered by the system and the executor is not used.
+    pub fn connect_on<F, T: Send>(
+        self,
+        executor: &dyn InputExecutor,
+        port: &MidiInputPort,
+        port_name: &str,
+        callback: F,
+        data: T,
+    ) -> Result<MidiInputConnection<T>, ConnectError<MidiInput>>
+    where
+        F: FnMut(u64, &[u8], &mut T) + Send + 'static,
+    {
+        let listeners = Arc::new(Mutex::new(Listeners(Vec::new())));
+        let callback = self.wrap_callback(callback, listeners.clone());
+        let connected_port = ConnectedPort::new(port.id(), self.port_name(port).ok());
+        let result = self
+            .imp
+            .connect_on(executor, &port.imp, port_name, callback, data);
+        finish_connect(self.settings, result, listeners, connected_port)
+    }
+}
+
+impl MidiIO for MidiInput {
+    type Port = MidiInputPort;
+
+    fn ports(&self) -> MidiInputPorts {
+        self.imp.ports_internal()
+    }
+
+    fn port_count(&self) -> usize {
+        self.imp.port_count()
+    }
+
+    fn port_name(&self, port: &MidiInputPort) -> Result<String, PortInfoError> {
+        self.imp.port_name(&port.imp)
+    }
+
+    fn port_metadata(&self, port: &MidiInputPort) -> Result<PortMetadata, PortInfoError> {
+        self.imp.port_metadata(&port.imp)
+    }
+}
+
+#[cfg(unix)]
+impl<T: Send> crate::os::unix::VirtualInput<T> for MidiInput {
+    fn create_virtual<F>(
+        self,
+        port_name: &str,
+        callback: F,
+        data: T,
+    ) -> Result<MidiInputConnection<T>, ConnectError<Self>>
+    where
+        F: FnMut(u64, &[u8], &mut T) + Send + 'static,
+    {
+        let listeners = Arc::new(Mutex::new(Listeners(Vec::new())));
+        let callback = self.wrap_callback(callback, listeners.clone());
+        let result = self.imp.create_virtual(port_name, callback, data);
+        finish_connect(
+            self.settings,
+            result,
+            listeners,
+            ConnectedPort::virtual_port(port_name),
+        )
+    }
+}
+
+#[cfg(unix)]
+impl crate::os::unix::VirtualPortOptions for MidiInput {
+    fn reject_duplicate_virtual_names(&mut self, reject: bool) {
+        self.imp.reject_duplicate_virtual_names(reject);
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl crate::os::unix::alsa::AlsaCompat for MidiInput {
+    fn set_compat(&mut self, compat: crate::os::unix::alsa::Compat) {
+        self.imp.set_compat(compat)
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl crate::os::unix::alsa::AlsaHwInfo<MidiInputPort> for MidiInput {
+    fn hw_info(&self, port: &MidiInputPort) -> Option<(i32, i32)> {
+        self.imp.hw_info(&port.imp)
+    }
+}
+
+/// A function that observes every incoming message of a
+/// `MidiInputConnection` before the ignore filter is applied
+/// (see `MidiInputConnection::set_raw_tap`).
+pub type RawTap = Box<dyn FnMut(u64, &[u8]) + Send>;
+
+/// Identifies a listener that has been added to a `MidiInputConnection`
+/// (see `MidiInputConnection::add_listener`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ListenerId(u64);
+
+type Listener = Box<dyn FnMut(u64, &[u8]) + Send>;
+
+struct Listeners(Vec<(ListenerId, Listener)>);
+
+/// Watches for changes of the default port on a background thread
+/// (see `MidiInput::watch_default_port` and `MidiOutput::watch_default_port`).
+/// Watching stops when this is dropped.
+#[cfg(not(target_arch = "wasm32"))]
+pub struct DefaultPortWatcher {
+    stop: Option<mpsc::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl DefaultPortWatcher {
+    fn spawn<P, D, I, F>(mut default_port: D, id: I, interval: Duration, mut callback: F) -> Self
+    where
+        D: FnMut() -> Option<P> + Send + 'static,
+        I: Fn(&P) -> String + Send + 'static,
+        F: FnMut(Option<P>) + Send + 'static,
+    {
+        let (stop, stopped) = mpsc::channel();
+        let thread = thread::spawn(move || {
+            let mut current = default_port().as_ref().map(&id);
+            // the sender is dropped (or used) to stop watching
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                let port = default_port();
+                let new = port.as_ref().map(&id);
+                if new != current {
+                    current = new;
+                    callback(port);
+                }
             }
+        });
+        DefaultPortWatcher {
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Drop for DefaultPortWatcher {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// A change of the available ports, as reported by `MidiInput::watch` and
+/// `MidiOutput::watch`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DeviceEvent<P> {
+    /// The port has become available.
+    Added(P),
+    /// The port is no longer available.
+    Removed(P),
+}
+
+impl<P> DeviceEvent<P> {
+    pub(crate) fn map<Q>(self, f: impl FnOnce(P) -> Q) -> DeviceEvent<Q> {
+        match self {
+            DeviceEvent::Added(port) => DeviceEvent::Added(f(port)),
+            DeviceEvent::Removed(port) => DeviceEvent::Removed(f(port)),
+        }
+    }
+}
+
+/// Watches for ports that appear or disappear (see `MidiInput::watch` and
+/// `MidiOutput::watch`). Watching stops when this is dropped.
+pub struct PortWatcher {
+    _imp: backend::PortWatcher,
+}
+
+/// Runs the input handler loop of a connection on a thread chosen by the
+/// user (see `MidiInput::connect_on`).
+pub trait InputExecutor {
+    /// Run the given task. The task blocks until the connection is closed,
+    /// so `execute` must not run it synchronously. Closing the connection
+    /// waits until the task has been run or dropped. If it is dropped
+    /// without running, no messages are received, and
+    /// `MidiInputConnection::close` returns the data passed to `connect_on`
+    /// unchanged.
+    fn execute(&self, task: Box<dyn FnOnce() + Send>);
+}
+
+/// The port that a connection has been opened to, as shown by the `Debug`
+/// implementation of the connection.
+struct ConnectedPort {
+    /// `None` for virtual ports, which don't have an ID before they are
+    /// listed by other clients.
+    id: Option<String>,
+    name: Option<String>,
+}
+
+impl ConnectedPort {
+    fn new(id: String, name: Option<String>) -> ConnectedPort {
+        ConnectedPort { id: Some(id), name }
+    }
+
+    fn virtual_port(name: &str) -> ConnectedPort {
+        ConnectedPort {
+            id: None,
+            name: Some(name.to_owned()),
         }
     }
 }
 
-impl MidiIO for MidiInput {
-    type Port = MidiInputPort;
+/// The state of `MidiInput::ports_generation`: the hash of the port IDs at the
+/// time of the previous call (if any) and the current generation.
+#[derive(Default)]
+struct PortsGeneration(Mutex<(Option<u64>, u64)>);
+
+impl PortsGeneration {
+    fn update<I: Iterator<Item = String>>(&self, ids: I) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        for id in ids {
+            id.hash(&mut hasher);
+        }
+        let hash = hasher.finish();
+        let mut state = self.0.lock().unwrap();
+        if state.0.is_some_and(|previous| previous != hash) {
+            state.1 += 1;
+        }
+        state.0 = Some(hash);
+        state.1
+    }
+}
+
+/// Represents an open connection to a MIDI input port.
+pub struct MidiInputConnection<T: 'static> {
+    imp: MidiInputConnectionImpl<T>,
+    settings: InputSettings,
+    listeners: Arc<Mutex<Listeners>>,
+    next_listener_id: u64,
+    port: ConnectedPort,
+    #[cfg(not(target_arch = "wasm32"))]
+    opened_at: Instant,
+}
+
+impl<T> MidiInputConnection<T> {
+    /// Closes the connection. The returned values allow you to
+    /// inspect the additional data passed to the callback (the `data`
+    /// parameter of `connect`), or to reuse the `MidiInput` object,
+    /// but they can be safely ignored.
+    pub fn close(self) -> (MidiInput, T) {
+        let (imp, data) = self.imp.close();
+        (
+            MidiInput {
+                imp,
+                settings: self.settings,
+            },
+            data,
+        )
+    }
+
+    /// Closes the connection like `close`, but also returns the messages that
+    /// have been received by the backend but not passed to the callback yet,
+    /// so that e.g. a recording application does not lose them. Each message
+    /// is returned with its timestamp (in the unit of the callback).
+    ///
+    /// All backends pass on complete messages before the connection is
+    /// closed, so this only contains a SysEx message whose end has not been
+    /// received yet (on ALSA, CoreMIDI, WinMM and WinRT), or on Web MIDI, the
+    /// messages that are buffered until the port has been opened (see
+    /// `EarlyMessagePolicy::Buffer`).
+    pub fn close_draining(self) -> (MidiInput, T, Vec<(u64, Vec<u8>)>) {
+        let (imp, data, pending) = self.imp.close_draining();
+        let convert = self.settings.timestamp_converter();
+        (
+            MidiInput {
+                imp,
+                settings: self.settings,
+            },
+            data,
+            pending
+                .into_iter()
+                .map(|(nanos, message)| (convert(nanos), message))
+                .collect(),
+        )
+    }
+
+    /// Set a function that is invoked with the timestamp and bytes of every
+    /// incoming message, before the message is checked against the ignore
+    /// flags set with `MidiInput::ignore`. This allows observing e.g. clock
+    /// or active sensing messages that are not delivered to the callback.
+    /// Pass `None` to remove a previously set tap.
+    ///
+    /// The tap is called on the same thread as the callback, right before it.
+    pub fn set_raw_tap(&mut self, tap: Option<RawTap>) {
+        let convert = self.settings.timestamp_converter();
+        self.imp.set_raw_tap(tap.map(|mut tap| {
+            Box::new(move |nanos, message: &[u8]| tap(convert(nanos), message)) as RawTap
+        }));
+    }
+
+    /// Add a function that is called with the timestamp and bytes of every
+    /// incoming message, in addition to the callback passed to `connect`.
+    /// This allows several parts of an application to observe the same
+    /// connection. Listeners are called on the same thread as the callback,
+    /// right after it, in the order in which they have been added. Ignored
+    /// messages (see `MidiInput::ignore`) are not passed to listeners.
+    ///
+    /// So that the thread of the callback never has to wait, a message that
+    /// arrives while a listener is being added or removed is not passed to
+    /// any listener.
+    ///
+    /// The returned `ListenerId` can be used to remove the listener again.
+    pub fn add_listener<F>(&mut self, listener: F) -> ListenerId
+    where
+        F: FnMut(u64, &[u8]) + Send + 'static,
+    {
+        let id = ListenerId(self.next_listener_id);
+        self.next_listener_id += 1;
+        self.listeners
+            .lock()
+            .unwrap()
+            .0
+            .push((id, Box::new(listener)));
+        id
+    }
+
+    /// Remove a listener that has been added with `add_listener`.
+    /// Returns `false` if there is no such listener (anymore).
+    ///
+    /// The listener might still be called once if a message is being
+    /// delivered concurrently.
+    pub fn remove_listener(&mut self, id: ListenerId) -> bool {
+        let mut listeners = self.listeners.lock().unwrap();
+        let count = listeners.0.len();
+        listeners.0.retain(|(listener_id, _)| *listener_id != id);
+        listeners.0.len() != count
+    }
+
+    /// Discard all input that has been received, but not yet passed to the
+    /// callback (e.g. when resuming after a pause, to avoid a burst of stale
+    /// messages). This includes a partially received sysex message.
+    ///
+    /// Messages that are already being delivered may still reach the callback
+    /// after this returns.
+    pub fn clear_input(&mut self) {
+        self.imp.clear_input();
+    }
+
+    /// Get the number of messages that have been received on this connection,
+    /// but were not passed to the callback because they are filtered out by
+    /// the `Ignore` flags (or because they exceed the size set with
+    /// `MidiInput::set_max_sysex_size`). A SysEx message that arrives in
+    /// several segments is counted once.
+    ///
+    /// This helps to find out why expected messages (e.g. SysEx) don't arrive.
+    /// On JACK, the ignore flags are not applied, so this is always 0.
+    pub fn filtered_count(&self) -> u64 {
+        self.imp.filtered_count()
+    }
+
+    /// Get the ID of the thread that invokes the callback, if *midir* has
+    /// spawned a dedicated thread for this connection (currently only ALSA,
+    /// unless the connection was opened with `connect_on`).
+    ///
+    /// On all other backends, the callback is invoked on a thread owned by the
+    /// operating system, driver or browser, so this returns `None`.
+    pub fn callback_thread_id(&self) -> Option<ThreadId> {
+        self.imp.callback_thread_id()
+    }
+
+    /// Set the maximum time to wait for the backend to shut down the port
+    /// when the connection is closed or dropped. If the timeout expires, a
+    /// warning is printed and the port is abandoned (leaking its resources)
+    /// so that the application does not freeze, e.g. on exit. Messages that
+    /// arrive afterwards are discarded, and `close_draining` returns no
+    /// pending messages. By default (`None`), there is no timeout.
+    ///
+    /// This is currently only implemented for WinMM, where some drivers are
+    /// known to hang while the port is being reset.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn set_close_timeout(&mut self, timeout: Option<Duration>) {
+        self.imp.set_close_timeout(timeout);
+    }
+
+    /// Returns the point in time at which this connection was opened.
+    ///
+    /// This is not available on the Web MIDI backend, because `Instant`
+    /// is not supported on `wasm32`.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn opened_at(&self) -> Instant {
+        self.opened_at
+    }
+
+    /// Returns how long this connection has been open.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn uptime(&self) -> Duration {
+        self.opened_at.elapsed()
+    }
+}
+
+/// Shows the backend, the port and the state of the connection, which is
+/// helpful when reporting issues.
+impl<T> fmt::Debug for MidiInputConnection<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut s = f.debug_struct("MidiInputConnection");
+        s.field("backend", &self.imp.backend_name())
+            .field("port_id", &self.port.id)
+            .field("port_name", &self.port.name);
+        #[cfg(not(target_arch = "wasm32"))]
+        s.field("uptime", &self.uptime());
+        s.field("filtered_count", &self.filtered_count())
+            .field("listeners", &self.listeners.lock().unwrap().0.len())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Represents an open connection to a MIDI input port whose messages are
+/// polled instead of being passed to a callback (see `MidiInput::connect_rx`).
+#[derive(Debug)]
+pub struct MidiInputRx {
+    conn: MidiInputConnection<mpsc::Sender<(u64, Vec<u8>)>>,
+    receiver: mpsc::Receiver<(u64, Vec<u8>)>,
+}
+
+impl MidiInputRx {
+    /// Closes the connection. Messages that have not been polled yet are
+    /// discarded. The returned `MidiInput` can be reused.
+    pub fn close(self) -> MidiInput {
+        self.conn.close().0
+    }
+
+    /// Returns the oldest message that has not been polled yet, together
+    /// with its timestamp, or `None` if there is none. This never blocks.
+    pub fn try_recv(&self) -> Option<(u64, Vec<u8>)> {
+        self.receiver.try_recv().ok()
+    }
+
+    /// Like `try_recv`, but waits up to `timeout` for a message to arrive.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<(u64, Vec<u8>)> {
+        self.receiver.recv_timeout(timeout).ok()
+    }
+}
+
+#[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "jack")))]
+impl crate::os::unix::coremidi::CoreMidiPort for MidiInputPort {
+    fn is_virtual(&self) -> bool {
+        self.imp.is_virtual()
+    }
+}
+
+#[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "jack")))]
+impl crate::os::unix::coremidi::CoreMidiPort for MidiOutputPort {
+    fn is_virtual(&self) -> bool {
+        self.imp.is_virtual()
+    }
+}
+
+#[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "jack")))]
+impl<T> crate::os::unix::coremidi::CoreMidiConnection for MidiInputConnection<T> {
+    fn set_port_name(&mut self, name: &str) -> Result<(), ConnectErrorKind> {
+        self.imp.set_port_name(name)
+    }
+}
+
+#[cfg(all(target_os = "windows", not(feature = "winrt")))]
+impl<T> crate::os::windows::winmm::WinMmInputConnection for MidiInputConnection<T> {
+    fn pause(&mut self) -> Result<(), ConnectErrorKind> {
+        self.imp.pause()
+    }
+
+    fn resume(&mut self) -> Result<(), ConnectErrorKind> {
+        self.imp.resume()
+    }
+
+    fn is_receiving(&self) -> bool {
+        self.imp.is_receiving()
+    }
+}
+
+#[cfg(all(feature = "jack", unix))]
+impl crate::os::unix::jack::JackInput for MidiInput {
+    fn set_connect_options(&mut self, options: crate::os::unix::jack::JackConnectOptions) {
+        self.imp.set_connect_options(options);
+    }
 
-    fn ports(&self) -> MidiInputPorts {
-        self.imp.ports_internal()
+    fn set_frame_timestamps(&mut self, enable: bool) {
+        self.imp.set_frame_timestamps(enable);
+        // Only JACK reports frame times, so this stays `None` with ALSA
+        self.settings.frame_rate = self.imp.frame_rate();
     }
+}
 
-    fn port_count(&self) -> usize {
-        self.imp.port_count()
+#[cfg(all(feature = "jack", unix))]
+impl<T> crate::os::unix::jack::JackConnection for MidiInputConnection<T> {
+    fn set_latency_range(
+        &mut self,
+        mode: crate::os::unix::jack::LatencyMode,
+        min: u32,
+        max: u32,
+    ) -> Result<(), ConnectErrorKind> {
+        self.imp.set_latency_range(mode, min, max)
     }
 
-    fn port_name(&self, port: &MidiInputPort) -> Result<String, PortInfoError> {
-        self.imp.port_name(&port.imp)
+    fn latency_range(
+        &self,
+        mode: crate::os::unix::jack::LatencyMode,
+    ) -> Result<(u32, u32), ConnectErrorKind> {
+        self.imp.latency_range(mode)
     }
 }
 
-#[cfg(unix)]
-impl<T: Send> crate::os::unix::VirtualInput<T> for MidiInput {
-    fn create_virtual<F>(
-        self,
-        port_name: &str,
-        callback: F,
-        data: T,
-    ) -> Result<MidiInputConnection<T>, ConnectError<Self>>
-    where
-        F: FnMut(u64, &[u8], &mut T) + Send + 'static,
-    {
-        match self.imp.create_virtual(port_name, callback, data) {
-            Ok(imp) => Ok(MidiInputConnection { imp }),
-            Err(imp) => {
-                let kind = imp.kind();
-                Err(ConnectError::new(
-                    kind,
-                    MidiInput {
-                        imp: imp.into_inner(),
-                    },
-                ))
-            }
-        }
+#[cfg(target_os = "linux")]
+impl<T> crate::os::unix::alsa::AlsaInputConnection for MidiInputConnection<T> {
+    fn subscribe_from(&mut self, sender: (i32, i32)) -> Result<(), ConnectErrorKind> {
+        self.imp.subscribe_from(sender)
     }
-}
 
-/// Represents an open connection to a MIDI input port.
-pub struct MidiInputConnection<T: 'static> {
-    imp: MidiInputConnectionImpl<T>,
-}
+    fn unsubscribe_from(&mut self, sender: (i32, i32)) -> Result<(), ConnectErrorKind> {
+        self.imp.unsubscribe_from(sender)
+    }
 
-impl<T> MidiInputConnection<T> {
-    /// Closes the connection. The returned values allow you to
-    /// inspect the additional data passed to the callback (the `data`
-    /// parameter of `connect`), or to reuse the `MidiInput` object,
-    /// but they can be safely ignored.
-    pub fn close(self) -> (MidiInput, T) {
-        let (imp, data) = self.imp.close();
-        (MidiInput { imp }, data)
+    fn on_subscription_change(
+        &mut self,
+        callback: Option<crate::os::unix::alsa::SubscriptionCallback>,
+    ) -> Result<(), ConnectErrorKind> {
+        self.imp.on_subscription_change(callback)
+    }
+
+    fn timestamps_degraded(&self) -> bool {
+        self.imp.timestamps_degraded()
     }
 }
 
@@ -218,6 +1748,44 @@ impl MidiOutputPort {
     pub fn id(&self) -> String {
         self.imp.id()
     }
+
+    /// Construct a port object from an identifier returned by `id` (e.g. one
+    /// that has been stored in the settings of an application), without
+    /// enumerating the ports first. Whether the port actually exists is
+    /// checked when connecting to it.
+    ///
+    /// On ALSA, JACK and WinRT the identifier is all that is needed to
+    /// connect. On CoreMIDI and WinMM, the port has to be present, and on
+    /// Web MIDI, access to MIDI devices has to be granted already, otherwise
+    /// `None` is returned (as it is for identifiers that are malformed).
+    pub fn from_id(id: &str) -> Option<MidiOutputPort> {
+        MidiOutputPortImpl::from_id(id).map(|imp| MidiOutputPort { imp })
+    }
+
+    /// Get an object that displays the name of this port, as returned by
+    /// `MidiOutput::port_name`, or its ID if the name can't be retrieved.
+    pub fn display_with<'a>(&'a self, midi_out: &'a MidiOutput) -> impl fmt::Display + 'a {
+        PortDisplay {
+            midi_io: midi_out,
+            port: self,
+        }
+    }
+}
+
+/// Shows the ID of the port (see `MidiOutputPort::id`).
+impl fmt::Debug for MidiOutputPort {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MidiOutputPort")
+            .field("id", &self.id())
+            .finish()
+    }
+}
+
+/// Displays the ID of the port (see `id`). Use `display_with` to show its name.
+impl fmt::Display for MidiOutputPort {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.id())
+    }
 }
 
 /// A collection of output ports.
@@ -235,6 +1803,72 @@ impl MidiOutput {
         MidiOutputImpl::new(client_name).map(|imp| MidiOutput { imp })
     }
 
+    /// Creates a new `MidiOutput` object that uses the given backend.
+    /// `new` uses JACK in this configuration.
+    #[cfg(all(target_os = "linux", feature = "jack"))]
+    pub fn new_with_backend(backend: Backend, client_name: &str) -> Result<Self, InitError> {
+        MidiOutputImpl::new_with_backend(backend, client_name).map(|imp| MidiOutput { imp })
+    }
+
+    /// Creates a new `MidiOutput` object that uses the JACK backend with the
+    /// given options, e.g. to start the JACK server if it is not running
+    /// (`new` never does this). If this fails, the `InitError` tells why.
+    #[cfg(all(feature = "jack", unix))]
+    pub fn new_with_options(
+        client_name: &str,
+        options: crate::os::unix::jack::JackStartOptions,
+    ) -> Result<Self, InitError> {
+        MidiOutputImpl::new_with_options(client_name, options).map(|imp| MidiOutput { imp })
+    }
+
+    /// Get the backend that this `MidiOutput` object uses.
+    #[cfg(all(target_os = "linux", feature = "jack"))]
+    pub fn backend(&self) -> Backend {
+        self.imp.backend()
+    }
+
+    /// Creates a new `MidiOutput` object and immediately connects to the port
+    /// chosen by `selector`. This combines `new`, `ports` and `connect` in a
+    /// single call, using `client_name` also as the name of the connection.
+    pub fn open(
+        client_name: &str,
+        selector: PortSelector,
+    ) -> Result<MidiOutputConnection, OpenError> {
+        let midi_out = MidiOutput::new(client_name).map_err(OpenError::Init)?;
+        let port = select_port(&midi_out, &selector, MidiOutputPort::id)
+            .ok_or(OpenError::NoMatchingPort)?;
+        midi_out
+            .connect(&port, client_name)
+            .map_err(|e| OpenError::Connect(e.kind()))
+    }
+
+    /// Change the client name that was passed to `new`, i.e. the name under
+    /// which this object appears to other applications (e.g. in `aconnect -l`
+    /// on ALSA or as the CoreMIDI client name).
+    ///
+    /// The client itself is kept, so other applications see the same client
+    /// (with the same client ID on ALSA) under its new name, and connections
+    /// opened afterwards belong to the renamed client. Since `connect`
+    /// consumes this object, its own connections must be closed first (which
+    /// returns it); connections of other `MidiInput` or `MidiOutput` objects
+    /// are not affected, because each of them is a separate client.
+    ///
+    /// This is only supported on ALSA, CoreMIDI and WebMIDI (where the name
+    /// is only used by `client_name`). All other backends return a
+    /// `ConnectErrorKind::Other` error saying that renaming is not supported.
+    pub fn set_client_name(&mut self, name: &str) -> Result<(), ConnectErrorKind> {
+        self.imp.set_client_name(name)
+    }
+
+    /// Whether the backend can create virtual ports (see `os::unix::VirtualOutput`),
+    /// so that portable code can decide at runtime whether to offer them.
+    ///
+    /// This is the case for ALSA, CoreMIDI and JACK, but not for WinMM, WinRT
+    /// and WebMIDI (where `VirtualOutput` is not available at all).
+    pub fn supports_virtual_ports(&self) -> bool {
+        self.imp.supports_virtual_ports()
+    }
+
     /// Get a collection of all MIDI output ports that *midir* can connect to.
     /// The resulting vector contains one object per port, which you can use to
     /// query metadata about the port or connect to it in order to send
@@ -243,11 +1877,63 @@ impl MidiOutput {
         self.imp.ports_internal()
     }
 
+    /// Get the available MIDI output ports, without those that are known to be
+    /// unavailable, e.g. because another application has an exclusive
+    /// connection (ALSA) or because the device is offline (CoreMIDI) or
+    /// disconnected (WebMIDI). The ports are not opened to find this out, so
+    /// connecting to one of the returned ports may still fail. Ports for which
+    /// this can't be determined (e.g. on WinMM) are always included.
+    pub fn connectable_ports(&self) -> MidiOutputPorts {
+        self.ports()
+            .into_iter()
+            .filter(|port| self.imp.is_connectable(&port.imp))
+            .collect()
+    }
+
+    /// Get a collection of all MIDI output ports like `ports`, but on Web MIDI,
+    /// wait until the browser has granted access to MIDI devices first
+    /// (requesting it if necessary). Before that, `ports` returns no ports.
+    ///
+    /// On all other backends, the ports are available right away.
+    pub async fn ports_async(&self) -> MidiOutputPorts {
+        #[cfg(target_arch = "wasm32")]
+        backend::wait_for_access().await;
+        self.ports()
+    }
+
+    /// Get the available MIDI output ports in a stable order, which does not
+    /// change when ports are enumerated again, so that lists shown to users
+    /// don't jump around (unlike `ports`, whose order depends on the backend
+    /// and may change when devices are connected).
+    ///
+    /// The ports are sorted by the name of their device, then by their own
+    /// name and finally by their unique identifier.
+    pub fn ports_sorted(&self) -> MidiOutputPorts {
+        let mut ports = self.ports();
+        ports.sort_by_cached_key(|port| {
+            (
+                self.imp.device_name(&port.imp).unwrap_or_default(),
+                self.port_name(port).unwrap_or_default(),
+                port.id(),
+            )
+        });
+        ports
+    }
+
     /// Get the number of available MIDI output ports that *midir* can connect to.
     pub fn port_count(&self) -> usize {
         self.imp.port_count()
     }
 
+    /// Get the client name that was passed to `new` (or `set_client_name`).
+    ///
+    /// The Web MIDI API has no notion of client names, so the name is not
+    /// passed on to the browser and this is the only place where it appears.
+    #[cfg(target_arch = "wasm32")]
+    pub fn client_name(&self) -> &str {
+        self.imp.client_name()
+    }
+
     /// Get the name of a specified MIDI output port.
     ///
     /// An error will be returned when the port is no longer valid
@@ -256,11 +1942,156 @@ impl MidiOutput {
         self.imp.port_name(&port.imp)
     }
 
+    /// Get information about the device of a specified MIDI output port
+    /// (see `MidiIO::port_metadata`).
+    pub fn port_metadata(&self, port: &MidiOutputPort) -> Result<PortMetadata, PortInfoError> {
+        self.imp.port_metadata(&port.imp)
+    }
+
+    /// Get all MIDI output ports together with their names, leaving out the
+    /// ports whose name can't be retrieved (see `MidiIO::ports_with_names`).
+    pub fn ports_with_names(&self) -> Vec<(MidiOutputPort, String)> {
+        <Self as MidiIO>::ports_with_names(self)
+    }
+
+    /// Returns whether the given port belongs to a synthesizer, e.g. a software
+    /// synth like FluidSynth or the Microsoft GS Wavetable Synth.
+    ///
+    /// Only ALSA, WinMM and WinRT can detect synthesizers; on all other
+    /// backends this always returns `false`.
+    pub fn is_synth_port(&self, port: &MidiOutputPort) -> bool {
+        self.imp.is_synth_port(&port.imp)
+    }
+
+    /// Find the first port that belongs to a synthesizer (see `is_synth_port`).
+    /// This is useful to play notes without asking the user to choose a port.
+    pub fn find_synth_port(&self) -> Option<MidiOutputPort> {
+        self.ports()
+            .into_iter()
+            .find(|port| self.is_synth_port(port))
+    }
+
+    /// Find the port of the software synthesizer that comes with the system,
+    /// so that notes can be played without any hardware.
+    ///
+    /// This matches the names of well-known synthesizers: the Microsoft GS
+    /// Wavetable Synth on Windows, and FluidSynth or TiMidity++ (which many
+    /// Linux distributions provide) on ALSA and JACK. The Apple DLS Synth on
+    /// macOS is an Audio Unit without a MIDI endpoint, so there is no such
+    /// port on CoreMIDI and this returns `None` there.
+    pub fn builtin_synth_port(&self) -> Option<MidiOutputPort> {
+        const BUILTIN_SYNTH_NAMES: &[&str] = &[
+            "Microsoft GS Wavetable Synth",
+            "FLUID Synth",
+            "fluidsynth",
+            "TiMidity",
+        ];
+        self.ports().into_iter().find(|port| {
+            self.port_name(port)
+                .is_ok_and(|name| BUILTIN_SYNTH_NAMES.iter().any(|synth| name.contains(synth)))
+        })
+    }
+
+    /// Find the output port that belongs to the same device as the given input
+    /// port, e.g. to avoid echoing messages back to a keyboard when forwarding
+    /// its input.
+    ///
+    /// On ALSA and JACK this matches ports of the same client, on WebMIDI ports
+    /// with the same name and manufacturer, and on the other backends ports with
+    /// the same name (because the backends don't expose which device a port
+    /// belongs to).
+    pub fn find_sibling_port(&self, input: &MidiInputPort) -> Option<MidiOutputPort> {
+        self.ports()
+            .into_iter()
+            .find(|port| self.imp.is_sibling_port(&input.imp, &port.imp))
+    }
+
     /// Get a MIDI output port by its unique identifier.
     pub fn find_port_by_id(&self, id: String) -> Option<MidiOutputPort> {
         self.ports().into_iter().find(|port| port.id() == id)
     }
 
+    /// Get a MIDI output port by its name, which is compared to `pattern`
+    /// according to `mode`. If several ports match, the first one in the
+    /// order of `ports` is returned. Ports whose name can't be retrieved
+    /// never match.
+    pub fn find_port_by_name(&self, pattern: &str, mode: MatchMode) -> Option<MidiOutputPort> {
+        find_port_by_name(self, pattern, mode)
+    }
+
+    /// Get the MIDI output ports that have been created by a client (i.e. an
+    /// application or a device driver) whose name contains `name_substr`,
+    /// e.g. `"FluidSynth"`. This is useful for diagnostics and routing in
+    /// setups with several applications.
+    ///
+    /// On ALSA and JACK, this is the name of the sequencer or JACK client that
+    /// owns the port. On CoreMIDI, the name of the device or application that
+    /// the port belongs to is used instead.
+    #[cfg(unix)]
+    pub fn ports_by_client(&self, name_substr: &str) -> Vec<MidiOutputPort> {
+        self.ports()
+            .into_iter()
+            .filter(|port| {
+                self.imp
+                    .device_name(&port.imp)
+                    .is_some_and(|name| name.contains(name_substr))
+            })
+            .collect()
+    }
+
+    /// Get the default MIDI output port. None of the backends has a notion of
+    /// a system default output, so this is the first available port.
+    pub fn default_port(&self) -> Option<MidiOutputPort> {
+        self.ports().into_iter().next()
+    }
+
+    /// Invoke `callback` whenever `default_port` would return a different
+    /// port than before (e.g. because a device has been connected or
+    /// disconnected), until the returned `DefaultPortWatcher` is dropped.
+    ///
+    /// A separate `MidiOutput` is created with the given `client_name`, which
+    /// is polled in the given `interval` on a background thread, because
+    /// the backends don't provide notifications for this.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn watch_default_port<F>(
+        client_name: &str,
+        interval: Duration,
+        callback: F,
+    ) -> Result<DefaultPortWatcher, InitError>
+    where
+        F: FnMut(Option<MidiOutputPort>) + Send + 'static,
+    {
+        let midi_out = MidiOutput::new(client_name)?;
+        Ok(DefaultPortWatcher::spawn(
+            move || midi_out.default_port(),
+            MidiOutputPort::id,
+            interval,
+            callback,
+        ))
+    }
+
+    /// Invoke `callback` whenever a MIDI output port appears or disappears
+    /// (e.g. because a device has been connected or disconnected), until the
+    /// returned `PortWatcher` is dropped.
+    ///
+    /// This uses the notifications of the backend: the announcements of the
+    /// system client on ALSA, the port registration callback on JACK, the
+    /// `MIDINotifyProc` on CoreMIDI, a `DeviceWatcher` on WinRT and the
+    /// `statechange` event on Web MIDI. WinMM doesn't report when ports appear
+    /// or disappear, so an error is returned there. The callback is invoked on
+    /// a thread of the backend (on CoreMIDI, the run loop of the thread that
+    /// called `watch`), and the port that is passed with `DeviceEvent::Removed`
+    /// may no longer be used to query its name.
+    pub fn watch<F>(&self, mut callback: F) -> Result<PortWatcher, InitError>
+    where
+        F: FnMut(DeviceEvent<MidiOutputPort>) + Send + 'static,
+    {
+        let imp = self.imp.watch(Box::new(move |event| {
+            callback(event.map(|imp| MidiOutputPort { imp }))
+        }))?;
+        Ok(PortWatcher { _imp: imp })
+    }
+
     /// Connect to a specified MIDI output port in order to send messages.
     /// The connection will be kept open as long as the returned
     /// `MidiOutputConnection` is kept alive.
@@ -275,8 +2106,15 @@ impl MidiOutput {
         port: &MidiOutputPort,
         port_name: &str,
     ) -> Result<MidiOutputConnection, ConnectError<MidiOutput>> {
+        let connected_port = ConnectedPort::new(port.id(), self.port_name(port).ok());
         match self.imp.connect(&port.imp, port_name) {
-            Ok(imp) => Ok(MidiOutputConnection { imp }),
+            Ok(imp) => Ok(MidiOutputConnection {
+                imp,
+                transform: None,
+                port: connected_port,
+                #[cfg(not(target_arch = "wasm32"))]
+                opened_at: Instant::now(),
+            }),
             Err(imp) => {
                 let kind = imp.kind();
                 Err(ConnectError::new(
@@ -288,6 +2126,35 @@ impl MidiOutput {
             }
         }
     }
+
+    /// Like `connect`, but verifies that the port accepts messages right
+    /// away by sending the given `probe` message, so that a dead connection
+    /// is noticed before the first real message is sent.
+    ///
+    /// The probe should be a message that the device ignores or that doesn't
+    /// change its state, e.g. a SysEx message with a manufacturer ID that it
+    /// doesn't respond to. Active Sensing (`0xFE`) is not a good choice: a
+    /// device that receives it expects it to be repeated at least every
+    /// 300 ms, and silences its notes if it isn't.
+    ///
+    /// This can only detect failures that the backend reports when sending.
+    /// Most backends don't get any confirmation of delivery from the device,
+    /// so an unresponsive device can still go unnoticed.
+    pub fn connect_verified(
+        self,
+        port: &MidiOutputPort,
+        port_name: &str,
+        probe: &[u8],
+    ) -> Result<MidiOutputConnection, ConnectError<MidiOutput>> {
+        let mut conn = self.connect(port, port_name)?;
+        match conn.imp.send(probe) {
+            Ok(()) => Ok(conn),
+            Err(_) => Err(ConnectError::other(
+                "port did not accept the verification message",
+                conn.close(),
+            )),
+        }
+    }
 }
 
 impl MidiIO for MidiOutput {
@@ -304,6 +2171,10 @@ impl MidiIO for MidiOutput {
     fn port_name(&self, port: &MidiOutputPort) -> Result<String, PortInfoError> {
         self.imp.port_name(&port.imp)
     }
+
+    fn port_metadata(&self, port: &MidiOutputPort) -> Result<PortMetadata, PortInfoError> {
+        self.imp.port_metadata(&port.imp)
+    }
 }
 
 #[cfg(unix)]
@@ -313,7 +2184,13 @@ impl crate::os::unix::VirtualOutput for MidiOutput {
         port_name: &str,
     ) -> Result<MidiOutputConnection, ConnectError<MidiOutput>> {
         match self.imp.create_virtual(port_name) {
-            Ok(imp) => Ok(MidiOutputConnection { imp }),
+            Ok(imp) => Ok(MidiOutputConnection {
+                imp,
+                transform: None,
+                port: ConnectedPort::virtual_port(port_name),
+                #[cfg(not(target_arch = "wasm32"))]
+                opened_at: Instant::now(),
+            }),
             Err(imp) => {
                 let kind = imp.kind();
                 Err(ConnectError::new(
@@ -327,27 +2204,370 @@ impl crate::os::unix::VirtualOutput for MidiOutput {
     }
 }
 
+#[cfg(unix)]
+impl crate::os::unix::VirtualPortOptions for MidiOutput {
+    fn reject_duplicate_virtual_names(&mut self, reject: bool) {
+        self.imp.reject_duplicate_virtual_names(reject);
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl crate::os::unix::alsa::AlsaHwInfo<MidiOutputPort> for MidiOutput {
+    fn hw_info(&self, port: &MidiOutputPort) -> Option<(i32, i32)> {
+        self.imp.hw_info(&port.imp)
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl crate::os::unix::alsa::AlsaOutputConnection for MidiOutputConnection {
+    fn set_running_status(&mut self, enable: bool) -> Result<(), ConnectErrorKind> {
+        self.imp.set_running_status(enable)
+    }
+}
+
+#[cfg(all(feature = "jack", unix))]
+impl crate::os::unix::jack::JackOutput for MidiOutput {
+    fn set_connect_options(&mut self, options: crate::os::unix::jack::JackConnectOptions) {
+        self.imp.set_connect_options(options);
+    }
+}
+
+#[cfg(all(feature = "jack", unix))]
+impl crate::os::unix::jack::JackConnection for MidiOutputConnection {
+    fn set_latency_range(
+        &mut self,
+        mode: crate::os::unix::jack::LatencyMode,
+        min: u32,
+        max: u32,
+    ) -> Result<(), ConnectErrorKind> {
+        self.imp.set_latency_range(mode, min, max)
+    }
+
+    fn latency_range(
+        &self,
+        mode: crate::os::unix::jack::LatencyMode,
+    ) -> Result<(u32, u32), ConnectErrorKind> {
+        self.imp.latency_range(mode)
+    }
+}
+
+#[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "jack")))]
+impl crate::os::unix::coremidi::CoreMidiConnection for MidiOutputConnection {
+    fn set_port_name(&mut self, name: &str) -> Result<(), ConnectErrorKind> {
+        self.imp.set_port_name(name)
+    }
+}
+
+/// A function that is applied to every outgoing message of a
+/// `MidiOutputConnection` (see `MidiOutputConnection::set_transform`).
+/// Returning `None` drops the message.
+pub type OutputTransform = Box<dyn FnMut(&[u8]) -> Option<Vec<u8>> + Send>;
+
 /// Represents an open connection to a MIDI output port.
 pub struct MidiOutputConnection {
     imp: MidiOutputConnectionImpl,
+    transform: Option<OutputTransform>,
+    port: ConnectedPort,
+    #[cfg(not(target_arch = "wasm32"))]
+    opened_at: Instant,
 }
 
 impl MidiOutputConnection {
     /// Closes the connection. The returned value allows you to
     /// reuse the `MidiOutput` object, but it can be safely ignored.
+    ///
+    /// On Web MIDI, closing a port is asynchronous, so the port may still be
+    /// open when this returns. Use `close_async` to wait until it is closed.
     pub fn close(self) -> MidiOutput {
         MidiOutput {
             imp: self.imp.close(),
         }
     }
 
+    /// Closes the connection and waits until the browser has closed the port
+    /// (see `close`).
+    #[cfg(target_arch = "wasm32")]
+    pub async fn close_async(self) -> MidiOutput {
+        MidiOutput {
+            imp: self.imp.close_async().await,
+        }
+    }
+
     /// Send a message to the port that this output connection is connected to.
     /// The message must be a valid MIDI message (see https://www.midi.org/specifications-old/item/table-1-summary-of-midi-message).
+    ///
+    /// If a transform has been set using `set_transform`, it is applied before
+    /// the message is handed to the backend.
+    ///
+    /// Messages sent on the same connection are delivered in the order of the
+    /// calls to `send` by all backends; midir never reorders them in its
+    /// internal buffers. There is no ordering guarantee across different
+    /// connections.
     pub fn send(&mut self, message: &[u8]) -> Result<(), SendError> {
-        self.imp.send(message)
+        match self.transform {
+            Some(ref mut transform) => match transform(message) {
+                Some(transformed) => self.imp.send(&transformed),
+                None => Ok(()),
+            },
+            None => self.imp.send(message),
+        }
+    }
+
+    /// Send a message at the given time, measured in microseconds since this
+    /// connection has been opened (see `uptime`), instead of right away. This
+    /// returns immediately, so that e.g. a whole bar of a sequence can be
+    /// sent at once, and the backend sends the message when it is due. A
+    /// message whose time has already passed is sent right away.
+    ///
+    /// Scheduling is supported by ALSA (using a queue of the sequencer, which
+    /// is allocated when this is first called) and CoreMIDI (using the
+    /// timestamp of the packet). The other backends return
+    /// `SendError::Other("scheduled send not supported")`. Messages that are
+    /// still scheduled when the connection is closed are dropped on ALSA.
+    ///
+    /// The ordering guarantee of `send` only holds among messages with
+    /// increasing times, not between scheduled messages and messages that
+    /// are sent with `send`. This is not available on the Web MIDI backend,
+    /// because `Instant` is not supported on `wasm32`.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn send_at(&mut self, message: &[u8], timestamp_us: u64) -> Result<(), SendError> {
+        let delay = Duration::from_micros(timestamp_us).saturating_sub(self.uptime());
+        match self.transform {
+            Some(ref mut transform) => match transform(message) {
+                Some(transformed) => self.imp.send_at(&transformed, delay),
+                None => Ok(()),
+            },
+            None => self.imp.send_at(message, delay),
+        }
+    }
+
+    /// Send All Sound Off and All Notes Off on all 16 channels, to silence
+    /// notes that would otherwise keep sounding (e.g. because their Note Off
+    /// messages got lost).
+    ///
+    /// All messages are attempted even if one of them fails; the first error
+    /// is returned.
+    pub fn panic(&mut self) -> Result<(), SendError> {
+        let mut result = Ok(());
+        for channel in 0..16 {
+            // All Sound Off (120), All Notes Off (123)
+            for controller in [120, 123] {
+                let sent = self.send(&[0xB0 | channel, controller, 0]);
+                if result.is_ok() {
+                    result = sent;
+                }
+            }
+        }
+        result
+    }
+
+    /// Wrap this connection in a `PanicGuardedOutput`, which calls `panic`
+    /// when it is dropped, so that no notes keep sounding when the connection
+    /// goes away unexpectedly (e.g. because of a panic or an early return).
+    pub fn with_panic_guard(self) -> PanicGuardedOutput {
+        PanicGuardedOutput { conn: Some(self) }
+    }
+
+    /// Send several messages at once, in the given order. If a message can't
+    /// be sent, the following ones are not sent either.
+    ///
+    /// On WinRT, all messages are sent in one buffer, which is considerably
+    /// faster than sending them one by one. The other backends send them
+    /// individually. If a transform has been set using `set_transform`, it is
+    /// applied to every message, which then are always sent individually.
+    pub fn send_all(&mut self, messages: &[&[u8]]) -> Result<(), SendError> {
+        if self.transform.is_some() {
+            for message in messages {
+                self.send(message)?;
+            }
+            Ok(())
+        } else {
+            self.imp.send_all(messages)
+        }
+    }
+
+    /// Send a `wmidi::MidiMessage` like `send`. SysEx messages are sent with
+    /// the leading `0xF0` and the trailing `0xF7` added.
+    #[cfg(feature = "wmidi")]
+    pub fn send_wmidi(&mut self, message: &wmidi::MidiMessage<'_>) -> Result<(), SendError> {
+        with_wmidi_bytes(message, |bytes| self.send(bytes))
+    }
+
+    /// Set a function that transforms every message passed to `send` (e.g. to
+    /// transpose notes or remap channels) before it is sent. If the function
+    /// returns `None`, the message is dropped silently. Pass `None` to remove
+    /// a previously set transform.
+    ///
+    /// The transform runs synchronously on the thread that calls `send` and
+    /// allocates a new buffer for every message it returns, so it should be
+    /// kept cheap for dense output.
+    pub fn set_transform(&mut self, transform: Option<OutputTransform>) {
+        self.transform = transform;
+    }
+
+    /// Returns whether the port accepts system exclusive messages, or `None`
+    /// if this is not known.
+    ///
+    /// Only Web MIDI reports this (depending on whether sysex access has been
+    /// granted); none of the other backends know the capabilities of the
+    /// device behind a port.
+    pub fn supports_sysex(&self) -> Option<bool> {
+        self.imp.supports_sysex()
+    }
+
+    /// Returns the maximum size of a system exclusive message (in bytes)
+    /// that the port accepts, or `None` if there is no known limit.
+    ///
+    /// No backend currently advertises such a limit, so this always returns
+    /// `None` for now.
+    pub fn max_sysex_size(&self) -> Option<usize> {
+        self.imp.max_sysex_size()
+    }
+
+    /// Returns the point in time at which this connection was opened.
+    ///
+    /// This is not available on the Web MIDI backend, because `Instant`
+    /// is not supported on `wasm32`.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn opened_at(&self) -> Instant {
+        self.opened_at
+    }
+
+    /// Returns how long this connection has been open.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn uptime(&self) -> Duration {
+        self.opened_at.elapsed()
     }
 }
 
+/// Shows the backend, the port and the state of the connection, which is
+/// helpful when reporting issues.
+impl fmt::Debug for MidiOutputConnection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut s = f.debug_struct("MidiOutputConnection");
+        s.field("backend", &self.imp.backend_name())
+            .field("port_id", &self.port.id)
+            .field("port_name", &self.port.name);
+        #[cfg(not(target_arch = "wasm32"))]
+        s.field("uptime", &self.uptime());
+        s.field("transform", &self.transform.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+/// An output connection that sends All Sound Off and All Notes Off on all
+/// channels when it is dropped (see `MidiOutputConnection::with_panic_guard`).
+///
+/// It dereferences to the wrapped `MidiOutputConnection`, so it can be used
+/// for sending like the connection itself.
+#[derive(Debug)]
+pub struct PanicGuardedOutput {
+    // Only `None` after `into_inner`
+    conn: Option<MidiOutputConnection>,
+}
+
+impl PanicGuardedOutput {
+    /// Returns the wrapped connection without sending anything.
+    pub fn into_inner(mut self) -> MidiOutputConnection {
+        self.conn.take().unwrap()
+    }
+
+    /// Silences all notes (see `MidiOutputConnection::panic`) and closes the
+    /// connection, returning the `MidiOutput` object like
+    /// `MidiOutputConnection::close`.
+    pub fn close(self) -> MidiOutput {
+        let mut conn = self.into_inner();
+        let _ = conn.panic();
+        conn.close()
+    }
+}
+
+impl std::ops::Deref for PanicGuardedOutput {
+    type Target = MidiOutputConnection;
+
+    fn deref(&self) -> &MidiOutputConnection {
+        self.conn.as_ref().unwrap()
+    }
+}
+
+impl std::ops::DerefMut for PanicGuardedOutput {
+    fn deref_mut(&mut self) -> &mut MidiOutputConnection {
+        self.conn.as_mut().unwrap()
+    }
+}
+
+impl Drop for PanicGuardedOutput {
+    fn drop(&mut self) {
+        if let Some(ref mut conn) = self.conn {
+            // Errors can't be reported from here, and the port may already be gone
+            let _ = conn.panic();
+        }
+    }
+}
+
+/// Passes the bytes of a `wmidi::MidiMessage` to `f`, without allocating
+/// unless it is a SysEx message.
+#[cfg(feature = "wmidi")]
+fn with_wmidi_bytes<R>(message: &wmidi::MidiMessage<'_>, f: impl FnOnce(&[u8]) -> R) -> R {
+    let mut buffer = [0; 3];
+    match message.copy_to_slice(&mut buffer) {
+        Ok(len) => f(&buffer[..len]),
+        Err(_) => f(&message.to_vec()),
+    }
+}
+
+/// A virtual MIDI Thru port, created by `create_thru`. The ports stay open as
+/// long as this object is kept alive.
+#[cfg(unix)]
+#[derive(Debug)]
+pub struct ThruConnection {
+    imp: MidiInputConnection<MidiOutputConnection>,
+}
+
+#[cfg(unix)]
+impl ThruConnection {
+    /// Closes the virtual ports. This is equivalent to dropping the object.
+    pub fn close(self) {
+        let (_, conn_out) = self.imp.close();
+        conn_out.close();
+    }
+}
+
+/// Creates a virtual input port and a virtual output port with the given
+/// name, and forwards every message received on the input port to the
+/// output port, like a MIDI Thru box. If a `transform` is given, it is
+/// applied to every message before it is forwarded (see
+/// `MidiOutputConnection::set_transform`).
+///
+/// Virtual ports are only supported on unix platforms (see
+/// `os::unix::VirtualInput`). Messages are forwarded on the callback
+/// thread of the input port.
+#[cfg(unix)]
+pub fn create_thru(
+    name: &str,
+    transform: Option<OutputTransform>,
+) -> Result<ThruConnection, OpenError> {
+    use crate::os::unix::{VirtualInput, VirtualOutput};
+
+    let mut midi_in = MidiInput::new(name).map_err(OpenError::Init)?;
+    midi_in.ignore(Ignore::None);
+    let midi_out = MidiOutput::new(name).map_err(OpenError::Init)?;
+    let mut conn_out = midi_out
+        .create_virtual(name)
+        .map_err(|e| OpenError::Connect(e.kind()))?;
+    conn_out.set_transform(transform);
+    let imp = midi_in
+        .create_virtual(
+            name,
+            |_, message, conn_out: &mut MidiOutputConnection| {
+                let _ = conn_out.send(message);
+            },
+            conn_out,
+        )
+        .map_err(|e| OpenError::Connect(e.kind()))?;
+    Ok(ThruConnection { imp })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -365,6 +2585,7 @@ mod tests {
             // Prev. discussion:    https://github.com/Boddlnagg/midir/pull/47
             is_send::<MidiInputPort>();
             is_send::<MidiInputConnection<()>>();
+            is_send::<MidiInputRx>();
             is_send::<MidiOutputPort>();
             is_send::<MidiOutputConnection>();
         }
@@ -376,5 +2597,151 @@ mod tests {
 
         is_partial_eq::<MidiInputPort>();
         is_partial_eq::<MidiOutputPort>();
+
+        // make sure that the public data types implement `Debug`
+        fn is_debug<T: fmt::Debug>() {}
+        is_debug::<Device>();
+        is_debug::<MidiInputPort>();
+        is_debug::<MidiOutputPort>();
+    }
+
+    #[test]
+    fn test_match_mode() {
+        assert!(MatchMode::Exact.matches("Midi Through", "Midi Through"));
+        assert!(!MatchMode::Exact.matches("Midi Through", "midi through"));
+        assert!(MatchMode::CaseInsensitive.matches("Midi Through", "midi THROUGH"));
+        assert!(!MatchMode::CaseInsensitive.matches("Midi Through", "midi"));
+        assert!(MatchMode::Substring.matches("Midi Through", "Thr"));
+        assert!(!MatchMode::Substring.matches("Midi Through", "thr"));
+    }
+
+    #[test]
+    fn test_relative_timestamps_dont_underflow() {
+        let settings = InputSettings {
+            timestamp_unit: TimestampUnit::Nanos,
+            timestamp_mode: TimestampMode::Relative,
+            ..InputSettings::default()
+        };
+        let listeners = Arc::new(Mutex::new(Listeners(Vec::new())));
+        let mut callback = settings.wrap_callback(
+            |timestamp, _, _, (), stamps: &mut Vec<u64>| stamps.push(timestamp),
+            listeners,
+        );
+        let mut stamps = Vec::new();
+        callback(1_000, &[0x90, 60, 1], (), &mut stamps);
+        callback(3_000, &[0x90, 60, 0], (), &mut stamps);
+        // The time of the backend went backwards (e.g. because the queue has been restarted)
+        callback(2_000, &[0x90, 60, 1], (), &mut stamps);
+        assert_eq!(stamps, [0, 2_000, 0]);
+    }
+
+    #[test]
+    fn test_frame_timestamps_are_not_converted() {
+        let settings = InputSettings {
+            timestamp_unit: TimestampUnit::Micros,
+            dedup_window: Some(std::time::Duration::from_millis(1)),
+            frame_rate: std::num::NonZeroU32::new(48_000),
+            ..InputSettings::default()
+        };
+        let listeners = Arc::new(Mutex::new(Listeners(Vec::new())));
+        let mut callback = settings.wrap_callback(
+            |timestamp, _, _, (), stamps: &mut Vec<u64>| stamps.push(timestamp),
+            listeners,
+        );
+        let mut stamps = Vec::new();
+        callback(1_000, &[0x80, 60, 0], (), &mut stamps);
+        // Within the window of 48 frames, so this is dropped as a repetition
+        callback(1_040, &[0x80, 60, 0], (), &mut stamps);
+        callback(1_100, &[0x80, 60, 0], (), &mut stamps);
+        assert_eq!(stamps, [1_000, 1_100]);
+        assert_eq!(settings.timestamp_converter()(1_100), 1_100);
+    }
+
+    #[test]
+    fn test_dedup_window() {
+        let settings = InputSettings {
+            dedup_window: Some(std::time::Duration::from_millis(30)),
+            ..InputSettings::default()
+        };
+        let listeners = Arc::new(Mutex::new(Listeners(Vec::new())));
+        let mut callback = settings.wrap_callback(
+            |timestamp, _, message: &[u8], (), received: &mut Vec<(u64, u8)>| {
+                received.push((timestamp, message[0]))
+            },
+            listeners,
+        );
+        let millis = |ms: u64| ms * 1_000_000;
+        let mut received = Vec::new();
+        // The window starts at the last delivered message, so it doesn't slide
+        callback(millis(0), &[0x80, 60, 0], (), &mut received);
+        callback(millis(20), &[0x80, 60, 0], (), &mut received);
+        callback(millis(40), &[0x80, 60, 0], (), &mut received);
+        // Timing Clock is never suppressed
+        for ms in [100, 121, 142] {
+            callback(millis(ms), &[0xF8], (), &mut received);
+        }
+        assert_eq!(
+            received,
+            [
+                (0, 0x80),
+                (40_000, 0x80),
+                (100_000, 0xF8),
+                (121_000, 0xF8),
+                (142_000, 0xF8)
+            ]
+        );
+
+        // Without timestamps, the window is ignored instead of suppressing all repetitions
+        let settings = InputSettings {
+            dedup_window: Some(std::time::Duration::from_millis(30)),
+            untimestamped: true,
+            ..InputSettings::default()
+        };
+        let listeners = Arc::new(Mutex::new(Listeners(Vec::new())));
+        let mut callback =
+            settings.wrap_callback(|_, _, _, (), count: &mut usize| *count += 1, listeners);
+        let mut count = 0;
+        callback(0, &[0x80, 60, 0], (), &mut count);
+        callback(0, &[0x80, 60, 0], (), &mut count);
+        assert_eq!(count, 2);
+    }
+
+    #[test]
+    #[cfg(feature = "wmidi")]
+    fn test_wmidi_round_trip() {
+        let messages: [&[u8]; 4] = [
+            &[0x90, 60, 100],
+            &[0xC1, 5],
+            &[0xF8],
+            &[0xF0, 0x7E, 0x7F, 0x06, 0x01, 0xF7],
+        ];
+        for bytes in messages {
+            let message = wmidi::MidiMessage::try_from(bytes).unwrap();
+            with_wmidi_bytes(&message, |encoded| assert_eq!(encoded, bytes));
+        }
+        let sysex = wmidi::MidiMessage::try_from(messages[3]).unwrap();
+        assert!(matches!(sysex, wmidi::MidiMessage::SysEx(data) if data.len() == 4));
+    }
+
+    #[test]
+    fn test_ports_generation() {
+        let ids = |ids: &[&str]| ids.iter().map(|id| id.to_string()).collect::<Vec<_>>();
+        let generation = PortsGeneration::default();
+        assert_eq!(generation.update(ids(&["a", "b"]).into_iter()), 0);
+        assert_eq!(generation.update(ids(&["a", "b"]).into_iter()), 0);
+        assert_eq!(generation.update(ids(&["a"]).into_iter()), 1);
+        assert_eq!(generation.update(ids(&["b", "a"]).into_iter()), 2);
+        assert_eq!(generation.update(ids(&["b", "a"]).into_iter()), 2);
+    }
+
+    #[test]
+    #[cfg(all(target_os = "linux", not(feature = "jack")))]
+    fn test_port_from_id() {
+        let port = MidiInputPort::from_id("128:0").unwrap();
+        assert_eq!(port.id(), "128:0");
+        let port = MidiOutputPort::from_id("14:1").unwrap();
+        assert_eq!(port.id(), "14:1");
+        assert!(MidiInputPort::from_id("128").is_none());
+        assert!(MidiOutputPort::from_id("system:midi_playback_1").is_none());
     }
 }
@@ -0,0 +1,218 @@
+//! Mapping of incoming control change (CC) messages to named parameters.
+//!
+//! A `CcMapper` is fed with the raw messages received by an input connection
+//! (e.g. from within the callback passed to `MidiInput::connect`) and invokes
+//! its own callback with the name and the new value of every parameter that
+//! is changed by a message.
+
+/// How the values of a controller are interpreted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CcMode {
+    /// The controller sends absolute 7-bit values (0 to 127).
+    Absolute,
+    /// The controller sends absolute 14-bit values, with the most significant
+    /// 7 bits on the mapped controller number (0 to 31) and the least
+    /// significant 7 bits on the controller number + 32.
+    Absolute14Bit,
+    /// The controller is a relative encoder that sends increments as 1 to 63
+    /// and decrements in two's complement, i.e. 127 is -1, 126 is -2, etc.
+    RelativeTwosComplement,
+    /// The controller is a relative encoder that sends the magnitude of the
+    /// change in the lower 6 bits, and sets bit 6 (64) for decrements.
+    RelativeSignMagnitude,
+}
+
+/// The mapping of a single controller to a named parameter.
+#[derive(Debug, Clone)]
+pub struct CcMapping {
+    name: String,
+    channel: Option<u8>,
+    cc: u8,
+    mode: CcMode,
+    min: f64,
+    max: f64,
+    step: f64,
+    smoothing: f64,
+    // state
+    value: f64,
+    smoothed: f64,
+    msb: u8,
+}
+
+impl CcMapping {
+    /// Map controller `cc` (on any channel) to the parameter `name`.
+    pub fn new(name: &str, cc: u8, mode: CcMode) -> CcMapping {
+        CcMapping {
+            name: name.to_owned(),
+            channel: None,
+            cc: cc & 0x7F,
+            mode,
+            min: 0.0,
+            max: 1.0,
+            step: 1.0 / 127.0,
+            smoothing: 0.0,
+            value: 0.0,
+            smoothed: 0.0,
+            msb: 0,
+        }
+    }
+
+    /// Only react to messages on the given channel (0 to 15).
+    pub fn channel(mut self, channel: u8) -> CcMapping {
+        self.channel = Some(channel & 0x0F);
+        self
+    }
+
+    /// Scale the parameter to the range from `min` to `max`
+    /// (instead of the default range from 0 to 1).
+    pub fn range(mut self, min: f64, max: f64) -> CcMapping {
+        self.min = min;
+        self.max = max;
+        self
+    }
+
+    /// Set how much a single tick of a relative encoder changes the
+    /// parameter, relative to its full range (1/127 by default).
+    pub fn step(mut self, step: f64) -> CcMapping {
+        self.step = step;
+        self
+    }
+
+    /// Smooth changes of the parameter exponentially. A factor of 0 (the
+    /// default) disables smoothing, values closer to 1 smooth more strongly.
+    pub fn smoothing(mut self, factor: f64) -> CcMapping {
+        self.smoothing = factor.clamp(0.0, 0.999);
+        self
+    }
+
+    /// Updates the state with the given controller value and returns the
+    /// new normalized (0 to 1) value, if the parameter has changed.
+    fn update(&mut self, cc: u8, data: u8) -> Option<f64> {
+        let value = match self.mode {
+            CcMode::Absolute if cc == self.cc => data as f64 / 127.0,
+            CcMode::Absolute14Bit if cc == self.cc => {
+                // A new MSB resets the LSB
+                self.msb = data;
+                (data as u16) as f64 * 128.0 / 16383.0
+            }
+            CcMode::Absolute14Bit if self.cc < 32 && cc == self.cc + 32 => {
+                (((self.msb as u16) << 7) | data as u16) as f64 / 16383.0
+            }
+            CcMode::RelativeTwosComplement if cc == self.cc => {
+                let delta = if data < 64 {
+                    data as i16
+                } else {
+                    data as i16 - 128
+                };
+                self.value + delta as f64 * self.step
+            }
+            CcMode::RelativeSignMagnitude if cc == self.cc => {
+                let magnitude = (data & 0x3F) as f64;
+                let delta = if data & 0x40 != 0 {
+                    -magnitude
+                } else {
+                    magnitude
+                };
+                self.value + delta * self.step
+            }
+            _ => return None,
+        };
+        self.value = value.clamp(0.0, 1.0);
+        self.smoothed = self.smoothed * self.smoothing + self.value * (1.0 - self.smoothing);
+        Some(self.smoothed)
+    }
+}
+
+/// Maps incoming control change messages to named parameters (see the
+/// module documentation).
+pub struct CcMapper<F> {
+    mappings: Vec<CcMapping>,
+    callback: F,
+}
+
+impl<F> CcMapper<F>
+where
+    F: FnMut(&str, f64),
+{
+    /// Create a mapper without any mappings. The `callback` is invoked with
+    /// the name and the new value of every parameter that changes.
+    pub fn new(callback: F) -> CcMapper<F> {
+        CcMapper {
+            mappings: Vec::new(),
+            callback,
+        }
+    }
+
+    /// Add a mapping. Several parameters can be mapped to the same controller.
+    pub fn add(&mut self, mapping: CcMapping) -> &mut Self {
+        self.mappings.push(mapping);
+        self
+    }
+
+    /// Process a raw MIDI message. Messages that are not control changes
+    /// or that don't belong to any mapping are ignored.
+    pub fn process(&mut self, message: &[u8]) {
+        if message.len() < 3 || message[0] & 0xF0 != 0xB0 {
+            return;
+        }
+        let channel = message[0] & 0x0F;
+        let cc = message[1] & 0x7F;
+        let data = message[2] & 0x7F;
+        for mapping in &mut self.mappings {
+            if matches!(mapping.channel, Some(c) if c != channel) {
+                continue;
+            }
+            if let Some(value) = mapping.update(cc, data) {
+                let scaled = mapping.min + value * (mapping.max - mapping.min);
+                (self.callback)(&mapping.name, scaled);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn collect(mapping: CcMapping, messages: &[[u8; 3]]) -> Vec<f64> {
+        let mut values = Vec::new();
+        let mut mapper = CcMapper::new(|_: &str, value| values.push(value));
+        mapper.add(mapping);
+        for message in messages {
+            mapper.process(message);
+        }
+        values
+    }
+
+    #[test]
+    fn test_absolute() {
+        let mapping = CcMapping::new("volume", 7, CcMode::Absolute).range(0.0, 2.0);
+        let values = collect(mapping, &[[0xB0, 7, 127], [0xB0, 8, 10], [0x90, 7, 0]]);
+        assert_eq!(values, vec![2.0]);
+
+        let mapping = CcMapping::new("volume", 7, CcMode::Absolute).channel(1);
+        assert!(collect(mapping, &[[0xB0, 7, 127]]).is_empty());
+    }
+
+    #[test]
+    fn test_14bit() {
+        let mapping = CcMapping::new("cutoff", 1, CcMode::Absolute14Bit);
+        let values = collect(mapping, &[[0xB0, 1, 127], [0xB0, 33, 127]]);
+        assert_eq!(values.len(), 2);
+        assert_eq!(values[1], 1.0);
+    }
+
+    #[test]
+    fn test_relative() {
+        let mapping = CcMapping::new("pan", 10, CcMode::RelativeTwosComplement).step(0.25);
+        let values = collect(mapping, &[[0xB0, 10, 1], [0xB0, 10, 2], [0xB0, 10, 127]]);
+        assert_eq!(values, vec![0.25, 0.75, 0.5]);
+
+        let mapping = CcMapping::new("pan", 10, CcMode::RelativeSignMagnitude).step(0.25);
+        let values = collect(
+            mapping,
+            &[[0xB0, 10, 3], [0xB0, 10, 0x41], [0xB0, 10, 0x45]],
+        );
+        assert_eq!(values, vec![0.75, 0.5, 0.0]);
+    }
+}
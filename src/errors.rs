@@ -2,58 +2,117 @@ use std::error::Error;
 use std::fmt;
 
 const INVALID_PORT_MSG: &str = "invalid port";
-const PORT_OUT_OF_RANGE_MSG: &str = "provided port number was out of range";
 const CANNOT_RETRIEVE_PORT_NAME_MSG: &str = "unknown error when trying to retrieve the port name";
+const NO_MATCHING_PORT_MSG: &str = "no port matched the given selector";
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug)]
 /// An error that can occur during initialization (i.e., while
 /// creating a `MidiInput` or `MidiOutput` object).
-pub struct InitError;
+///
+/// It carries the reason for the failure if the backend reports one, e.g.
+/// that the ALSA sequencer device could not be opened, that the JACK server
+/// is not running or that the client name is taken.
+///
+/// On WinRT, this is also returned if the app is not allowed to enumerate
+/// MIDI devices (e.g. a packaged app that does not declare the required
+/// device capabilities in its manifest).
+pub struct InitError {
+    source: Option<Box<dyn Error + Send + Sync>>,
+}
 
-impl Error for InitError {}
+impl InitError {
+    /// Create an error with a message that tells why initialization failed.
+    pub fn new(msg: impl Into<String>) -> InitError {
+        InitError {
+            source: Some(msg.into().into()),
+        }
+    }
+
+    /// Create an error that has been caused by `err`, which is returned by
+    /// `Error::source`.
+    pub fn with_source(err: impl Into<Box<dyn Error + Send + Sync>>) -> InitError {
+        InitError {
+            source: Some(err.into()),
+        }
+    }
+}
+
+impl Error for InitError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.source.as_ref().map(|err| &**err as _)
+    }
+}
 
 impl fmt::Display for InitError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        "MIDI support could not be initialized".fmt(f)
+        "MIDI support could not be initialized".fmt(f)?;
+        match self.source {
+            Some(ref reason) => write!(f, ": {}", reason),
+            None => Ok(()),
+        }
     }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
 /// An error that can occur when retrieving information about
 /// available ports.
 pub enum PortInfoError {
-    PortNumberOutOfRange, // TODO: don't expose this
     InvalidPort,
     CannotRetrievePortName,
 }
 
+impl PortInfoError {
+    /// A description of the error.
+    pub fn message(&self) -> &'static str {
+        match *self {
+            PortInfoError::InvalidPort => INVALID_PORT_MSG,
+            PortInfoError::CannotRetrievePortName => CANNOT_RETRIEVE_PORT_NAME_MSG,
+        }
+    }
+
+    /// The error code reported by the operating system, or `None` if the
+    /// backend does not provide one (which currently none of them does).
+    pub fn os_code(&self) -> Option<i32> {
+        None
+    }
+}
+
 impl Error for PortInfoError {}
 
 impl fmt::Display for PortInfoError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match *self {
-            PortInfoError::PortNumberOutOfRange => PORT_OUT_OF_RANGE_MSG.fmt(f),
-            PortInfoError::InvalidPort => INVALID_PORT_MSG.fmt(f),
-            PortInfoError::CannotRetrievePortName => CANNOT_RETRIEVE_PORT_NAME_MSG.fmt(f),
-        }
+        self.message().fmt(f)
     }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
 /// The kind of error for a `ConnectError`.
 pub enum ConnectErrorKind {
     InvalidPort,
     Other(&'static str),
 }
 
-impl ConnectErrorKind {}
+impl ConnectErrorKind {
+    /// A description of the error.
+    pub fn message(&self) -> &'static str {
+        match *self {
+            ConnectErrorKind::InvalidPort => INVALID_PORT_MSG,
+            ConnectErrorKind::Other(msg) => msg,
+        }
+    }
+
+    /// The error code reported by the operating system, or `None` if the
+    /// backend does not provide one (which currently none of them does).
+    pub fn os_code(&self) -> Option<i32> {
+        None
+    }
+}
 
 impl fmt::Display for ConnectErrorKind {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match *self {
-            ConnectErrorKind::InvalidPort => INVALID_PORT_MSG.fmt(f),
-            ConnectErrorKind::Other(msg) => msg.fmt(f),
-        }
+        self.message().fmt(f)
     }
 }
 
@@ -96,19 +155,76 @@ impl<T> fmt::Display for ConnectError<T> {
 
 impl<T> Error for ConnectError<T> {}
 
+#[derive(Debug)]
+#[non_exhaustive]
+/// An error that can occur when opening a connection in a single step
+/// (i.e., using `MidiInput::open`, `MidiOutput::open` or `create_thru`).
+pub enum OpenError {
+    /// The `MidiInput` or `MidiOutput` object could not be created.
+    Init(InitError),
+    /// No port matched the given `PortSelector`.
+    NoMatchingPort,
+    /// The selected port was found, but connecting to it failed.
+    Connect(ConnectErrorKind),
+}
+
+impl Error for OpenError {}
+
+impl fmt::Display for OpenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            OpenError::Init(ref err) => err.fmt(f),
+            OpenError::NoMatchingPort => NO_MATCHING_PORT_MSG.fmt(f),
+            OpenError::Connect(ref kind) => kind.fmt(f),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
 /// An error that can occur when sending MIDI messages.
 pub enum SendError {
     InvalidData(&'static str),
     Other(&'static str),
 }
 
+impl SendError {
+    /// A description of the error.
+    pub fn message(&self) -> &'static str {
+        match *self {
+            SendError::InvalidData(msg) | SendError::Other(msg) => msg,
+        }
+    }
+
+    /// The error code reported by the operating system, or `None` if the
+    /// backend does not provide one (which currently none of them does).
+    pub fn os_code(&self) -> Option<i32> {
+        None
+    }
+}
+
 impl Error for SendError {}
 
 impl fmt::Display for SendError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match *self {
-            SendError::InvalidData(msg) | SendError::Other(msg) => msg.fmt(f),
-        }
+        self.message().fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_init_error() {
+        let err = InitError::new("the JACK server is not running");
+        assert_eq!(
+            err.to_string(),
+            "MIDI support could not be initialized: the JACK server is not running"
+        );
+
+        let cause = fmt::Error;
+        let err = InitError::with_source(cause);
+        assert!(err.source().unwrap().is::<fmt::Error>());
     }
 }
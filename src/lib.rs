@@ -21,25 +21,170 @@ pub enum Ignore {
     All = 0x07,
 }
 
+impl Ignore {
+    #[inline(always)]
+    fn from_bits(bits: u8) -> Ignore {
+        // this is safe because all combinations of the lower three bits exist as variants
+        unsafe { std::mem::transmute(bits & Ignore::All as u8) }
+    }
+
+    #[inline(always)]
+    pub fn contains(self, other: Ignore) -> bool {
+        self as u8 & other as u8 != 0
+    }
+
+    /// Whether the system real-time message with the given status byte is ignored.
+    #[inline]
+    #[allow(dead_code)] // not used by backends that receive complete messages
+    fn contains_realtime(self, status: u8) -> bool {
+        match status {
+            0xF8 | 0xF9 => self.contains(Ignore::Time),
+            0xFE => self.contains(Ignore::ActiveSense),
+            _ => false,
+        }
+    }
+}
+
 impl std::ops::BitOr for Ignore {
     type Output = Ignore;
     #[inline(always)]
     fn bitor(self, rhs: Self) -> Self::Output {
-        // this is safe because all combinations also exist as variants
-        unsafe { std::mem::transmute(self as u8 | rhs as u8) }
+        Ignore::from_bits(self as u8 | rhs as u8)
     }
 }
 
-impl Ignore {
+impl std::ops::BitAnd for Ignore {
+    type Output = Ignore;
     #[inline(always)]
-    pub fn contains(self, other: Ignore) -> bool {
-        self as u8 & other as u8 != 0
+    fn bitand(self, rhs: Self) -> Self::Output {
+        Ignore::from_bits(self as u8 & rhs as u8)
+    }
+}
+
+impl std::ops::BitXor for Ignore {
+    type Output = Ignore;
+    #[inline(always)]
+    fn bitxor(self, rhs: Self) -> Self::Output {
+        Ignore::from_bits(self as u8 ^ rhs as u8)
+    }
+}
+
+impl std::ops::Sub for Ignore {
+    type Output = Ignore;
+    /// Removes the flags in `rhs` from `self`.
+    #[inline(always)]
+    fn sub(self, rhs: Self) -> Self::Output {
+        self & !rhs
+    }
+}
+
+impl std::ops::Not for Ignore {
+    type Output = Ignore;
+    #[inline(always)]
+    fn not(self) -> Self::Output {
+        Ignore::from_bits(!(self as u8))
     }
 }
 
+impl std::ops::BitOrAssign for Ignore {
+    #[inline(always)]
+    fn bitor_assign(&mut self, rhs: Self) {
+        *self = *self | rhs;
+    }
+}
+
+impl std::ops::BitAndAssign for Ignore {
+    #[inline(always)]
+    fn bitand_assign(&mut self, rhs: Self) {
+        *self = *self & rhs;
+    }
+}
+
+impl std::ops::SubAssign for Ignore {
+    #[inline(always)]
+    fn sub_assign(&mut self, rhs: Self) {
+        *self = *self - rhs;
+    }
+}
+
+#[cfg(all(target_os = "linux", feature = "jack"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// An enum that is used to select the backend at runtime on Linux, where
+/// both ALSA and JACK are available if the `jack` feature is enabled.
+pub enum Backend {
+    /// The ALSA sequencer.
+    Alsa,
+    /// The JACK audio connection kit (the default).
+    Jack,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+/// An enum that is used to specify how SysEx messages that arrive in
+/// several segments are passed to the callback.
+pub enum SysexMode {
+    /// Reassemble the segments and invoke the callback once with the
+    /// complete message (this is the default).
+    #[default]
+    Complete,
+    /// Invoke the callback once per segment, as soon as it arrives.
+    /// Only the first segment starts with `0xF0`, and more segments
+    /// are coming until one ends with `0xF7`.
+    Streaming,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+/// An enum that is used to specify what happens to messages that arrive
+/// before a port has been opened completely, which only happens on backends
+/// that open ports asynchronously (i.e., Web MIDI).
+pub enum EarlyMessagePolicy {
+    /// Pass the messages to the callback as they arrive (this is the default).
+    #[default]
+    Deliver,
+    /// Collect the messages and pass them to the callback once the port has
+    /// been opened.
+    Buffer,
+    /// Discard the messages.
+    Drop,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+/// An enum that is used to specify the unit of the timestamps that
+/// are passed to input callbacks.
+pub enum TimestampUnit {
+    /// Microseconds (this is the default).
+    #[default]
+    Micros,
+    /// Nanoseconds.
+    Nanos,
+}
+
+impl TimestampUnit {
+    #[inline(always)]
+    fn convert_nanos(self, nanos: u64) -> u64 {
+        match self {
+            TimestampUnit::Micros => nanos / 1_000,
+            TimestampUnit::Nanos => nanos,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+/// An enum that is used to specify what the timestamps that are passed to
+/// input callbacks are measured from.
+pub enum TimestampMode {
+    /// The time since a point in time that is arbitrary, but does not change
+    /// for the lifetime of a connection, so that timestamps keep increasing
+    /// (this is the default).
+    #[default]
+    Absolute,
+    /// The time since the previous message that has been passed to the
+    /// callback, which is 0 for the first message.
+    Relative,
+}
+
 /// A MIDI structure used internally by some backends to store incoming
 /// messages. Each message represents one and only one MIDI message.
-/// The timestamp is represented as the elapsed microseconds since
+/// The timestamp is represented as the elapsed nanoseconds since
 /// a point in time that is arbitrary, but does not change for the
 /// lifetime of a given MidiInputConnection.
 #[derive(Debug, Clone)]
@@ -57,7 +202,80 @@ impl MidiMessage {
     }
 }
 
+/// Whether a segment of a SysEx message ends the message, i.e. whether its last
+/// byte (apart from interleaved real-time messages) is `0xF7`.
+#[allow(dead_code)] // not used by backends that receive complete messages
+fn is_last_sysex_segment(segment: &[u8]) -> bool {
+    segment.iter().rev().find(|&&byte| byte < 0xF8) == Some(&0xF7)
+}
+
+/// Splits a segment of a SysEx message into the SysEx data, which is appended
+/// to `sysex` (unless it is `None`, e.g. because SysEx is ignored), and the
+/// system real-time messages that are interleaved with it, which are passed to
+/// `realtime` one by one (in order). Real-time messages may be sent at any
+/// time, even in the middle of a SysEx message, but they are not part of it.
+#[allow(dead_code)] // not used by backends that receive complete messages
+fn split_sysex_segment<F: FnMut(u8)>(
+    segment: &[u8],
+    mut sysex: Option<&mut Vec<u8>>,
+    mut realtime: F,
+) {
+    let mut start = 0;
+    for (i, &byte) in segment.iter().enumerate() {
+        if byte >= 0xF8 {
+            if let Some(ref mut sysex) = sysex {
+                sysex.extend_from_slice(&segment[start..i]);
+            }
+            realtime(byte);
+            start = i + 1;
+        }
+    }
+    if let Some(sysex) = sysex {
+        sysex.extend_from_slice(&segment[start..]);
+    }
+}
+
+thread_local! {
+    static IN_MIDI_CALLBACK: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
+}
+
+/// Returns whether the current thread is running a handler of midir, i.e. an
+/// input callback or the process callback of JACK.
+///
+/// This is meant for assertions during development, e.g. to make sure that
+/// no work that is forbidden in real-time code (like allocating or locking)
+/// is done. On ALSA, the whole input handler loop counts as a handler.
+pub fn in_midi_callback() -> bool {
+    IN_MIDI_CALLBACK.with(|in_callback| in_callback.get())
+}
+
+/// Marks the current thread as running a handler (see `in_midi_callback`)
+/// until it is dropped.
+struct CallbackScope(bool);
+
+impl CallbackScope {
+    fn enter() -> CallbackScope {
+        CallbackScope(IN_MIDI_CALLBACK.with(|in_callback| in_callback.replace(true)))
+    }
+}
+
+impl Drop for CallbackScope {
+    fn drop(&mut self) {
+        IN_MIDI_CALLBACK.with(|in_callback| in_callback.set(self.0));
+    }
+}
+
+pub mod ci;
+pub mod control;
+pub mod message;
 pub mod os; // include platform-specific behaviour
+pub mod parse;
+#[cfg(all(feature = "synth", unix))]
+pub mod synth;
+pub mod time;
+pub mod transport;
+#[cfg(feature = "wmidi")]
+pub use wmidi;
 
 mod errors;
 pub use errors::*;
@@ -66,3 +284,69 @@ mod common;
 pub use common::*;
 
 mod backend;
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        in_midi_callback, is_last_sysex_segment, split_sysex_segment, CallbackScope, Ignore,
+    };
+
+    #[test]
+    fn test_callback_scope() {
+        assert!(!in_midi_callback());
+        {
+            let _outer = CallbackScope::enter();
+            {
+                let _inner = CallbackScope::enter();
+                assert!(in_midi_callback());
+            }
+            assert!(in_midi_callback());
+        }
+        assert!(!in_midi_callback());
+    }
+
+    #[test]
+    fn test_ignore_ops() {
+        assert_eq!(Ignore::Sysex | Ignore::Time, Ignore::SysexAndTime);
+        assert_eq!(Ignore::All & !Ignore::Sysex, Ignore::TimeAndActiveSense);
+        assert_eq!(Ignore::All - Ignore::ActiveSense, Ignore::SysexAndTime);
+        assert_eq!(Ignore::SysexAndTime ^ Ignore::All, Ignore::ActiveSense);
+        assert_eq!(!Ignore::None, Ignore::All);
+        assert_eq!(!Ignore::All, Ignore::None);
+
+        let mut flags = Ignore::None;
+        flags |= Ignore::Time;
+        flags |= Ignore::Sysex;
+        flags -= Ignore::Time;
+        assert_eq!(flags, Ignore::Sysex);
+        flags &= Ignore::Time;
+        assert_eq!(flags, Ignore::None);
+
+        assert!(Ignore::SysexAndTime.contains(Ignore::Time));
+        assert!(!(Ignore::All & !Ignore::Time).contains(Ignore::Time));
+    }
+
+    #[test]
+    fn test_interleaved_realtime() {
+        let stream: &[&[u8]] = &[
+            &[0xF0, 0x7E, 0xF8, 0x01],
+            &[0x02, 0xFE, 0xF8],
+            &[0x03, 0xF7],
+        ];
+        let mut sysex = Vec::new();
+        let mut realtime = Vec::new();
+        for segment in stream {
+            split_sysex_segment(segment, Some(&mut sysex), |status| realtime.push(status));
+        }
+        assert_eq!(sysex, [0xF0, 0x7E, 0x01, 0x02, 0x03, 0xF7]);
+        assert_eq!(realtime, [0xF8, 0xFE, 0xF8]);
+        assert!(!is_last_sysex_segment(stream[1]));
+        assert!(is_last_sysex_segment(&[0x03, 0xF7, 0xF8]));
+
+        realtime.clear();
+        split_sysex_segment(&[0xF0, 0xF8, 0xF7], None, |status| realtime.push(status));
+        assert_eq!(realtime, [0xF8]);
+        assert!(Ignore::Time.contains_realtime(0xF8));
+        assert!(!Ignore::Time.contains_realtime(0xFA));
+    }
+}
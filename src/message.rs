@@ -0,0 +1,345 @@
+//! A typed representation of single MIDI messages, as they are passed to the
+//! callback of an input connection (midir splits the incoming data into
+//! messages, so running status never needs to be handled).
+//!
+//! `MidiMessage::parse` decodes a message and rejects malformed ones, while
+//! `MidiMessage::to_bytes` encodes it again, e.g. to send it. Messages can be
+//! kept beyond the callback with `MidiMessage::into_owned` (see also
+//! `parse::Message`).
+
+use std::borrow::Cow;
+use std::error::Error;
+use std::fmt;
+
+/// A complete MIDI message, which borrows the data of SysEx messages from the
+/// raw bytes when it is parsed. Channels are numbered from 0 to 15.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MidiMessage<'a> {
+    /// Note Off (`0x8n`).
+    NoteOff { channel: u8, note: u8, velocity: u8 },
+    /// Note On (`0x9n`). A velocity of 0 is passed on unchanged.
+    NoteOn { channel: u8, note: u8, velocity: u8 },
+    /// Polyphonic Key Pressure (`0xAn`).
+    Aftertouch { channel: u8, note: u8, pressure: u8 },
+    /// Control Change (`0xBn`), including the channel mode messages.
+    ControlChange {
+        channel: u8,
+        controller: u8,
+        value: u8,
+    },
+    /// Program Change (`0xCn`).
+    ProgramChange { channel: u8, program: u8 },
+    /// Channel Pressure (`0xDn`).
+    ChannelPressure { channel: u8, pressure: u8 },
+    /// Pitch Bend Change (`0xEn`), in the range 0 to 16383 (8192 is no bend).
+    PitchBend { channel: u8, value: u16 },
+    /// System Exclusive (`0xF0`). Contains the bytes between `0xF0` and the
+    /// terminating `0xF7`, i.e. starting with the manufacturer ID.
+    SysEx(Cow<'a, [u8]>),
+    /// MIDI Time Code Quarter Frame (`0xF1`), with the raw data byte.
+    TimeCodeQuarterFrame(u8),
+    /// Song Position Pointer (`0xF2`), in MIDI beats.
+    SongPosition(u16),
+    /// Song Select (`0xF3`).
+    SongSelect(u8),
+    /// Tune Request (`0xF6`).
+    TuneRequest,
+    /// Timing Clock (`0xF8`).
+    TimingClock,
+    /// Start (`0xFA`).
+    Start,
+    /// Continue (`0xFB`).
+    Continue,
+    /// Stop (`0xFC`).
+    Stop,
+    /// Active Sensing (`0xFE`).
+    ActiveSensing,
+    /// System Reset (`0xFF`).
+    Reset,
+}
+
+impl<'a> MidiMessage<'a> {
+    /// Decode a single raw MIDI message. Messages that don't start with a
+    /// status byte (as with running status), are truncated or too long, or
+    /// contain an invalid data byte are rejected. Never allocates.
+    pub fn parse(msg: &'a [u8]) -> Result<MidiMessage<'a>, ParseMessageError> {
+        let (&status, data) = msg.split_first().ok_or(ParseMessageError::Empty)?;
+        let expected_len = match status {
+            0x80..=0xBF | 0xE0..=0xEF | 0xF2 => 3,
+            0xC0..=0xDF | 0xF1 | 0xF3 => 2,
+            0xF6 | 0xF8 | 0xFA..=0xFC | 0xFE | 0xFF => 1,
+            0xF0 => {
+                return match data.split_last() {
+                    Some((0xF7, payload)) => match payload.iter().find(|&&b| b >= 0x80) {
+                        Some(&b) => Err(ParseMessageError::InvalidDataByte(b)),
+                        None => Ok(MidiMessage::SysEx(Cow::Borrowed(payload))),
+                    },
+                    _ => Err(ParseMessageError::UnterminatedSysEx),
+                };
+            }
+            _ => return Err(ParseMessageError::InvalidStatus(status)),
+        };
+        if msg.len() != expected_len {
+            return Err(ParseMessageError::WrongLength {
+                expected: expected_len,
+                actual: msg.len(),
+            });
+        }
+        if let Some(&b) = data.iter().find(|&&b| b >= 0x80) {
+            return Err(ParseMessageError::InvalidDataByte(b));
+        }
+
+        let channel = status & 0x0F;
+        let first = data.first().copied().unwrap_or(0);
+        let second = data.get(1).copied().unwrap_or(0);
+        Ok(match status {
+            0x80..=0x8F => MidiMessage::NoteOff {
+                channel,
+                note: first,
+                velocity: second,
+            },
+            0x90..=0x9F => MidiMessage::NoteOn {
+                channel,
+                note: first,
+                velocity: second,
+            },
+            0xA0..=0xAF => MidiMessage::Aftertouch {
+                channel,
+                note: first,
+                pressure: second,
+            },
+            0xB0..=0xBF => MidiMessage::ControlChange {
+                channel,
+                controller: first,
+                value: second,
+            },
+            0xC0..=0xCF => MidiMessage::ProgramChange {
+                channel,
+                program: first,
+            },
+            0xD0..=0xDF => MidiMessage::ChannelPressure {
+                channel,
+                pressure: first,
+            },
+            0xE0..=0xEF => MidiMessage::PitchBend {
+                channel,
+                value: ((second as u16) << 7) | first as u16,
+            },
+            0xF1 => MidiMessage::TimeCodeQuarterFrame(first),
+            0xF2 => MidiMessage::SongPosition(((second as u16) << 7) | first as u16),
+            0xF3 => MidiMessage::SongSelect(first),
+            0xF6 => MidiMessage::TuneRequest,
+            0xF8 => MidiMessage::TimingClock,
+            0xFA => MidiMessage::Start,
+            0xFB => MidiMessage::Continue,
+            0xFC => MidiMessage::Stop,
+            0xFE => MidiMessage::ActiveSensing,
+            _ => MidiMessage::Reset,
+        })
+    }
+
+    /// Turn the message into one that owns the data of SysEx messages, so
+    /// that it can be kept after the callback has returned. This only
+    /// allocates for SysEx messages.
+    pub fn into_owned(self) -> MidiMessage<'static> {
+        match self {
+            MidiMessage::NoteOff {
+                channel,
+                note,
+                velocity,
+            } => MidiMessage::NoteOff {
+                channel,
+                note,
+                velocity,
+            },
+            MidiMessage::NoteOn {
+                channel,
+                note,
+                velocity,
+            } => MidiMessage::NoteOn {
+                channel,
+                note,
+                velocity,
+            },
+            MidiMessage::Aftertouch {
+                channel,
+                note,
+                pressure,
+            } => MidiMessage::Aftertouch {
+                channel,
+                note,
+                pressure,
+            },
+            MidiMessage::ControlChange {
+                channel,
+                controller,
+                value,
+            } => MidiMessage::ControlChange {
+                channel,
+                controller,
+                value,
+            },
+            MidiMessage::ProgramChange { channel, program } => {
+                MidiMessage::ProgramChange { channel, program }
+            }
+            MidiMessage::ChannelPressure { channel, pressure } => {
+                MidiMessage::ChannelPressure { channel, pressure }
+            }
+            MidiMessage::PitchBend { channel, value } => MidiMessage::PitchBend { channel, value },
+            MidiMessage::SysEx(data) => MidiMessage::SysEx(Cow::Owned(data.into_owned())),
+            MidiMessage::TimeCodeQuarterFrame(data) => MidiMessage::TimeCodeQuarterFrame(data),
+            MidiMessage::SongPosition(position) => MidiMessage::SongPosition(position),
+            MidiMessage::SongSelect(song) => MidiMessage::SongSelect(song),
+            MidiMessage::TuneRequest => MidiMessage::TuneRequest,
+            MidiMessage::TimingClock => MidiMessage::TimingClock,
+            MidiMessage::Start => MidiMessage::Start,
+            MidiMessage::Continue => MidiMessage::Continue,
+            MidiMessage::Stop => MidiMessage::Stop,
+            MidiMessage::ActiveSensing => MidiMessage::ActiveSensing,
+            MidiMessage::Reset => MidiMessage::Reset,
+        }
+    }
+
+    /// Encode the message and append its raw bytes to `buf`. Values that
+    /// don't fit into their data bytes (e.g. a channel above 15) are masked.
+    pub fn to_bytes(&self, buf: &mut Vec<u8>) {
+        match *self {
+            MidiMessage::NoteOff {
+                channel,
+                note,
+                velocity,
+            } => buf.extend_from_slice(&[0x80 | (channel & 0x0F), note & 0x7F, velocity & 0x7F]),
+            MidiMessage::NoteOn {
+                channel,
+                note,
+                velocity,
+            } => buf.extend_from_slice(&[0x90 | (channel & 0x0F), note & 0x7F, velocity & 0x7F]),
+            MidiMessage::Aftertouch {
+                channel,
+                note,
+                pressure,
+            } => buf.extend_from_slice(&[0xA0 | (channel & 0x0F), note & 0x7F, pressure & 0x7F]),
+            MidiMessage::ControlChange {
+                channel,
+                controller,
+                value,
+            } => buf.extend_from_slice(&[0xB0 | (channel & 0x0F), controller & 0x7F, value & 0x7F]),
+            MidiMessage::ProgramChange { channel, program } => {
+                buf.extend_from_slice(&[0xC0 | (channel & 0x0F), program & 0x7F])
+            }
+            MidiMessage::ChannelPressure { channel, pressure } => {
+                buf.extend_from_slice(&[0xD0 | (channel & 0x0F), pressure & 0x7F])
+            }
+            MidiMessage::PitchBend { channel, value } => buf.extend_from_slice(&[
+                0xE0 | (channel & 0x0F),
+                (value & 0x7F) as u8,
+                ((value >> 7) & 0x7F) as u8,
+            ]),
+            MidiMessage::SysEx(ref data) => {
+                buf.push(0xF0);
+                buf.extend_from_slice(data);
+                buf.push(0xF7);
+            }
+            MidiMessage::TimeCodeQuarterFrame(data) => buf.extend_from_slice(&[0xF1, data & 0x7F]),
+            MidiMessage::SongPosition(position) => buf.extend_from_slice(&[
+                0xF2,
+                (position & 0x7F) as u8,
+                ((position >> 7) & 0x7F) as u8,
+            ]),
+            MidiMessage::SongSelect(song) => buf.extend_from_slice(&[0xF3, song & 0x7F]),
+            MidiMessage::TuneRequest => buf.push(0xF6),
+            MidiMessage::TimingClock => buf.push(0xF8),
+            MidiMessage::Start => buf.push(0xFA),
+            MidiMessage::Continue => buf.push(0xFB),
+            MidiMessage::Stop => buf.push(0xFC),
+            MidiMessage::ActiveSensing => buf.push(0xFE),
+            MidiMessage::Reset => buf.push(0xFF),
+        }
+    }
+}
+
+/// An error that can occur when parsing a `MidiMessage` (or a
+/// `parse::Message`) from raw bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ParseMessageError {
+    /// The message is empty.
+    Empty,
+    /// The first byte is not a status byte, or a status byte that is
+    /// undefined or cannot start a message (e.g. a lone `0xF7`).
+    InvalidStatus(u8),
+    /// The message is too short or too long for its status byte.
+    WrongLength { expected: usize, actual: usize },
+    /// A data byte has the high bit set.
+    InvalidDataByte(u8),
+    /// A SysEx message does not end with `0xF7`.
+    UnterminatedSysEx,
+}
+
+impl Error for ParseMessageError {}
+
+impl fmt::Display for ParseMessageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            ParseMessageError::Empty => write!(f, "empty MIDI message"),
+            ParseMessageError::InvalidStatus(status) => {
+                write!(f, "invalid status byte 0x{:02X}", status)
+            }
+            ParseMessageError::WrongLength { expected, actual } => write!(
+                f,
+                "MIDI message has {} bytes, but {} were expected",
+                actual, expected
+            ),
+            ParseMessageError::InvalidDataByte(byte) => {
+                write!(f, "invalid data byte 0x{:02X}", byte)
+            }
+            ParseMessageError::UnterminatedSysEx => write!(f, "SysEx message is not terminated"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let messages: &[&[u8]] = &[
+            &[0x81, 60, 0],
+            &[0x9F, 127, 127],
+            &[0xB0, 7, 100],
+            &[0xC3, 5],
+            &[0xE0, 0x00, 0x40],
+            &[0xF0, 0x7E, 0x7F, 0x06, 0x01, 0xF7],
+            &[0xF2, 0x10, 0x01],
+            &[0xF8],
+        ];
+        let mut buf = Vec::new();
+        for &message in messages {
+            buf.clear();
+            MidiMessage::parse(message).unwrap().to_bytes(&mut buf);
+            assert_eq!(&buf[..], message);
+        }
+    }
+
+    #[test]
+    fn test_parse_rejects_malformed() {
+        // running status (data bytes without a status byte)
+        assert_eq!(
+            MidiMessage::parse(&[60, 100]),
+            Err(ParseMessageError::InvalidStatus(60))
+        );
+        // truncated messages
+        assert_eq!(
+            MidiMessage::parse(&[0xB0, 7]),
+            Err(ParseMessageError::WrongLength {
+                expected: 3,
+                actual: 2
+            })
+        );
+        assert_eq!(
+            MidiMessage::parse(&[0xF0, 0x7E]),
+            Err(ParseMessageError::UnterminatedSysEx)
+        );
+    }
+}
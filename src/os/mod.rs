@@ -1,2 +1,15 @@
+#[cfg(all(
+    feature = "unsafe-raw",
+    not(feature = "jack"),
+    any(
+        target_os = "linux",
+        target_os = "macos",
+        target_os = "ios",
+        all(target_os = "windows", not(feature = "winrt"))
+    )
+))]
+pub mod raw;
 #[cfg(unix)]
 pub mod unix;
+#[cfg(windows)]
+pub mod windows;
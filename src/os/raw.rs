@@ -0,0 +1,19 @@
+//! Access to the events of the backends before midir processes them
+//! (requires the `unsafe-raw` feature).
+//!
+//! `MidiInput::connect_raw` passes a `RawEvent` to the callback, which wraps
+//! whatever the backend has received from the system:
+//!
+//! * ALSA: the sequencer event (`alsa::seq::Event`), including events that
+//!   are not MIDI messages, such as port subscriptions
+//! * CoreMIDI: each `coremidi::Packet` of a packet list, with its host
+//!   timestamp
+//! * WinMM: the parameters of the `MidiInProc` callback, including the
+//!   sysex buffer (`MIDIHDR`) for `MIM_LONGDATA` and `MIM_LONGERROR`
+//!
+//! The layout of these types follows the system APIs and the versions of the
+//! binding crates that midir depends on, so it may change with any release
+//! of midir. Raw input is not available with the JACK, WinRT and Web MIDI
+//! backends.
+
+pub use crate::backend::RawEvent;
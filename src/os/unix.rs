@@ -2,6 +2,13 @@ use crate::{ConnectError, MidiInputConnection, MidiOutputConnection};
 
 // TODO: maybe move to module `virtual` instead of `os::unix`?
 
+#[cfg(target_os = "linux")]
+pub mod alsa;
+#[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "jack")))]
+pub mod coremidi;
+#[cfg(feature = "jack")]
+pub mod jack;
+
 /// Trait that is implemented by `MidiInput` on platforms that
 /// support virtual ports (currently every platform but Windows).
 pub trait VirtualInput<T: Send>
@@ -32,3 +39,16 @@ where
     /// receive MIDI messages that are sent to this port.
     fn create_virtual(self, port_name: &str) -> Result<MidiOutputConnection, ConnectError<Self>>;
 }
+
+/// Trait that is implemented by `MidiInput` and `MidiOutput` on platforms
+/// that support virtual ports, to configure how these ports are created.
+pub trait VirtualPortOptions {
+    /// Set whether `create_virtual` should fail with an error if a port with
+    /// the same name already exists in the system (`false` by default).
+    ///
+    /// This is only relevant for CoreMIDI, which otherwise silently creates
+    /// several endpoints with the same name, making it ambiguous to find them
+    /// by name. On ALSA and JACK, port names are always qualified by the
+    /// owning client, so this setting has no effect there.
+    fn reject_duplicate_virtual_names(&mut self, reject: bool);
+}
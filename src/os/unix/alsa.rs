@@ -0,0 +1,114 @@
+//! Extensions that are specific to the ALSA backend.
+
+use crate::ConnectErrorKind;
+
+/// Trait that is implemented by `MidiInputConnection` when using the ALSA
+/// backend, to manage the subscriptions of the connection's port. If the
+/// connection has been made with JACK (see `Backend`), all methods that
+/// return a `Result` fail.
+///
+/// This allows a single connection to merge the messages of several sources,
+/// which can be added and removed while the connection is open. Sources are
+/// specified as ALSA sequencer addresses, i.e. `(client, port)` pairs, as
+/// listed e.g. by `aconnect -i`.
+pub trait AlsaInputConnection {
+    /// Subscribe the port of this connection to the given source port,
+    /// in addition to the port it is already connected to.
+    fn subscribe_from(&mut self, sender: (i32, i32)) -> Result<(), ConnectErrorKind>;
+
+    /// Remove a subscription that has been added with `subscribe_from`
+    /// (or the one that has been created by `connect`).
+    fn unsubscribe_from(&mut self, sender: (i32, i32)) -> Result<(), ConnectErrorKind>;
+
+    /// Set a function that is invoked whenever a port is connected to or
+    /// disconnected from the port of this connection, e.g. when a DAW
+    /// connects to a virtual port. Pass `None` to remove a previously set
+    /// function.
+    ///
+    /// The function is called on the same thread as the callback of the
+    /// connection. The subscription that is created by `connect` (or
+    /// `subscribe_from`) is reported as well.
+    fn on_subscription_change(
+        &mut self,
+        callback: Option<SubscriptionCallback>,
+    ) -> Result<(), ConnectErrorKind>;
+
+    /// Returns whether the input queue, which is needed to timestamp incoming
+    /// events, could not be allocated when connecting (e.g. because of
+    /// resource limits). In that case, all timestamps are 0. This is always
+    /// `false` for connections that are not made with ALSA.
+    fn timestamps_degraded(&self) -> bool;
+}
+
+/// A change of the subscriptions of the port of an input connection
+/// (see `AlsaInputConnection::on_subscription_change`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubscriptionEvent {
+    /// The given sender port has been connected.
+    Subscribed {
+        /// The `(client, port)` address of the sender.
+        sender: (i32, i32),
+    },
+    /// The given sender port has been disconnected.
+    Unsubscribed {
+        /// The `(client, port)` address of the sender.
+        sender: (i32, i32),
+    },
+}
+
+/// A function that is notified about subscription changes
+/// (see `AlsaInputConnection::on_subscription_change`).
+pub type SubscriptionCallback = Box<dyn FnMut(SubscriptionEvent) + Send>;
+
+/// Trait that is implemented by `MidiOutputConnection` when using the ALSA
+/// backend. If the connection has been made with JACK (see `Backend`), the
+/// method fails.
+pub trait AlsaOutputConnection {
+    /// Enable or disable running status in the MIDI event encoder of this
+    /// connection (by calling `snd_midi_event_no_status`). It is enabled by
+    /// default.
+    ///
+    /// Running status allows status bytes that repeat the previous one to be
+    /// left out, which saves bandwidth on DIN-MIDI. Note that whether status
+    /// bytes are actually omitted on the wire is also up to the driver of the
+    /// receiving port.
+    fn set_running_status(&mut self, enable: bool) -> Result<(), ConnectErrorKind>;
+}
+
+/// Trait that is implemented by `MidiInput` and `MidiOutput` when using the
+/// ALSA backend, to find the sound card and device behind a sequencer port
+/// (e.g. for correlating ports with `/proc/asound`).
+pub trait AlsaHwInfo<P> {
+    /// Get the `(card, device)` numbers of the hardware behind the given port.
+    ///
+    /// This is a best-effort mapping that relies on the numbering scheme of
+    /// the kernel's sequencer clients for rawmidi devices. It returns `None`
+    /// for ports that don't belong to a sound card (e.g. ports of
+    /// applications or virtual ports) and if the port is not found.
+    fn hw_info(&self, port: &P) -> Option<(i32, i32)>;
+}
+
+/// A compatibility mode of the ALSA backend, which avoids features of the
+/// sequencer that are not supported well by some implementations of its API
+/// (see `AlsaCompat::set_compat`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum Compat {
+    /// Use all features (this is the default).
+    #[default]
+    None,
+    /// Avoid the features that misbehave with the ALSA sequencer emulation of
+    /// PipeWire: no queue is allocated for input connections and their ports
+    /// are not timestamped by the sequencer. Timestamps are then taken when
+    /// midir reads an event, which makes them less precise (this is the same
+    /// as with the `avoid_timestamping` feature).
+    PipeWire,
+}
+
+/// Trait that is implemented by `MidiInput` when using the ALSA backend, to
+/// work around limitations of the sequencer implementation. If the `MidiInput`
+/// uses JACK (see `Backend`), the setting has no effect.
+pub trait AlsaCompat {
+    /// Set the compatibility mode for connections opened afterwards.
+    fn set_compat(&mut self, compat: Compat);
+}
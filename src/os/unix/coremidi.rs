@@ -0,0 +1,30 @@
+//! Extensions that are specific to the CoreMIDI backend.
+//!
+//! With CoreMIDI, the name passed to `MidiInput::new` or `MidiOutput::new`
+//! names the client, while the `port_name` passed to `connect` names the port
+//! that the client creates for the connection. For virtual ports, the port
+//! name is the name of the endpoint that other applications see (they usually
+//! don't show the client name), so it is the name that matters.
+
+use crate::ConnectErrorKind;
+
+/// Trait that is implemented by `MidiInputConnection` and
+/// `MidiOutputConnection` when using the CoreMIDI backend.
+pub trait CoreMidiConnection {
+    /// Change the name of the port (or the virtual endpoint) that has been
+    /// created for this connection. Other applications are notified about
+    /// the change by CoreMIDI.
+    fn set_port_name(&mut self, name: &str) -> Result<(), ConnectErrorKind>;
+}
+
+/// Trait that is implemented by `MidiInputPort` and `MidiOutputPort` when
+/// using the CoreMIDI backend.
+pub trait CoreMidiPort {
+    /// Whether this is a virtual endpoint, which has been created by an
+    /// application (e.g. with `create_virtual`) instead of belonging to a
+    /// device of a driver. This allows grouping or hiding software ports.
+    ///
+    /// Note that the IAC buses and network sessions are owned by drivers, so
+    /// they are not considered virtual.
+    fn is_virtual(&self) -> bool;
+}
@@ -0,0 +1,100 @@
+//! Extensions that are specific to the JACK backend.
+
+use crate::ConnectErrorKind;
+
+/// Selects one of the two latency values that JACK keeps for every port.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LatencyMode {
+    /// How long ago the data that is read from (or written to) the port has
+    /// been captured, e.g. by a physical input.
+    Capture,
+    /// How long it takes until the data that is read from (or written to)
+    /// the port is played back, e.g. by a physical output.
+    Playback,
+}
+
+/// Options for creating a `MidiInput` or `MidiOutput` object that uses the
+/// JACK backend (see `MidiInput::new_with_options` and
+/// `MidiOutput::new_with_options`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct JackStartOptions {
+    /// Whether the JACK server is started if it is not running yet (disabled
+    /// by default, which is what `new` does). The server is started with the
+    /// default settings of the JACK installation.
+    pub start_server: bool,
+}
+
+/// Options for the connections that are opened with `connect` when using the
+/// JACK backend (see `JackInput::set_connect_options` and
+/// `JackOutput::set_connect_options`).
+///
+/// For every connection, a port of this client is registered first and then
+/// connected to the given port. If registering the port fails, `connect`
+/// returns a `ConnectErrorKind::Other` error, if connecting it fails (e.g.
+/// because the given port has disappeared) `ConnectErrorKind::InvalidPort`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JackConnectOptions {
+    /// Whether the registered port is connected to the given port right away
+    /// (enabled by default). If disabled, the port is only registered, so
+    /// that it can be wired up by the user in a patchbay (like `qjackctl`)
+    /// instead. The given port is still reported as the port of the
+    /// connection then.
+    pub auto_connect: bool,
+}
+
+impl Default for JackConnectOptions {
+    fn default() -> Self {
+        JackConnectOptions { auto_connect: true }
+    }
+}
+
+/// Trait that is implemented by `MidiInput` when using the JACK backend, to
+/// configure the connections that are opened afterwards. If ALSA is used
+/// instead (see `Backend`), the settings have no effect.
+pub trait JackInput {
+    /// Set the options for the connections that are opened afterwards.
+    fn set_connect_options(&mut self, options: JackConnectOptions);
+
+    /// Set whether incoming messages are timestamped with JACK frame times
+    /// instead of the microseconds of `jack_get_time` (disabled by default).
+    ///
+    /// The frame time of a message is the frame time at the start of the
+    /// process cycle in which it has been received (`jack_last_frame_time`)
+    /// plus its offset within the cycle, so it is sample-accurate and can be
+    /// aligned with the audio of other JACK clients. Frame times are passed
+    /// on unchanged, so the unit set with `MidiInput::set_timestamp_unit`
+    /// has no effect while this is enabled (and applies again once it has
+    /// been disabled).
+    fn set_frame_timestamps(&mut self, enable: bool);
+}
+
+/// Trait that is implemented by `MidiOutput` when using the JACK backend, to
+/// configure the connections that are opened afterwards. If ALSA is used
+/// instead (see `Backend`), the settings have no effect.
+pub trait JackOutput {
+    /// Set the options for the connections that are opened afterwards.
+    fn set_connect_options(&mut self, options: JackConnectOptions);
+}
+
+/// Trait that is implemented by `MidiInputConnection` and
+/// `MidiOutputConnection` when using the JACK backend, to take part in
+/// JACK's latency compensation. If the connection has been made with ALSA
+/// (see `Backend`), all methods fail.
+///
+/// Latencies are given in frames. By default, the ports created by *midir*
+/// report a latency of zero.
+pub trait JackConnection {
+    /// Set the latency range of the port that has been created for this
+    /// connection and let the JACK server recompute the latencies of the
+    /// graph, so that other clients see the change.
+    fn set_latency_range(
+        &mut self,
+        mode: LatencyMode,
+        min: u32,
+        max: u32,
+    ) -> Result<(), ConnectErrorKind>;
+
+    /// Get the latency range of the port that has been created for this
+    /// connection, as `(min, max)`.
+    fn latency_range(&self, mode: LatencyMode) -> Result<(u32, u32), ConnectErrorKind>;
+}
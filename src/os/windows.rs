@@ -0,0 +1,2 @@
+#[cfg(not(feature = "winrt"))]
+pub mod winmm;
@@ -0,0 +1,21 @@
+//! Extensions that are specific to the WinMM backend.
+
+use crate::ConnectErrorKind;
+
+/// Trait that is implemented by `MidiInputConnection` when using the WinMM
+/// backend, to temporarily stop receiving messages without closing the port
+/// (using `midiInStop` and `midiInStart`).
+///
+/// Messages that arrive while input is paused are discarded by the driver.
+/// A SysEx message that is cut off by pausing is dropped as well.
+pub trait WinMmInputConnection {
+    /// Stop receiving messages. Does nothing if input is already paused.
+    fn pause(&mut self) -> Result<(), ConnectErrorKind>;
+
+    /// Start receiving messages again after `pause`. Does nothing if input
+    /// is not paused.
+    fn resume(&mut self) -> Result<(), ConnectErrorKind>;
+
+    /// Whether input is currently running, i.e. the connection is not paused.
+    fn is_receiving(&self) -> bool;
+}
@@ -0,0 +1,224 @@
+//! Helpers for decoding individual fields of raw MIDI messages, as they are
+//! passed to the callback of an input connection.
+//!
+//! `ChannelVoiceEvent` decodes complete channel voice messages (see also
+//! `MidiInput::connect_events`), while `Message` can be used to parse any
+//! single message and to reject malformed ones. Both are decoded with
+//! `message::MidiMessage::parse`.
+
+use crate::message::MidiMessage;
+pub use crate::message::ParseMessageError;
+
+/// Decode a Song Position Pointer message (`0xF2`) into the position in the
+/// song, in MIDI beats (sixteenth notes) since the start of the song.
+///
+/// The position is sent as two 7-bit data bytes (least significant first),
+/// so it is in the range 0 to 16383. Returns `None` if the message is not
+/// a complete Song Position Pointer or a data byte has the high bit set.
+pub fn song_position(msg: &[u8]) -> Option<u16> {
+    match *msg {
+        [0xF2, lsb, msb] if lsb < 0x80 && msb < 0x80 => Some(((msb as u16) << 7) | lsb as u16),
+        _ => None,
+    }
+}
+
+/// A channel voice message, decoded from its raw bytes (see
+/// `ChannelVoiceEvent::parse`). Channels are numbered from 0 to 15.
+///
+/// Decoding never allocates: all fields are copied out of the message, and
+/// `Other` borrows the raw bytes of any message that is not a channel voice
+/// message (i.e. system messages, including SysEx).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelVoiceEvent<'a> {
+    /// Note Off (`0x8n`).
+    NoteOff { channel: u8, note: u8, velocity: u8 },
+    /// Note On (`0x9n`). A velocity of 0 is commonly used instead of a Note
+    /// Off message, but it is passed on unchanged.
+    NoteOn { channel: u8, note: u8, velocity: u8 },
+    /// Polyphonic Key Pressure (`0xAn`).
+    Aftertouch { channel: u8, note: u8, pressure: u8 },
+    /// Control Change (`0xBn`), including the channel mode messages.
+    ControlChange {
+        channel: u8,
+        controller: u8,
+        value: u8,
+    },
+    /// Program Change (`0xCn`).
+    ProgramChange { channel: u8, program: u8 },
+    /// Channel Pressure (`0xDn`).
+    ChannelPressure { channel: u8, pressure: u8 },
+    /// Pitch Bend Change (`0xEn`). The value is in the range 0 to 16383,
+    /// with 8192 meaning no bend.
+    PitchBend { channel: u8, value: u16 },
+    /// Any other message, including channel voice messages that are
+    /// incomplete or have a data byte with the high bit set.
+    Other(&'a [u8]),
+}
+
+impl<'a> ChannelVoiceEvent<'a> {
+    /// Decode a raw MIDI message.
+    pub fn parse(msg: &'a [u8]) -> ChannelVoiceEvent<'a> {
+        match MidiMessage::parse(msg) {
+            Ok(MidiMessage::NoteOff {
+                channel,
+                note,
+                velocity,
+            }) => ChannelVoiceEvent::NoteOff {
+                channel,
+                note,
+                velocity,
+            },
+            Ok(MidiMessage::NoteOn {
+                channel,
+                note,
+                velocity,
+            }) => ChannelVoiceEvent::NoteOn {
+                channel,
+                note,
+                velocity,
+            },
+            Ok(MidiMessage::Aftertouch {
+                channel,
+                note,
+                pressure,
+            }) => ChannelVoiceEvent::Aftertouch {
+                channel,
+                note,
+                pressure,
+            },
+            Ok(MidiMessage::ControlChange {
+                channel,
+                controller,
+                value,
+            }) => ChannelVoiceEvent::ControlChange {
+                channel,
+                controller,
+                value,
+            },
+            Ok(MidiMessage::ProgramChange { channel, program }) => {
+                ChannelVoiceEvent::ProgramChange { channel, program }
+            }
+            Ok(MidiMessage::ChannelPressure { channel, pressure }) => {
+                ChannelVoiceEvent::ChannelPressure { channel, pressure }
+            }
+            Ok(MidiMessage::PitchBend { channel, value }) => {
+                ChannelVoiceEvent::PitchBend { channel, value }
+            }
+            _ => ChannelVoiceEvent::Other(msg),
+        }
+    }
+}
+
+/// A complete MIDI message that owns the data of SysEx messages, parsed from
+/// its raw bytes with `Message::try_from` (see `MidiMessage::into_owned`).
+pub type Message = MidiMessage<'static>;
+
+impl<'a> TryFrom<&'a [u8]> for Message {
+    type Error = ParseMessageError;
+
+    fn try_from(msg: &'a [u8]) -> Result<Message, ParseMessageError> {
+        MidiMessage::parse(msg).map(MidiMessage::into_owned)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_song_position() {
+        assert_eq!(song_position(&[0xF2, 0x00, 0x00]), Some(0));
+        assert_eq!(song_position(&[0xF2, 0x7F, 0x7F]), Some(16383));
+        assert_eq!(song_position(&[0xF2, 0x10, 0x01]), Some(144));
+        assert_eq!(song_position(&[0xF2, 0x10]), None);
+        assert_eq!(song_position(&[0xF2, 0x80, 0x00]), None);
+        assert_eq!(song_position(&[0xF3, 0x00, 0x00]), None);
+    }
+
+    #[test]
+    fn test_channel_voice_event() {
+        assert_eq!(
+            ChannelVoiceEvent::parse(&[0x93, 60, 100]),
+            ChannelVoiceEvent::NoteOn {
+                channel: 3,
+                note: 60,
+                velocity: 100
+            }
+        );
+        assert_eq!(
+            ChannelVoiceEvent::parse(&[0xC0, 5]),
+            ChannelVoiceEvent::ProgramChange {
+                channel: 0,
+                program: 5
+            }
+        );
+        assert_eq!(
+            ChannelVoiceEvent::parse(&[0xEF, 0x00, 0x40]),
+            ChannelVoiceEvent::PitchBend {
+                channel: 15,
+                value: 8192
+            }
+        );
+        assert_eq!(
+            ChannelVoiceEvent::parse(&[0x90, 60]),
+            ChannelVoiceEvent::Other(&[0x90, 60])
+        );
+        assert_eq!(
+            ChannelVoiceEvent::parse(&[0xC0, 5, 6]),
+            ChannelVoiceEvent::Other(&[0xC0, 5, 6])
+        );
+        assert_eq!(
+            ChannelVoiceEvent::parse(&[0xF8]),
+            ChannelVoiceEvent::Other(&[0xF8])
+        );
+        assert_eq!(ChannelVoiceEvent::parse(&[]), ChannelVoiceEvent::Other(&[]));
+    }
+
+    #[test]
+    fn test_message_try_from() {
+        assert_eq!(
+            Message::try_from(&[0x93, 60, 100][..]),
+            Ok(Message::NoteOn {
+                channel: 3,
+                note: 60,
+                velocity: 100
+            })
+        );
+        assert_eq!(
+            Message::try_from(&[0xF2, 0x10, 0x01][..]),
+            Ok(Message::SongPosition(144))
+        );
+        assert_eq!(Message::try_from(&[0xF8][..]), Ok(Message::TimingClock));
+        assert_eq!(
+            Message::try_from(&[0xF0, 0x7E, 0x01, 0xF7][..]),
+            Ok(Message::SysEx(vec![0x7E, 0x01].into()))
+        );
+        // The data is copied, so the message can outlive the raw bytes
+        let sysex = Message::try_from(&[0xF0, 0x7E, 0x01, 0xF7][..]).unwrap();
+        assert!(matches!(sysex, Message::SysEx(std::borrow::Cow::Owned(_))));
+        assert_eq!(Message::try_from(&[][..]), Err(ParseMessageError::Empty));
+        assert_eq!(
+            Message::try_from(&[0x40, 0x00][..]),
+            Err(ParseMessageError::InvalidStatus(0x40))
+        );
+        assert_eq!(
+            Message::try_from(&[0xF4][..]),
+            Err(ParseMessageError::InvalidStatus(0xF4))
+        );
+        assert_eq!(
+            Message::try_from(&[0x90, 60][..]),
+            Err(ParseMessageError::WrongLength {
+                expected: 3,
+                actual: 2
+            })
+        );
+        assert_eq!(
+            Message::try_from(&[0xC0, 0x80][..]),
+            Err(ParseMessageError::InvalidDataByte(0x80))
+        );
+        assert_eq!(
+            Message::try_from(&[0xF0, 0x7E, 0x01][..]),
+            Err(ParseMessageError::UnterminatedSysEx)
+        );
+    }
+}
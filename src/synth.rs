@@ -0,0 +1,279 @@
+//! A simple software synthesizer for demos and tests without MIDI hardware
+//! (requires the `synth` feature).
+//!
+//! `SoftSynth::start` creates a virtual input port that plays the notes it
+//! receives on the default audio output device. Since it is a virtual port,
+//! it is listed by `MidiOutput::ports` like any other output port (also to
+//! other applications), so e.g. the `test_play` example can send to it.
+//!
+//! The synthesizer only knows a single sine wave sound and ignores all
+//! messages except Note On/Off and All Notes Off/All Sound Off.
+
+use std::error::Error;
+use std::fmt;
+use std::io::{stderr, Write};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{FromSample, SampleFormat, SizedSample, StreamConfig};
+
+use crate::os::unix::VirtualInput;
+use crate::{ConnectErrorKind, Ignore, InitError, MidiInput, MidiInputConnection};
+
+const MAX_VOICES: usize = 32;
+const MASTER_GAIN: f32 = 0.2;
+/// How fast a voice approaches its target level per sample (avoids clicks).
+const ENVELOPE_RATE: f32 = 0.002;
+
+/// An error that can occur when starting a `SoftSynth`.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum SynthError {
+    /// The MIDI input could not be created.
+    Init(InitError),
+    /// The virtual port could not be created.
+    Connect(ConnectErrorKind),
+    /// The audio output could not be opened.
+    Audio(&'static str),
+}
+
+impl Error for SynthError {}
+
+impl fmt::Display for SynthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            SynthError::Init(ref err) => err.fmt(f),
+            SynthError::Connect(ref kind) => kind.fmt(f),
+            SynthError::Audio(msg) => msg.fmt(f),
+        }
+    }
+}
+
+struct Voice {
+    channel: u8,
+    note: u8,
+    phase: f32,
+    step: f32,
+    level: f32,
+    target: f32,
+}
+
+/// The sound generation, shared between the MIDI callback and the audio thread.
+struct Engine {
+    sample_rate: f32,
+    voices: Vec<Voice>,
+}
+
+impl Engine {
+    fn new(sample_rate: f32) -> Engine {
+        Engine {
+            sample_rate,
+            voices: Vec::with_capacity(MAX_VOICES),
+        }
+    }
+
+    fn handle(&mut self, message: &[u8]) {
+        match *message {
+            [status, note, velocity, ..] if status & 0xF0 == 0x90 && velocity > 0 => {
+                self.note_on(status & 0x0F, note & 0x7F, velocity & 0x7F)
+            }
+            [status, note, ..] if status & 0xF0 == 0x80 || status & 0xF0 == 0x90 => {
+                self.note_off(|voice| voice.channel == status & 0x0F && voice.note == note)
+            }
+            // All Sound Off, All Notes Off
+            [status, 120 | 123, ..] if status & 0xF0 == 0xB0 => {
+                self.note_off(|voice| voice.channel == status & 0x0F)
+            }
+            _ => {}
+        }
+    }
+
+    fn note_on(&mut self, channel: u8, note: u8, velocity: u8) {
+        if self.voices.len() == MAX_VOICES {
+            // Steal the oldest voice
+            self.voices.remove(0);
+        }
+        let frequency = 440.0 * 2f32.powf((note as f32 - 69.0) / 12.0);
+        self.voices.push(Voice {
+            channel,
+            note,
+            phase: 0.0,
+            step: frequency / self.sample_rate,
+            level: 0.0,
+            target: velocity as f32 / 127.0,
+        });
+    }
+
+    fn note_off<F: Fn(&Voice) -> bool>(&mut self, matches: F) {
+        for voice in self.voices.iter_mut().filter(|voice| matches(voice)) {
+            voice.target = 0.0;
+        }
+    }
+
+    fn next_sample(&mut self) -> f32 {
+        let mut sample = 0.0;
+        for voice in &mut self.voices {
+            voice.level += (voice.target - voice.level) * ENVELOPE_RATE;
+            sample += (voice.phase * std::f32::consts::TAU).sin() * voice.level;
+            voice.phase = (voice.phase + voice.step).fract();
+        }
+        self.voices
+            .retain(|voice| voice.target > 0.0 || voice.level > 0.0001);
+        (sample * MASTER_GAIN).clamp(-1.0, 1.0)
+    }
+}
+
+/// Keeps the audio stream alive on its own thread, because streams can't be
+/// sent to other threads on all platforms.
+struct AudioThread {
+    stop: Option<mpsc::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl AudioThread {
+    fn spawn(engine: Arc<Mutex<Engine>>) -> Result<AudioThread, SynthError> {
+        let (ready_tx, ready_rx) = mpsc::channel();
+        let (stop_tx, stop_rx) = mpsc::channel::<()>();
+        let thread = thread::Builder::new()
+            .name("midir software synth".to_owned())
+            .spawn(move || match open_stream(engine) {
+                Ok(stream) => {
+                    let _ = ready_tx.send(Ok(()));
+                    // Block until the synth is stopped (or the sender is dropped)
+                    let _ = stop_rx.recv();
+                    drop(stream);
+                }
+                Err(err) => {
+                    let _ = ready_tx.send(Err(err));
+                }
+            })
+            .map_err(|_| SynthError::Audio("could not start audio thread"))?;
+        match ready_rx.recv() {
+            Ok(Ok(())) => Ok(AudioThread {
+                stop: Some(stop_tx),
+                thread: Some(thread),
+            }),
+            Ok(Err(err)) => Err(err),
+            Err(_) => Err(SynthError::Audio("audio thread terminated unexpectedly")),
+        }
+    }
+}
+
+impl Drop for AudioThread {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn open_stream(engine: Arc<Mutex<Engine>>) -> Result<cpal::Stream, SynthError> {
+    let device = cpal::default_host()
+        .default_output_device()
+        .ok_or(SynthError::Audio("no audio output device available"))?;
+    let supported = device
+        .default_output_config()
+        .map_err(|_| SynthError::Audio("could not query audio output configuration"))?;
+    let sample_format = supported.sample_format();
+    let config: StreamConfig = supported.into();
+    engine.lock().unwrap().sample_rate = config.sample_rate.0 as f32;
+    let stream = match sample_format {
+        SampleFormat::F32 => build_stream::<f32>(&device, &config, engine),
+        SampleFormat::I16 => build_stream::<i16>(&device, &config, engine),
+        SampleFormat::U16 => build_stream::<u16>(&device, &config, engine),
+        _ => return Err(SynthError::Audio("unsupported audio sample format")),
+    }?;
+    stream
+        .play()
+        .map_err(|_| SynthError::Audio("could not start audio output"))?;
+    Ok(stream)
+}
+
+fn build_stream<T>(
+    device: &cpal::Device,
+    config: &StreamConfig,
+    engine: Arc<Mutex<Engine>>,
+) -> Result<cpal::Stream, SynthError>
+where
+    T: SizedSample + FromSample<f32>,
+{
+    let channels = config.channels as usize;
+    device
+        .build_output_stream(
+            config,
+            move |data: &mut [T], _| {
+                let mut engine = engine.lock().unwrap();
+                for frame in data.chunks_mut(channels) {
+                    let value = T::from_sample(engine.next_sample());
+                    for sample in frame {
+                        *sample = value;
+                    }
+                }
+            },
+            |err| {
+                let _ = writeln!(stderr(), "Error in midir software synth: {}", err);
+            },
+            None,
+        )
+        .map_err(|_| SynthError::Audio("could not open audio output stream"))
+}
+
+/// A software synthesizer that plays the messages sent to its virtual port
+/// (see the module documentation). The port is removed and the audio output
+/// is closed when this object is dropped.
+pub struct SoftSynth {
+    // The connection is dropped first, so that no messages arrive after the audio has stopped
+    _conn: MidiInputConnection<()>,
+    _audio: AudioThread,
+}
+
+impl SoftSynth {
+    /// Open the default audio output device and create a virtual port named
+    /// `port_name` (using the same name for the client) that plays the notes
+    /// it receives.
+    pub fn start(port_name: &str) -> Result<SoftSynth, SynthError> {
+        let engine = Arc::new(Mutex::new(Engine::new(44100.0)));
+        let audio = AudioThread::spawn(engine.clone())?;
+        let mut midi_in = MidiInput::new(port_name).map_err(SynthError::Init)?;
+        midi_in.ignore(Ignore::All);
+        let conn = midi_in
+            .create_virtual(
+                port_name,
+                move |_, message, _| engine.lock().unwrap().handle(message),
+                (),
+            )
+            .map_err(|e| SynthError::Connect(e.kind()))?;
+        Ok(SoftSynth {
+            _conn: conn,
+            _audio: audio,
+        })
+    }
+
+    /// Stop the synthesizer. This is equivalent to dropping the object.
+    pub fn stop(self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_voices() {
+        let mut engine = Engine::new(1000.0);
+        engine.handle(&[0x90, 69, 127]);
+        engine.handle(&[0x91, 60, 100]);
+        assert_eq!(engine.voices.len(), 2);
+        assert!((0..100).any(|_| engine.next_sample() != 0.0));
+
+        // Note On with velocity 0 is a Note Off
+        engine.handle(&[0x90, 69, 0]);
+        engine.handle(&[0xB1, 123, 0]);
+        for _ in 0..100_000 {
+            engine.next_sample();
+        }
+        assert!(engine.voices.is_empty());
+    }
+}
@@ -0,0 +1,119 @@
+//! Conversion of the timestamps of the different backends.
+//!
+//! The timestamps that are passed to input callbacks are normalized to
+//! nanoseconds (and then converted to the unit set with
+//! `MidiInput::set_timestamp_unit`), but their reference point depends on
+//! the backend:
+//!
+//! * ALSA: the start of the input queue of the connection (a real time of
+//!   `tv_sec` seconds and `tv_nsec` nanoseconds)
+//! * CoreMIDI: the host time (in ticks of `mach_absolute_time`), i.e. usually
+//!   the time since the system has been booted
+//! * JACK: the time of the JACK server (`jack_get_time`, in microseconds)
+//! * WinMM: the opening of the port (in milliseconds)
+//! * WinRT: the creation of the port (in ticks of 100 nanoseconds)
+//! * Web MIDI: the time origin of the page (`performance.now()`, in
+//!   fractional milliseconds)
+//!
+//! `Timestamp` converts the raw times of the backends, e.g. those obtained
+//! through the platform APIs, into the same representation.
+
+use std::time::Duration;
+
+use crate::TimestampUnit;
+
+/// A point in time, in nanoseconds since the reference point of a backend
+/// (see the module documentation).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Timestamp(u64);
+
+impl Timestamp {
+    /// Create a timestamp from nanoseconds, as reported by the backends.
+    pub const fn from_nanos(nanos: u64) -> Timestamp {
+        Timestamp(nanos)
+    }
+
+    /// Convert an ALSA sequencer real time (`snd_seq_real_time_t`).
+    pub const fn from_alsa(tv_sec: u32, tv_nsec: u32) -> Timestamp {
+        Timestamp(tv_sec as u64 * 1_000_000_000 + tv_nsec as u64)
+    }
+
+    /// Convert a CoreMIDI host time (`MIDITimeStamp`), using
+    /// `AudioConvertHostTimeToNanos`.
+    #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "jack")))]
+    pub fn from_coremidi_host_time(host_time: u64) -> Timestamp {
+        Timestamp(crate::backend::host_time_to_nanos(host_time))
+    }
+
+    /// Convert a JACK time (`jack_time_t`), which is in microseconds.
+    pub const fn from_jack_micros(micros: u64) -> Timestamp {
+        Timestamp(micros * 1_000)
+    }
+
+    /// Convert a WinMM input timestamp, which is in milliseconds.
+    pub const fn from_winmm_millis(millis: u32) -> Timestamp {
+        Timestamp(millis as u64 * 1_000_000)
+    }
+
+    /// Convert a WinRT `TimeSpan`, which is in ticks of 100 nanoseconds.
+    /// Negative durations are clamped to zero.
+    pub const fn from_winrt_ticks(ticks: i64) -> Timestamp {
+        if ticks < 0 {
+            Timestamp(0)
+        } else {
+            Timestamp(ticks as u64 * 100)
+        }
+    }
+
+    /// Convert a Web MIDI `DOMHighResTimeStamp`, which is in (fractional)
+    /// milliseconds. Negative or invalid values are clamped to zero.
+    pub fn from_webmidi_millis(millis: f64) -> Timestamp {
+        Timestamp((millis * 1_000_000.0) as u64)
+    }
+
+    /// The timestamp in nanoseconds.
+    pub const fn as_nanos(self) -> u64 {
+        self.0
+    }
+
+    /// The timestamp in microseconds (rounded down), which is the unit that
+    /// is passed to input callbacks by default.
+    pub const fn as_micros(self) -> u64 {
+        self.0 / 1_000
+    }
+
+    /// The timestamp in the given unit, as it is passed to input callbacks
+    /// (see `MidiInput::set_timestamp_unit`).
+    pub fn in_unit(self, unit: TimestampUnit) -> u64 {
+        unit.convert_nanos(self.0)
+    }
+
+    /// The time since the reference point.
+    pub const fn as_duration(self) -> Duration {
+        Duration::from_nanos(self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_conversions() {
+        assert_eq!(Timestamp::from_alsa(2, 500).as_nanos(), 2_000_000_500);
+        assert_eq!(Timestamp::from_jack_micros(1_500).as_nanos(), 1_500_000);
+        assert_eq!(Timestamp::from_winmm_millis(3).as_micros(), 3_000);
+        assert_eq!(Timestamp::from_winrt_ticks(7).as_nanos(), 700);
+        assert_eq!(Timestamp::from_winrt_ticks(-7).as_nanos(), 0);
+        assert_eq!(Timestamp::from_webmidi_millis(1.5).as_nanos(), 1_500_000);
+        assert_eq!(Timestamp::from_webmidi_millis(-1.0).as_nanos(), 0);
+        assert_eq!(
+            Timestamp::from_nanos(1_234_567).in_unit(TimestampUnit::Micros),
+            1_234
+        );
+        assert_eq!(
+            Timestamp::from_nanos(1_234_567).as_duration(),
+            Duration::from_nanos(1_234_567)
+        );
+    }
+}
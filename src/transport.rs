@@ -0,0 +1,259 @@
+//! Decoding of transport messages, i.e. the song position and the system
+//! realtime messages Start, Stop and Continue, as well as MIDI Machine
+//! Control (MMC) commands.
+//!
+//! A `TransportDecoder` is fed with the raw messages received by an input
+//! connection and keeps track of the transport state (whether the sender is
+//! playing and its position in the song).
+
+/// A frame rate of MIDI time code (as encoded in an MMC Locate command).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameRate {
+    /// 24 frames per second.
+    Fps24,
+    /// 25 frames per second.
+    Fps25,
+    /// 29.97 frames per second (drop frame).
+    Fps30Drop,
+    /// 30 frames per second.
+    Fps30,
+}
+
+/// A position in time code, as sent with the MMC Locate command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Locate {
+    /// The frame rate of the time code.
+    pub frame_rate: FrameRate,
+    /// Hours (0 to 23).
+    pub hours: u8,
+    /// Minutes (0 to 59).
+    pub minutes: u8,
+    /// Seconds (0 to 59).
+    pub seconds: u8,
+    /// Frames (0 to the frame rate - 1).
+    pub frames: u8,
+    /// Fractional frames (in 1/100 of a frame).
+    pub subframes: u8,
+}
+
+/// An MMC command other than Stop, Play and Locate (see `Transport`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MmcCommand {
+    /// Fast forward.
+    FastForward,
+    /// Rewind.
+    Rewind,
+    /// Start recording (punch in).
+    RecordStrobe,
+    /// Stop recording (punch out).
+    RecordExit,
+    /// Pause recording.
+    RecordPause,
+    /// Pause.
+    Pause,
+    /// Eject the medium.
+    Eject,
+    /// Chase the time code.
+    Chase,
+    /// Reset the MMC state of the device.
+    Reset,
+    /// Any other command, with its code.
+    Other(u8),
+}
+
+/// A transport event (see `TransportDecoder::decode`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    /// Start playing from the beginning of the song (`0xFA`).
+    Start,
+    /// Stop playing (`0xFC` or the MMC Stop command).
+    Stop,
+    /// Continue playing from the current position (`0xFB` or the MMC Play
+    /// and Deferred Play commands).
+    Continue,
+    /// Set the position in the song, in MIDI beats (sixteenth notes) since
+    /// the start of the song (`0xF2`).
+    SongPosition(u16),
+    /// Move to the given time code position (MMC Locate command).
+    Locate(Locate),
+    /// Any other MMC command.
+    Mmc(MmcCommand),
+}
+
+/// The MMC device ID that addresses all devices.
+pub const ALL_CALL: u8 = 0x7F;
+
+const CLOCKS_PER_BEAT: u32 = 6;
+
+/// Decodes transport events from incoming messages and keeps track of
+/// the transport state.
+#[derive(Debug, Clone)]
+pub struct TransportDecoder {
+    device_id: u8,
+    playing: bool,
+    clocks: u32,
+}
+
+impl Default for TransportDecoder {
+    fn default() -> Self {
+        TransportDecoder::new(ALL_CALL)
+    }
+}
+
+impl TransportDecoder {
+    /// Create a decoder that accepts MMC commands sent to `device_id` (or
+    /// to all devices). With `ALL_CALL`, all MMC commands are accepted.
+    pub fn new(device_id: u8) -> TransportDecoder {
+        TransportDecoder {
+            device_id,
+            playing: false,
+            clocks: 0,
+        }
+    }
+
+    /// Whether the sender is currently playing.
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+
+    /// The current position in the song, in MIDI beats (sixteenth notes),
+    /// as set by Song Position messages and advanced by timing clock
+    /// messages (`0xF8`) while playing.
+    pub fn song_position(&self) -> u32 {
+        self.clocks / CLOCKS_PER_BEAT
+    }
+
+    /// Process a raw MIDI message, update the transport state and return
+    /// the transport event it represents, if any. Timing clock messages
+    /// update the position, but don't produce an event.
+    pub fn decode(&mut self, message: &[u8]) -> Option<Transport> {
+        let event = match *message {
+            [0xF8, ..] => {
+                if self.playing {
+                    self.clocks += 1;
+                }
+                return None;
+            }
+            [0xFA, ..] => Transport::Start,
+            [0xFB, ..] => Transport::Continue,
+            [0xFC, ..] => Transport::Stop,
+            [0xF2, ..] => Transport::SongPosition(crate::parse::song_position(message)?),
+            [0xF0, 0x7F, device_id, 0x06, ..] => {
+                if device_id != ALL_CALL
+                    && self.device_id != ALL_CALL
+                    && device_id != self.device_id
+                {
+                    return None;
+                }
+                parse_mmc(&message[4..])?
+            }
+            _ => return None,
+        };
+        match event {
+            Transport::Start => {
+                self.playing = true;
+                self.clocks = 0;
+            }
+            Transport::Continue => self.playing = true,
+            Transport::Stop => self.playing = false,
+            Transport::SongPosition(beats) => self.clocks = beats as u32 * CLOCKS_PER_BEAT,
+            _ => {}
+        }
+        Some(event)
+    }
+}
+
+/// Parses an MMC command (the part of the sysex message after the sub-ID).
+fn parse_mmc(command: &[u8]) -> Option<Transport> {
+    let mmc = match *command.first()? {
+        0x01 => return Some(Transport::Stop),
+        0x02 | 0x03 => return Some(Transport::Continue),
+        0x04 => MmcCommand::FastForward,
+        0x05 => MmcCommand::Rewind,
+        0x06 => MmcCommand::RecordStrobe,
+        0x07 => MmcCommand::RecordExit,
+        0x08 => MmcCommand::RecordPause,
+        0x09 => MmcCommand::Pause,
+        0x0A => MmcCommand::Eject,
+        0x0B => MmcCommand::Chase,
+        0x0D => MmcCommand::Reset,
+        // Locate with the "target" sub-command: <count = 6> <01> hr mn sc fr ff
+        0x44 => {
+            return match *command {
+                [0x44, 0x06, 0x01, hr, mn, sc, fr, ff, ..] => Some(Transport::Locate(Locate {
+                    frame_rate: match (hr >> 5) & 0x03 {
+                        0 => FrameRate::Fps24,
+                        1 => FrameRate::Fps25,
+                        2 => FrameRate::Fps30Drop,
+                        _ => FrameRate::Fps30,
+                    },
+                    hours: hr & 0x1F,
+                    minutes: mn & 0x3F,
+                    seconds: sc & 0x3F,
+                    frames: fr & 0x1F,
+                    subframes: ff & 0x7F,
+                })),
+                _ => None,
+            };
+        }
+        0xF7 => return None,
+        other => MmcCommand::Other(other),
+    };
+    Some(Transport::Mmc(mmc))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_realtime() {
+        let mut decoder = TransportDecoder::default();
+        assert_eq!(
+            decoder.decode(&[0xF2, 0x10, 0x01]),
+            Some(Transport::SongPosition(144))
+        );
+        assert_eq!(decoder.song_position(), 144);
+        assert_eq!(decoder.decode(&[0xFB]), Some(Transport::Continue));
+        for _ in 0..12 {
+            assert_eq!(decoder.decode(&[0xF8]), None);
+        }
+        assert_eq!(decoder.song_position(), 146);
+        assert_eq!(decoder.decode(&[0xFC]), Some(Transport::Stop));
+        assert!(!decoder.is_playing());
+        decoder.decode(&[0xF8]);
+        assert_eq!(decoder.song_position(), 146);
+        assert_eq!(decoder.decode(&[0xFA]), Some(Transport::Start));
+        assert_eq!(decoder.song_position(), 0);
+        assert!(decoder.is_playing());
+    }
+
+    #[test]
+    fn test_mmc() {
+        let mut decoder = TransportDecoder::new(0x10);
+        assert_eq!(
+            decoder.decode(&[0xF0, 0x7F, 0x7F, 0x06, 0x02, 0xF7]),
+            Some(Transport::Continue)
+        );
+        assert_eq!(
+            decoder.decode(&[0xF0, 0x7F, 0x10, 0x06, 0x05, 0xF7]),
+            Some(Transport::Mmc(MmcCommand::Rewind))
+        );
+        // addressed to another device
+        assert_eq!(decoder.decode(&[0xF0, 0x7F, 0x11, 0x06, 0x01, 0xF7]), None);
+        assert_eq!(
+            decoder.decode(&[
+                0xF0, 0x7F, 0x10, 0x06, 0x44, 0x06, 0x01, 0x21, 0x02, 0x03, 0x04, 0x00, 0xF7
+            ]),
+            Some(Transport::Locate(Locate {
+                frame_rate: FrameRate::Fps25,
+                hours: 1,
+                minutes: 2,
+                seconds: 3,
+                frames: 4,
+                subframes: 0,
+            }))
+        );
+        assert_eq!(decoder.decode(&[0xF0, 0x7F, 0x10, 0x06, 0x44, 0xF7]), None);
+    }
+}
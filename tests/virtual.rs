@@ -1,14 +1,19 @@
 //! This file contains automated tests, but they require virtual ports and therefore can't work on Windows or Web MIDI ...
 #![cfg(not(any(windows, target_arch = "wasm32")))]
 
+use std::sync::Mutex;
 use std::thread::sleep;
 use std::time::Duration;
 
 use midir::os::unix::{VirtualInput, VirtualOutput};
-use midir::{Ignore, MidiInput, MidiOutput, MidiOutputPort};
+use midir::{Ignore, InputExecutor, MidiInput, MidiOutput, MidiOutputPort, PortInfoError};
+
+// The tests compare port (and file descriptor) counts, so they must not run concurrently
+static SERIAL: Mutex<()> = Mutex::new(());
 
 #[test]
 fn end_to_end() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
     let mut midi_in = MidiInput::new("My Test Input").unwrap();
     midi_in.ignore(Ignore::None);
     let midi_out = MidiOutput::new("My Test Output").unwrap();
@@ -28,7 +33,7 @@ fn end_to_end() {
 
     assert_eq!(midi_out.port_count(), previous_count + 1);
 
-    let new_port: MidiOutputPort = midi_out.ports().into_iter().rev().next().unwrap();
+    let new_port: MidiOutputPort = midi_out.ports().into_iter().next_back().unwrap();
 
     println!(
         "Connecting to port '{}' ...",
@@ -57,7 +62,7 @@ fn end_to_end() {
     let mut conn_out = midi_out.create_virtual("midir-test").unwrap();
     assert_eq!(midi_in.port_count(), previous_count + 1);
 
-    let new_port = midi_in.ports().into_iter().rev().next().unwrap();
+    let new_port = midi_in.ports().into_iter().next_back().unwrap();
 
     println!(
         "Connecting to port '{}' ...",
@@ -88,3 +93,308 @@ fn end_to_end() {
     conn_out.close();
     assert_eq!(midi_in.port_count(), previous_count);
 }
+
+#[cfg(target_os = "linux")]
+fn open_fd_count() -> usize {
+    std::fs::read_dir("/proc/self/fd").unwrap().count()
+}
+
+#[test]
+fn reuse_after_close() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let mut midi_in = MidiInput::new("My Test Input").unwrap();
+    let mut midi_out = MidiOutput::new("My Test Output").unwrap();
+
+    let previous_count = midi_out.port_count();
+    #[cfg(target_os = "linux")]
+    let previous_fds = open_fd_count();
+
+    for _ in 0..100 {
+        let conn_in = midi_in
+            .create_virtual("midir-test", |_, _, _| {}, ())
+            .unwrap();
+        let new_port = midi_out.ports().into_iter().next_back().unwrap();
+        let mut conn_out = midi_out.connect(&new_port, "midir-test").unwrap();
+        conn_out.send(&[144, 60, 1]).unwrap();
+        midi_out = conn_out.close();
+        midi_in = conn_in.close().0;
+    }
+
+    assert_eq!(midi_out.port_count(), previous_count);
+    #[cfg(target_os = "linux")]
+    assert_eq!(open_fd_count(), previous_fds);
+}
+
+/// An executor that drops the tasks instead of running them.
+#[cfg(target_os = "linux")]
+struct DroppingExecutor;
+
+#[cfg(target_os = "linux")]
+impl InputExecutor for DroppingExecutor {
+    fn execute(&self, task: Box<dyn FnOnce() + Send>) {
+        drop(task);
+    }
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn drop_with_dropped_executor_task() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let midi_in = MidiInput::new("My Test Input").unwrap();
+    let midi_out = MidiOutput::new("My Test Output").unwrap();
+
+    let conn_out = midi_out.create_virtual("midir-test").unwrap();
+    let new_port = midi_in.ports().into_iter().next_back().unwrap();
+    let previous_fds = open_fd_count();
+    let conn_in = midi_in
+        .connect_on(&DroppingExecutor, &new_port, "midir-test", |_, _, _| {}, ())
+        .unwrap();
+    // Dropping must neither panic nor leak the file descriptors of the connection. The
+    // sequencer handle of `midi_in` is closed together with the connection, too.
+    drop(conn_in);
+    assert_eq!(open_fd_count(), previous_fds - 1);
+    conn_out.close();
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn close_with_dropped_executor_task() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let midi_in = MidiInput::new("My Test Input").unwrap();
+    let midi_out = MidiOutput::new("My Test Output").unwrap();
+
+    let conn_out = midi_out.create_virtual("midir-test").unwrap();
+    let new_port = midi_in.ports().into_iter().next_back().unwrap();
+    let conn_in = midi_in
+        .connect_on(&DroppingExecutor, &new_port, "midir-test", |_, _, _| {}, 42)
+        .unwrap();
+    // The data has never been handed to the task, so it is returned as it was
+    let (midi_in, data) = conn_in.close();
+    assert_eq!(data, 42);
+    assert!(midi_in.ports().contains(&new_port));
+    conn_out.close();
+}
+
+#[test]
+#[cfg(all(target_os = "linux", not(feature = "jack")))]
+fn backend_options() {
+    let mut midi_in = MidiInput::new("My Test Input").unwrap();
+    assert!(midi_in
+        .set_backend_option("alsa.input_pool_size", "500")
+        .is_ok());
+    assert!(midi_in
+        .set_backend_option("alsa.input_pool_size", "many")
+        .is_err());
+    assert!(midi_in.set_backend_option("unknown.option", "1").is_ok());
+}
+
+#[test]
+fn ordering_under_load() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let midi_in = MidiInput::new("My Test Input").unwrap();
+    let midi_out = MidiOutput::new("My Test Output").unwrap();
+
+    let conn_in = midi_in
+        .create_virtual(
+            "midir-test",
+            |_, message, received: &mut Vec<Vec<u8>>| received.push(message.to_vec()),
+            Vec::new(),
+        )
+        .unwrap();
+    let new_port = midi_out.ports().into_iter().next_back().unwrap();
+    let mut conn_out = midi_out.connect(&new_port, "midir-test").unwrap();
+
+    let sent: Vec<Vec<u8>> = (0..1000u16)
+        .map(|i| vec![0xB0, (i >> 7) as u8, (i & 0x7F) as u8])
+        .collect();
+    for (i, message) in sent.iter().enumerate() {
+        conn_out.send(message).unwrap();
+        // give the receiver a chance to keep up, so that no messages are dropped
+        if i % 100 == 99 {
+            sleep(Duration::from_millis(10));
+        }
+    }
+    sleep(Duration::from_millis(200));
+
+    conn_out.close();
+    let (_, received) = conn_in.close();
+    assert_eq!(received, sent);
+}
+
+#[test]
+#[cfg(feature = "wmidi")]
+fn wmidi_round_trip() {
+    use midir::wmidi::{Channel, MidiMessage, Note, U7};
+
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let mut midi_in = MidiInput::new("My Test Input").unwrap();
+    midi_in.ignore(Ignore::None);
+    let midi_out = MidiOutput::new("My Test Output").unwrap();
+
+    let mut conn_out = midi_out.create_virtual("midir-test").unwrap();
+    let new_port = midi_in.ports().into_iter().next_back().unwrap();
+    let conn_in = midi_in
+        .connect_wmidi(
+            &new_port,
+            "midir-test",
+            |_, message, received: &mut Vec<MidiMessage<'static>>| {
+                received.push(message.to_owned())
+            },
+            Vec::new(),
+        )
+        .unwrap();
+
+    let sysex = U7::try_from_bytes(&[0x7E, 0x7F, 0x06, 0x01]).unwrap();
+    let sent = [
+        MidiMessage::NoteOn(Channel::Ch1, Note::C4, U7::MAX),
+        MidiMessage::SysEx(sysex),
+        MidiMessage::NoteOff(Channel::Ch1, Note::C4, U7::MIN),
+    ];
+    for message in &sent {
+        conn_out.send_wmidi(message).unwrap();
+    }
+    sleep(Duration::from_millis(200));
+
+    conn_out.close();
+    let (_, received) = conn_in.close();
+    let sent: Vec<_> = sent.iter().map(MidiMessage::to_owned).collect();
+    assert_eq!(received, sent);
+}
+
+#[test]
+fn connect_rx_polling() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let midi_in = MidiInput::new("My Test Input").unwrap();
+    let midi_out = MidiOutput::new("My Test Output").unwrap();
+
+    let mut conn_out = midi_out.create_virtual("midir-test").unwrap();
+    let new_port = midi_in.ports().into_iter().next_back().unwrap();
+    let rx = midi_in.connect_rx(&new_port, "midir-test").unwrap();
+    assert_eq!(rx.try_recv(), None);
+
+    conn_out.send(&[144, 60, 1]).unwrap();
+    conn_out.send(&[128, 60, 0]).unwrap();
+    let timeout = Duration::from_secs(1);
+    assert_eq!(rx.recv_timeout(timeout).unwrap().1, [144, 60, 1]);
+    assert_eq!(rx.recv_timeout(timeout).unwrap().1, [128, 60, 0]);
+    assert_eq!(rx.try_recv(), None);
+
+    rx.close();
+    conn_out.close();
+}
+
+#[test]
+fn send_right_after_connect() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let midi_in = MidiInput::new("My Test Input").unwrap();
+    let midi_out = MidiOutput::new("My Test Output").unwrap();
+
+    // Input arrives as soon as the port exists, so nothing may be lost (or
+    // passed to a callback without a port) while the connection is set up
+    let conn_in = midi_in
+        .create_virtual(
+            "midir-test",
+            |_, message, received: &mut Vec<Vec<u8>>| received.push(message.to_vec()),
+            Vec::new(),
+        )
+        .unwrap();
+    let new_port = midi_out.ports().into_iter().next_back().unwrap();
+    let mut conn_out = midi_out.connect(&new_port, "midir-test").unwrap();
+    conn_out.send(&[144, 60, 1]).unwrap();
+    sleep(Duration::from_millis(200));
+
+    conn_out.close();
+    let (_, received) = conn_in.close();
+    assert_eq!(received, vec![vec![144, 60, 1]]);
+}
+
+#[test]
+fn max_sysex_size() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let mut midi_in = MidiInput::new("My Test Input").unwrap();
+    midi_in.set_max_sysex_size(Some(300));
+    let midi_out = MidiOutput::new("My Test Output").unwrap();
+
+    let conn_in = midi_in
+        .create_virtual(
+            "midir-test",
+            |_, message, received: &mut Vec<Vec<u8>>| received.push(message.to_vec()),
+            Vec::new(),
+        )
+        .unwrap();
+    let new_port = midi_out.ports().into_iter().next_back().unwrap();
+    let mut conn_out = midi_out.connect(&new_port, "midir-test").unwrap();
+
+    // The long message arrives in several segments and is dropped when it exceeds
+    // the limit, but the following messages are received as usual
+    let mut long = vec![0xF0];
+    long.extend_from_slice(&[0x01; 1000]);
+    long.push(0xF7);
+    conn_out.send(&long).unwrap();
+    conn_out.send(&[0xF0, 0x01, 0x02, 0xF7]).unwrap();
+    conn_out.send(&[144, 60, 1]).unwrap();
+    sleep(Duration::from_millis(200));
+
+    conn_out.close();
+    assert_eq!(conn_in.filtered_count(), 1);
+    let (_, received) = conn_in.close();
+    assert_eq!(
+        received,
+        vec![vec![0xF0, 0x01, 0x02, 0xF7], vec![144, 60, 1]]
+    );
+}
+
+// CoreMIDI can't tell a disposed endpoint from one without a name
+#[cfg(target_os = "linux")]
+#[test]
+fn port_name_of_stale_port() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let midi_in = MidiInput::new("My Test Input").unwrap();
+    let midi_out = MidiOutput::new("My Test Output").unwrap();
+
+    let conn_in = midi_in
+        .create_virtual("midir-test", |_, _, _| {}, ())
+        .unwrap();
+    let port = midi_out.ports().into_iter().next_back().unwrap();
+    assert_eq!(midi_out.port_name(&port).unwrap(), "midir-test");
+    conn_in.close();
+
+    assert_eq!(midi_out.port_name(&port), Err(PortInfoError::InvalidPort));
+}
+
+// CoreMIDI only delivers the notifications on a run loop, which the test thread doesn't run
+#[cfg(target_os = "linux")]
+#[test]
+fn watch_virtual_port() {
+    use midir::DeviceEvent;
+    use std::sync::mpsc;
+
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let midi_in = MidiInput::new("My Test Input").unwrap();
+    let midi_out = MidiOutput::new("My Test Output").unwrap();
+
+    let (tx, rx) = mpsc::channel();
+    let watcher = midi_out
+        .watch(move |event| {
+            let _ = tx.send(event);
+        })
+        .unwrap();
+
+    let conn_in = midi_in
+        .create_virtual("midir-test", |_, _, _| {}, ())
+        .unwrap();
+    let port = match rx.recv_timeout(Duration::from_secs(1)).unwrap() {
+        DeviceEvent::Added(port) => port,
+        event => panic!("unexpected event: {:?}", event),
+    };
+    assert_eq!(midi_out.port_name(&port).unwrap(), "midir-test");
+
+    conn_in.close();
+    assert_eq!(
+        rx.recv_timeout(Duration::from_secs(1)).unwrap(),
+        DeviceEvent::Removed(port)
+    );
+
+    drop(watcher);
+    assert!(rx.recv_timeout(Duration::from_millis(100)).is_err());
+}